@@ -0,0 +1,45 @@
+// Benchmark small responses, run with `cargo bench`.
+
+extern crate pencil;
+
+use std::io;
+use std::time::Instant;
+
+use pencil::Response;
+use pencil::wrappers::ResponseBody;
+
+
+const ITERATIONS: u32 = 1000000;
+
+
+fn bench<F: Fn()>(name: &str, f: F) {
+    for _ in 0..ITERATIONS / 10 {
+        f();
+    }
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed();
+    let nanos = elapsed.as_secs() * 1000000000 + elapsed.subsec_nanos() as u64;
+    println!("{:<24} {:>8} ns/iter", name, nanos / ITERATIONS as u64);
+}
+
+
+fn write_response(mut response: Response) {
+    let mut body = response.body.take().unwrap();
+    body.write_body(&mut ResponseBody::new(io::sink())).unwrap();
+}
+
+
+fn main() {
+    bench("response from str", || {
+        write_response(Response::from("Hello World!"));
+    });
+    bench("response from string", || {
+        write_response(Response::from(String::from("Hello World!")));
+    });
+    bench("response from bytes", || {
+        write_response(Response::from(&b"Hello World!"[..]));
+    });
+}
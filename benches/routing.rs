@@ -0,0 +1,81 @@
+// Benchmark URL matching with many rules, run with `cargo bench`.
+
+extern crate pencil;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use pencil::method::Get;
+use pencil::routing::{Map, MapAdapterMatched, Rule};
+
+
+const ITERATIONS: u32 = 100000;
+
+
+fn bench<F: Fn()>(name: &str, f: F) {
+    for _ in 0..ITERATIONS / 10 {
+        f();
+    }
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed();
+    let nanos = elapsed.as_secs() * 1000000000 + elapsed.subsec_nanos() as u64;
+    println!("{:<24} {:>8} ns/iter", name, nanos / ITERATIONS as u64);
+}
+
+
+/// Match from several threads at once, the reported time is the wall
+/// clock time per match over all threads.
+fn bench_concurrent(name: &str, map: &Arc<Map>, threads: u32) {
+    let start = Instant::now();
+    let handles: Vec<_> = (0..threads).map(|i| {
+        let map = map.clone();
+        thread::spawn(move || {
+            let paths: Vec<String> = (0..100).map(|j| format!("/section{}/{}/edit", (i * 7 + j) % 100, j)).collect();
+            for n in 0..ITERATIONS as usize {
+                match_path(&map, &paths[n % paths.len()]);
+            }
+        })
+    }).collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let elapsed = start.elapsed();
+    let nanos = elapsed.as_secs() * 1000000000 + elapsed.subsec_nanos() as u64;
+    println!("{:<24} {:>8} ns/iter", name, nanos / (ITERATIONS * threads) as u64);
+}
+
+
+fn match_path(map: &Map, path: &str) {
+    match map.bind(String::from("localhost"), String::from(path), None, Get).matched() {
+        MapAdapterMatched::MatchedRule(_) => (),
+        _ => panic!("{} should match", path),
+    }
+}
+
+
+fn main() {
+    let mut map = Map::new();
+    for i in 0..100 {
+        map.add(Rule::new(format!("/section{}/", i).into(), &[Get], &format!("section{}", i)));
+        map.add(Rule::new(format!("/section{}/<id:int>", i).into(), &[Get], &format!("item{}", i)));
+        map.add(Rule::new(format!("/section{}/<id:int>/edit", i).into(), &[Get], &format!("edit{}", i)));
+    }
+    bench("first static rule", || match_path(&map, "/section0/"));
+    bench("last static rule", || match_path(&map, "/section99/"));
+    bench("last dynamic rule", || match_path(&map, "/section99/42/edit"));
+
+    let mut cached = map.clone();
+    cached.enable_match_cache(1024);
+    bench("last dynamic rule cached", || match_path(&cached, "/section99/42/edit"));
+
+    let map = Arc::new(map);
+    let cached = Arc::new(cached);
+    for &threads in &[1, 4, 8] {
+        bench_concurrent(&format!("{} threads", threads), &map, threads);
+        bench_concurrent(&format!("{} threads cached", threads), &cached, threads);
+    }
+}
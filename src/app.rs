@@ -1,22 +1,33 @@
 //! This module implements the central application object.
 
+use std::any::TypeId;
 use std::convert::Into;
-use std::sync::RwLock;
+use std::sync::{Arc, Mutex, RwLock};
+use std::ops::{Deref, Range};
 use std::fmt;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::fs::File;
 use std::path::PathBuf;
 use std::net::ToSocketAddrs;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Duration;
+use std::env;
+use std::io::{self, Write};
+use std::process;
 
 use rustc_serialize::json::Json;
 use rustc_serialize::json::ToJson;
+use rustc_serialize::Encodable;
 use handlebars::Handlebars;
 use hyper;
 use hyper::method::Method;
 use hyper::status::StatusCode;
 use hyper::server::Request as HTTPRequest;
 use hyper::server::Response as HTTPResponse;
+use hyper::header::{Allow, CacheControl, CacheDirective, ContentLength};
+use mime_guess::guess_mime_type;
+use typemap::ShareMap;
 
 use types::{
     PencilError,
@@ -25,26 +36,52 @@ use types::{
 
     UserError,
     PencilResult,
+    ViewArgs,
     ViewFunc,
+    IntoViewFunc,
     HTTPErrorHandler,
     UserErrorHandler,
     BeforeRequestFunc,
     AfterRequestFunc,
     TeardownRequestFunc,
+    MiddlewareFunc,
+    CommandFunc,
+    ConfigReloadFunc,
+    ConverterFunc,
+    user_error_handler,
+    user_error_desc_handler,
 };
 use wrappers::{
     Request,
     Response,
 };
-use helpers::{PathBound, send_from_directory, redirect};
-use config::Config;
+use helpers::{PathBound, UrlFor, send_file_auto, send_file_range, redirect, add_static_cache_headers};
+use config::{Config, ConfigReloader};
+#[cfg(unix)]
+use config::reload_on_sighup;
 use logging;
-use serving::run_server;
-use routing::{Map, Rule, Matcher};
-use testing::PencilClient;
-use http_errors::{HTTPError, NotFound, InternalServerError};
-use templating::{render_template, render_template_string, load_template};
+use serving::{run_server, ServerOptions};
+#[cfg(feature = "ssl")]
+use serving::run_tls_server;
+#[cfg(feature = "ssl")]
+use tls::TlsConfig;
+use tls::{ClientCertificate, client_certificate};
+use routing::{Map, Rule, RuleError, Matcher, Redirect, PathNormalization, LocalePrefix};
+use testing::{PencilClient, TestRequest};
+use http_errors::{HTTPError, NotFound, InternalServerError, RequestEntityTooLarge};
+use templating::{render_template, render_template_string, load_template, create_registry};
 use module::Module;
+use cli;
+use pool::{Pool, PoolKey, release_connection};
+use sessions::{Session, SessionCookieConfig, save_session};
+use auth::{RememberMe, RememberStore};
+use signing::{SecretKeys, Signer};
+use idempotency::{Idempotency, IdempotencyStore};
+use staticfiles::{StaticProvider, FilesystemProvider};
+use sitemaps::{SitemapFunc, send_sitemap, send_sitemap_page};
+use cors::Cors;
+use signals::Signals;
+use json::{JsonEncoderFunc, JsonResponse};
 
 
 /// The pencil type.  It acts as the central application object.  Once it is created it
@@ -70,20 +107,45 @@ pub struct Pencil {
     pub url_map: Map,
     /// All the attached modules in a hashmap by name.
     pub modules: HashMap<String, Module>,
+    /// State managed by the application and shared by all requests, like
+    /// connection pools.
+    pub state: ShareMap,
+    /// The signals of the application, subscribe to them to be notified
+    /// about requests and template rendering.
+    pub signals: Signals,
     /// A dictionary of all view functions registered.  The key will be endpoint.
     view_functions: HashMap<String, ViewFunc>,
+    before_first_request_funcs: Vec<Box<Fn(&Pencil) + Send + Sync>>,
+    /// Whether the `before_first_request` functions have finished.
+    first_request_done: Mutex<bool>,
     before_request_funcs: Vec<BeforeRequestFunc>,
     after_request_funcs: Vec<AfterRequestFunc>,
     teardown_request_funcs: Vec<TeardownRequestFunc>,
+    endpoint_hooks: HashMap<String, EndpointHooks>,
     http_error_handlers: HashMap<u16, HTTPErrorHandler>,
-    user_error_handlers: HashMap<String, UserErrorHandler>,
+    http_error_range_handlers: Vec<(Range<u16>, HTTPErrorHandler)>,
+    error_templates: HashMap<u16, String>,
+    user_error_handlers: HashMap<TypeId, UserErrorHandler>,
+    user_error_desc_handlers: HashMap<String, UserErrorHandler>,
+    idempotency: Option<Idempotency>,
+    static_provider: Option<Box<StaticProvider>>,
+    sitemap_func: Option<SitemapFunc>,
+    robots_txt: Option<String>,
+    favicon_path: Option<String>,
+    commands: HashMap<String, CommandFunc>,
+    release_funcs: Vec<fn(&mut Request)>,
+    session_signer: Option<Signer>,
+    remember_me: Option<RememberMe>,
+    config_reloader: Option<Arc<ConfigReloader>>,
+    response_time_header: bool,
+    cors: Option<Cors>,
+    trusted_proxies: usize,
+    spa_index: Option<String>,
+    json_encoders: Vec<JsonEncoderFunc>,
 }
 
 fn default_config() -> Config {
-    let mut config = Config::new();
-    config.set("DEBUG", Json::Boolean(false));
-    config.set("TESTING", Json::Boolean(false));
-    config
+    Config::new()
 }
 
 impl Pencil {
@@ -108,28 +170,60 @@ impl Pencil {
             static_url_path: String::from("/static"),
             template_folder: String::from("templates"),
             config: default_config(),
-            handlebars_registry: RwLock::new(Box::new(Handlebars::new())),
+            handlebars_registry: RwLock::new(Box::new(create_registry())),
             url_map: Map::new(),
             modules: HashMap::new(),
+            state: ShareMap::custom(),
+            signals: Signals::new(),
             view_functions: HashMap::new(),
+            before_first_request_funcs: vec![],
+            first_request_done: Mutex::new(false),
             before_request_funcs: vec![],
             after_request_funcs: vec![],
             teardown_request_funcs: vec![],
+            endpoint_hooks: HashMap::new(),
             http_error_handlers: HashMap::new(),
+            http_error_range_handlers: vec![],
+            error_templates: HashMap::new(),
             user_error_handlers: HashMap::new(),
+            user_error_desc_handlers: HashMap::new(),
+            idempotency: None,
+            static_provider: None,
+            sitemap_func: None,
+            robots_txt: None,
+            favicon_path: None,
+            commands: HashMap::new(),
+            release_funcs: vec![],
+            session_signer: None,
+            remember_me: None,
+            config_reloader: None,
+            response_time_header: false,
+            cors: None,
+            trusted_proxies: 0,
+            spa_index: None,
+            json_encoders: vec![],
         }
     }
 
+    /// The environment the application runs in.  This field is configured
+    /// from the config with the `ENV` configuration key, see
+    /// `Config::from_env_profile`.  Defaults to `production`.
+    pub fn env(&self) -> String {
+        self.config.env()
+    }
+
     /// The debug flag.  This field is configured from the config
-    /// with the `DEBUG` configuration key.  Defaults to `False`.
+    /// with the `DEBUG` configuration key.  Defaults to `True` in the
+    /// `development` environment and `False` otherwise.
     pub fn is_debug(&self) -> bool {
-        self.config.get_boolean("DEBUG", false)
+        self.config.get_boolean("DEBUG", self.env() == "development")
     }
 
     /// The testing flag.  This field is configured from the config
-    /// with the `TESTING` configuration key.  Defaults to `False`.
+    /// with the `TESTING` configuration key.  Defaults to `True` in the
+    /// `testing` environment and `False` otherwise.
     pub fn is_testing(&self) -> bool {
-        self.config.get_boolean("TESTING", false)
+        self.config.get_boolean("TESTING", self.env() == "testing")
     }
 
     /// Set the debug flag.  This field is configured from the config
@@ -146,13 +240,46 @@ impl Pencil {
         self.config.set("TESTING", Json::Boolean(flag));
     }
 
+    /// The secret keys from the `SECRET_KEY` and `SECRET_KEY_FALLBACKS`
+    /// configuration keys.  This returns `None` if no secret key is set.
+    pub fn secret_keys(&self) -> Option<SecretKeys> {
+        SecretKeys::from_config(&self.config)
+    }
+
+    /// Create a signer for the feature named by the salt.  Features that
+    /// sign values call this when they are enabled, so a missing secret
+    /// key fails at startup instead of on the first request.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no `SECRET_KEY` is configured.
+    pub fn signer(&self, salt: &str) -> Signer {
+        match self.secret_keys() {
+            Some(keys) => Signer::new(keys, salt),
+            None => panic!("A SECRET_KEY is required for {}, set it in the configuration \
+                            or load it with `config.secret_key_from_envvar`.", salt),
+        }
+    }
+
     /// Set global log level based on the application's debug flag.
     /// This is only useful for `env_logger` crate users.
-    /// On debug mode, this turns on all debug logging.
+    /// On debug mode, this turns on all debug logging.  Otherwise
+    /// production logs warnings and other environments log info, unless
+    /// `RUST_LOG` is set already.
     pub fn set_log_level(&self) {
         logging::set_log_level(self);
     }
 
+    /// Log to a rotating file instead of using `env_logger`, for
+    /// deployments without a log shipper.  The file is configured with the
+    /// `LOG_FILE`, `LOG_FILE_MAX_SIZE`, `LOG_FILE_BACKUP_COUNT`, `LOG_LEVEL`
+    /// and `ACCESS_LOG_LEVEL` configuration keys, nothing happens if
+    /// `LOG_FILE` is not set.  The file gets the error logs and an access log line for
+    /// every request.
+    pub fn init_file_logger(&self) -> Result<(), String> {
+        logging::init_file_logger(self)
+    }
+
     /// This is used to register a view function for a given URL rule.
     /// Basically this example:
     ///
@@ -162,46 +289,330 @@ impl Pencil {
     /// ```
     ///
     /// A rule that listens for `GET` will implicitly listen for `HEAD`.
+    /// The view function may be a closure and may return anything that
+    /// implements `IntoPencilResponse`, like a string or a
+    /// `(body, status)` tuple.
     ///
-    pub fn route<M: Into<Matcher>, N: AsRef<[Method]>>(&mut self, rule: M, methods: N, endpoint: &str, view_func: ViewFunc) {
+    pub fn route<M, N, V>(&mut self, rule: M, methods: N, endpoint: &str, view_func: V)
+        where M: Into<Matcher>, N: AsRef<[Method]>, V: IntoViewFunc {
         self.add_url_rule(rule.into(), methods.as_ref(), endpoint, view_func);
     }
 
+    /// Like `route`, but an invalid rule, an unknown converter or an
+    /// endpoint that already has a view function is an error instead of a
+    /// panic or a replaced view function:
+    ///
+    /// ```rust,ignore
+    /// if let Err(err) = app.try_route(&rule_from_config, &[Get], "page", page) {
+    ///     error!("Could not add the page route: {}", err);
+    /// }
+    /// ```
+    pub fn try_route<N, V>(&mut self, rule: &str, methods: N, endpoint: &str, view_func: V) -> Result<(), RuleError>
+        where N: AsRef<[Method]>, V: IntoViewFunc {
+        self.try_add_url_rule(rule, methods.as_ref(), endpoint, view_func)
+    }
+
     /// This is a shortcut for `route`, register a view function for
     /// a given URL rule with just `GET` method (implicitly `HEAD`).
-    pub fn get<M: Into<Matcher>>(&mut self, rule: M, endpoint: &str, view_func: ViewFunc) {
+    pub fn get<M: Into<Matcher>, V: IntoViewFunc>(&mut self, rule: M, endpoint: &str, view_func: V) {
         self.route(rule, &[Method::Get], endpoint, view_func);
     }
 
     /// This is a shortcut for `route`, register a view function for
     /// a given URL rule with just `POST` method.
-    pub fn post<M: Into<Matcher>>(&mut self, rule: M, endpoint: &str, view_func: ViewFunc) {
+    pub fn post<M: Into<Matcher>, V: IntoViewFunc>(&mut self, rule: M, endpoint: &str, view_func: V) {
         self.route(rule, &[Method::Post], endpoint, view_func);
     }
 
     /// This is a shortcut for `route`, register a view function for
     /// a given URL rule with just `DELETE` method.
-    pub fn delete<M: Into<Matcher>>(&mut self, rule: M, endpoint: &str, view_func: ViewFunc) {
+    pub fn delete<M: Into<Matcher>, V: IntoViewFunc>(&mut self, rule: M, endpoint: &str, view_func: V) {
         self.route(rule, &[Method::Delete], endpoint, view_func);
     }
 
     /// This is a shortcut for `route`, register a view function for
     /// a given URL rule with just `PATCH` method.
-    pub fn patch<M: Into<Matcher>>(&mut self, rule: M, endpoint: &str, view_func: ViewFunc) {
+    pub fn patch<M: Into<Matcher>, V: IntoViewFunc>(&mut self, rule: M, endpoint: &str, view_func: V) {
         self.route(rule, &[Method::Patch], endpoint, view_func);
     }
 
     /// This is a shortcut for `route`, register a view function for
     /// a given URL rule with just `PUT` method.
-    pub fn put<M: Into<Matcher>>(&mut self, rule: M, endpoint: &str, view_func: ViewFunc) {
+    pub fn put<M: Into<Matcher>, V: IntoViewFunc>(&mut self, rule: M, endpoint: &str, view_func: V) {
         self.route(rule, &[Method::Put], endpoint, view_func);
     }
 
+    /// Like `route`, but returns the hooks of the endpoint, so hooks and
+    /// middlewares can be attached to this route only:
+    ///
+    /// ```rust,ignore
+    /// app.route_with("/admin", &[Get], "admin", admin).before(require_admin);
+    /// ```
+    pub fn route_with<M, N, V>(&mut self, rule: M, methods: N, endpoint: &str, view_func: V) -> RouteHooks
+        where M: Into<Matcher>, N: AsRef<[Method]>, V: IntoViewFunc {
+        self.route(rule, methods, endpoint, view_func);
+        self.endpoint_hooks(endpoint)
+    }
+
+    /// Like `get`, but returns the hooks of the endpoint, see `route_with`.
+    pub fn get_with<M: Into<Matcher>, V: IntoViewFunc>(&mut self, rule: M, endpoint: &str, view_func: V) -> RouteHooks {
+        self.route_with(rule, &[Method::Get], endpoint, view_func)
+    }
+
+    /// Like `post`, but returns the hooks of the endpoint, see `route_with`.
+    pub fn post_with<M: Into<Matcher>, V: IntoViewFunc>(&mut self, rule: M, endpoint: &str, view_func: V) -> RouteHooks {
+        self.route_with(rule, &[Method::Post], endpoint, view_func)
+    }
+
+    /// The hooks of an endpoint, this works for module endpoints like
+    /// `admin.index` too.
+    pub fn endpoint_hooks(&mut self, endpoint: &str) -> RouteHooks {
+        RouteHooks {
+            app: self,
+            endpoint: endpoint.to_string(),
+        }
+    }
+
     /// Connects a URL rule.
-    pub fn add_url_rule(&mut self, matcher: Matcher, methods: &[Method], endpoint: &str, view_func: ViewFunc) {
+    pub fn add_url_rule<V: IntoViewFunc>(&mut self, matcher: Matcher, methods: &[Method], endpoint: &str, view_func: V) {
         let url_rule = Rule::new(matcher, methods, endpoint);
+        self.add_rule(url_rule, view_func);
+    }
+
+    /// Connects a URL rule, see `try_route`.
+    pub fn try_add_url_rule<V: IntoViewFunc>(&mut self, rule: &str, methods: &[Method], endpoint: &str, view_func: V) -> Result<(), RuleError> {
+        if self.view_functions.contains_key(endpoint) {
+            return Err(RuleError::EndpointCollision(endpoint.to_string()));
+        }
+        let matcher = try!(self.url_map.compile_rule(rule));
+        self.add_url_rule(matcher, methods, endpoint, view_func);
+        Ok(())
+    }
+
+    /// Connects a rule, like `add_url_rule` but for rules with further
+    /// options like a subdomain:
+    ///
+    /// ```rust,ignore
+    /// app.config.set("SERVER_NAME", "example.com".to_json());
+    /// app.add_rule(Rule::new("/".into(), &[Method::Get], "api_index").subdomain("api"), api_index);
+    /// ```
+    pub fn add_rule<V: IntoViewFunc>(&mut self, rule: Rule, view_func: V) {
+        self.add_view_func(rule, view_func.into_view_func());
+    }
+
+    #[doc(hidden)]
+    pub fn add_view_func(&mut self, rule: Rule, view_func: ViewFunc) {
+        let endpoint = rule.endpoint.clone();
+        self.url_map.add(rule);
+        self.view_functions.insert(endpoint, view_func);
+    }
+
+    /// Registers a custom URL converter, see `Map::add_converter`.  Register
+    /// converters before the routes that use them:
+    ///
+    /// ```rust,no_run
+    /// use pencil::Pencil;
+    ///
+    /// fn parse_year(value: &str) -> Option<String> {
+    ///     match value.parse::<u32>() {
+    ///         Ok(year) if year >= 1970 => Some(value.to_string()),
+    ///         _ => None,
+    ///     }
+    /// }
+    ///
+    /// let mut app = Pencil::new("/web/demo");
+    /// app.add_converter("year", r"\d{4}", parse_year);
+    /// ```
+    pub fn add_converter(&mut self, name: &str, regex: &str, parse: ConverterFunc) {
+        self.url_map.add_converter(name, regex, parse);
+    }
+
+    /// Build the URL path for an endpoint.  Values that are not used by the
+    /// URL rule are appended as query string:
+    ///
+    /// ```rust,ignore
+    /// let mut values = HashMap::new();
+    /// values.insert(String::from("user_id"), String::from("42"));
+    /// assert!(app.url_for("user", &values) == Some(String::from("/user/42")));
+    /// ```
+    ///
+    /// If the rule belongs to a subdomain and `SERVER_NAME` is set, an
+    /// absolute URL like `http://api.example.com/users` is built, the scheme
+    /// is taken from the `PREFERRED_URL_SCHEME` configuration key.
+    pub fn url_for(&self, endpoint: &str, values: &ViewArgs) -> Option<String> {
+        let scheme = match self.config.get("PREFERRED_URL_SCHEME") {
+            Some(Json::String(scheme)) => scheme,
+            _ => String::from("http"),
+        };
+        self.build_url(endpoint, values, Some(""), &scheme, "")
+    }
+
+    /// Build the URL for an endpoint from the given subdomain, the URL is
+    /// absolute if the rule belongs to another subdomain of `SERVER_NAME`.
+    #[doc(hidden)]
+    pub fn build_url(&self, endpoint: &str, values: &ViewArgs, subdomain: Option<&str>,
+                     scheme: &str, path_prefix: &str) -> Option<String> {
+        self.url_map.build_with_subdomain(endpoint, values).map(|(path, rule_subdomain)| {
+            let path = format!("{}{}", path_prefix, path);
+            if subdomain == Some(&rule_subdomain[..]) {
+                return path;
+            }
+            match self.config.get("SERVER_NAME") {
+                Some(Json::String(ref server_name)) if rule_subdomain.is_empty() => {
+                    format!("{}://{}{}", scheme, server_name, path)
+                },
+                Some(Json::String(ref server_name)) => {
+                    format!("{}://{}.{}{}", scheme, rule_subdomain, server_name, path)
+                },
+                _ => path,
+            }
+        })
+    }
+
+    /// Registers redirects from old paths, they are used when no URL rule
+    /// matches the requested path.  This is useful for URL migrations:
+    ///
+    /// ```rust,no_run
+    /// use pencil::Pencil;
+    /// use pencil::routing::Redirect;
+    ///
+    ///
+    /// fn main() {
+    ///     let mut app = Pencil::new("/web/demo");
+    ///     app.add_redirects(vec![
+    ///         ("/about-us", Redirect::to_location("/about", 301)),
+    ///         ("/members", Redirect::to_endpoint("users", 302)),
+    ///     ]);
+    /// }
+    /// ```
+    pub fn add_redirects<K, I>(&mut self, redirects: I)
+        where K: AsRef<str>, I: IntoIterator<Item=(K, Redirect)> {
+        for (path, redirect) in redirects {
+            self.url_map.add_redirect(path.as_ref(), redirect);
+        }
+    }
+
+    /// A table of the URL rules with their endpoints and methods, in the
+    /// order they were added.  This is what the `routes` command prints:
+    ///
+    /// ```text
+    /// Endpoint     Methods             Rule
+    /// index        GET, HEAD, OPTIONS  /
+    /// update_user  OPTIONS, POST       /users/<id:int>
+    /// ```
+    ///
+    /// Use `url_map.iter_rules()` to inspect the rules directly.
+    pub fn list_routes(&self) -> String {
+        cli::routes(self)
+    }
+
+    /// Registers a URL rule that permanently moved, `GET` requests that
+    /// match it are redirected to the URL of the endpoint.  The rule may have
+    /// variables, they are used to build the new URL:
+    ///
+    /// ```rust,ignore
+    /// app.get("/users/<user_id:int>", "user", user);
+    /// app.redirect_rule("/members/<user_id:int>", "user", 301);
+    /// ```
+    ///
+    /// Unlike `add_redirects`, the redirect takes precedence over rules
+    /// added later.
+    pub fn redirect_rule<M: Into<Matcher>>(&mut self, rule: M, endpoint: &str, code: u16) {
+        let url_rule = Rule::new(rule.into(), &[Method::Get], endpoint)
+            .redirect_to(Redirect::to_endpoint(endpoint, code));
         self.url_map.add(url_rule);
-        self.view_functions.insert(endpoint.to_string(), view_func);
+    }
+
+    /// Registers the redirects from the `REDIRECTS` configuration key.  It is
+    /// an object with the old paths as keys, the values are either the new
+    /// location (redirected with 301) or an object like
+    /// `{"location": "/new", "code": 302}` or `{"endpoint": "users"}`.
+    pub fn add_redirects_from_config(&mut self) {
+        let mut redirects = Vec::new();
+        if let Some(Json::Object(object)) = self.config.get("REDIRECTS") {
+            for (path, value) in object {
+                let redirect = match value {
+                    Json::String(ref location) => Redirect::to_location(location, 301),
+                    Json::Object(ref options) => {
+                        let code = options.get("code").and_then(|code| code.as_u64()).unwrap_or(301) as u16;
+                        let location = options.get("location").and_then(|location| location.as_string());
+                        let endpoint = options.get("endpoint").and_then(|endpoint| endpoint.as_string());
+                        match (location, endpoint) {
+                            (Some(location), _) => Redirect::to_location(location, code),
+                            (None, Some(endpoint)) => Redirect::to_endpoint(endpoint, code),
+                            (None, None) => panic!("The redirect for {} has no location or endpoint.", path),
+                        }
+                    },
+                    _ => panic!("The redirect for {} is invalid.", path),
+                };
+                redirects.push((path, redirect));
+            }
+        }
+        self.add_redirects(redirects);
+    }
+
+    /// Enables a cache of the matched rules for the `capacity` most recently
+    /// requested URLs, see `Map::enable_match_cache`.  This is worth it for
+    /// applications with hundreds of rules:
+    ///
+    /// ```rust,ignore
+    /// app.enable_match_cache(1024);
+    /// ```
+    pub fn enable_match_cache(&mut self, capacity: usize) {
+        self.url_map.enable_match_cache(capacity);
+    }
+
+    /// Set whether a missing or extra trailing slash is an error for the
+    /// rules that are added after this, see `Rule::strict_slashes`.  With
+    /// `false` both `/users` and `/users/` match the rule `/users/` without
+    /// a redirect:
+    ///
+    /// ```rust,ignore
+    /// app.set_strict_slashes(false);
+    /// app.get("/users/", "users", users);
+    /// ```
+    pub fn set_strict_slashes(&mut self, flag: bool) {
+        self.url_map.set_strict_slashes(flag);
+    }
+
+    /// Enables normalization of request paths before they are matched, for
+    /// example duplicate slashes are collapsed so `//api//users` matches
+    /// `/api/users`.  Depending on the options the path is rewritten in place
+    /// or the client is redirected to the normalized URL:
+    ///
+    /// ```rust,no_run
+    /// use pencil::Pencil;
+    /// use pencil::routing::PathNormalization;
+    ///
+    ///
+    /// fn main() {
+    ///     let mut app = Pencil::new("/web/demo");
+    ///     let mut normalization = PathNormalization::new();
+    ///     normalization.redirect = false;
+    ///     app.enable_url_normalization(normalization);
+    /// }
+    /// ```
+    pub fn enable_url_normalization(&mut self, normalization: PathNormalization) {
+        self.url_map.set_normalization(Some(normalization));
+    }
+
+    /// Enables routing with a locale prefix for the whole application, a
+    /// request to `/de/about` matches the rule `/about` and the request's
+    /// locale becomes `de`.  Paths without a supported locale prefix are
+    /// matched as they are with the default locale.  `Request::url_for`
+    /// adds the prefix of the current request back:
+    ///
+    /// ```rust,no_run
+    /// use pencil::Pencil;
+    ///
+    ///
+    /// fn main() {
+    ///     let mut app = Pencil::new("/web/demo");
+    ///     app.enable_locale_prefix(&["en", "de", "fr"], "en");
+    /// }
+    /// ```
+    pub fn enable_locale_prefix(&mut self, supported: &[&str], default: &str) {
+        self.url_map.set_locale_prefix(Some(LocalePrefix::new(supported, default)));
     }
 
     /// Register a module on the application.
@@ -209,29 +620,305 @@ impl Pencil {
         module.register(self);
     }
 
-    /// Enables static file handling.
+    /// Register a copy of a module under another name and url prefix, see
+    /// `Module::register_as`.
+    pub fn register_module_as(&mut self, module: &Module, name: &str, url_prefix: &str) {
+        module.register_as(self, name, url_prefix);
+    }
+
+    /// Enables static file handling.  With `STATIC_DIRECTORY_LISTING` the
+    /// static root itself is listed too.
     pub fn enable_static_file_handling(&mut self) {
         let mut rule = self.static_url_path.clone();
         rule = rule + "/<filename:path>";
         let rule_str: &str = &rule;
         self.route(rule_str, &[Method::Get], "static", send_app_static_file);
+        let root_rule = self.static_url_path.trim_right_matches('/').to_string() + "/";
+        let root_rule_str: &str = &root_rule;
+        self.route(root_rule_str, &[Method::Get], "static", send_app_static_file);
+    }
+
+    /// Sets the provider of the files served by the static file handling,
+    /// the default provider reads them from the static folder on disk.
+    /// See the `staticfiles` module.
+    pub fn set_static_provider<P: StaticProvider + 'static>(&mut self, provider: P) {
+        self.static_provider = Some(Box::new(provider));
+    }
+
+    /// Serves the given file for `GET` requests that don't match any route,
+    /// so a single-page app can do its own routing on the client.  The
+    /// path is relative to the root path of the application.  Requests for
+    /// static files and requests that don't accept HTML still get
+    /// `404 Not Found`:
+    ///
+    /// ```rust,no_run
+    /// use pencil::Pencil;
+    ///
+    /// let mut app = Pencil::new("/web/demo");
+    /// app.enable_static_file_handling();
+    /// app.enable_spa("static/index.html");
+    /// ```
+    pub fn enable_spa(&mut self, index_path: &str) {
+        self.spa_index = Some(index_path.to_string());
+    }
+
+    /// Enables support for the `Idempotency-Key` header.  The first response
+    /// to an unsafe request (like `POST`) with such a header is stored in the
+    /// given store for `ttl`, retries with the same key get the stored
+    /// response replayed instead of running the view again.  Keys are scoped
+    /// to the caller (the `Authorization` header, the session cookie or the
+    /// remote address), a retry with another body is rejected with `422`
+    /// and a retry while the first request still runs with `409`.  A
+    /// request that fails or panics releases its key, a claim that is lost
+    /// otherwise expires after `IDEMPOTENCY_CLAIM_TIMEOUT` seconds (60 by
+    /// default):
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    ///
+    /// use pencil::Pencil;
+    /// use pencil::idempotency::MemoryStore;
+    ///
+    ///
+    /// fn main() {
+    ///     let mut app = Pencil::new("/web/demo");
+    ///     app.enable_idempotency(MemoryStore::new(), Duration::from_secs(24 * 60 * 60));
+    /// }
+    /// ```
+    pub fn enable_idempotency<S: IdempotencyStore + 'static>(&mut self, store: S, ttl: Duration) {
+        self.idempotency = Some(Idempotency::new(store, ttl));
+    }
+
+    /// Enables serving a sitemap at `/sitemap.xml`.  The sitemap contains
+    /// all rules that listen for `GET` and have no variables, plus the
+    /// entries returned by the given function for dynamic pages.  Sitemaps
+    /// with more than 50,000 URLs are served as a sitemap index pointing to
+    /// `/sitemap1.xml`, `/sitemap2.xml` and so on:
+    ///
+    /// ```rust,no_run
+    /// use pencil::{Pencil, Request};
+    /// use pencil::sitemaps::{SitemapEntry, ChangeFreq};
+    ///
+    ///
+    /// fn sitemap_entries(_: &mut Request) -> Vec<SitemapEntry> {
+    ///     vec![SitemapEntry::new("/post/hello-world").changefreq(ChangeFreq::Weekly)]
+    /// }
+    ///
+    ///
+    /// fn main() {
+    ///     let mut app = Pencil::new("/web/demo");
+    ///     app.enable_sitemap(sitemap_entries);
+    /// }
+    /// ```
+    pub fn enable_sitemap(&mut self, f: SitemapFunc) {
+        self.sitemap_func = Some(f);
+        self.get("/sitemap.xml", "sitemap", send_sitemap);
+        self.get("/sitemap<page:int>.xml", "sitemap_page", send_sitemap_page);
+    }
+
+    /// Serves the given rules at `/robots.txt`, the response may be cached
+    /// by clients for one day:
+    ///
+    /// ```rust,no_run
+    /// let mut app = pencil::Pencil::new("/web/demo");
+    /// app.serve_robots("User-agent: *\nDisallow: /admin/\n");
+    /// ```
+    pub fn serve_robots(&mut self, rules: &str) {
+        self.robots_txt = Some(rules.to_string());
+        self.get("/robots.txt", "robots_txt", send_robots_txt);
+    }
+
+    /// Serves the given file at `/favicon.ico`, the path is relative to the
+    /// root path of the application.  The response may be cached by clients
+    /// for one week.
+    pub fn serve_favicon(&mut self, path: &str) {
+        self.favicon_path = Some(path.to_string());
+        self.get("/favicon.ico", "favicon", send_favicon);
+    }
+
+    /// Enables a connection pool that is created from the config, see the
+    /// `pool` module.  Views check out a connection with
+    /// `pool::connection`, it is returned when the request is torn down.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool can't be created from the config.
+    pub fn enable_pool<P: Pool>(&mut self) {
+        match P::from_config(&self.config) {
+            Ok(pool) => self.add_pool(pool),
+            Err(err) => panic!("Could not create the connection pool: {}", err),
+        }
+    }
+
+    /// Enables a connection pool that was created already.
+    pub fn add_pool<P: Pool>(&mut self, pool: P) {
+        self.state.insert::<PoolKey<P>>(pool);
+        self.release_funcs.push(release_connection::<P>);
     }
 
-    /// Registers a function to run before each request.
-    pub fn before_request(&mut self, f: BeforeRequestFunc) {
-        self.before_request_funcs.push(f);
+    /// Enables sessions stored in signed cookies, views use them with
+    /// `request.session()`.  The cookie is configured with the
+    /// `SESSION_COOKIE_*` and `PERMANENT_SESSION_LIFETIME` configuration
+    /// keys, see `SessionCookieConfig`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no `SECRET_KEY` is configured.
+    pub fn enable_sessions(&mut self) {
+        self.session_signer = Some(self.signer("session"));
+    }
+
+    /// Trust the `X-Forwarded-For`, `X-Forwarded-Proto` and
+    /// `X-Forwarded-Host` headers set by the given number of reverse
+    /// proxies in front of the application, like nginx.  `remote_addr`,
+    /// `scheme()`, `host()` and `is_secure()` of a request then describe
+    /// the client's request instead of the request of the last proxy.
+    ///
+    /// Only enable this if the application is not reachable without the
+    /// proxies, clients can send these headers themselves.
+    pub fn enable_proxy_fix(&mut self, trusted_proxies: usize) {
+        self.trusted_proxies = trusted_proxies;
+    }
+
+    #[doc(hidden)]
+    pub fn trusted_proxies(&self) -> usize {
+        self.trusted_proxies
+    }
+
+    /// Enables CORS for the whole application, see `cors::Cors`.  Modules
+    /// can use their own configuration with `Module::enable_cors`.
+    pub fn enable_cors(&mut self, cors: Cors) {
+        self.cors = Some(cors);
+    }
+
+    /// The CORS configuration for a request, the configuration of the
+    /// request's module is preferred.
+    fn cors_for<'r, 'a, 'b: 'a>(&'r self, request: &Request<'r, 'a, 'b>) -> Option<&'r Cors> {
+        request.modules().into_iter().filter_map(|module| module.cors.as_ref()).next().or(self.cors.as_ref())
+    }
+
+    /// Adds an `X-Response-Time` header to every response with the time it
+    /// took to handle the request in milliseconds, like `12.345ms`.
+    pub fn enable_response_time_header(&mut self) {
+        self.response_time_header = true;
+    }
+
+    /// Enables remember-me logins that last for `lifetime`, see
+    /// `auth::RememberMe`.  Views use them with `request.app.remember_me()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no `SECRET_KEY` is configured.
+    pub fn enable_remember_me<S: RememberStore + 'static>(&mut self, store: S, lifetime: Duration) {
+        let signer = self.signer("remember-me");
+        self.remember_me = Some(RememberMe::new(store, signer, lifetime));
+    }
+
+    /// The remember-me logins if they are enabled.
+    pub fn remember_me(&self) -> Option<&RememberMe> {
+        self.remember_me.as_ref()
+    }
+
+    /// Enables reloading configuration values from a JSON file while the
+    /// application runs.  The file is loaded now, reloads happen on
+    /// `SIGHUP` on unix or with `reload_config`, for example from an admin
+    /// endpoint with the `config::reload_config` view.  `config.get`
+    /// returns the values of the last reload, the values of the file take
+    /// precedence over the ones set in code.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file can't be loaded or reloading is already enabled.
+    pub fn enable_config_reload(&mut self, filepath: &str) {
+        if self.config_reloader.is_some() {
+            panic!("Config reloading is already enabled.");
+        }
+        let reloader = Arc::new(ConfigReloader::new(self.config.clone(), filepath));
+        if let Err(err) = reloader.reload() {
+            panic!("{}", err);
+        }
+        reloader.watch(&mut self.config);
+        #[cfg(unix)]
+        reload_on_sighup(reloader.clone());
+        self.config_reloader = Some(reloader);
+    }
+
+    /// Registers a function that is called with the new config after every
+    /// reload, for example to change the log level.
+    ///
+    /// # Panics
+    ///
+    /// Panics if config reloading is not enabled.
+    pub fn on_config_reload(&mut self, f: ConfigReloadFunc) {
+        match self.config_reloader {
+            Some(ref reloader) => reloader.add_listener(f),
+            None => panic!("Config reloading is not enabled, call enable_config_reload first."),
+        }
+    }
+
+    /// Reloads the config file, see `enable_config_reload`.
+    pub fn reload_config(&self) -> Result<(), String> {
+        match self.config_reloader {
+            Some(ref reloader) => reloader.reload(),
+            None => Err(String::from("Config reloading is not enabled")),
+        }
+    }
+
+    /// A snapshot of the current config, this includes reloaded values if
+    /// config reloading is enabled.  Unlike `config` it doesn't change
+    /// during a reload.
+    pub fn live_config(&self) -> Arc<Config> {
+        match self.config_reloader {
+            Some(ref reloader) => reloader.config(),
+            None => Arc::new(self.config.clone()),
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn session_signer(&self) -> Option<&Signer> {
+        self.session_signer.as_ref()
+    }
+
+    #[doc(hidden)]
+    pub fn sitemap_func(&self) -> Option<SitemapFunc> {
+        self.sitemap_func
+    }
+
+    /// Registers a function to run once before the first request is
+    /// dispatched, for warm-up work like filling caches.  Requests that
+    /// arrive meanwhile on other threads wait until it has finished.  If a
+    /// function panics the request fails with `500 Internal Server Error`
+    /// and the functions run again before the next request.
+    pub fn before_first_request<F>(&mut self, f: F) where F: Fn(&Pencil) + Send + Sync + 'static {
+        self.before_first_request_funcs.push(Box::new(f));
+    }
+
+    /// Registers a function to run before each request.  Functions and
+    /// closures can be used, closures can capture state:
+    ///
+    /// ```rust,ignore
+    /// let counter = Arc::new(AtomicUsize::new(0));
+    /// let requests = counter.clone();
+    /// app.before_request(move |_| {
+    ///     requests.fetch_add(1, Ordering::SeqCst);
+    ///     None
+    /// });
+    /// ```
+    pub fn before_request<F>(&mut self, f: F)
+        where F: Fn(&mut Request) -> Option<PencilResult> + Send + Sync + 'static {
+        self.before_request_funcs.push(Arc::new(f));
     }
 
     /// Registers a function to run after each request.  Your function
     /// must take a response object and modify it.
-    pub fn after_request(&mut self, f: AfterRequestFunc) {
-        self.after_request_funcs.push(f);
+    pub fn after_request<F>(&mut self, f: F) where F: Fn(&mut Response) + Send + Sync + 'static {
+        self.after_request_funcs.push(Arc::new(f));
     }
 
     /// Registers a function to run at the end of each request,
     /// regardless of whether there was an error or not.
-    pub fn teardown_request(&mut self, f: TeardownRequestFunc) {
-        self.teardown_request_funcs.push(f);
+    pub fn teardown_request<F>(&mut self, f: F) where F: Fn(Option<&PencilError>) + Send + Sync + 'static {
+        self.teardown_request_funcs.push(Arc::new(f));
     }
 
     /// Registers a function as one http error handler.
@@ -240,12 +927,6 @@ impl Pencil {
         self.http_error_handlers.insert(status_code, f);
     }
 
-    /// Registers a function as one user error handler.
-    /// Same to `usererrorhandler`.
-    pub fn register_user_error_handler(&mut self, error_desc: &str, f: UserErrorHandler) {
-        self.user_error_handlers.insert(error_desc.to_string(), f);
-    }
-
     /// Registers a function as one http error handler.  Example:
     ///
     /// ```rust,no_run
@@ -269,63 +950,85 @@ impl Pencil {
         self.register_http_error_handler(status_code, f);
     }
 
-    /// Registers a function as one user error handler.  There are two ways to handle
-    /// user errors currently, you can do it in your own view like this:
+    /// Registers a http error handler for a range of status codes, so all
+    /// client errors can be formatted the same way:
     ///
     /// ```rust,no_run
-    /// use pencil::Request;
-    /// use pencil::{PencilResult, Response};
+    /// use pencil::{Pencil, PencilResult, Response};
+    /// use pencil::HTTPError;
     ///
     ///
-    /// #[derive(Clone, Copy)]
-    /// struct MyErr(isize);
+    /// fn client_error(error: HTTPError) -> PencilResult {
+    ///     let mut response = Response::from(format!("Oops: {}", error.name()));
+    ///     response.status_code = error.code();
+    ///     Ok(response)
+    /// }
     ///
     ///
-    /// fn some_operation() -> Result<isize, MyErr> {
-    ///     return Err(MyErr(10));
+    /// fn main() {
+    ///     let mut app = Pencil::new("/web/demo");
+    ///     app.httperrorhandler_range(400..500, client_error);
     /// }
+    /// ```
     ///
+    /// Handlers for a single status code are preferred, then the handler
+    /// with the narrowest range.
+    pub fn httperrorhandler_range(&mut self, status_codes: Range<u16>, f: HTTPErrorHandler) {
+        self.http_error_range_handlers.push((status_codes, f));
+    }
+
+    /// Registers a http error handler for all client and server errors,
+    /// it is used when no other handler matches.
+    pub fn any_error_handler(&mut self, f: HTTPErrorHandler) {
+        self.httperrorhandler_range(400..600, f);
+    }
+
+    /// Registers a template for the error page of a status code, it is used
+    /// when there is no error handler for the code.  The template is
+    /// rendered with `code`, `name`, `description`, `method`, `path` and
+    /// `url` in the context:
     ///
-    /// fn my_err_handler(_: MyErr) -> PencilResult {
-    ///     Ok(Response::from("My err occurred!"))
-    /// }
+    /// ```rust,no_run
+    /// use pencil::Pencil;
     ///
     ///
-    /// fn hello(_: &mut Request) -> PencilResult {
-    ///     match some_operation() {
-    ///         Ok(_) => Ok(Response::from("Hello!")),
-    ///         Err(e) => my_err_handler(e),
-    ///     }
+    /// fn main() {
+    ///     let mut app = Pencil::new("/web/demo");
+    ///     app.error_template(404, "errors/404.html");
+    ///     app.error_template(500, "errors/500.html");
     /// }
     /// ```
-    ///
-    /// The problem with this is that you have to do it in all of your views, it brings
-    /// a lot of redundance, so pencil provides another solution, currently I still
-    /// haven't got any better idea on how to store user error handlers, this feature is
-    /// really just experimental, if you have any good idea, please wake me up.  Here is
-    /// one simple example:
+    pub fn error_template(&mut self, status_code: u16, template_name: &str) {
+        self.register_template(template_name);
+        self.error_templates.insert(status_code, template_name.to_string());
+    }
+
+    /// Registers a handler for user errors that were created from an error
+    /// of type `E` with `UserError::from_error`.  The handler gets the
+    /// original error:
     ///
     /// ```rust,no_run
-    /// use std::convert;
+    /// use std::error::Error;
+    /// use std::fmt;
     ///
     /// use pencil::Request;
     /// use pencil::{Pencil, PencilResult, Response};
-    /// use pencil::{PencilError, PenUserError, UserError};
+    /// use pencil::UserError;
     ///
     ///
-    /// #[derive(Clone, Copy)]
-    /// pub struct MyErr(isize);
+    /// #[derive(Debug)]
+    /// struct MyErr(isize);
     ///
-    /// impl convert::From<MyErr> for PencilError {
-    ///     fn from(err: MyErr) -> PencilError {
-    ///         let user_error = UserError::new("MyErr");
-    ///         return PenUserError(user_error);
+    /// impl fmt::Display for MyErr {
+    ///     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    ///         write!(f, "my err {}", self.0)
     ///     }
     /// }
     ///
-    ///
-    /// fn my_err_handler(_: UserError) -> PencilResult {
-    ///     Ok(Response::from("My err occurred!"))
+    /// impl Error for MyErr {
+    ///     fn description(&self) -> &str {
+    ///         "my err"
+    ///     }
     /// }
     ///
     ///
@@ -335,19 +1038,43 @@ impl Pencil {
     ///
     ///
     /// fn hello(_: &mut Request) -> PencilResult {
-    ///     let rv = try!(some_operation());
+    ///     let rv = try!(some_operation().map_err(UserError::from_error));
     ///     return Ok(rv.into());
     /// }
     ///
     ///
     /// fn main() {
     ///     let mut app = Pencil::new("/web/demo");
-    ///     // Use error description as key to store handlers, really ugly...
-    ///     app.usererrorhandler("MyErr", my_err_handler);
+    ///     app.register_error_handler(|err: &MyErr| {
+    ///         Ok(Response::from(format!("My err {} occurred!", err.0)))
+    ///     });
     /// }
     /// ```
-    pub fn usererrorhandler(&mut self, error_desc: &str, f: UserErrorHandler) {
-        self.register_user_error_handler(error_desc, f);
+    ///
+    /// User errors without a handler become internal server errors.
+    pub fn register_error_handler<E, F>(&mut self, f: F)
+        where E: Error + 'static, F: Fn(&E) -> PencilResult + Send + Sync + 'static {
+        self.user_error_handlers.insert(TypeId::of::<E>(), user_error_handler(f));
+    }
+
+    #[doc(hidden)]
+    pub fn add_user_error_handler(&mut self, type_id: TypeId, handler: UserErrorHandler) {
+        self.user_error_handlers.insert(type_id, handler);
+    }
+
+    /// Registers a function as one user error handler for user errors
+    /// with the given description.
+    #[deprecated(note = "use `register_error_handler` instead")]
+    pub fn usererrorhandler(&mut self, error_desc: &str, f: fn(UserError) -> PencilResult) {
+        self.user_error_desc_handlers.insert(error_desc.to_string(), user_error_desc_handler(f));
+    }
+
+    /// Registers a function as one user error handler.
+    /// Same to `usererrorhandler`.
+    #[deprecated(note = "use `register_error_handler` instead")]
+    #[allow(deprecated)]
+    pub fn register_user_error_handler(&mut self, error_desc: &str, f: fn(UserError) -> PencilResult) {
+        self.usererrorhandler(error_desc, f);
     }
 
     /// Creates a test client for this application, you can use it
@@ -355,18 +1082,38 @@ impl Pencil {
     ///
     /// ```ignore
     /// let client = app.test_client();
-    /// let response = client.get('/');
-    /// assert!(response.code, 200);
+    /// let response = client.get_path("/").send().unwrap();
+    /// assert!(response.status_code == 200);
     /// ```
-    #[allow(dead_code)]
-    fn test_client(&self) -> PencilClient {
+    pub fn test_client(&self) -> PencilClient {
         PencilClient::new(self)
     }
 
+    /// Start building a request that is created in memory, without a server,
+    /// see `TestRequest::with_request`.  Use it to test hooks and helpers
+    /// that work on a request, or pass the request to `handle_request`.
+    pub fn test_request_context(&self, method: Method, path: &str) -> TestRequest {
+        TestRequest::new(self, method, path)
+    }
+
     /// Called before the actual request dispatching, you can return value
     /// from here and stop the further request handling.
     fn preprocess_request(&self, request: &mut Request) -> Option<PencilResult> {
-        if let Some(module) = self.get_module(request.module_name()) {
+        if let Some(max_content_length) = request.max_content_length() {
+            if let Some(&ContentLength(length)) = request.headers().get::<ContentLength>() {
+                if length > max_content_length {
+                    return Some(Err(PenHTTPError(RequestEntityTooLarge)));
+                }
+            }
+        }
+        if let Some(hooks) = request.url_rule.as_ref().and_then(|rule| self.endpoint_hooks.get(&rule.endpoint)) {
+            for func in &hooks.before_request_funcs {
+                if let Some(result) = func(request) {
+                    return Some(result);
+                }
+            }
+        }
+        for module in request.modules() {
             for func in &module.before_request_funcs {
                 if let Some(result) = func(request) {
                     return Some(result);
@@ -385,6 +1132,11 @@ impl Pencil {
     /// value of the view.
     fn dispatch_request(&self, request: &mut Request) -> PencilResult {
         if let Some(ref routing_error) = request.routing_error {
+            if routing_error.code() == 404 {
+                if let Some(result) = self.send_spa_index(request) {
+                    return result;
+                }
+            }
             return Err(PenHTTPError(routing_error.clone()));
         }
         if let Some((ref redirect_url, redirect_code)) = request.routing_redirect {
@@ -393,9 +1145,13 @@ impl Pencil {
         if let Some(default_options_response) = self.make_default_options_response(request) {
             return Ok(default_options_response);
         }
-        match self.view_functions.get(&request.endpoint().unwrap()) {
-            Some(&view_func) => {
-                view_func(request)
+        let view_func = request.url_rule.as_ref().and_then(|rule| self.view_functions.get(&rule.endpoint));
+        match view_func {
+            Some(view_func) => {
+                match request.url_rule.as_ref().and_then(|rule| self.endpoint_hooks.get(&rule.endpoint)) {
+                    Some(hooks) => call_with_middlewares(&hooks.middlewares, view_func, request),
+                    None => view_func(request),
+                }
             },
             None => {
                 Err(PenHTTPError(NotFound))
@@ -403,6 +1159,31 @@ impl Pencil {
         }
     }
 
+    /// Sends the index file of the single-page app for an unmatched
+    /// request, see `enable_spa`.
+    fn send_spa_index(&self, request: &Request) -> Option<PencilResult> {
+        let index_path = match self.spa_index {
+            Some(ref index_path) => index_path,
+            None => return None,
+        };
+        if request.method() != Method::Get && request.method() != Method::Head {
+            return None;
+        }
+        if request.path().starts_with(&format!("{}/", self.static_url_path.trim_right_matches('/'))) {
+            return None;
+        }
+        if request.best_mimetype(&["text/html"]).is_none() {
+            return None;
+        }
+        let mut path = PathBuf::from(&self.root_path);
+        path.push(index_path);
+        let result = send_file_range(request, &path, guess_mime_type(path.as_path()), false).map(|mut response| {
+            response.headers.set(CacheControl(vec![CacheDirective::NoCache]));
+            response
+        });
+        Some(result)
+    }
+
     /// This method is called to create the default `OPTIONS` response.
     fn make_default_options_response(&self, request: &Request) -> Option<Response> {
         if let Some(ref rule) = request.url_rule {
@@ -411,25 +1192,28 @@ impl Pencil {
             if rule.provide_automatic_options && request.method() == Method::Options {
                 let url_adapter = request.url_adapter();
                 let mut response = Response::new_empty();
-                response.headers.set(hyper::header::Allow(url_adapter.allowed_methods()));
+                let allowed_methods = url_adapter.allowed_methods();
+                if let Some(cors) = self.cors_for(request) {
+                    cors.apply_preflight(request, allowed_methods.clone(), &mut response);
+                }
+                response.headers.set(hyper::header::Allow(allowed_methods));
                 return Some(response);
             }
         }
         None
     }
 
-    /// Get a module by its name.
-    fn get_module(&self, module_name: Option<String>) -> Option<&Module> {
-        if let Some(name) = module_name {
-            self.modules.get(&name)
-        } else {
-            None
-        }
-    }
-
     /// Modify the response object before it's sent to the HTTP server.
-    fn process_response(&self, request: &Request, response: &mut Response) {
-        if let Some(module) = self.get_module(request.module_name()) {
+    fn process_response(&self, request: &mut Request, response: &mut Response) {
+        for func in request.take_after_this_request() {
+            func(response);
+        }
+        if let Some(hooks) = request.url_rule.as_ref().and_then(|rule| self.endpoint_hooks.get(&rule.endpoint)) {
+            for func in hooks.after_request_funcs.iter().rev() {
+                func(response);
+            }
+        }
+        for module in request.modules() {
             for func in module.after_request_funcs.iter().rev() {
                 func(response);
             }
@@ -437,11 +1221,22 @@ impl Pencil {
         for func in self.after_request_funcs.iter().rev() {
             func(response);
         }
+        if let Some(ref signer) = self.session_signer {
+            if let Some(session) = request.extensions_data.get::<Session>() {
+                save_session(signer, &SessionCookieConfig::from_config(&self.config), session, response);
+            }
+        }
+        if let Some(cookie) = RememberMe::pending_cookie(request) {
+            response.add_cookie(cookie.clone());
+        }
+        if let Some(cors) = self.cors_for(request) {
+            cors.apply(request, response);
+        }
     }
 
     /// Called after the actual request dispatching.
     fn do_teardown_request(&self, request: &Request, e: Option<&PencilError>) {
-        if let Some(module) = self.get_module(request.module_name()) {
+        for module in request.modules() {
             for func in module.teardown_request_funcs.iter().rev() {
                 func(e);
             }
@@ -461,30 +1256,104 @@ impl Pencil {
 
     /// Handles an User error.
     fn handle_user_error(&self, request: &Request, e: UserError) -> PencilResult {
-        if let Some(module) = self.get_module(request.module_name()) {
-            if let Some(handler) = module.user_error_handlers.get(&e.desc) {
-                return handler(e);
+        if let Some(source_type) = e.source_type() {
+            for module in request.modules() {
+                if let Some(handler) = module.user_error_handlers.get(&source_type) {
+                    return handler(&e);
+                }
+            }
+            if let Some(handler) = self.user_error_handlers.get(&source_type) {
+                return handler(&e);
             }
         }
-        if let Some(handler) = self.user_error_handlers.get(&e.desc) {
-            return handler(e);
+        for module in request.modules() {
+            if let Some(handler) = module.user_error_desc_handlers.get(&e.desc) {
+                return handler(&e);
+            }
+        }
+        if let Some(handler) = self.user_error_desc_handlers.get(&e.desc) {
+            return handler(&e);
         }
         Err(PenUserError(e))
     }
 
     /// Handles an HTTP error.
     fn handle_http_error(&self, request: &Request, e: HTTPError) -> PencilResult {
-        if let Some(module) = self.get_module(request.module_name()) {
-            if let Some(handler) = module.http_error_handlers.get(&e.code()) {
-                return handler(e);
+        if let Some(handler) = self.find_http_error_handler(request, e.code()) {
+            let allowed_methods = e.allowed_methods().cloned();
+            let mut rv = handler(e);
+            // Handlers build their own responses, keep the `Allow` header
+            // required for 405 responses.
+            if let (&mut Ok(ref mut response), Some(allowed_methods)) = (&mut rv, allowed_methods) {
+                if response.status_code == 405 && !response.headers.has::<Allow>() {
+                    response.headers.set(Allow(allowed_methods));
+                }
             }
+            return rv;
         }
-        if let Some(handler) = self.http_error_handlers.get(&e.code()) {
-            return handler(e);
+        if self.wants_json_errors(request) {
+            return Ok(e.to_json_response());
+        }
+        if let Some(template_name) = self.error_templates.get(&e.code()) {
+            return Ok(self.render_error_template(request, &e, template_name));
         }
         Ok(e.to_response())
     }
 
+    /// Find the http error handler for a status code, module handlers are
+    /// preferred.
+    fn find_http_error_handler(&self, request: &Request, code: u16) -> Option<HTTPErrorHandler> {
+        for module in request.modules() {
+            if let Some(&handler) = module.http_error_handlers.get(&code) {
+                return Some(handler);
+            }
+            if let Some(handler) = find_range_handler(&module.http_error_range_handlers, code) {
+                return Some(handler);
+            }
+        }
+        if let Some(&handler) = self.http_error_handlers.get(&code) {
+            return Some(handler);
+        }
+        find_range_handler(&self.http_error_range_handlers, code)
+    }
+
+    /// Whether the default error response should be JSON.  This is
+    /// configured with the `ERROR_FORMAT` config key, `"json"` and `"html"`
+    /// always use that format, the default `"auto"` sends JSON when the
+    /// `Accept` header prefers `application/json` over `text/html`.
+    fn wants_json_errors(&self, request: &Request) -> bool {
+        match request.config_value("ERROR_FORMAT") {
+            Some(Json::String(ref format)) if format == "json" => true,
+            Some(Json::String(ref format)) if format == "html" => false,
+            _ => request.prefers_json(),
+        }
+    }
+
+    /// Renders the error page from a template, the default error page is
+    /// used if the template fails to render.
+    fn render_error_template(&self, request: &Request, e: &HTTPError, template_name: &str) -> Response {
+        let mut context = BTreeMap::new();
+        context.insert("code".to_string(), e.code().to_json());
+        context.insert("name".to_string(), e.name().to_json());
+        context.insert("description".to_string(), e.description().to_json());
+        context.insert("method".to_string(), request.method().to_string().to_json());
+        context.insert("path".to_string(), request.path().to_json());
+        context.insert("url".to_string(), request.url.to_string().to_json());
+        let mut response = e.to_response();
+        match self.render_template(template_name, &context) {
+            Ok(rendered) => {
+                if let Some(&ContentLength(length)) = rendered.headers.get::<ContentLength>() {
+                    response.set_content_length(length as usize);
+                }
+                response.body = rendered.body;
+            },
+            Err(err) => {
+                error!("Error page template {} failed to render: {}", template_name, err.description());
+            },
+        }
+        response
+    }
+
     /// Default error handing that kicks in when an error occurs that is not
     /// handled.
     fn handle_error(&self, request: &Request, e: &PencilError) -> Response {
@@ -503,12 +1372,43 @@ impl Pencil {
         error!("Error on {} [{}]: {}", request.path(), request.method(), e.description());
     }
 
+    /// Runs the `before_first_request` functions if they haven't finished
+    /// yet.  A panic is turned into an error, so the functions run again
+    /// for the next request instead of poisoning the application.
+    fn try_trigger_before_first_request(&self) -> Result<(), PencilError> {
+        let mut done = match self.first_request_done.lock() {
+            Ok(done) => done,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if *done {
+            return Ok(());
+        }
+        for func in &self.before_first_request_funcs {
+            if panic::catch_unwind(AssertUnwindSafe(|| func(self))).is_err() {
+                return Err(PenUserError(UserError::new("A before_first_request function panicked.")));
+            }
+        }
+        *done = true;
+        Ok(())
+    }
+
     /// Dispatches the request and performs request pre and postprocessing
     /// as well as HTTP error handling and User error handling.
     fn full_dispatch_request(&self, request: &mut Request) -> Result<Response, PencilError> {
+        try!(self.try_trigger_before_first_request());
+        let mut claim = None;
         let result = match self.preprocess_request(request) {
             Some(result) => result,
-            None => self.dispatch_request(request),
+            None => {
+                match self.idempotency.as_ref().map(|idempotency| idempotency.replay(request)) {
+                    Some(Err(result)) => result,
+                    Some(Ok(key_claim)) => {
+                        claim = key_claim;
+                        self.dispatch_request(request)
+                    },
+                    None => self.dispatch_request(request),
+                }
+            },
         };
         let rv = match result {
             Ok(response) => Ok(response),
@@ -517,38 +1417,67 @@ impl Pencil {
         match rv {
             Ok(mut response) => {
                 self.process_response(request, &mut response);
+                if let (Some(claim), Some(idempotency)) = (claim, self.idempotency.as_ref()) {
+                    if idempotency.remember(request, &mut response) {
+                        claim.keep();
+                    }
+                }
                 Ok(response)
             },
+            // The claim is dropped and released.
             Err(e) => Err(e),
         }
     }
 
     /// Load and compile and register a template.
     pub fn register_template(&mut self, template_name: &str) {
+        let source = match load_template(self, template_name) {
+            Some(Ok(source)) => source,
+            Some(Err(err)) => {
+                panic!(format!("Template {} can't be loaded: {}", template_name, err));
+            },
+            None => {
+                panic!(format!("Template not found: {}", template_name));
+            }
+        };
         let registry_write_rv = self.handlebars_registry.write();
         if registry_write_rv.is_err() {
             panic!("Can't write handlebars registry");
         }
         let mut registry = registry_write_rv.unwrap();
-        match load_template(self, template_name) {
-            Some(source_rv) => {
-                match source_rv {
-                    Ok(source) => {
-                        if let Err(err) = registry.register_template_string(template_name, source) {
-                            panic!(format!("Template compile error: {}", err));
-                        }
-                    },
-                    Err(err) => {
-                        panic!(format!("Template {} can't be loaded: {}", template_name, err));
-                    }
-                }
-            },
-            None => {
-                panic!(format!("Template not found: {}", template_name));
-            }
+        if let Err(err) = registry.register_template_string(template_name, source) {
+            panic!(format!("Template compile error: {}", err));
         }
     }
 
+    /// Registers a JSON encoder hook.  It gets every value that is encoded
+    /// by `Pencil::jsonify` or passed to a template, and returns a
+    /// replacement for the values it handles, so types like dates don't
+    /// need to be converted by every caller.  The free `jsonify` function
+    /// doesn't know the application and doesn't apply the hooks:
+    ///
+    /// ```rust,ignore
+    /// // `Money` is encoded as `{"cents": 1250}`, send it as `"12.50"`.
+    /// app.json_encoder(|value| {
+    ///     value.find("cents").and_then(|cents| cents.as_i64())
+    ///          .map(|cents| Json::String(format!("{}.{:02}", cents / 100, cents % 100)))
+    /// });
+    /// ```
+    pub fn json_encoder<F>(&mut self, f: F) where F: Fn(&Json) -> Option<Json> + Send + Sync + 'static {
+        self.json_encoders.push(Arc::new(f));
+    }
+
+    #[doc(hidden)]
+    pub fn json_encoders(&self) -> &[JsonEncoderFunc] {
+        &self.json_encoders
+    }
+
+    /// Like the `jsonify` function, but the JSON encoder hooks of the
+    /// application are applied.
+    pub fn jsonify<T: Encodable>(&self, object: &T) -> PencilResult {
+        JsonResponse::with_encoders(object, self).build()
+    }
+
     /// We use `handlebars-rs` as template engine.
     /// Renders a template from the template folder with the given context.
     /// The template name is the name of the template to be rendered.
@@ -569,29 +1498,168 @@ impl Pencil {
     /// The actual application handler.
     pub fn handle_request(&self, request: &mut Request) -> Response {
         request.match_request();
-        match self.full_dispatch_request(request) {
+        self.signals.send_request_started(request);
+        let mut response = match self.full_dispatch_request(request) {
             Ok(response) => {
+                self.signals.send_request_finished(request, &response);
                 self.do_teardown_request(request, None);
-                return response;
+                response
             },
             Err(e) => {
+                self.signals.send_got_request_exception(request, &e);
                 let response = self.handle_error(request, &e);
+                self.signals.send_request_finished(request, &response);
                 self.do_teardown_request(request, Some(&e));
-                return response;
+                response
             }
         };
+        for func in &self.release_funcs {
+            func(request);
+        }
+        let elapsed = request.elapsed();
+        let millis = elapsed.as_secs() as f64 * 1e3 + elapsed.subsec_nanos() as f64 / 1e6;
+        if self.response_time_header {
+            response.headers.set_raw("X-Response-Time", vec![format!("{:.3}ms", millis).into_bytes()]);
+        }
+        // `SLOW_REQUEST_MS` configures the duration above which a request
+        // is logged as slow, modules can override it.
+        if let Some(threshold) = request.config_value("SLOW_REQUEST_MS").and_then(|threshold| threshold.as_f64()) {
+            if millis > threshold {
+                warn!("Slow request to {} \"{} {}\" took {:.3}ms, view args: {:?}",
+                      request.endpoint().unwrap_or_else(|| String::from("<unmatched>")),
+                      request.method(), request.path(), millis, request.view_args);
+            }
+        }
+        info!(target: logging::ACCESS_LOG_TARGET, "{} \"{} {}\" {}",
+              request.remote_addr.ip(), request.method(), request.path(), response.status_code);
+        response
     }
 
-    /// Runs the application on a hyper HTTP server.
+    /// Finishes the setup of the application.  The built application can't
+    /// be changed anymore, it is cheap to clone and can be shared by several
+    /// servers and threads.  This makes it easy to use an application
+    /// factory, for example to create an application with a different
+    /// configuration for every test:
+    ///
+    /// ```rust,no_run
+    /// use pencil::{Pencil, PencilApp, Config};
+    ///
+    /// fn create_app(config: Config) -> PencilApp {
+    ///     let mut app = Pencil::new("/web/demo");
+    ///     app.config = config;
+    ///     app.build()
+    /// }
+    /// ```
+    pub fn build(self) -> PencilApp {
+        PencilApp {
+            inner: Arc::new(self),
+        }
+    }
+
+    /// Runs the application on a hyper HTTP server.  The server options are
+    /// read from the configuration, see `ServerOptions::from_config`.
     pub fn run<A: ToSocketAddrs>(self, addr: A) {
-        run_server(self, addr);
+        self.build().run(addr);
+    }
+
+    /// Runs the application on a hyper HTTP server with the given options.
+    pub fn run_with_options<A: ToSocketAddrs>(self, addr: A, options: ServerOptions) {
+        self.build().run_with_options(addr, options);
+    }
+
+    /// Runs the application on a hyper HTTPS server.  If the TLS settings
+    /// verify client certificates, the subject of the certificate is
+    /// available to views as `ClientCertificate` in `extensions_data`.
+    #[cfg(feature = "ssl")]
+    pub fn run_tls<A: ToSocketAddrs>(self, addr: A, tls: TlsConfig) {
+        self.build().run_tls(addr, tls);
+    }
+
+    /// Registers a command that can be run with `execute_from_args`.
+    /// The function gets the configured application and the arguments
+    /// after the command name:
+    ///
+    /// ```rust,no_run
+    /// use pencil::Pencil;
+    ///
+    ///
+    /// fn seed_db(_: &Pencil, args: &[String]) -> Result<(), String> {
+    ///     println!("Seeding with {:?}", args);
+    ///     Ok(())
+    /// }
+    ///
+    ///
+    /// fn main() {
+    ///     let mut app = Pencil::new("/web/demo");
+    ///     app.add_command("seed-db", seed_db);
+    ///     app.execute_from_args();
+    /// }
+    /// ```
+    pub fn add_command(&mut self, name: &str, f: CommandFunc) {
+        self.commands.insert(name.to_string(), f);
+    }
+
+    /// Runs the command given by the arguments of the process.  Besides the
+    /// registered commands there are `serve [address]` to run the server,
+    /// defaults to `127.0.0.1:5000`, and `routes` to list the URL rules.
+    /// The process exits with status 1 if the command fails.
+    pub fn execute_from_args(self) {
+        let args: Vec<String> = env::args().skip(1).collect();
+        if let Err(err) = self.execute(&args) {
+            let _ = writeln!(io::stderr(), "Error: {}", err);
+            process::exit(1);
+        }
+    }
+
+    /// Runs the command given by the arguments, the first argument is the
+    /// command name.
+    pub fn execute(self, args: &[String]) -> Result<(), String> {
+        let name = match args.first() {
+            Some(name) => name.clone(),
+            None => {
+                print!("{}", cli::usage(&self));
+                return Ok(());
+            },
+        };
+        match &name as &str {
+            "help" | "--help" | "-h" => {
+                print!("{}", cli::usage(&self));
+                Ok(())
+            },
+            "serve" => {
+                let addr = args.get(1).cloned().unwrap_or_else(|| String::from("127.0.0.1:5000"));
+                self.run(&addr as &str);
+                Ok(())
+            },
+            "routes" => {
+                print!("{}", self.list_routes());
+                Ok(())
+            },
+            _ => {
+                match self.commands.get(&name) {
+                    Some(f) => f(&self, &args[1..]),
+                    None => Err(format!("Unknown command: {}", name)),
+                }
+            },
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn command_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.commands.keys().cloned().collect();
+        names.sort();
+        names
     }
 }
 
 impl hyper::server::Handler for Pencil {
     fn handle(&self, req: HTTPRequest, mut res: HTTPResponse) {
+        let client_cert = client_certificate(&req);
         match Request::new(self, req) {
             Ok(mut request) => {
+                if let Some(client_cert) = client_cert {
+                    request.extensions_data.insert::<ClientCertificate>(client_cert);
+                }
                 let response = self.handle_request(&mut request);
                 response.write(request.method(), res);
             }
@@ -605,6 +1673,138 @@ impl hyper::server::Handler for Pencil {
     }
 }
 
+/// Find the http error handler with the narrowest range that contains the
+/// status code, the earlier registered handler wins on equal ranges.
+fn find_range_handler(handlers: &[(Range<u16>, HTTPErrorHandler)], code: u16) -> Option<HTTPErrorHandler> {
+    let mut found: Option<&(Range<u16>, HTTPErrorHandler)> = None;
+    for handler in handlers {
+        if handler.0.start <= code && code < handler.0.end &&
+           found.map_or(true, |current| handler.0.len() < current.0.len()) {
+            found = Some(handler);
+        }
+    }
+    found.map(|&(_, handler)| handler)
+}
+
+/// The hooks and middlewares of one endpoint.
+#[derive(Default)]
+struct EndpointHooks {
+    before_request_funcs: Vec<BeforeRequestFunc>,
+    after_request_funcs: Vec<AfterRequestFunc>,
+    middlewares: Vec<MiddlewareFunc>,
+}
+
+
+/// Attaches hooks and middlewares to one endpoint, see `Pencil::route_with`.
+/// The hooks of the endpoint run before the hooks of its modules and the
+/// application.
+pub struct RouteHooks<'a> {
+    app: &'a mut Pencil,
+    endpoint: String,
+}
+
+impl<'a> RouteHooks<'a> {
+    fn hooks(&mut self) -> &mut EndpointHooks {
+        self.app.endpoint_hooks.entry(self.endpoint.clone()).or_insert_with(EndpointHooks::default)
+    }
+
+    /// Registers a function to run before requests of the endpoint.
+    pub fn before<F>(mut self, f: F) -> RouteHooks<'a>
+        where F: Fn(&mut Request) -> Option<PencilResult> + Send + Sync + 'static {
+        self.hooks().before_request_funcs.push(Arc::new(f));
+        self
+    }
+
+    /// Registers a function to run after requests of the endpoint.
+    pub fn after<F>(mut self, f: F) -> RouteHooks<'a> where F: Fn(&mut Response) + Send + Sync + 'static {
+        self.hooks().after_request_funcs.push(Arc::new(f));
+        self
+    }
+
+    /// Wraps the view of the endpoint with a middleware.  The middleware
+    /// gets the request and the next handler, it can return early or
+    /// change the result of the next handler.  The first middleware is the
+    /// outermost:
+    ///
+    /// ```rust,ignore
+    /// app.get_with("/report", "report", report).wrap(|request, next| {
+    ///     let started = Instant::now();
+    ///     let result = next(request);
+    ///     info!("report took {:?}", started.elapsed());
+    ///     result
+    /// });
+    /// ```
+    pub fn wrap<F>(mut self, f: F) -> RouteHooks<'a>
+        where F: Fn(&mut Request, &Fn(&mut Request) -> PencilResult) -> PencilResult + Send + Sync + 'static {
+        self.hooks().middlewares.push(Arc::new(f));
+        self
+    }
+
+    /// Wraps the view of the endpoint with a middleware that is already a
+    /// shared function, like the middlewares of the `auth` module.
+    pub fn wrap_with(mut self, middleware: MiddlewareFunc) -> RouteHooks<'a> {
+        self.hooks().middlewares.push(middleware);
+        self
+    }
+}
+
+
+/// Call the view through the middlewares, the first one is the outermost.
+fn call_with_middlewares(middlewares: &[MiddlewareFunc], view_func: &ViewFunc, request: &mut Request) -> PencilResult {
+    match middlewares.split_first() {
+        Some((middleware, rest)) => {
+            middleware(request, &|request: &mut Request| call_with_middlewares(rest, view_func, request))
+        },
+        None => view_func(request),
+    }
+}
+
+
+/// A built application, see `Pencil::build`.  It dereferences to the
+/// `Pencil` it was built from.
+#[derive(Clone)]
+pub struct PencilApp {
+    inner: Arc<Pencil>,
+}
+
+impl PencilApp {
+    /// Runs the application on a hyper HTTP server.
+    pub fn run<A: ToSocketAddrs>(&self, addr: A) {
+        self.run_with_options(addr, ServerOptions::from_config(&self.config));
+    }
+
+    /// Runs the application on a hyper HTTP server with the given options.
+    pub fn run_with_options<A: ToSocketAddrs>(&self, addr: A, options: ServerOptions) {
+        run_server(self.clone(), addr, &options);
+    }
+
+    /// Runs the application on a hyper HTTPS server, see `Pencil::run_tls`.
+    #[cfg(feature = "ssl")]
+    pub fn run_tls<A: ToSocketAddrs>(&self, addr: A, tls: TlsConfig) {
+        run_tls_server(self.clone(), addr, tls, &ServerOptions::from_config(&self.config));
+    }
+}
+
+impl Deref for PencilApp {
+    type Target = Pencil;
+
+    fn deref(&self) -> &Pencil {
+        &self.inner
+    }
+}
+
+impl hyper::server::Handler for PencilApp {
+    fn handle<'a, 'k>(&'a self, req: HTTPRequest<'a, 'k>, res: HTTPResponse<'a>) {
+        self.inner.handle(req, res);
+    }
+}
+
+impl fmt::Debug for PencilApp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.inner, f)
+    }
+}
+
 impl PathBound for Pencil {
     fn open_resource(&self, resource: &str) -> File {
         let mut pathbuf = PathBuf::from(&self.root_path);
@@ -613,6 +1813,12 @@ impl PathBound for Pencil {
     }
 }
 
+impl UrlFor for Pencil {
+    fn url_for(&self, endpoint: &str, values: &ViewArgs) -> Option<String> {
+        Pencil::url_for(self, endpoint, values)
+    }
+}
+
 impl fmt::Display for Pencil {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "<Pencil application {}>", self.name)
@@ -628,9 +1834,45 @@ impl fmt::Debug for Pencil {
 /// View function used internally to send static files from the static folder
 /// to the browser.
 fn send_app_static_file(request: &mut Request) -> PencilResult {
-    let mut static_path = PathBuf::from(&request.app.root_path);
-    static_path.push(&request.app.static_folder);
-    let static_path_str = static_path.to_str().unwrap();
-    let filename = request.view_args.get("filename").unwrap();
-    send_from_directory(static_path_str, filename, false)
+    // The static root has no filename.
+    let filename = request.view_args.get("filename").map_or("", |filename| &filename[..]);
+    let mut response = match request.app.static_provider {
+        Some(ref provider) => try!(provider.send(request, filename)),
+        None => {
+            let mut static_path = PathBuf::from(&request.app.root_path);
+            static_path.push(&request.app.static_folder);
+            let static_path_str = static_path.to_str().unwrap();
+            try!(FilesystemProvider::new(static_path_str).send(request, filename))
+        },
+    };
+    add_static_cache_headers(request, filename, &mut response);
+    Ok(response)
+}
+
+
+/// View function used internally to send the robots.txt rules.
+fn send_robots_txt(request: &mut Request) -> PencilResult {
+    match request.app.robots_txt {
+        Some(ref rules) => {
+            let mut response = Response::from(rules.clone());
+            response.set_content_type("text/plain");
+            response.headers.set(CacheControl(vec![CacheDirective::Public, CacheDirective::MaxAge(86400)]));
+            Ok(response)
+        },
+        None => Err(PenHTTPError(NotFound)),
+    }
+}
+
+/// View function used internally to send the favicon.
+fn send_favicon(request: &mut Request) -> PencilResult {
+    match request.app.favicon_path {
+        Some(ref favicon_path) => {
+            let mut path = PathBuf::from(&request.app.root_path);
+            path.push(favicon_path);
+            let mut response = try!(send_file_auto(&path, false));
+            response.headers.set(CacheControl(vec![CacheDirective::Public, CacheDirective::MaxAge(604800)]));
+            Ok(response)
+        },
+        None => Err(PenHTTPError(NotFound)),
+    }
 }
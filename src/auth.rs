@@ -0,0 +1,425 @@
+//! This module implements authentication helpers.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::header::CookiePair;
+use rand::{OsRng, Rng};
+use rustc_serialize::hex::ToHex;
+use time;
+use typemap::Key;
+
+use config::Config;
+use crypto::{sha256, constant_time_eq};
+use http_errors::Unauthorized;
+use signing::Signer;
+use types::{MiddlewareFunc, PencilResult};
+use wrappers::Request;
+
+
+/// One remember-me token as it is stored on the server.  Only the hash of
+/// the validator is stored, so a leaked store can't be used to log in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RememberToken {
+    pub user_id: String,
+    pub validator_hash: Vec<u8>,
+    /// The expiry as seconds since the epoch.
+    pub expires: i64,
+    /// The hash of the validator before the last rotation, it stays valid
+    /// for a short grace period.
+    pub previous_validator_hash: Option<Vec<u8>>,
+    /// When the validator was last rotated, as seconds since the epoch.
+    pub rotated: i64,
+}
+
+
+/// The server-side store for remember-me tokens.  Implement this for your
+/// database so tokens survive restarts and can be revoked, for example
+/// when the user changes the password.
+pub trait RememberStore: Send + Sync {
+    /// Get the token for a selector.
+    fn get(&self, selector: &str) -> Option<RememberToken>;
+    /// Store the token for a selector, replacing an existing one.
+    fn save(&self, selector: &str, token: RememberToken);
+    /// Remove the token for a selector.
+    fn remove(&self, selector: &str);
+    /// Remove all tokens of a user.
+    fn revoke_user(&self, user_id: &str);
+}
+
+
+/// A simple in memory store, tokens are lost on restart.
+pub struct MemoryRememberStore {
+    tokens: Mutex<HashMap<String, RememberToken>>,
+}
+
+impl MemoryRememberStore {
+    pub fn new() -> MemoryRememberStore {
+        MemoryRememberStore {
+            tokens: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Default for MemoryRememberStore {
+    fn default() -> MemoryRememberStore {
+        MemoryRememberStore::new()
+    }
+}
+
+impl RememberStore for MemoryRememberStore {
+    fn get(&self, selector: &str) -> Option<RememberToken> {
+        self.tokens.lock().ok().and_then(|tokens| tokens.get(selector).cloned())
+    }
+
+    fn save(&self, selector: &str, token: RememberToken) {
+        if let Ok(mut tokens) = self.tokens.lock() {
+            let now = time::get_time().sec;
+            tokens.retain(|_, token| token.expires > now);
+            tokens.insert(selector.to_string(), token);
+        }
+    }
+
+    fn remove(&self, selector: &str) {
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.remove(selector);
+        }
+    }
+
+    fn revoke_user(&self, user_id: &str) {
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.retain(|_, token| token.user_id != user_id);
+        }
+    }
+}
+
+
+/// The remember-me cookie that will be set on the response.
+struct PendingRememberCookie;
+
+impl Key for PendingRememberCookie {
+    type Value = CookiePair;
+}
+
+
+/// Long-lived remember-me logins, separate from the session.  The cookie
+/// holds a signed `<selector>:<validator>` pair, the store keeps the hash
+/// of the validator.  Every successful use rotates the validator, so a
+/// stolen cookie stops working once the real user returns.  If a known
+/// selector comes with a wrong validator the cookie was probably stolen
+/// and all tokens of that user are revoked.  Parallel requests of a
+/// browser, like a page and its XHR requests, all send the cookie from
+/// before the rotation, so the previous validator stays valid for
+/// `REMEMBER_ROTATION_GRACE` seconds (defaults to `30`).
+///
+/// The cookie is configured with `REMEMBER_COOKIE_NAME` (defaults to
+/// `"remember_token"`), `REMEMBER_COOKIE_DOMAIN` and
+/// `REMEMBER_COOKIE_SECURE` (defaults to `false`).
+///
+/// ```rust,ignore
+/// fn login(request: &mut Request) -> PencilResult {
+///     let user_id = try!(check_password(request));
+///     if request.form().get("remember").is_some() {
+///         request.app.remember_me().unwrap().remember(request, &user_id);
+///     }
+///     Ok(Response::from("Logged in"))
+/// }
+///
+/// fn current_user(request: &mut Request) -> Option<String> {
+///     request.app.remember_me().and_then(|remember_me| remember_me.authenticate(request))
+/// }
+/// ```
+pub struct RememberMe {
+    store: Box<RememberStore>,
+    signer: Signer,
+    lifetime: Duration,
+}
+
+impl RememberMe {
+    pub fn new<S: RememberStore + 'static>(store: S, signer: Signer, lifetime: Duration) -> RememberMe {
+        RememberMe {
+            store: Box::new(store),
+            signer: signer,
+            lifetime: lifetime,
+        }
+    }
+
+    /// Create a random hex string.
+    fn random_hex(len: usize) -> String {
+        let mut bytes = vec![0u8; len];
+        match OsRng::new() {
+            Ok(mut rng) => rng.fill_bytes(&mut bytes),
+            Err(e) => panic!("Could not access the OS random number generator: {}", e),
+        }
+        bytes.to_hex()
+    }
+
+    /// Create the cookie for the request's response.
+    fn cookie(config: &Config, value: String) -> CookiePair {
+        let name = config.get("REMEMBER_COOKIE_NAME").and_then(|name| name.as_string().map(|name| name.to_string()))
+                         .unwrap_or_else(|| String::from("remember_token"));
+        let mut cookie = CookiePair::new(name, value);
+        cookie.domain = config.get("REMEMBER_COOKIE_DOMAIN").and_then(|domain| domain.as_string().map(|domain| domain.to_string()));
+        cookie.path = Some(String::from("/"));
+        cookie.secure = config.get_boolean("REMEMBER_COOKIE_SECURE", false);
+        cookie.httponly = true;
+        cookie.custom.insert(String::from("SameSite"), String::from("Lax"));
+        cookie
+    }
+
+    /// Issue a new token for the user and set the cookie on the response,
+    /// `previous_validator_hash` is the hash of the rotated validator.
+    fn issue(&self, request: &mut Request, selector: &str, user_id: &str, previous_validator_hash: Option<Vec<u8>>) {
+        let validator = RememberMe::random_hex(32);
+        let now = time::get_time().sec;
+        let token = RememberToken {
+            user_id: user_id.to_string(),
+            validator_hash: sha256(validator.as_bytes()),
+            expires: now + self.lifetime.as_secs() as i64,
+            previous_validator_hash: previous_validator_hash,
+            rotated: now,
+        };
+        self.store.save(selector, token);
+        let mut cookie = RememberMe::cookie(&request.app.config, self.signer.sign(&format!("{}:{}", selector, validator)));
+        cookie.max_age = Some(self.lifetime.as_secs());
+        cookie.expires = Some(time::now_utc() + time::Duration::seconds(self.lifetime.as_secs() as i64));
+        request.extensions_data.insert::<PendingRememberCookie>(cookie);
+    }
+
+    /// Remember the user, the cookie is set when the response is processed.
+    pub fn remember(&self, request: &mut Request, user_id: &str) {
+        let selector = RememberMe::random_hex(12);
+        self.issue(request, &selector, user_id, None);
+    }
+
+    /// The selector and validator from the cookie of the request.
+    fn read_cookie(&self, request: &Request) -> Option<(String, String)> {
+        let name = request.app.config.get("REMEMBER_COOKIE_NAME").and_then(|name| name.as_string().map(|name| name.to_string()))
+                                     .unwrap_or_else(|| String::from("remember_token"));
+        let value = match request.get_cookie(&name) {
+            Some(value) => value,
+            None => return None,
+        };
+        let value = match self.signer.unsign(&value) {
+            Ok(value) => value.to_string(),
+            Err(_) => return None,
+        };
+        let mut parts = value.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(selector), Some(validator)) => Some((selector.to_string(), validator.to_string())),
+            _ => None,
+        }
+    }
+
+    /// Check the remember-me cookie of the request and return the user id.
+    /// On success the token is rotated, the new cookie is set when the
+    /// response is processed.
+    pub fn authenticate(&self, request: &mut Request) -> Option<String> {
+        let (selector, validator) = match self.read_cookie(request) {
+            Some(cookie) => cookie,
+            None => return None,
+        };
+        let token = match self.store.get(&selector) {
+            Some(token) => token,
+            None => return None,
+        };
+        let now = time::get_time().sec;
+        if token.expires <= now {
+            self.store.remove(&selector);
+            return None;
+        }
+        let validator_hash = sha256(validator.as_bytes());
+        if constant_time_eq(&token.validator_hash, &validator_hash) {
+            self.issue(request, &selector, &token.user_id, Some(validator_hash));
+            return Some(token.user_id);
+        }
+        // A parallel request with the cookie from before the rotation, the
+        // browser gets the new cookie from the first response.
+        let grace = request.app.config.get("REMEMBER_ROTATION_GRACE").and_then(|grace| grace.as_i64()).unwrap_or(30);
+        if let Some(ref previous_validator_hash) = token.previous_validator_hash {
+            if now - token.rotated < grace && constant_time_eq(previous_validator_hash, &validator_hash) {
+                return Some(token.user_id.clone());
+            }
+        }
+        warn!("Invalid remember-me validator, revoking all tokens of the user");
+        self.store.revoke_user(&token.user_id);
+        None
+    }
+
+    /// Forget the token of the request and delete the cookie, like on logout.
+    pub fn forget(&self, request: &mut Request) {
+        if let Some((selector, _)) = self.read_cookie(request) {
+            self.store.remove(&selector);
+        }
+        let mut cookie = RememberMe::cookie(&request.app.config, String::new());
+        cookie.max_age = Some(0);
+        cookie.expires = Some(time::at_utc(time::Timespec::new(0, 0)));
+        request.extensions_data.insert::<PendingRememberCookie>(cookie);
+    }
+
+    /// Revoke all tokens of a user, like after a password change.
+    pub fn revoke_user(&self, user_id: &str) {
+        self.store.revoke_user(user_id);
+    }
+
+    #[doc(hidden)]
+    pub fn pending_cookie<'c>(request: &'c Request) -> Option<&'c CookiePair> {
+        request.extensions_data.get::<PendingRememberCookie>()
+    }
+}
+
+
+/// A middleware for HTTP Basic authentication.  Requests without valid
+/// credentials get a `401 Unauthorized` response with a
+/// `WWW-Authenticate` challenge for the realm, the check function gets
+/// the username and the password:
+///
+/// ```rust,ignore
+/// app.get_with("/admin", "admin", admin)
+///    .wrap_with(basic_auth("Admin area", |username, password| {
+///        username == "admin" && password == "secret"
+///    }));
+/// ```
+pub fn basic_auth<F>(realm: &str, check: F) -> MiddlewareFunc
+    where F: Fn(&str, &str) -> bool + Send + Sync + 'static {
+    let challenge = format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm.replace('"', "\\\""));
+    Arc::new(move |request: &mut Request, next: &Fn(&mut Request) -> PencilResult| {
+        let authorized = match request.basic_auth() {
+            Some((username, password)) => check(&username, &password),
+            None => false,
+        };
+        if authorized {
+            return next(request);
+        }
+        let mut response = Unauthorized.to_response();
+        response.headers.set_raw("WWW-Authenticate", vec![challenge.clone().into_bytes()]);
+        Ok(response)
+    })
+}
+
+
+/// A middleware for bearer token authentication, for example with JWTs.
+/// The validator gets the token and returns the authenticated principal,
+/// it is stored in `extensions_data` under the key `K` for the view.
+/// Requests without a valid token get a `401 Unauthorized` response with a
+/// `WWW-Authenticate: Bearer` challenge:
+///
+/// ```rust,ignore
+/// struct CurrentUser;
+///
+/// impl Key for CurrentUser {
+///     type Value = User;
+/// }
+///
+/// fn profile(request: &mut Request) -> PencilResult {
+///     let user = request.extensions_data.get::<CurrentUser>().unwrap();
+///     Ok(Response::from(user.name.clone()))
+/// }
+///
+/// app.get_with("/profile", "profile", profile)
+///    .wrap_with(token_auth::<CurrentUser, _>(|token| verify_jwt(token)));
+/// ```
+pub fn token_auth<K, F>(validator: F) -> MiddlewareFunc
+    where K: Key, K::Value: Any, F: Fn(&str) -> Option<K::Value> + Send + Sync + 'static {
+    Arc::new(move |request: &mut Request, next: &Fn(&mut Request) -> PencilResult| {
+        let challenge = match request.bearer_token() {
+            Some(token) => {
+                match validator(&token) {
+                    Some(principal) => {
+                        request.extensions_data.insert::<K>(principal);
+                        return next(request);
+                    },
+                    None => "Bearer error=\"invalid_token\"",
+                }
+            },
+            None => "Bearer",
+        };
+        let mut response = Unauthorized.to_response();
+        response.headers.set_raw("WWW-Authenticate", vec![challenge.as_bytes().to_vec()]);
+        Ok(response)
+    })
+}
+
+
+#[test]
+fn test_remember_me_rotation_and_theft() {
+    use app::Pencil;
+    use hyper::header::{Cookie, Headers, SetCookie};
+    use hyper::method::Method;
+    use rustc_serialize::json::ToJson;
+    use testing::{PencilClient, TestResponse};
+    use types::PencilResult;
+    use wrappers::Response;
+    fn login(request: &mut Request) -> PencilResult {
+        request.app.remember_me().unwrap().remember(request, "42");
+        Ok(Response::from("ok"))
+    }
+    fn whoami(request: &mut Request) -> PencilResult {
+        let user_id = request.app.remember_me().unwrap().authenticate(request);
+        Ok(Response::from(user_id.unwrap_or_default()))
+    }
+    let mut app = Pencil::new("/test");
+    app.config.set("SECRET_KEY", "secret".to_json());
+    app.config.set("REMEMBER_ROTATION_GRACE", 0.to_json());
+    app.enable_remember_me(MemoryRememberStore::new(), Duration::from_secs(3600));
+    app.get("/login", "login", login);
+    app.get("/whoami", "whoami", whoami);
+    let client = PencilClient::new(&app);
+    let whoami = |cookie: &CookiePair| {
+        let mut headers = Headers::new();
+        headers.set(Cookie(vec![CookiePair::new(cookie.name.clone(), cookie.value.clone())]));
+        let mut response = client.request_with_headers(Method::Get, "/whoami", &headers).unwrap();
+        let body = response.body_bytes();
+        let cookie = response.headers.get::<SetCookie>().map(|cookies| cookies.0[0].clone());
+        (String::from_utf8(body).unwrap(), cookie)
+    };
+    let response = client.request(Method::Get, "/login").unwrap();
+    let first = response.headers.get::<SetCookie>().unwrap().0[0].clone();
+    assert!(first.name == "remember_token");
+    let (user_id, rotated) = whoami(&first);
+    assert!(user_id == "42");
+    let rotated = rotated.unwrap();
+    assert!(rotated.value != first.value);
+    // Reusing the old cookie looks like theft and revokes the new one too.
+    assert!(whoami(&first).0 == "");
+    assert!(whoami(&rotated).0 == "");
+}
+
+
+#[test]
+fn test_remember_me_parallel_requests() {
+    use app::Pencil;
+    use hyper::header::SetCookie;
+    use rustc_serialize::json::ToJson;
+    use testing::TestResponse;
+    use types::PencilResult;
+    use wrappers::Response;
+    fn login(request: &mut Request) -> PencilResult {
+        request.app.remember_me().unwrap().remember(request, "42");
+        Ok(Response::from("ok"))
+    }
+    fn whoami(request: &mut Request) -> PencilResult {
+        let user_id = request.app.remember_me().unwrap().authenticate(request);
+        Ok(Response::from(user_id.unwrap_or_default()))
+    }
+    let mut app = Pencil::new("/test");
+    app.config.set("SECRET_KEY", "secret".to_json());
+    app.enable_remember_me(MemoryRememberStore::new(), Duration::from_secs(3600));
+    app.get("/login", "login", login);
+    app.get("/whoami", "whoami", whoami);
+    let client = app.test_client();
+    let response = client.get_path("/login").send().unwrap();
+    let first = response.headers.get::<SetCookie>().unwrap().0[0].clone();
+    let cookie = format!("{}={}", first.name, first.value);
+    // A page and its XHR request both send the cookie from before the rotation.
+    let mut page = client.get_path("/whoami").header("Cookie", &cookie).send().unwrap();
+    let rotated = page.headers.get::<SetCookie>().unwrap().0[0].clone();
+    let mut xhr = client.get_path("/whoami").header("Cookie", &cookie).send().unwrap();
+    assert!(xhr.headers.get::<SetCookie>().is_none());
+    assert!(page.body_text() == "42");
+    assert!(xhr.body_text() == "42");
+    let rotated = format!("{}={}", rotated.name, rotated.value);
+    assert!(client.get_path("/whoami").header("Cookie", &rotated).send().unwrap().body_text() == "42");
+}
@@ -0,0 +1,114 @@
+//! This module implements a per-thread pool of response body buffers, so
+//! that small responses don't allocate a new buffer for every request, and
+//! the default response headers.
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::mem;
+use std::ops::{Deref, DerefMut};
+
+use hyper::header::{Headers, ContentType};
+use hyper::mime::{Mime, TopLevel, SubLevel, Attr, Value};
+
+use wrappers::{BodyWrite, ResponseBody};
+
+
+/// How many buffers are kept per thread.
+const MAX_POOLED_BUFFERS: usize = 64;
+/// Bigger buffers are dropped instead of kept in the pool.
+const MAX_POOLED_CAPACITY: usize = 64 * 1024;
+
+
+thread_local!(static POOL: RefCell<Vec<Vec<u8>>> = RefCell::new(Vec::new()));
+thread_local!(static DEFAULT_HEADERS: Headers = {
+    let mut headers = Headers::new();
+    headers.set(ContentType(Mime(TopLevel::Text, SubLevel::Html, vec![(Attr::Charset, Value::Utf8)])));
+    headers
+});
+
+
+/// The headers of a new response, `Content-Type: text/html; charset=UTF-8`.
+/// They are built once per thread and cloned, which is cheaper than parsing
+/// the content type for every response.
+pub fn default_headers() -> Headers {
+    DEFAULT_HEADERS.with(|headers| headers.clone())
+}
+
+
+/// A body buffer that goes back to the pool of the thread when it is
+/// dropped, usually after the response is written.
+pub struct PooledBuffer(Vec<u8>);
+
+impl PooledBuffer {
+    /// Take an empty buffer from the pool.
+    pub fn new() -> PooledBuffer {
+        let buffer = POOL.with(|pool| pool.borrow_mut().pop());
+        PooledBuffer(buffer.unwrap_or_else(Vec::new))
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.0
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.0
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let mut buffer = mem::replace(&mut self.0, Vec::new());
+        if buffer.capacity() == 0 || buffer.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+        buffer.clear();
+        POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.len() < MAX_POOLED_BUFFERS {
+                pool.push(buffer);
+            }
+        });
+    }
+}
+
+impl BodyWrite for PooledBuffer {
+    fn write_body(&mut self, body: &mut ResponseBody) -> io::Result<()> {
+        body.write_all(&self.0)
+    }
+}
+
+
+#[test]
+fn test_buffer_reuse() {
+    let mut buffer = PooledBuffer::new();
+    buffer.extend_from_slice(b"hello");
+    let ptr = buffer.as_ptr();
+    drop(buffer);
+    let buffer = PooledBuffer::new();
+    assert!(buffer.is_empty());
+    assert!(buffer.as_ptr() == ptr);
+}
+
+
+#[test]
+fn test_caller_buffers_are_not_pooled() {
+    use wrappers::Response;
+    let bytes = b"hello".to_vec();
+    let ptr = bytes.as_ptr();
+    drop(Response::from(bytes));
+    assert!(PooledBuffer::new().as_ptr() != ptr);
+}
+
+
+#[test]
+fn test_default_headers() {
+    let headers = default_headers();
+    assert!(headers.len() == 1);
+    assert!(headers.get_raw("Content-Type") == Some(&[b"text/html; charset=utf-8".to_vec()][..]));
+}
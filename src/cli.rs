@@ -0,0 +1,51 @@
+//! This module implements the output of the builtin commands.
+
+use app::Pencil;
+
+
+/// The usage text listing all commands.
+pub fn usage(app: &Pencil) -> String {
+    let mut usage = String::from("Usage: <command> [args...]\n\nCommands:\n");
+    usage.push_str("  serve [address]  Run the development server\n");
+    usage.push_str("  routes           Show the URL rules\n");
+    for name in app.command_names() {
+        usage.push_str(&format!("  {}\n", name));
+    }
+    usage
+}
+
+
+/// A table of the URL rules with their endpoints and methods.
+pub fn routes(app: &Pencil) -> String {
+    let mut rows = vec![(String::from("Endpoint"), String::from("Methods"), String::from("Rule"))];
+    for rule in app.url_map.iter_rules() {
+        rows.push((rule.endpoint.clone(), rule.method_names().join(", "), rule.rule_str()));
+    }
+    let endpoint_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0);
+    let methods_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+    let mut table = String::new();
+    for (endpoint, methods, rule) in rows {
+        table.push_str(&format!("{:ew$}  {:mw$}  {}\n", endpoint, methods, rule,
+                                ew = endpoint_width, mw = methods_width));
+    }
+    table
+}
+
+
+#[test]
+fn test_routes_table() {
+    use types::PencilResult;
+    use wrappers::{Request, Response};
+    fn index(_: &mut Request) -> PencilResult {
+        Ok(Response::from("index"))
+    }
+    let mut app = Pencil::new("/test");
+    app.get("/", "index", index);
+    app.post("/users/<id:int>", "update_user", index);
+    let table = routes(&app);
+    assert!(app.list_routes() == table);
+    let lines: Vec<&str> = table.lines().collect();
+    assert!(lines[0] == "Endpoint     Methods             Rule");
+    assert!(lines[1] == "index        GET, HEAD, OPTIONS  /");
+    assert!(lines[2] == "update_user  OPTIONS, POST       /users/<id:int>");
+}
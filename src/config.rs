@@ -6,8 +6,13 @@ use std::io::Read;
 use std::fs::File;
 use std::path::Path;
 use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
 use rustc_serialize::json::{Object, Json};
 
+use http_errors::InternalServerError;
+use types::{ConfigReloadFunc, PencilResult, PenHTTPError};
+use wrappers::{Request, Response};
+
 
 /// The pencil `Config` type, We provide ways to fill it from JSON files:
 ///
@@ -33,6 +38,9 @@ use rustc_serialize::json::{Object, Json};
 #[derive(Clone)]
 pub struct Config {
     config: Object,
+    /// The values of a reloadable config file, they are shared with the
+    /// `ConfigReloader` and take precedence over `config`.
+    overlay: Option<Arc<RwLock<Arc<Object>>>>,
 }
 
 impl Default for Config {
@@ -47,6 +55,7 @@ impl Config {
         let json_object: Object = BTreeMap::new();
         Config {
             config: json_object,
+            overlay: None,
         }
     }
 
@@ -55,9 +64,20 @@ impl Config {
         self.config.insert(key.to_string(), value);
     }
 
-    /// Returns a reference to the value corresponding to the key.
-    pub fn get(&self, key: &str) -> Option<&Json> {
-        self.config.get(&key.to_string())
+    /// Returns a copy of the value corresponding to the key.  If config
+    /// reloading is enabled the values of the file take precedence and
+    /// change with every reload.
+    pub fn get(&self, key: &str) -> Option<Json> {
+        if let Some(ref overlay) = self.overlay {
+            let overlay = match overlay.read() {
+                Ok(overlay) => overlay.clone(),
+                Err(poisoned) => poisoned.into_inner().clone(),
+            };
+            if let Some(value) = overlay.get(key) {
+                return Some(value.clone());
+            }
+        }
+        self.config.get(key).cloned()
     }
 
     /// Get a boolean configuration value.  If the key doesn't exist
@@ -66,7 +86,7 @@ impl Config {
     pub fn get_boolean(&self, key: &str, default: bool) -> bool {
         match self.get(key) {
             Some(value) => {
-                match *value {
+                match value {
                     Json::Boolean(value) => value,
                     _ => default
                 }   
@@ -97,6 +117,65 @@ impl Config {
         }
     }
 
+    /// The environment the application runs in, like `development`,
+    /// `production` or `testing`.  This is the `ENV` configuration key,
+    /// defaults to `production`.
+    pub fn env(&self) -> String {
+        match self.get("ENV") {
+            Some(Json::String(env)) => env,
+            _ => String::from("production"),
+        }
+    }
+
+    /// Loads the configuration profile of the environment from a directory.
+    /// The environment is read from the `PENCIL_ENV` environment variable,
+    /// falling back to the `ENV` configuration key.  First `default.json` is
+    /// loaded and then `<env>.json` like `production.json` overrides it,
+    /// missing files are skipped:
+    ///
+    /// ```rust,no_run
+    /// let mut app = pencil::Pencil::new("/demo");
+    /// app.config.from_env_profile("/demo/config");
+    /// ```
+    ///
+    /// `DEBUG` defaults to `true` in `development` and `TESTING` defaults
+    /// to `true` in `testing`, values that are already set and the ones in
+    /// the profiles take precedence.
+    pub fn from_env_profile(&mut self, dirpath: &str) {
+        let env = match env::var("PENCIL_ENV") {
+            Ok(ref value) if !value.is_empty() => value.clone(),
+            _ => self.env(),
+        };
+        self.set("ENV", Json::String(env.clone()));
+        let dir = Path::new(dirpath);
+        for filename in &[String::from("default.json"), format!("{}.json", env)] {
+            let path = dir.join(filename);
+            if path.is_file() {
+                if let Some(path) = path.to_str() {
+                    self.from_jsonfile(path);
+                }
+            }
+        }
+    }
+
+    /// Loads the `SECRET_KEY` from an environment variable.
+    pub fn secret_key_from_envvar(&mut self, variable_name: &str) {
+        match env::var(variable_name) {
+            Ok(value) => self.set("SECRET_KEY", Json::String(value)),
+            Err(_) => panic!("The environment variable {} is not set.", variable_name),
+        }
+    }
+
+    /// Loads the `SECRET_KEY` from a file, like a mounted secret.
+    /// Surrounding whitespace is stripped.
+    pub fn secret_key_from_file(&mut self, filepath: &str) {
+        let mut content = String::new();
+        match File::open(filepath).and_then(|mut file| file.read_to_string(&mut content)) {
+            Ok(_) => self.set("SECRET_KEY", Json::String(content.trim().to_string())),
+            Err(e) => panic!("Could not read the secret key file {}: {}", filepath, e),
+        }
+    }
+
     /// Updates the values from the given `Object`.
     pub fn from_object(&mut self, object: Object) {
         for (key, value) in &object {
@@ -110,3 +189,161 @@ impl fmt::Debug for Config {
         write!(f, "<Pencil Config {:?}>", self.config)
     }
 }
+
+
+/// Read a JSON object from a file without panicking.
+fn read_jsonfile(filepath: &str) -> Result<Object, String> {
+    let mut content = String::new();
+    try!(File::open(filepath).and_then(|mut file| file.read_to_string(&mut content))
+                             .map_err(|e| format!("Could not read {}: {}", filepath, e)));
+    match Json::from_str(&content) {
+        Ok(Json::Object(object)) => Ok(object),
+        Ok(_) => Err(format!("The configuration file {} is not an JSON object.", filepath)),
+        Err(e) => Err(format!("Could not parse {}: {}", filepath, e)),
+    }
+}
+
+
+/// Reloads configuration values from a JSON file while the application
+/// runs, like log levels and feature flags.  Every reload starts from the
+/// config the application was started with and applies the file on top,
+/// the result replaces the current snapshot and is passed to the
+/// listeners.  A file that can't be read or parsed keeps the current
+/// snapshot.
+///
+/// The values of the file are shared with the configs returned by
+/// `watch`, so `Config::get` sees them as soon as a reload is done.
+pub struct ConfigReloader {
+    base: Config,
+    filepath: String,
+    current: RwLock<Arc<Config>>,
+    overlay: Arc<RwLock<Arc<Object>>>,
+    listeners: RwLock<Vec<ConfigReloadFunc>>,
+}
+
+impl ConfigReloader {
+    pub fn new(base: Config, filepath: &str) -> ConfigReloader {
+        ConfigReloader {
+            current: RwLock::new(Arc::new(base.clone())),
+            base: base,
+            filepath: filepath.to_string(),
+            overlay: Arc::new(RwLock::new(Arc::new(BTreeMap::new()))),
+            listeners: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Make the config see the values of every reload.
+    pub fn watch(&self, config: &mut Config) {
+        config.overlay = Some(self.overlay.clone());
+    }
+
+    /// Register a function that is called with the new config after
+    /// every successful reload.
+    pub fn add_listener(&self, f: ConfigReloadFunc) {
+        if let Ok(mut listeners) = self.listeners.write() {
+            listeners.push(f);
+        }
+    }
+
+    /// The current snapshot of the config.
+    pub fn config(&self) -> Arc<Config> {
+        match self.current.read() {
+            Ok(current) => current.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Read the file again and swap the snapshot.
+    pub fn reload(&self) -> Result<(), String> {
+        let object = try!(read_jsonfile(&self.filepath));
+        let mut config = self.base.clone();
+        config.from_object(object.clone());
+        let config = Arc::new(config);
+        let object = Arc::new(object);
+        match self.overlay.write() {
+            Ok(mut overlay) => *overlay = object,
+            Err(poisoned) => *poisoned.into_inner() = object,
+        }
+        match self.current.write() {
+            Ok(mut current) => *current = config.clone(),
+            Err(poisoned) => *poisoned.into_inner() = config.clone(),
+        }
+        if let Ok(listeners) = self.listeners.read() {
+            for listener in listeners.iter() {
+                listener(&config);
+            }
+        }
+        info!("Configuration reloaded from {}", self.filepath);
+        Ok(())
+    }
+}
+
+
+/// A view function that reloads the configuration, register it for an
+/// admin endpoint that is protected by your authentication:
+///
+/// ```rust,ignore
+/// app.enable_config_reload("/etc/myapp/config.json");
+/// app.post("/admin/reload-config", "reload_config", pencil::config::reload_config);
+/// ```
+pub fn reload_config(request: &mut Request) -> PencilResult {
+    match request.app.reload_config() {
+        Ok(()) => Ok(Response::from("Configuration reloaded")),
+        Err(err) => {
+            error!("Configuration reload failed: {}", err);
+            Err(PenHTTPError(InternalServerError))
+        },
+    }
+}
+
+
+/// Reload the configuration of the application when the process gets
+/// `SIGHUP`.  The signal handler only sets a flag, a background thread
+/// checks the flag every second and reloads all registered reloaders.
+/// The thread is started once per process.
+#[cfg(unix)]
+#[allow(deprecated)]
+pub fn reload_on_sighup(reloader: Arc<ConfigReloader>) {
+    use std::sync::{Mutex, Once, ONCE_INIT};
+    use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+    use std::thread;
+    use std::time::Duration;
+    use libc;
+
+    static SIGHUP_RECEIVED: AtomicBool = ATOMIC_BOOL_INIT;
+    static START: Once = ONCE_INIT;
+
+    lazy_static! {
+        static ref RELOADERS: Mutex<Vec<Arc<ConfigReloader>>> = Mutex::new(Vec::new());
+    }
+
+    extern "C" fn on_sighup(_: libc::c_int) {
+        SIGHUP_RECEIVED.store(true, Ordering::SeqCst);
+    }
+
+    match RELOADERS.lock() {
+        Ok(mut reloaders) => reloaders.push(reloader),
+        Err(poisoned) => poisoned.into_inner().push(reloader),
+    }
+    START.call_once(|| {
+        unsafe {
+            libc::signal(libc::SIGHUP, on_sighup as libc::sighandler_t);
+        }
+        thread::spawn(|| {
+            loop {
+                thread::sleep(Duration::from_secs(1));
+                if SIGHUP_RECEIVED.swap(false, Ordering::SeqCst) {
+                    let reloaders = match RELOADERS.lock() {
+                        Ok(reloaders) => reloaders.clone(),
+                        Err(poisoned) => poisoned.into_inner().clone(),
+                    };
+                    for reloader in reloaders {
+                        if let Err(err) = reloader.reload() {
+                            error!("Configuration reload failed: {}", err);
+                        }
+                    }
+                }
+            }
+        });
+    });
+}
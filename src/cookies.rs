@@ -0,0 +1,120 @@
+//! This module implements the cookie builder.
+
+use std::fmt;
+
+use hyper::header::CookiePair;
+use time;
+
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = match *self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        };
+        f.write_str(value)
+    }
+}
+
+
+/// A cookie that is set on a response with `Response::set_cookie`:
+///
+/// ```rust
+/// use pencil::Response;
+/// use pencil::cookies::{Cookie, SameSite};
+///
+/// let mut response = Response::from("Hello");
+/// response.set_cookie(Cookie::new("theme", "dark").max_age(3600).http_only(true).same_site(SameSite::Lax));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Cookie {
+    pair: CookiePair,
+}
+
+impl Cookie {
+    /// Create a cookie, the path defaults to `/`.
+    pub fn new(name: &str, value: &str) -> Cookie {
+        let mut pair = CookiePair::new(name.to_string(), value.to_string());
+        pair.path = Some(String::from("/"));
+        Cookie {
+            pair: pair,
+        }
+    }
+
+    /// The name of the cookie.
+    pub fn name(&self) -> &str {
+        &self.pair.name
+    }
+
+    /// The value of the cookie.
+    pub fn value(&self) -> &str {
+        &self.pair.value
+    }
+
+    /// Set the lifetime in seconds.
+    pub fn max_age(mut self, seconds: u64) -> Cookie {
+        self.pair.max_age = Some(seconds);
+        self
+    }
+
+    /// Set the time the cookie expires.
+    pub fn expires(mut self, expires: time::Tm) -> Cookie {
+        self.pair.expires = Some(expires);
+        self
+    }
+
+    /// Set the path the cookie is sent for.
+    pub fn path(mut self, path: &str) -> Cookie {
+        self.pair.path = Some(path.to_string());
+        self
+    }
+
+    /// Set the domain the cookie is sent to.
+    pub fn domain(mut self, domain: &str) -> Cookie {
+        self.pair.domain = Some(domain.to_string());
+        self
+    }
+
+    /// Only send the cookie over https.
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.pair.secure = secure;
+        self
+    }
+
+    /// Hide the cookie from JavaScript.
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.pair.httponly = http_only;
+        self
+    }
+
+    /// Set the `SameSite` attribute.
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.pair.custom.insert(String::from("SameSite"), same_site.to_string());
+        self
+    }
+
+    /// A cookie that tells the browser to delete the cookie with the name.
+    pub fn removal(name: &str) -> Cookie {
+        Cookie::new(name, "").max_age(0).expires(time::at_utc(time::Timespec::new(0, 0)))
+    }
+
+    /// The hyper cookie.
+    pub fn into_pair(self) -> CookiePair {
+        self.pair
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.pair.fmt(f)
+    }
+}
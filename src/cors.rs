@@ -0,0 +1,177 @@
+//! This module implements Cross-Origin Resource Sharing.  Enable it for the
+//! application or a module:
+//!
+//! ```rust,no_run
+//! use pencil::Pencil;
+//! use pencil::cors::Cors;
+//! use pencil::method::{Get, Post};
+//!
+//! let mut app = Pencil::new("/web/demo");
+//! app.enable_cors(Cors::new()
+//!     .allow_origin("https://example.com")
+//!     .allow_methods(&[Get, Post])
+//!     .supports_credentials(true)
+//!     .max_age(3600));
+//! ```
+//!
+//! Preflight `OPTIONS` requests are answered automatically for routes that
+//! don't handle `OPTIONS` themselves, the other responses get the
+//! `Access-Control-Allow-Origin` header when the origin is allowed.
+
+use hyper::method::Method;
+
+use utils::join_string;
+use wrappers::{Request, Response};
+
+
+/// The CORS configuration of an application or module.
+#[derive(Clone, Debug, Default)]
+pub struct Cors {
+    allowed_origins: Vec<String>,
+    allowed_methods: Vec<Method>,
+    allowed_headers: Vec<String>,
+    expose_headers: Vec<String>,
+    supports_credentials: bool,
+    max_age: Option<u64>,
+}
+
+impl Cors {
+    /// Create a CORS configuration that allows every origin, the methods
+    /// of the matched route and the headers the client asks for.
+    pub fn new() -> Cors {
+        Cors::default()
+    }
+
+    /// Allow an origin like `https://example.com`, once an origin is added
+    /// only the added origins are allowed.
+    pub fn allow_origin(mut self, origin: &str) -> Cors {
+        self.allowed_origins.push(origin.trim_right_matches('/').to_string());
+        self
+    }
+
+    /// Allow these methods instead of the methods of the matched route.
+    pub fn allow_methods(mut self, methods: &[Method]) -> Cors {
+        self.allowed_methods = methods.to_vec();
+        self
+    }
+
+    /// Allow these request headers instead of the headers the client asks
+    /// for in a preflight request.
+    pub fn allow_headers(mut self, headers: &[&str]) -> Cors {
+        self.allowed_headers = headers.iter().map(|header| header.to_string()).collect();
+        self
+    }
+
+    /// Response headers that the client is allowed to read.
+    pub fn expose_headers(mut self, headers: &[&str]) -> Cors {
+        self.expose_headers = headers.iter().map(|header| header.to_string()).collect();
+        self
+    }
+
+    /// Allow requests with credentials like cookies.  Credentials are only
+    /// allowed for the origins added with `allow_origin`, without them no
+    /// origin is allowed, since any website could read the responses of
+    /// its visitors otherwise.
+    pub fn supports_credentials(mut self, flag: bool) -> Cors {
+        self.supports_credentials = flag;
+        self
+    }
+
+    /// How long in seconds the client may cache the preflight response.
+    pub fn max_age(mut self, seconds: u64) -> Cors {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    /// Check if an origin is allowed.
+    pub fn origin_allowed(&self, origin: &str) -> bool {
+        if self.allowed_origins.is_empty() {
+            !self.supports_credentials
+        } else {
+            self.allowed_origins.iter().any(|allowed| allowed == origin)
+        }
+    }
+
+    /// The value of `Access-Control-Allow-Origin` for an allowed origin.
+    fn allow_origin_value(&self, origin: &str) -> String {
+        if self.allowed_origins.is_empty() {
+            String::from("*")
+        } else {
+            origin.to_string()
+        }
+    }
+
+    /// Adds the headers of a preflight response.  `route_methods` are the
+    /// methods of the matched route.
+    #[doc(hidden)]
+    pub fn apply_preflight(&self, request: &Request, route_methods: Vec<Method>, response: &mut Response) {
+        if request_origin(request).map_or(true, |origin| !self.origin_allowed(&origin)) {
+            return;
+        }
+        let requested_method = match request_header(request, "Access-Control-Request-Method") {
+            Some(method) => method,
+            None => return,
+        };
+        let methods = if self.allowed_methods.is_empty() { route_methods } else { self.allowed_methods.clone() };
+        if !methods.iter().any(|method| method.as_ref() == requested_method.trim()) {
+            return;
+        }
+        let methods: Vec<String> = methods.iter().map(|method| method.to_string()).collect();
+        response.headers.set_raw("Access-Control-Allow-Methods", vec![join_string(methods, ", ").into_bytes()]);
+        let headers = if self.allowed_headers.is_empty() {
+            request_header(request, "Access-Control-Request-Headers")
+        } else {
+            Some(join_string(self.allowed_headers.clone(), ", "))
+        };
+        if let Some(headers) = headers {
+            response.headers.set_raw("Access-Control-Allow-Headers", vec![headers.into_bytes()]);
+        }
+        if let Some(max_age) = self.max_age {
+            response.headers.set_raw("Access-Control-Max-Age", vec![max_age.to_string().into_bytes()]);
+        }
+    }
+
+    /// Adds the headers of an actual response.
+    #[doc(hidden)]
+    pub fn apply(&self, request: &Request, response: &mut Response) {
+        let origin = match request_origin(request) {
+            Some(origin) => origin,
+            None => return,
+        };
+        if !self.allowed_origins.is_empty() || self.supports_credentials {
+            add_vary_origin(response);
+        }
+        if !self.origin_allowed(&origin) {
+            return;
+        }
+        response.headers.set_raw("Access-Control-Allow-Origin", vec![self.allow_origin_value(&origin).into_bytes()]);
+        if self.supports_credentials {
+            response.headers.set_raw("Access-Control-Allow-Credentials", vec![b"true".to_vec()]);
+        }
+        if !self.expose_headers.is_empty() {
+            let headers = join_string(self.expose_headers.clone(), ", ");
+            response.headers.set_raw("Access-Control-Expose-Headers", vec![headers.into_bytes()]);
+        }
+    }
+}
+
+
+fn request_header(request: &Request, name: &str) -> Option<String> {
+    request.headers().get_raw(name).and_then(|values| values.first())
+        .map(|value| String::from_utf8_lossy(value).into_owned())
+}
+
+
+fn request_origin(request: &Request) -> Option<String> {
+    request_header(request, "Origin")
+}
+
+
+/// The response depends on the `Origin` header, caches must know that.
+fn add_vary_origin(response: &mut Response) {
+    let vary = match response.headers.get_raw("Vary").and_then(|values| values.first()) {
+        Some(value) => format!("{}, Origin", String::from_utf8_lossy(value)),
+        None => String::from("Origin"),
+    };
+    response.headers.set_raw("Vary", vec![vary.into_bytes()]);
+}
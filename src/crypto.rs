@@ -0,0 +1,71 @@
+//! This module implements the hashing primitives used for signing, they
+//! are thin wrappers around OpenSSL.
+
+use openssl::crypto::hash::{self, Type};
+use openssl::crypto::hmac as openssl_hmac;
+use openssl::crypto::memcmp;
+
+
+/// The supported digest algorithms.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Digest {
+    Sha1,
+    Sha256,
+}
+
+impl Digest {
+    fn hash_type(&self) -> Type {
+        match *self {
+            Digest::Sha1 => Type::SHA1,
+            Digest::Sha256 => Type::SHA256,
+        }
+    }
+
+    /// Hash the data with this digest.
+    pub fn hash(&self, data: &[u8]) -> Vec<u8> {
+        hash::hash(self.hash_type(), data)
+    }
+}
+
+
+/// Compute the SHA-256 digest of the data.
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    Digest::Sha256.hash(data)
+}
+
+
+/// Compute the HMAC of the message with the given key and digest.
+pub fn hmac(digest: Digest, key: &[u8], message: &[u8]) -> Vec<u8> {
+    openssl_hmac::hmac(digest.hash_type(), key, message)
+}
+
+
+/// Compare two byte strings in constant time so that comparing signatures
+/// doesn't leak how many leading bytes matched.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && memcmp::eq(a, b)
+}
+
+
+#[test]
+fn test_sha_digests() {
+    use rustc_serialize::hex::ToHex;
+    assert!(Digest::Sha1.hash(b"abc").to_hex() == "a9993e364706816aba3e25717850c26c9cd0d89d");
+    assert!(sha256(b"").to_hex() == "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    assert!(sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq").to_hex() ==
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+}
+
+
+#[test]
+fn test_hmac() {
+    use rustc_serialize::hex::ToHex;
+    let message = b"The quick brown fox jumps over the lazy dog";
+    assert!(hmac(Digest::Sha1, b"key", message).to_hex() ==
+            "de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9");
+    assert!(hmac(Digest::Sha256, b"key", message).to_hex() ==
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8");
+    assert!(constant_time_eq(b"abc", b"abc"));
+    assert!(!constant_time_eq(b"abc", b"abd"));
+    assert!(!constant_time_eq(b"abc", b"ab"));
+}
@@ -1,6 +1,7 @@
 //! This module implements some useful objects.
 
 use std::iter;
+use std::str::FromStr;
 use std::collections::HashMap;
 use std::collections::hash_map;
 
@@ -56,21 +57,39 @@ impl<T> MultiDict<T> {
     }
 
     /// Removes an existing key first and add the value.
-    pub fn set(&mut self, key: &str, value: T) {
-        self.map.insert(key.to_string(), vec![value]);
+    pub fn set<K: Into<String>>(&mut self, key: K, value: T) {
+        self.map.insert(key.into(), vec![value]);
     }
 
     /// Adds a new value for the key.
-    pub fn add(&mut self, key: String, value: T) {
-        match self.map.remove(&key) {
-            Some(mut v) => {
-                v.push(value);
-                self.map.insert(key, v);
-            },
-            None => {
-                self.map.insert(key, vec![value]);
-            },
-        }
+    pub fn add<K: Into<String>>(&mut self, key: K, value: T) {
+        self.map.entry(key.into()).or_insert_with(Vec::new).push(value);
+    }
+
+    /// Return the first value for this key, the default is added first if
+    /// the key is missing.
+    pub fn setdefault<K: Into<String>>(&mut self, key: K, default: T) -> &mut T {
+        &mut self.map.entry(key.into()).or_insert_with(|| vec![default])[0]
+    }
+
+    /// Removes a key and returns its values.
+    pub fn remove(&mut self, key: &str) -> Option<Vec<T>> {
+        self.map.remove(key)
+    }
+
+    /// Check if the key has values.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// The number of keys.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Check if there are no keys.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
     }
 
     /// Return the list of items for a given key.
@@ -108,3 +127,40 @@ impl<T> MultiDict<T> {
         self.map.values()
     }
 }
+
+impl<T> Default for MultiDict<T> {
+    fn default() -> MultiDict<T> {
+        MultiDict::new()
+    }
+}
+
+impl<K: Into<String>, T> iter::Extend<(K, T)> for MultiDict<T> {
+    /// Adds the values, see `add`.
+    fn extend<I: IntoIterator<Item=(K, T)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.add(key, value);
+        }
+    }
+}
+
+impl MultiDict<String> {
+    /// Parse the first value for this key, `None` if the key is missing or
+    /// the value can't be parsed:
+    ///
+    /// ```rust,ignore
+    /// let page: Option<u32> = request.args().get_parsed("page");
+    /// ```
+    pub fn get_parsed<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.get(key).and_then(|value| value.parse().ok())
+    }
+
+    /// Like `get_parsed`, but returns the default if the key is missing or
+    /// the value can't be parsed:
+    ///
+    /// ```rust,ignore
+    /// let page = request.args().get_or("page", 1);
+    /// ```
+    pub fn get_or<T: FromStr>(&self, key: &str, default: T) -> T {
+        self.get_parsed(key).unwrap_or(default)
+    }
+}
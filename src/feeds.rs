@@ -0,0 +1,319 @@
+//! This module implements helpers to generate Atom and RSS feeds.
+
+use time::{self, Tm};
+
+use helpers::escape;
+use wrappers::Response;
+
+
+/// Escape a string for XML text and attribute values.
+fn xml_escape(s: &str) -> String {
+    escape(s.to_string()).replace("'", "&#39;")
+}
+
+
+/// One entry of a feed.
+#[derive(Clone, Debug)]
+pub struct FeedEntry {
+    pub title: String,
+    pub link: String,
+    pub updated: Tm,
+    pub id: Option<String>,
+    pub published: Option<Tm>,
+    pub author: Option<String>,
+    pub summary: Option<String>,
+    pub content: Option<String>,
+}
+
+impl FeedEntry {
+    /// Create a feed entry with the title, the link to the entry and the
+    /// time it was last updated.
+    pub fn new(title: &str, link: &str, updated: Tm) -> FeedEntry {
+        FeedEntry {
+            title: title.to_string(),
+            link: link.to_string(),
+            updated: updated,
+            id: None,
+            published: None,
+            author: None,
+            summary: None,
+            content: None,
+        }
+    }
+
+    /// Set a unique id for the entry, defaults to the link.
+    pub fn id(mut self, id: &str) -> FeedEntry {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    /// Set the time the entry was published.
+    pub fn published(mut self, published: Tm) -> FeedEntry {
+        self.published = Some(published);
+        self
+    }
+
+    /// Set the name of the author.
+    pub fn author(mut self, author: &str) -> FeedEntry {
+        self.author = Some(author.to_string());
+        self
+    }
+
+    /// Set a short summary, it may contain HTML.
+    pub fn summary(mut self, summary: &str) -> FeedEntry {
+        self.summary = Some(summary.to_string());
+        self
+    }
+
+    /// Set the full content, it may contain HTML.
+    pub fn content(mut self, content: &str) -> FeedEntry {
+        self.content = Some(content.to_string());
+        self
+    }
+
+    fn entry_id(&self) -> &str {
+        match self.id {
+            Some(ref id) => id,
+            None => &self.link,
+        }
+    }
+}
+
+
+/// Find the latest update time of the entries.
+fn latest_update(entries: &[FeedEntry]) -> Tm {
+    entries.iter()
+           .map(|entry| entry.updated.to_utc())
+           .max_by_key(|updated| updated.to_timespec())
+           .unwrap_or_else(time::now_utc)
+}
+
+
+/// A builder for Atom feeds:
+///
+/// ```rust,ignore
+/// fn recent_posts(_: &mut Request) -> PencilResult {
+///     let mut feed = AtomFeed::new("Recent Posts", "http://example.com/")
+///         .feed_url("http://example.com/recent.atom");
+///     for post in posts() {
+///         feed = feed.entry(FeedEntry::new(&post.title, &post.url, post.updated)
+///             .author(&post.author)
+///             .content(&post.html));
+///     }
+///     Ok(feed.to_response())
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct AtomFeed {
+    pub title: String,
+    pub link: String,
+    pub id: Option<String>,
+    pub feed_url: Option<String>,
+    pub subtitle: Option<String>,
+    pub author: Option<String>,
+    pub updated: Option<Tm>,
+    pub entries: Vec<FeedEntry>,
+}
+
+impl AtomFeed {
+    /// Create an Atom feed with the title and the link to the website.
+    pub fn new(title: &str, link: &str) -> AtomFeed {
+        AtomFeed {
+            title: title.to_string(),
+            link: link.to_string(),
+            id: None,
+            feed_url: None,
+            subtitle: None,
+            author: None,
+            updated: None,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Set a unique id for the feed, defaults to the feed URL or the link.
+    pub fn id(mut self, id: &str) -> AtomFeed {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    /// Set the URL of the feed itself.
+    pub fn feed_url(mut self, feed_url: &str) -> AtomFeed {
+        self.feed_url = Some(feed_url.to_string());
+        self
+    }
+
+    /// Set a subtitle.
+    pub fn subtitle(mut self, subtitle: &str) -> AtomFeed {
+        self.subtitle = Some(subtitle.to_string());
+        self
+    }
+
+    /// Set the name of the author.
+    pub fn author(mut self, author: &str) -> AtomFeed {
+        self.author = Some(author.to_string());
+        self
+    }
+
+    /// Set the time the feed was last updated, defaults to the latest
+    /// update of the entries.
+    pub fn updated(mut self, updated: Tm) -> AtomFeed {
+        self.updated = Some(updated);
+        self
+    }
+
+    /// Add an entry.
+    pub fn entry(mut self, entry: FeedEntry) -> AtomFeed {
+        self.entries.push(entry);
+        self
+    }
+
+    /// Render the feed as XML.
+    pub fn to_xml(&self) -> String {
+        let id = match (&self.id, &self.feed_url) {
+            (&Some(ref id), _) => id,
+            (&None, &Some(ref feed_url)) => feed_url,
+            (&None, &None) => &self.link,
+        };
+        let updated = match self.updated {
+            Some(updated) => updated.to_utc(),
+            None => latest_update(&self.entries),
+        };
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        xml.push_str(&format!("  <title>{}</title>\n", xml_escape(&self.title)));
+        if let Some(ref subtitle) = self.subtitle {
+            xml.push_str(&format!("  <subtitle>{}</subtitle>\n", xml_escape(subtitle)));
+        }
+        xml.push_str(&format!("  <id>{}</id>\n", xml_escape(id)));
+        xml.push_str(&format!("  <updated>{}</updated>\n", updated.rfc3339()));
+        xml.push_str(&format!("  <link href=\"{}\" />\n", xml_escape(&self.link)));
+        if let Some(ref feed_url) = self.feed_url {
+            xml.push_str(&format!("  <link href=\"{}\" rel=\"self\" />\n", xml_escape(feed_url)));
+        }
+        if let Some(ref author) = self.author {
+            xml.push_str(&format!("  <author>\n    <name>{}</name>\n  </author>\n", xml_escape(author)));
+        }
+        xml.push_str("  <generator>Pencil</generator>\n");
+        for entry in &self.entries {
+            xml.push_str("  <entry>\n");
+            xml.push_str(&format!("    <title>{}</title>\n", xml_escape(&entry.title)));
+            xml.push_str(&format!("    <id>{}</id>\n", xml_escape(entry.entry_id())));
+            xml.push_str(&format!("    <updated>{}</updated>\n", entry.updated.to_utc().rfc3339()));
+            if let Some(published) = entry.published {
+                xml.push_str(&format!("    <published>{}</published>\n", published.to_utc().rfc3339()));
+            }
+            xml.push_str(&format!("    <link href=\"{}\" />\n", xml_escape(&entry.link)));
+            if let Some(ref author) = entry.author {
+                xml.push_str(&format!("    <author>\n      <name>{}</name>\n    </author>\n", xml_escape(author)));
+            }
+            if let Some(ref summary) = entry.summary {
+                xml.push_str(&format!("    <summary type=\"html\">{}</summary>\n", xml_escape(summary)));
+            }
+            if let Some(ref content) = entry.content {
+                xml.push_str(&format!("    <content type=\"html\">{}</content>\n", xml_escape(content)));
+            }
+            xml.push_str("  </entry>\n");
+        }
+        xml.push_str("</feed>\n");
+        xml
+    }
+
+    /// Create a response with the rendered feed.
+    pub fn to_response(&self) -> Response {
+        let mut response = Response::from(self.to_xml());
+        response.set_content_type("application/atom+xml");
+        response
+    }
+}
+
+
+/// A builder for RSS 2.0 feeds, it works like `AtomFeed`.
+#[derive(Clone, Debug)]
+pub struct RssFeed {
+    pub title: String,
+    pub link: String,
+    pub description: String,
+    pub language: Option<String>,
+    pub updated: Option<Tm>,
+    pub entries: Vec<FeedEntry>,
+}
+
+impl RssFeed {
+    /// Create an RSS feed with the title, the link to the website and
+    /// a description.
+    pub fn new(title: &str, link: &str, description: &str) -> RssFeed {
+        RssFeed {
+            title: title.to_string(),
+            link: link.to_string(),
+            description: description.to_string(),
+            language: None,
+            updated: None,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Set the language of the feed, like `en-us`.
+    pub fn language(mut self, language: &str) -> RssFeed {
+        self.language = Some(language.to_string());
+        self
+    }
+
+    /// Set the time the feed was last updated, defaults to the latest
+    /// update of the entries.
+    pub fn updated(mut self, updated: Tm) -> RssFeed {
+        self.updated = Some(updated);
+        self
+    }
+
+    /// Add an entry.
+    pub fn entry(mut self, entry: FeedEntry) -> RssFeed {
+        self.entries.push(entry);
+        self
+    }
+
+    /// Render the feed as XML.
+    pub fn to_xml(&self) -> String {
+        let updated = match self.updated {
+            Some(updated) => updated.to_utc(),
+            None => latest_update(&self.entries),
+        };
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str("<rss version=\"2.0\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n");
+        xml.push_str("  <channel>\n");
+        xml.push_str(&format!("    <title>{}</title>\n", xml_escape(&self.title)));
+        xml.push_str(&format!("    <link>{}</link>\n", xml_escape(&self.link)));
+        xml.push_str(&format!("    <description>{}</description>\n", xml_escape(&self.description)));
+        if let Some(ref language) = self.language {
+            xml.push_str(&format!("    <language>{}</language>\n", xml_escape(language)));
+        }
+        xml.push_str(&format!("    <lastBuildDate>{}</lastBuildDate>\n", updated.rfc822()));
+        xml.push_str("    <generator>Pencil</generator>\n");
+        for entry in &self.entries {
+            let published = entry.published.unwrap_or(entry.updated).to_utc();
+            xml.push_str("    <item>\n");
+            xml.push_str(&format!("      <title>{}</title>\n", xml_escape(&entry.title)));
+            xml.push_str(&format!("      <link>{}</link>\n", xml_escape(&entry.link)));
+            let is_permalink = if entry.id.is_none() { "true" } else { "false" };
+            xml.push_str(&format!("      <guid isPermaLink=\"{}\">{}</guid>\n", is_permalink, xml_escape(entry.entry_id())));
+            xml.push_str(&format!("      <pubDate>{}</pubDate>\n", published.rfc822()));
+            if let Some(ref author) = entry.author {
+                xml.push_str(&format!("      <dc:creator>{}</dc:creator>\n", xml_escape(author)));
+            }
+            let description = entry.summary.as_ref().or(entry.content.as_ref());
+            if let Some(description) = description {
+                xml.push_str(&format!("      <description>{}</description>\n", xml_escape(description)));
+            }
+            xml.push_str("    </item>\n");
+        }
+        xml.push_str("  </channel>\n");
+        xml.push_str("</rss>\n");
+        xml
+    }
+
+    /// Create a response with the rendered feed.
+    pub fn to_response(&self) -> Response {
+        let mut response = Response::from(self.to_xml());
+        response.set_content_type("application/rss+xml");
+        response
+    }
+}
@@ -1,64 +1,363 @@
 //! This module implements the form parsing. It supports url-encoded forms
-//! as well as multipart uploads.
+//! as well as multipart uploads.  Uploaded files are streamed to temporary
+//! files which are removed when the request is dropped, after teardown.
 
-use std::io::Read;
+use std::io::{self, Read};
+use std::collections::BTreeMap;
 
 use hyper::header::Headers;
-use hyper::mime::{Mime, TopLevel, SubLevel};
+use hyper::mime::{Attr, Mime, TopLevel, SubLevel};
 use formdata::{read_formdata, FilePart};
 use url::form_urlencoded;
+use rustc_serialize::json::Json;
 
 use datastructures::MultiDict;
+use http_errors::{HTTPError, BadRequest, RequestEntityTooLarge};
+
+
+/// A reader that fails once more than `limit` bytes are read.
+struct LimitReader<'a, R: 'a + ?Sized> {
+    inner: &'a mut R,
+    remaining: Option<u64>,
+    exceeded: bool,
+}
+
+impl<'a, R: Read + ?Sized> Read for LimitReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = try!(self.inner.read(buf));
+        if let Some(remaining) = self.remaining {
+            if size as u64 > remaining {
+                self.exceeded = true;
+                return Err(io::Error::new(io::ErrorKind::Other, "request body too large"));
+            }
+            self.remaining = Some(remaining - size as u64);
+        }
+        Ok(size)
+    }
+}
+
+
+/// A reader that fails once one part of a multipart body is larger than
+/// its limit, so an oversized file is not streamed to disk completely.
+/// It follows the part delimiters in the raw stream, parts with a
+/// `filename` in their headers are files, the others are fields.
+struct PartLimitReader<'a, R: 'a + ?Sized> {
+    inner: &'a mut R,
+    /// The delimiter of the parts, `\r\n--<boundary>`.
+    delimiter: Vec<u8>,
+    /// How many bytes of the delimiter were matched at the end of the
+    /// stream so far.
+    matched: usize,
+    /// The headers of the current part while they are read.
+    headers: Option<Vec<u8>>,
+    part_size: u64,
+    limit: Option<u64>,
+    max_file_size: Option<u64>,
+    max_field_size: Option<u64>,
+    exceeded: bool,
+}
+
+impl<'a, R: Read + ?Sized> PartLimitReader<'a, R> {
+    fn new(inner: &'a mut R, boundary: &str, max_file_size: Option<u64>, max_field_size: Option<u64>)
+           -> PartLimitReader<'a, R> {
+        PartLimitReader {
+            inner: inner,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            // The body starts with the first delimiter without the line break.
+            matched: 2,
+            headers: None,
+            part_size: 0,
+            limit: None,
+            max_file_size: max_file_size,
+            max_field_size: max_field_size,
+            exceeded: false,
+        }
+    }
+
+    fn feed(&mut self, byte: u8) {
+        if byte == self.delimiter[self.matched] {
+            self.matched += 1;
+            if self.matched == self.delimiter.len() {
+                self.matched = 0;
+                self.headers = Some(Vec::new());
+                self.limit = None;
+                return;
+            }
+        } else {
+            // The boundary can't contain a carriage return, so a mismatch
+            // can only start a new match at this byte.
+            self.matched = if byte == self.delimiter[0] { 1 } else { 0 };
+        }
+        let headers_done = match self.headers {
+            Some(ref mut headers) => {
+                headers.push(byte);
+                headers.ends_with(b"\r\n\r\n")
+            },
+            None => {
+                self.part_size += 1;
+                if let Some(limit) = self.limit {
+                    // The matched bytes may belong to the next delimiter.
+                    if self.part_size - self.matched as u64 > limit {
+                        self.exceeded = true;
+                    }
+                }
+                false
+            },
+        };
+        if headers_done {
+            let headers = String::from_utf8_lossy(&self.headers.take().unwrap()).to_lowercase();
+            self.limit = if headers.contains("filename=") { self.max_file_size } else { self.max_field_size };
+            self.part_size = 0;
+        }
+    }
+}
+
+impl<'a, R: Read + ?Sized> Read for PartLimitReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = try!(self.inner.read(buf));
+        for &byte in &buf[..size] {
+            self.feed(byte);
+        }
+        if self.exceeded {
+            return Err(io::Error::new(io::ErrorKind::Other, "form part too large"));
+        }
+        Ok(size)
+    }
+}
 
 
 /// This type implements parsing of form data for Pencil. It can parse
-/// multipart and url encoded form data.
-pub struct FormDataParser;
+/// multipart and url encoded form data.  Parsing fails with
+/// `413 Request Entity Too Large` if the body, one file or one field
+/// exceeds its limit, multipart bodies are checked while they are
+/// streamed.
+#[derive(Default)]
+pub struct FormDataParser {
+    max_content_length: Option<u64>,
+    max_file_size: Option<u64>,
+    max_field_size: Option<u64>,
+}
 
 impl FormDataParser {
     pub fn new() -> FormDataParser {
-        FormDataParser
+        FormDataParser::default()
     }
 
-    pub fn parse<B: Read>(&self, body: &mut B, headers: &Headers, mimetype: &Mime) -> (MultiDict<String>, MultiDict<FilePart>) {
-        let default = (MultiDict::new(), MultiDict::new());
-        match *mimetype {
+    /// Limit the size of the whole body in bytes.
+    pub fn max_content_length(mut self, limit: Option<u64>) -> FormDataParser {
+        self.max_content_length = limit;
+        self
+    }
+
+    /// Limit the size of every uploaded file in bytes.
+    pub fn max_file_size(mut self, limit: Option<u64>) -> FormDataParser {
+        self.max_file_size = limit;
+        self
+    }
+
+    /// Limit the size of every form field value in bytes.
+    pub fn max_field_size(mut self, limit: Option<u64>) -> FormDataParser {
+        self.max_field_size = limit;
+        self
+    }
+
+    pub fn parse<B: Read>(&self, body: &mut B, headers: &Headers, mimetype: &Mime)
+                          -> Result<(MultiDict<String>, MultiDict<FilePart>), HTTPError> {
+        let mut body = LimitReader { inner: body, remaining: self.max_content_length, exceeded: false };
+        let (form, files) = match *mimetype {
             Mime(TopLevel::Application, SubLevel::WwwFormUrlEncoded, _) => {
                 let mut body_vec: Vec<u8> = Vec::new();
-                match body.read_to_end(&mut body_vec) {
-                    Ok(_) => {
-                        let mut form = MultiDict::new();
-                        for (k, v) in form_urlencoded::parse(&body_vec).into_owned() {
-                            form.add(k, v);
-                        }
-                        (form, MultiDict::new())
-                    },
-                    Err(_) => {
-                        default
-                    }
+                if body.read_to_end(&mut body_vec).is_err() {
+                    return Err(self.read_error(&body));
+                }
+                let mut form = MultiDict::new();
+                for (k, v) in form_urlencoded::parse(&body_vec).into_owned() {
+                    form.add(k, v);
                 }
+                (form, Vec::new())
             },
-            Mime(TopLevel::Multipart, SubLevel::FormData, _) => {
-                match read_formdata(body, headers) {
+            Mime(TopLevel::Multipart, SubLevel::FormData, ref params) => {
+                let boundary = params.iter().find(|&&(ref attr, _)| *attr == Attr::Boundary)
+                                            .map(|&(_, ref value)| value.as_str().to_string());
+                let boundary = match boundary {
+                    Some(boundary) => boundary,
+                    None => return Err(BadRequest.with_description("The multipart boundary is missing.")),
+                };
+                let mut parts = PartLimitReader::new(&mut body, &boundary, self.max_file_size, self.max_field_size);
+                let parsed = read_formdata(&mut parts, headers);
+                if parts.exceeded {
+                    return Err(RequestEntityTooLarge);
+                }
+                match parsed {
                     Ok(form_data) => {
                         let mut form = MultiDict::new();
-                        let mut files = MultiDict::new();
                         for (name, value) in form_data.fields {
                             form.add(name, value);
                         }
-                        for (name, file) in form_data.files {
-                            files.add(name, file);
-                        }
-                        (form, files)
+                        (form, form_data.files)
                     },
                     Err(_) => {
-                        default
+                        return Err(self.read_error(&body));
                     }
                 }
             },
             _ => {
-                default
+                (MultiDict::new(), Vec::new())
+            }
+        };
+        if let Some(max_field_size) = self.max_field_size {
+            if form.listiter().any(|(_, values)| values.iter().any(|value| value.len() as u64 > max_field_size)) {
+                return Err(RequestEntityTooLarge);
             }
         }
+        let mut multi_files = MultiDict::new();
+        for (name, file) in files {
+            if let (Some(max_file_size), Some(size)) = (self.max_file_size, file.size) {
+                if size as u64 > max_file_size {
+                    return Err(RequestEntityTooLarge);
+                }
+            }
+            multi_files.add(name, file);
+        }
+        Ok((form, multi_files))
+    }
+
+    fn read_error<R: Read + ?Sized>(&self, body: &LimitReader<R>) -> HTTPError {
+        if body.exceeded {
+            RequestEntityTooLarge
+        } else {
+            BadRequest.with_description("The form data could not be parsed.")
+        }
+    }
+}
+
+
+
+/// Split a form key like `user[name]` or `tags[]` into its segments, an
+/// empty segment stands for an array.  Keys that are not well formed are
+/// returned as a single segment.
+fn split_form_key(key: &str) -> Vec<String> {
+    let base_end = match key.find('[') {
+        Some(0) | None => return vec![key.to_string()],
+        Some(pos) => pos,
+    };
+    let mut segments = vec![key[..base_end].to_string()];
+    let mut remaining = &key[base_end..];
+    while !remaining.is_empty() {
+        if !remaining.starts_with('[') {
+            return vec![key.to_string()];
+        }
+        match remaining.find(']') {
+            Some(end) => {
+                segments.push(remaining[1..end].to_string());
+                remaining = &remaining[end + 1..];
+            },
+            None => return vec![key.to_string()],
+        }
     }
+    segments
+}
+
+
+/// Insert one value into the nested structure.  The `index` is the position
+/// of the value in the list of values for its key, it decides which element
+/// of an array of objects (`items[][name]`) the value belongs to.
+fn insert_nested(target: &mut Json, segments: &[String], value: &str, index: usize) {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    match *target {
+        Json::Array(ref mut array) => {
+            if rest.is_empty() {
+                array.push(Json::String(value.to_string()));
+            } else {
+                while array.len() <= index {
+                    array.push(Json::Object(BTreeMap::new()));
+                }
+                insert_nested(&mut array[index], rest, value, index);
+            }
+        },
+        Json::Object(ref mut object) => {
+            if rest.is_empty() {
+                object.entry(segment.clone()).or_insert_with(|| Json::String(value.to_string()));
+            } else {
+                let child = object.entry(segment.clone()).or_insert_with(|| {
+                    if rest[0].is_empty() {
+                        Json::Array(Vec::new())
+                    } else {
+                        Json::Object(BTreeMap::new())
+                    }
+                });
+                insert_nested(child, rest, value, index);
+            }
+        },
+        _ => {}
+    }
+}
+
+
+/// Parse form keys like `user[name]=x` and `tags[]=a&tags[]=b` into a nested
+/// structure.  Keys without brackets become plain string values, only the
+/// first value is used for them.  A key may contain at most one array
+/// segment.
+pub fn parse_nested_form(form: &MultiDict<String>) -> Json {
+    let mut nested = Json::Object(BTreeMap::new());
+    for (key, values) in form.listiter() {
+        let segments = split_form_key(key);
+        // Nested arrays like `matrix[][]` are ambiguous, skip them.
+        if segments.iter().filter(|s| s.is_empty()).count() > 1 {
+            continue;
+        }
+        for (index, value) in values.iter().enumerate() {
+            insert_nested(&mut nested, &segments, value, index);
+        }
+    }
+    nested
+}
+
+
+#[test]
+fn test_part_limits_while_streaming() {
+    use std::io::Cursor;
+    let mut body = b"--x\r\nContent-Disposition: form-data; name=\"name\"\r\n\r\ncat\r\n".to_vec();
+    body.extend_from_slice(b"--x\r\nContent-Disposition: form-data; name=\"avatar\"; filename=\"cat.png\"\r\n\r\n");
+    body.extend_from_slice(&vec![b'a'; 1024 * 1024]);
+    body.extend_from_slice(b"\r\n--x--\r\n");
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=x".to_vec()]);
+    let mimetype: Mime = "multipart/form-data; boundary=x".parse().unwrap();
+    let mut cursor = Cursor::new(body.clone());
+    let result = FormDataParser::new().max_file_size(Some(10)).parse(&mut cursor, &headers, &mimetype);
+    assert!(result.err().map(|error| error.code()) == Some(413));
+    assert!(cursor.position() < 64 * 1024);
+    let result = FormDataParser::new().max_field_size(Some(2)).parse(&mut Cursor::new(body.clone()), &headers, &mimetype);
+    assert!(result.err().map(|error| error.code()) == Some(413));
+    let (form, files) = FormDataParser::new().max_field_size(Some(3)).max_file_size(Some(1024 * 1024))
+        .parse(&mut Cursor::new(body), &headers, &mimetype).unwrap();
+    assert!(form.get("name") == Some(&String::from("cat")));
+    assert!(files.get("avatar").unwrap().size == Some(1024 * 1024));
+}
+
+
+#[test]
+fn test_parse_nested_form() {
+    let mut form = MultiDict::new();
+    form.add("user[name]".to_string(), "x".to_string());
+    form.add("user[address][city]".to_string(), "y".to_string());
+    form.add("tags[]".to_string(), "a".to_string());
+    form.add("tags[]".to_string(), "b".to_string());
+    form.add("items[][name]".to_string(), "foo".to_string());
+    form.add("items[][name]".to_string(), "bar".to_string());
+    form.add("items[][price]".to_string(), "1".to_string());
+    form.add("items[][price]".to_string(), "2".to_string());
+    form.add("plain".to_string(), "value".to_string());
+    let nested = parse_nested_form(&form);
+    let expected = Json::from_str(r#"{
+        "user": {"name": "x", "address": {"city": "y"}},
+        "tags": ["a", "b"],
+        "items": [{"name": "foo", "price": "1"}, {"name": "bar", "price": "2"}],
+        "plain": "value"
+    }"#).unwrap();
+    assert!(nested == expected);
 }
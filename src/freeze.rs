@@ -0,0 +1,240 @@
+//! This module implements freezing an application into a static site.
+
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use hyper::method::Method;
+use url::percent_encoding::{percent_decode, utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+
+use app::Pencil;
+use testing::PencilClient;
+use types::ViewArgs;
+use wrappers::ResponseBody;
+
+
+/// URL generator type, it returns the view arguments for every URL of an
+/// endpoint with variables, like one entry for every blog post.
+pub type UrlGenerator = fn(&Pencil) -> Vec<ViewArgs>;
+
+
+/// The error type for freezing.
+#[derive(Debug)]
+pub enum FreezeError {
+    /// Writing the output failed.
+    Io(io::Error),
+    /// The URL couldn't be requested.
+    Request(String, String),
+    /// The URL responded with a status code other than `200`.
+    Status(String, u16),
+}
+
+impl From<io::Error> for FreezeError {
+    fn from(err: io::Error) -> FreezeError {
+        FreezeError::Io(err)
+    }
+}
+
+impl fmt::Display for FreezeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FreezeError::Io(ref err) => write!(f, "Failed to write the static site: {}", err),
+            FreezeError::Request(ref url, ref err) => write!(f, "Failed to request {}: {}", url, err),
+            FreezeError::Status(ref url, code) => write!(f, "Unexpected status code {} for {}", code, url),
+        }
+    }
+}
+
+impl error::Error for FreezeError {
+    fn description(&self) -> &str {
+        match *self {
+            FreezeError::Io(ref err) => err.description(),
+            FreezeError::Request(..) => "failed to request URL",
+            FreezeError::Status(..) => "unexpected status code",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            FreezeError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+
+/// Freezes an application into a static site.  Every `GET` rule without
+/// variables is requested in process and the response is written to the
+/// destination directory, rules with variables need a URL generator.  Static
+/// files are included if static file handling is enabled.
+///
+/// ```rust,no_run
+/// use std::collections::HashMap;
+///
+/// use pencil::{Pencil, ViewArgs};
+/// use pencil::freeze::Freezer;
+///
+///
+/// fn post_urls(_: &Pencil) -> Vec<ViewArgs> {
+///     let mut args = HashMap::new();
+///     args.insert("slug".to_string(), "hello-world".to_string());
+///     vec![args]
+/// }
+///
+///
+/// fn main() {
+///     let app = Pencil::new("/web/blog");
+///     let freezer = Freezer::new(&app).register_generator("post", post_urls);
+///     freezer.freeze("/web/blog/build").unwrap();
+/// }
+/// ```
+///
+/// A URL ending with `/` is written to `index.html` in that directory,
+/// other URLs are written to a file with the same path.
+pub struct Freezer<'a> {
+    app: &'a Pencil,
+    generators: HashMap<String, UrlGenerator>,
+    extra_urls: Vec<String>,
+}
+
+impl<'a> Freezer<'a> {
+    pub fn new(app: &'a Pencil) -> Freezer<'a> {
+        Freezer {
+            app: app,
+            generators: HashMap::new(),
+            extra_urls: Vec::new(),
+        }
+    }
+
+    /// Register a URL generator for an endpoint.
+    pub fn register_generator(mut self, endpoint: &str, f: UrlGenerator) -> Freezer<'a> {
+        self.generators.insert(endpoint.to_string(), f);
+        self
+    }
+
+    /// Add a URL that can't be found from the rules, like an error page.
+    pub fn add_url(mut self, url: &str) -> Freezer<'a> {
+        self.extra_urls.push(url.to_string());
+        self
+    }
+
+    /// Collect the URLs of the static files.
+    fn static_urls(&self) -> Vec<String> {
+        let mut urls = Vec::new();
+        let mut dirs = vec![(PathBuf::from(&self.app.static_folder), self.app.static_url_path.clone())];
+        while let Some((dir, url_path)) = dirs.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let name = entry.file_name();
+                let name = match name.to_str() {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let url = format!("{}/{}", url_path, utf8_percent_encode(name, PATH_SEGMENT_ENCODE_SET));
+                match entry.file_type() {
+                    Ok(file_type) if file_type.is_dir() => dirs.push((entry.path(), url)),
+                    Ok(_) => urls.push(url),
+                    Err(_) => {},
+                }
+            }
+        }
+        urls.sort();
+        urls
+    }
+
+    /// All URLs that will be frozen, without duplicates.
+    pub fn urls(&self) -> Vec<String> {
+        let mut urls = Vec::new();
+        for rule in self.app.url_map.iter_rules() {
+            if !rule.methods.contains(&Method::Get) || rule.redirect_to.is_some() {
+                continue;
+            }
+            if let Some(generator) = self.generators.get(&rule.endpoint) {
+                for values in generator(self.app) {
+                    match self.app.url_for(&rule.endpoint, &values) {
+                        Some(url) => urls.push(url),
+                        None => warn!("Could not build a URL for endpoint {} with {:?}", rule.endpoint, values),
+                    }
+                }
+            } else if rule.endpoint == "static" {
+                urls.extend(self.static_urls());
+            } else if let Some(ref rule_str) = rule.matcher.rule {
+                if !rule_str.contains('<') {
+                    urls.push(rule_str.clone());
+                }
+            }
+        }
+        urls.extend(self.extra_urls.iter().cloned());
+        let mut seen = HashSet::new();
+        urls.retain(|url| seen.insert(url.clone()));
+        urls
+    }
+
+    /// Request every URL and write the responses below the destination
+    /// directory.  This returns the frozen URLs.
+    pub fn freeze<P: AsRef<Path>>(&self, destination: P) -> Result<Vec<String>, FreezeError> {
+        let client = PencilClient::new(self.app);
+        let urls = self.urls();
+        for url in &urls {
+            let path = match output_path(destination.as_ref(), url) {
+                Some(path) => path,
+                None => return Err(FreezeError::Request(url.clone(), "Invalid path".to_string())),
+            };
+            let mut response = match client.request(Method::Get, url) {
+                Ok(response) => response,
+                Err(err) => return Err(FreezeError::Request(url.clone(), err)),
+            };
+            if response.status_code != 200 {
+                return Err(FreezeError::Status(url.clone(), response.status_code));
+            }
+            if let Some(parent) = path.parent() {
+                try!(fs::create_dir_all(parent));
+            }
+            let mut file = try!(File::create(&path));
+            if let Some(mut body) = response.body.take() {
+                try!(body.write_body(&mut ResponseBody::new(&mut file)));
+            }
+            try!(file.flush());
+        }
+        Ok(urls)
+    }
+}
+
+
+/// Map a URL to the file it is written to, this returns `None` for URLs
+/// that would escape the destination directory.
+fn output_path(destination: &Path, url: &str) -> Option<PathBuf> {
+    let path = url.splitn(2, '?').next().unwrap_or("");
+    let path = match percent_decode(path.as_bytes()).decode_utf8() {
+        Ok(path) => path.into_owned(),
+        Err(_) => return None,
+    };
+    let mut output = destination.to_path_buf();
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        if segment == "." || segment == ".." || segment.contains('\\') {
+            return None;
+        }
+        output.push(segment);
+    }
+    if path.is_empty() || path.ends_with('/') {
+        output.push("index.html");
+    }
+    Some(output)
+}
+
+
+#[test]
+fn test_output_path() {
+    let destination = Path::new("/build");
+    assert!(output_path(destination, "/") == Some(PathBuf::from("/build/index.html")));
+    assert!(output_path(destination, "/blog/") == Some(PathBuf::from("/build/blog/index.html")));
+    assert!(output_path(destination, "/static/a%20b.css") == Some(PathBuf::from("/build/static/a b.css")));
+    assert!(output_path(destination, "/feed.xml?page=1") == Some(PathBuf::from("/build/feed.xml")));
+    assert!(output_path(destination, "/../etc/passwd").is_none());
+}
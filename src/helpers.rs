@@ -1,23 +1,38 @@
 //! This module implements various helpers.
 
 use std::error::Error;
-use std::fs::File;
-use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
 
-use hyper::header::{Location, ContentType};
+use hyper::header::{Location, ContentType, CacheControl, CacheDirective};
+use hyper::header::{AcceptRanges, ByteRangeSpec, ContentRange, ContentRangeSpec, Range, RangeUnit};
+use hyper::header::{ETag, EntityTag};
+use hyper::method::Method;
 use mime_guess::guess_mime_type;
 use mime::Mime;
+use url::Url;
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+use rand;
+use time::{self, Tm, Timespec};
 
-use wrappers::Response;
+use wrappers::{Request, Response, BodyWrite, ResponseBody};
 use types::{
+    PencilError,
     PenHTTPError,
     PencilResult,
     UserError,
+    ViewArgs,
 };
 use http_errors::{
     HTTPError,
+        BadRequest,
         NotFound,
+        InternalServerError,
 };
+use httputils::get_attachment_disposition;
 
 
 /// Path bound trait.
@@ -51,13 +66,22 @@ pub trait PathBound {
 }
 
 
+/// URL building trait, it's implemented by the application and by requests
+/// so helpers like `redirect_to` work with both.  Requests add their locale
+/// prefix to the URLs.
+pub trait UrlFor {
+    /// Build the URL path for an endpoint, see `Pencil::url_for`.
+    fn url_for(&self, endpoint: &str, values: &ViewArgs) -> Option<String>;
+}
+
+
 /// Safely join directory and filename, otherwise this returns None.
 pub fn safe_join(directory: &str, filename: &str) -> Option<PathBuf> {
     let directory = Path::new(directory);
     let filename = Path::new(filename);
     match filename.to_str() {
         Some(filename_str) => {
-            if filename.is_absolute() | (filename_str == "..") | (filename_str.starts_with("../")) {
+            if filename.is_absolute() || filename.components().any(|component| component == Component::ParentDir) {
                 None
             } else {
                 Some(directory.join(filename_str))
@@ -68,12 +92,65 @@ pub fn safe_join(directory: &str, filename: &str) -> Option<PathBuf> {
 }
 
 
+/// Names that Windows reserves for devices, even with an extension.
+const WINDOWS_DEVICE_NAMES: [&'static str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+
+/// Returns a version of a filename that is safe to use for writing to disk,
+/// like the filename of an uploaded `FilePart`.  Path separators are
+/// replaced by `_`, control characters and leading dots are removed, so
+/// `../../etc/passwd` becomes `etc_passwd`.  The result may be empty, you
+/// should use a generated name then:
+///
+/// ```rust
+/// use pencil::secure_filename;
+///
+/// assert_eq!(secure_filename("My Report.pdf"), "My Report.pdf");
+/// assert_eq!(secure_filename("../../etc/passwd"), "etc_passwd");
+/// assert_eq!(secure_filename("..\\..\\.bashrc"), "bashrc");
+/// ```
+pub fn secure_filename(filename: &str) -> String {
+    let parts: Vec<&str> = filename.split(|c| c == '/' || c == '\\')
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty() && *part != "." && *part != "..")
+        .collect();
+    let joined: String = parts.join("_").chars().filter(|c| !c.is_control()).collect();
+    let filename = joined.trim_left_matches(|c: char| c == '.' || c.is_whitespace())
+        .trim_right_matches(|c: char| c == '.' || c.is_whitespace());
+    let stem = filename.split('.').next().unwrap_or("").to_uppercase();
+    if WINDOWS_DEVICE_NAMES.contains(&&stem[..]) {
+        format!("_{}", filename)
+    } else {
+        filename.to_string()
+    }
+}
+
+
 /// One helper function that can be used to return HTTP Error inside a view function.
 pub fn abort(code: u16) -> PencilResult {
     Err(PenHTTPError(HTTPError::new(code)))
 }
 
 
+/// Get a view argument parsed as the given type.  This fails with
+/// `404 Not Found` if the argument is missing and with `400 Bad Request`
+/// if it can't be parsed.  See the `view_args!` macro for getting several
+/// arguments at once.
+pub fn view_arg<T: FromStr>(request: &Request, name: &str) -> Result<T, PencilError> {
+    let value = match request.view_args.get(name) {
+        Some(value) => value,
+        None => return Err(PenHTTPError(NotFound)),
+    };
+    match value.parse() {
+        Ok(value) => Ok(value),
+        Err(_) => Err(PenHTTPError(BadRequest.with_description(format!("The {} parameter is invalid.", name)))),
+    }
+}
+
+
 /// Returns a response that redirects the client to the target location.
 pub fn redirect(location: &str, code: u16) -> PencilResult {
     let mut response = Response::from(format!(
@@ -90,6 +167,57 @@ pub fn redirect(location: &str, code: u16) -> PencilResult {
 }
 
 
+/// Returns a response that redirects the client to the URL of an endpoint,
+/// so the redirect keeps working when the URL rule changes:
+///
+/// ```rust,ignore
+/// fn create_user(request: &mut Request) -> PencilResult {
+///     let user_id = save_user(request);
+///     let mut values = ViewArgs::new();
+///     values.insert(String::from("user_id"), user_id.to_string());
+///     redirect_to(request, "user", &values, 303)
+/// }
+/// ```
+///
+/// This fails with `500 Internal Server Error` if the URL can't be built.
+pub fn redirect_to<U: UrlFor + ?Sized>(urls: &U, endpoint: &str, values: &ViewArgs, code: u16) -> PencilResult {
+    match urls.url_for(endpoint, values) {
+        Some(location) => redirect(&location, code),
+        None => {
+            let description = format!("Could not build the URL for endpoint {}.", endpoint);
+            Err(PenHTTPError(InternalServerError.with_description(description)))
+        },
+    }
+}
+
+
+/// Returns a response that redirects the client back to the page in the
+/// `Referer` header, or to `fallback` if there is none.  A referer on
+/// another host is never followed, so this can't be abused as an open
+/// redirect.
+pub fn redirect_back(request: &Request, fallback: &str) -> PencilResult {
+    let referer = request.headers().get_raw("Referer").and_then(|values| values.first())
+        .and_then(|value| String::from_utf8(value.clone()).ok());
+    match referer {
+        Some(ref referer) if is_same_origin(request, referer) => redirect(referer, 302),
+        _ => redirect(fallback, 302),
+    }
+}
+
+
+/// Whether a URL points to the host of the request, paths like `/users`
+/// always do.
+fn is_same_origin(request: &Request, url: &str) -> bool {
+    match Url::parse(url) {
+        Ok(url) => match Url::parse(&request.host_url()) {
+            Ok(host_url) => url.origin() == host_url.origin(),
+            Err(_) => false,
+        },
+        Err(_) => url.starts_with('/') && !url.starts_with("//") && !url.starts_with("/\\"),
+    }
+}
+
+
 /// Replace special characters "&", "<", ">" and (") to HTML-safe characters.
 pub fn escape(s: String) -> String {
     s.replace("&", "&amp;").replace("<", "&lt;")
@@ -100,9 +228,11 @@ pub fn escape(s: String) -> String {
 /// Sends the contents of a file to the client.  Please never pass filenames to this
 /// function from user sources without checking them first.  Set `as_attachment` to
 /// `true` if you want to send this file with a `Content-Disposition: attachment`
-/// header.  This will return `NotFound` if filepath is not one file.
-pub fn send_file(filepath: &str, mimetype: Mime, as_attachment: bool) -> PencilResult {
-    let filepath = Path::new(filepath);
+/// header, non-ASCII filenames are encoded per RFC 5987.  This will return
+/// `NotFound` if filepath is not one file.  See `send_file_auto` to guess the
+/// mimetype and `send_file_with` for more options.
+pub fn send_file<P: AsRef<Path>>(filepath: P, mimetype: Mime, as_attachment: bool) -> PencilResult {
+    let filepath = filepath.as_ref();
     if !filepath.is_file() {
         return Err(PenHTTPError(NotFound));
     }
@@ -119,7 +249,7 @@ pub fn send_file(filepath: &str, mimetype: Mime, as_attachment: bool) -> PencilR
             Some(file) => {
                 match file.to_str() {
                     Some(filename) => {
-                        let content_disposition = format!("attachment; filename={}", filename);
+                        let content_disposition = get_attachment_disposition(filename);
                         response.headers.set_raw("Content-Disposition", vec![content_disposition.as_bytes().to_vec()]);
                     },
                     None => {
@@ -136,6 +266,199 @@ pub fn send_file(filepath: &str, mimetype: Mime, as_attachment: bool) -> PencilR
 }
 
 
+/// Like `send_file`, but guesses the mimetype from the file extension.
+pub fn send_file_auto<P: AsRef<Path>>(filepath: P, as_attachment: bool) -> PencilResult {
+    let filepath = filepath.as_ref();
+    send_file(filepath, guess_mime_type(filepath), as_attachment)
+}
+
+
+/// The options of `send_file_with`:
+///
+/// ```rust,ignore
+/// send_file_with(request, "reports/2016.pdf", SendFileOptions::new()
+///     .as_attachment(true)
+///     .conditional(true))
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SendFileOptions {
+    mimetype: Option<Mime>,
+    as_attachment: bool,
+    conditional: bool,
+}
+
+impl SendFileOptions {
+    /// Options that send the file inline with a guessed mimetype.
+    pub fn new() -> SendFileOptions {
+        SendFileOptions::default()
+    }
+
+    /// Use this mimetype instead of guessing it from the file extension.
+    pub fn mimetype(mut self, mimetype: Mime) -> SendFileOptions {
+        self.mimetype = Some(mimetype);
+        self
+    }
+
+    /// Send the file with a `Content-Disposition: attachment` header.
+    pub fn as_attachment(mut self, flag: bool) -> SendFileOptions {
+        self.as_attachment = flag;
+        self
+    }
+
+    /// Answer conditional and `Range` requests, see `send_file_range`.
+    pub fn conditional(mut self, flag: bool) -> SendFileOptions {
+        self.conditional = flag;
+        self
+    }
+}
+
+
+/// Sends a file with the given options, see `SendFileOptions`.
+pub fn send_file_with<P: AsRef<Path>>(request: &Request, filepath: P, options: SendFileOptions) -> PencilResult {
+    let filepath = filepath.as_ref();
+    let mimetype = match options.mimetype {
+        Some(mimetype) => mimetype,
+        None => guess_mime_type(filepath),
+    };
+    if options.conditional {
+        send_file_range(request, filepath, mimetype, options.as_attachment)
+    } else {
+        send_file(filepath, mimetype, options.as_attachment)
+    }
+}
+
+
+/// Requests with more ranges get the whole file.
+const MAX_RANGES: usize = 16;
+
+
+/// The `ETag` and `Last-Modified` validators of a file, they are computed
+/// from its size and modification time.
+fn file_validators(filepath: &Path) -> Option<(EntityTag, Tm)> {
+    let metadata = match fs::metadata(filepath) {
+        Ok(metadata) => metadata,
+        Err(_) => return None,
+    };
+    let modified = match metadata.modified().ok().and_then(|modified| modified.duration_since(UNIX_EPOCH).ok()) {
+        Some(modified) => modified.as_secs(),
+        None => return None,
+    };
+    let etag = EntityTag::strong(format!("{:x}-{:x}", modified, metadata.len()));
+    Some((etag, time::at_utc(Timespec::new(modified as i64, 0))))
+}
+
+
+/// Like `send_file`, but answers `Range` requests for byte ranges with
+/// `206 Partial Content`, so downloads can be resumed.  A single range is
+/// sent as it is, several ranges are sent as `multipart/byteranges`.  If
+/// none of the ranges can be satisfied the response is
+/// `416 Requested Range Not Satisfiable`.
+///
+/// The response has `ETag` and `Last-Modified` headers, requests with
+/// matching `If-None-Match` or `If-Modified-Since` headers get
+/// `304 Not Modified` without the file.
+pub fn send_file_range<P: AsRef<Path>>(request: &Request, filepath: P, mimetype: Mime,
+                                       as_attachment: bool) -> PencilResult {
+    let filepath = filepath.as_ref();
+    let mut response = try!(send_file(filepath, mimetype.clone(), as_attachment));
+    response.headers.set(AcceptRanges(vec![RangeUnit::Bytes]));
+    if let Some((etag, last_modified)) = file_validators(filepath) {
+        response.headers.set(ETag(etag));
+        response.set_last_modified(last_modified);
+        response.make_conditional(request);
+        if response.status_code == 304 {
+            return Ok(response);
+        }
+    }
+    let length = match response.content_length() {
+        Some(length) => length as u64,
+        None => return Ok(response),
+    };
+    let specs = match request.headers().get::<Range>() {
+        Some(&Range::Bytes(ref specs)) if request.method() == Method::Get || request.method() == Method::Head => specs.clone(),
+        _ => return Ok(response),
+    };
+    if specs.len() > MAX_RANGES {
+        return Ok(response);
+    }
+    let ranges: Vec<(u64, u64)> = specs.iter().filter_map(|spec| resolve_range(spec, length)).collect();
+    if ranges.is_empty() {
+        let mut response = Response::new_empty();
+        response.status_code = 416;
+        response.headers.set(ContentRange(ContentRangeSpec::Bytes { range: None, instance_length: Some(length) }));
+        return Ok(response);
+    }
+    let file = match File::open(filepath) {
+        Ok(file) => file,
+        Err(e) => return Err(UserError::new(format!("couldn't open {}: {}", filepath.display(), e.description())).into()),
+    };
+    response.status_code = 206;
+    if ranges.len() == 1 {
+        let (start, end) = ranges[0];
+        response.headers.set(ContentRange(ContentRangeSpec::Bytes { range: Some((start, end)), instance_length: Some(length) }));
+        response.set_content_length((end - start + 1) as usize);
+        response.body = Some(Box::new(FileRanges {
+            file: file,
+            parts: vec![(Vec::new(), start, end)],
+            closing: Vec::new(),
+        }));
+        return Ok(response);
+    }
+    let boundary = format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>());
+    let mut content_length = 0;
+    let mut parts = Vec::new();
+    for (index, &(start, end)) in ranges.iter().enumerate() {
+        let header = format!("{}--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                             if index == 0 { "" } else { "\r\n" }, boundary, mimetype, start, end, length);
+        content_length += header.len() as u64 + end - start + 1;
+        parts.push((header.into_bytes(), start, end));
+    }
+    let closing = format!("\r\n--{}--\r\n", boundary).into_bytes();
+    content_length += closing.len() as u64;
+    response.headers.remove::<ContentRange>();
+    let content_type: Mime = format!("multipart/byteranges; boundary={}", boundary).parse().unwrap();
+    response.headers.set(ContentType(content_type));
+    response.set_content_length(content_length as usize);
+    response.body = Some(Box::new(FileRanges {
+        file: file,
+        parts: parts,
+        closing: closing,
+    }));
+    Ok(response)
+}
+
+
+/// The first and last byte of a range, `None` if the range can't be
+/// satisfied.
+fn resolve_range(spec: &ByteRangeSpec, length: u64) -> Option<(u64, u64)> {
+    match *spec {
+        ByteRangeSpec::FromTo(from, to) if from < length && from <= to => Some((from, to.min(length - 1))),
+        ByteRangeSpec::AllFrom(from) if from < length => Some((from, length - 1)),
+        ByteRangeSpec::Last(last) if last > 0 && length > 0 => Some((length - last.min(length), length - 1)),
+        _ => None,
+    }
+}
+
+
+/// The body of a range response, every part is written after its header.
+struct FileRanges {
+    file: File,
+    parts: Vec<(Vec<u8>, u64, u64)>,
+    closing: Vec<u8>,
+}
+
+impl BodyWrite for FileRanges {
+    fn write_body(&mut self, body: &mut ResponseBody) -> io::Result<()> {
+        for &(ref header, start, end) in &self.parts {
+            try!(body.write_all(header));
+            try!(self.file.seek(SeekFrom::Start(start)));
+            try!(io::copy(&mut (&mut self.file).take(end - start + 1), body));
+        }
+        body.write_all(&self.closing)
+    }
+}
+
+
 /// Send a file from a given directory with `send_file`.  This is a secure way to
 /// quickly expose static files from an folder.  This will guess the mimetype
 /// for you.
@@ -158,3 +481,188 @@ pub fn send_from_directory(directory: &str, filename: &str,
         }
     }
 }
+
+
+/// Sets the `Cache-Control` header of a static file response.  The max age
+/// in seconds is configured with `STATIC_CACHE_MAX_AGE`, the optional
+/// `STATIC_CACHE_MAX_AGE_BY_EXTENSION` object overrides it for file
+/// extensions, like `{"html": 0, "css": 31536000}`.  Modules can override
+/// both.
+#[doc(hidden)]
+pub fn add_static_cache_headers(request: &Request, filename: &str, response: &mut Response) {
+    if response.status_code >= 400 {
+        return;
+    }
+    let extension = Path::new(filename).extension().and_then(|extension| extension.to_str());
+    let by_extension = request.config_value("STATIC_CACHE_MAX_AGE_BY_EXTENSION")
+        .and_then(|map| map.as_object()
+                           .and_then(|map| extension.and_then(|extension| map.get(&extension.to_lowercase())))
+                           .and_then(|max_age| max_age.as_u64()));
+    let max_age = by_extension.or_else(|| request.config_value("STATIC_CACHE_MAX_AGE").and_then(|max_age| max_age.as_u64()));
+    if let Some(max_age) = max_age {
+        response.headers.set(CacheControl(vec![CacheDirective::Public, CacheDirective::MaxAge(max_age as u32)]));
+    }
+}
+
+
+/// The precompressed variants of a file with their content coding, the
+/// preferred one first.
+const PRECOMPRESSED: [(&'static str, &'static str); 2] = [("br", ".br"), ("gzip", ".gz")];
+
+
+/// Choose a precompressed variant of a file that the client accepts, the
+/// variant is a file next to it like `app.js.gz`.  Returns the path of the
+/// variant and its content coding.
+fn precompressed_variant(request: &Request, filepath: &str) -> Option<(String, &'static str)> {
+    let variant = |coding: &str| {
+        PRECOMPRESSED.iter()
+            .filter(|&&(name, _)| coding == "*" || coding.eq_ignore_ascii_case(name) ||
+                                  (name == "gzip" && coding.eq_ignore_ascii_case("x-gzip")))
+            .map(|&(name, extension)| (format!("{}{}", filepath, extension), name))
+            .find(|&(ref path, _)| Path::new(path).is_file())
+    };
+    for (coding, quality) in request.accept_encodings() {
+        if quality <= 0.0 {
+            continue;
+        }
+        if coding.eq_ignore_ascii_case("identity") {
+            return None;
+        }
+        if let Some(found) = variant(&coding) {
+            return Some(found);
+        }
+    }
+    None
+}
+
+
+/// Like `send_from_directory`, but answers `Range` requests, see
+/// `send_file_range`.  If the client accepts it, a precompressed variant
+/// of the file like `app.js.br` or `app.js.gz` is sent instead, with the
+/// `Content-Encoding` header and the content type of the original file.
+///
+/// If `STATIC_DIRECTORY_LISTING` is configured as `true` and the filename
+/// is a directory, a listing of the directory is sent, see
+/// `send_directory_listing`.
+pub fn send_from_directory_range(request: &Request, directory: &str, filename: &str,
+                                 as_attachment: bool) -> PencilResult {
+    match safe_join(directory, filename) {
+        Some(filepath) => {
+            if filepath.is_dir() {
+                let enabled = request.config_value("STATIC_DIRECTORY_LISTING")
+                    .and_then(|enabled| enabled.as_boolean()).unwrap_or(false);
+                if enabled {
+                    return directory_listing(request, &filepath, filename.is_empty());
+                }
+                return Err(PenHTTPError(NotFound));
+            }
+            let mimetype = guess_mime_type(filepath.as_path());
+            match filepath.as_path().to_str() {
+                Some(filepath) => {
+                    let has_variants = PRECOMPRESSED.iter()
+                        .any(|&(_, extension)| Path::new(&format!("{}{}", filepath, extension)).is_file());
+                    if !has_variants || !Path::new(filepath).is_file() {
+                        return send_file_range(request, filepath, mimetype, as_attachment);
+                    }
+                    let mut response = match precompressed_variant(request, filepath) {
+                        Some((variant, coding)) => {
+                            let mut response = try!(send_file_range(request, &variant, mimetype, as_attachment));
+                            response.headers.set_raw("Content-Encoding", vec![coding.as_bytes().to_vec()]);
+                            response
+                        },
+                        None => try!(send_file_range(request, filepath, mimetype, as_attachment)),
+                    };
+                    response.headers.set_raw("Vary", vec![b"Accept-Encoding".to_vec()]);
+                    Ok(response)
+                },
+                None => {
+                    Err(PenHTTPError(NotFound))
+                }
+            }
+        },
+        None => {
+            Err(PenHTTPError(NotFound))
+        }
+    }
+}
+
+
+/// Sends an HTML listing of a directory below the given directory with
+/// the size and modification time of every entry, which is handy for
+/// internal file-serving tools.  Hidden entries starting with a dot are
+/// left out.  Requests for a directory without a trailing slash are
+/// redirected, so the relative links of the listing work.
+pub fn send_directory_listing(request: &Request, directory: &str, filename: &str) -> PencilResult {
+    match safe_join(directory, filename) {
+        Some(dirpath) => {
+            if !dirpath.is_dir() {
+                return Err(PenHTTPError(NotFound));
+            }
+            directory_listing(request, &dirpath, filename.is_empty())
+        },
+        None => {
+            Err(PenHTTPError(NotFound))
+        }
+    }
+}
+
+
+fn directory_listing(request: &Request, dirpath: &Path, is_root: bool) -> PencilResult {
+    if !request.path().ends_with('/') {
+        return redirect(&format!("{}/", request.path()), 301);
+    }
+    let read_dir = match fs::read_dir(dirpath) {
+        Ok(read_dir) => read_dir,
+        Err(e) => {
+            return Err(UserError::new(format!("couldn't list {}: {}", dirpath.display(), e.description())).into());
+        }
+    };
+    let mut entries = Vec::new();
+    for entry in read_dir {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        let modified = metadata.modified().ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| time::at_utc(Timespec::new(modified.as_secs() as i64, 0)));
+        entries.push((!metadata.is_dir(), name, metadata.len(), modified));
+    }
+    entries.sort();
+    let title = escape(format!("Index of {}", request.path()));
+    let mut body = format!("<!DOCTYPE html>\n<title>{}</title>\n<h1>{}</h1>\n<table>\n\
+<tr><th>Name</th><th>Size</th><th>Last modified</th></tr>\n", title, title);
+    if !is_root {
+        body.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for (is_file, name, size, modified) in entries {
+        let mut href = utf8_percent_encode(&name, PATH_SEGMENT_ENCODE_SET).to_string();
+        let mut name = name;
+        if !is_file {
+            href.push('/');
+            name.push('/');
+        }
+        let size = if is_file { size.to_string() } else { String::from("-") };
+        let modified = match modified {
+            Some(modified) => time::strftime("%Y-%m-%d %H:%M:%S", &modified).unwrap_or_default(),
+            None => String::new(),
+        };
+        body.push_str(&format!("<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+                               escape(href), escape(name), size, modified));
+    }
+    body.push_str("</table>\n");
+    let mut response = Response::from(body);
+    response.set_content_type("text/html");
+    Ok(response)
+}
@@ -1,10 +1,12 @@
 //! This module implements a number of http errors.
 
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt;
 
 use hyper;
 use hyper::method::Method;
+use rustc_serialize::json::{Json, ToJson};
 
 use httputils::get_name_by_http_code;
 use wrappers::Response;
@@ -27,10 +29,15 @@ pub use self::HTTPError::{
     RequestedRangeNotSatisfiable,
     ExpectationFailed,
     ImATeapot,
+    MisdirectedRequest,
     UnprocessableEntity,
+    Locked,
+    FailedDependency,
+    UpgradeRequired,
     PreconditionRequired,
     TooManyRequests,
     RequestHeaderFieldsTooLarge,
+    UnavailableForLegalReasons,
     InternalServerError,
     NotImplemented,
     BadGateway,
@@ -81,14 +88,25 @@ pub enum HTTPError {
     RequestedRangeNotSatisfiable,
     ExpectationFailed,
     ImATeapot,
+    MisdirectedRequest,
     UnprocessableEntity,
+    Locked,
+    FailedDependency,
+    UpgradeRequired,
     PreconditionRequired,
     TooManyRequests,
     RequestHeaderFieldsTooLarge,
+    UnavailableForLegalReasons,
     InternalServerError,
     NotImplemented,
     BadGateway,
     ServiceUnavailable,
+    /// An HTTP error with a custom description, for example a `400 Bad Request`
+    /// explaining why the request data could not be understood.
+    WithDescription(Box<HTTPError>, String),
+    /// An HTTP error with a `Retry-After` header in seconds, see
+    /// `with_retry_after`.
+    WithRetryAfter(Box<HTTPError>, u64),
 }
 
 impl HTTPError {
@@ -112,10 +130,15 @@ impl HTTPError {
             416 => RequestedRangeNotSatisfiable,
             417 => ExpectationFailed,
             418 => ImATeapot,
+            421 => MisdirectedRequest,
             422 => UnprocessableEntity,
+            423 => Locked,
+            424 => FailedDependency,
+            426 => UpgradeRequired,
             428 => PreconditionRequired,
             429 => TooManyRequests,
             431 => RequestHeaderFieldsTooLarge,
+            451 => UnavailableForLegalReasons,
             // 500 => InternalServerError
             501 => NotImplemented,
             502 => BadGateway,
@@ -124,6 +147,68 @@ impl HTTPError {
         }
     }
 
+    /// Attach a custom description to this error, it replaces the default
+    /// description in the response body.
+    ///
+    /// ```rust,no_run
+    /// use pencil::{Request, PencilResult, PenHTTPError};
+    /// use pencil::http_errors::BadRequest;
+    ///
+    ///
+    /// fn view(_: &mut Request) -> PencilResult {
+    ///     Err(PenHTTPError(BadRequest.with_description("The page parameter is missing.")))
+    /// }
+    /// ```
+    pub fn with_description<T: AsRef<str>>(self, description: T) -> HTTPError {
+        let error = match self {
+            HTTPError::WithDescription(error, _) => error,
+            error => Box::new(error),
+        };
+        HTTPError::WithDescription(error, description.as_ref().to_owned())
+    }
+
+    /// Tell the client how many seconds to wait before retrying, this is
+    /// useful for `429 Too Many Requests` and `503 Service Unavailable`.
+    /// The value is sent in the `Retry-After` header:
+    ///
+    /// ```rust,no_run
+    /// use pencil::{Request, PencilResult, PenHTTPError};
+    /// use pencil::http_errors::TooManyRequests;
+    ///
+    ///
+    /// fn view(_: &mut Request) -> PencilResult {
+    ///     Err(PenHTTPError(TooManyRequests.with_retry_after(60)))
+    /// }
+    /// ```
+    pub fn with_retry_after(self, seconds: u64) -> HTTPError {
+        let error = match self {
+            HTTPError::WithRetryAfter(error, _) => error,
+            error => Box::new(error),
+        };
+        HTTPError::WithRetryAfter(error, seconds)
+    }
+
+    /// The `Retry-After` value in seconds, if there is one.
+    pub fn retry_after(&self) -> Option<u64> {
+        match *self {
+            HTTPError::WithRetryAfter(_, seconds) => Some(seconds),
+            HTTPError::WithDescription(ref error, _) => error.retry_after(),
+            _ => None,
+        }
+    }
+
+    /// The methods that are allowed for the requested URL, if this is a
+    /// `405 Method Not Allowed` error that knows them.  They are sent in
+    /// the `Allow` header.
+    pub fn allowed_methods(&self) -> Option<&Vec<Method>> {
+        match *self {
+            MethodNotAllowed(Some(ref valid_methods)) => Some(valid_methods),
+            HTTPError::WithDescription(ref error, _) => error.allowed_methods(),
+            HTTPError::WithRetryAfter(ref error, _) => error.allowed_methods(),
+            _ => None,
+        }
+    }
+
     /// The status code.
     pub fn code(&self) -> u16 {
         match *self {
@@ -144,14 +229,21 @@ impl HTTPError {
             RequestedRangeNotSatisfiable => 416,
             ExpectationFailed => 417,
             ImATeapot => 418,
+            MisdirectedRequest => 421,
             UnprocessableEntity => 422,
+            Locked => 423,
+            FailedDependency => 424,
+            UpgradeRequired => 426,
             PreconditionRequired => 428,
             TooManyRequests => 429,
             RequestHeaderFieldsTooLarge => 431,
+            UnavailableForLegalReasons => 451,
             InternalServerError => 500,
             NotImplemented => 501,
             BadGateway => 502,
             ServiceUnavailable => 503,
+            HTTPError::WithDescription(ref error, _) => error.code(),
+            HTTPError::WithRetryAfter(ref error, _) => error.code(),
         }
     }
 
@@ -201,12 +293,21 @@ impl HTTPError {
             RequestedRangeNotSatisfiable => "The server cannot provide the requested range.",
             ExpectationFailed => "The server could not meet the requirements of the Expect header",
             ImATeapot => "This server is a teapot, not a coffee machine",
+            MisdirectedRequest => "The request was directed at a server that is not able \
+                                   to produce a response for it.",
             UnprocessableEntity => "The request was well-formed but was unable to be \
                                     followed due to semantic errors.",
+            Locked => "The resource that is being accessed is locked.",
+            FailedDependency => "The request failed because it depended on another \
+                                 request that failed.",
+            UpgradeRequired => "The server refuses to perform the request using the \
+                                current protocol, the client should switch to a different \
+                                protocol.",
             PreconditionRequired => "This request is required to be conditional; try \
                                      using \"If-Match\" or \"If-Unmodified-Since\".",
             TooManyRequests => "This user has exceeded an allotted request count. Try again later.",
             RequestHeaderFieldsTooLarge => "One or more header fields exceeds the maximum size.",
+            UnavailableForLegalReasons => "The requested resource is unavailable for legal reasons.",
             InternalServerError => "The server encountered an internal error and was unable \
                                     to complete your request.  Either the server is overloaded \
                                     or there is an error in the application.",
@@ -215,6 +316,8 @@ impl HTTPError {
             ServiceUnavailable => "The server is temporarily unable to service your request \
                                    due to maintenance downtime or capacity problems.  Please \
                                    try again later.",
+            HTTPError::WithDescription(_, ref description) => description,
+            HTTPError::WithRetryAfter(ref error, _) => error.get_description(),
         }
     }
 
@@ -228,12 +331,36 @@ impl HTTPError {
 ", self.code().to_string(), self.name(), self.name(), self.get_description())
     }
 
+    /// Get the JSON body, like
+    /// `{"code":404,"description":"...","name":"Not Found"}`.
+    pub fn get_json_body(&self) -> String {
+        let mut object = BTreeMap::new();
+        object.insert(String::from("code"), self.code().to_json());
+        object.insert(String::from("name"), self.name().to_json());
+        object.insert(String::from("description"), self.get_description().to_json());
+        Json::Object(object).to_string()
+    }
+
     /// Get a response object.
     pub fn to_response(&self) -> Response {
         let mut response = Response::from(self.get_body());
-        response.status_code = self.code();
         response.set_content_type("text/html");
-        if let MethodNotAllowed(Some(ref valid_methods)) = *self {
+        self.finish_response(response)
+    }
+
+    /// Get a response object with a JSON body, see `get_json_body`.
+    pub fn to_json_response(&self) -> Response {
+        let mut response = Response::from(self.get_json_body());
+        response.set_content_type("application/json");
+        self.finish_response(response)
+    }
+
+    fn finish_response(&self, mut response: Response) -> Response {
+        response.status_code = self.code();
+        if let Some(seconds) = self.retry_after() {
+            response.headers.set_raw("Retry-After", vec![seconds.to_string().into_bytes()]);
+        }
+        if let Some(valid_methods) = self.allowed_methods() {
             response.headers.set(hyper::header::Allow(valid_methods.clone()));
         }
         response
@@ -1,13 +1,19 @@
 //! This module implements a bunch of utilities that help Pencil
 //! to deal with HTTP data.
 
+use std::cmp::Ordering;
+
 use hyper::header::Host;
 use hyper::status::StatusCode;
 
 
 /// Get HTTP status name by status code.
 pub fn get_name_by_http_code(code: u16) -> Option<&'static str> {
-    get_status_from_code(code).canonical_reason()
+    match code {
+        421 => Some("Misdirected Request"),
+        451 => Some("Unavailable For Legal Reasons"),
+        _ => get_status_from_code(code).canonical_reason(),
+    }
 }
 
 
@@ -98,8 +104,249 @@ pub fn get_status_from_code(code: u16) -> StatusCode {
 }
 
 
+
+/// Parse a header value with quality values, like `Accept`,
+/// `Accept-Encoding` or `Accept-Language`.  Every item is returned
+/// together with its quality, items keep their parameters except
+/// the `q` parameter itself.  Invalid qualities default to 1 and
+/// the result keeps the order of the header value.
+pub fn parse_quality_values(value: &str) -> Vec<(String, f32)> {
+    let mut items = Vec::new();
+    for item in value.split(',') {
+        let item = item.trim();
+        if item.is_empty() {
+            continue;
+        }
+        let mut quality = 1.0;
+        let mut parts: Vec<&str> = Vec::new();
+        for (i, part) in item.split(';').enumerate() {
+            let part = part.trim();
+            if i > 0 {
+                let mut kv = part.splitn(2, '=');
+                let key = kv.next().unwrap_or("").trim();
+                if key.eq_ignore_ascii_case("q") {
+                    let q = kv.next().unwrap_or("").trim();
+                    quality = match q.parse::<f32>() {
+                        Ok(q) if q.is_finite() => q.max(0.0).min(1.0),
+                        _ => 1.0,
+                    };
+                    continue;
+                }
+            }
+            if !part.is_empty() {
+                parts.push(part);
+            }
+        }
+        if !parts.is_empty() {
+            items.push((parts.join(";"), quality));
+        }
+    }
+    items
+}
+
+
+/// Sort quality values by their weight, the highest quality comes first.
+/// Ties are broken by the given specificity function (more specific values
+/// come first) and then by the original order in the header.
+pub fn sort_quality_values<F>(items: &mut Vec<(String, f32)>, specificity: F)
+    where F: Fn(&str) -> usize {
+    // `sort_by` is stable, so the original order wins on full ties.
+    items.sort_by(|a, b| {
+        b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal)
+           .then_with(|| specificity(&b.0).cmp(&specificity(&a.0)))
+    });
+}
+
+
+/// The specificity of a media range, `text/html;level=1` is more specific
+/// than `text/html`, which is more specific than `text/*` and `*/*`.
+fn media_range_specificity(value: &str) -> usize {
+    let mut parts = value.split(';');
+    let mimetype = parts.next().unwrap_or("");
+    let params = parts.count();
+    let mut specificity = params;
+    let mut types = mimetype.splitn(2, '/');
+    if types.next().unwrap_or("*").trim() != "*" {
+        specificity += 1;
+        if types.next().unwrap_or("*").trim() != "*" {
+            specificity += 1;
+        }
+    }
+    specificity
+}
+
+
+/// The specificity of a language range, `en-US` is more specific than
+/// `en`, which is more specific than `*`.
+fn language_range_specificity(value: &str) -> usize {
+    if value == "*" {
+        0
+    } else {
+        value.split('-').count()
+    }
+}
+
+
+/// The specificity of a content coding or charset, `*` is the least specific.
+fn coding_specificity(value: &str) -> usize {
+    if value == "*" { 0 } else { 1 }
+}
+
+
+/// Parse an `Accept` header value into media ranges sorted by quality.
+pub fn parse_accept_mimetypes(value: &str) -> Vec<(String, f32)> {
+    let mut items = parse_quality_values(value);
+    sort_quality_values(&mut items, media_range_specificity);
+    items
+}
+
+
+/// Whether a media range like `text/*` matches a mimetype.
+fn media_range_matches(range: &str, mimetype: &str) -> bool {
+    let range = range.split(';').next().unwrap_or("").trim();
+    if range == "*/*" {
+        return true;
+    }
+    if range.ends_with("/*") {
+        let main_type = &range[..range.len() - 1];
+        return mimetype.len() > main_type.len() && mimetype[..main_type.len()].eq_ignore_ascii_case(main_type);
+    }
+    range.eq_ignore_ascii_case(mimetype)
+}
+
+
+/// Choose the best of the offered mimetypes for an `Accept` header value.
+/// Every offer gets the quality of the most specific media range that
+/// matches it, ties go to the earlier offer.  Returns `None` if no offer
+/// is acceptable.
+pub fn best_mimetype_match<'a>(value: &str, offered: &[&'a str]) -> Option<&'a str> {
+    let ranges = parse_accept_mimetypes(value);
+    let mut best: Option<(&'a str, f32)> = None;
+    for &mimetype in offered {
+        let mut matched: Option<(usize, f32)> = None;
+        for &(ref range, quality) in &ranges {
+            if media_range_matches(range, mimetype) {
+                let specificity = media_range_specificity(range);
+                if matched.map_or(true, |(current, _)| specificity > current) {
+                    matched = Some((specificity, quality));
+                }
+            }
+        }
+        let quality = matched.map_or(0.0, |(_, quality)| quality);
+        if quality > 0.0 && best.map_or(true, |(_, current)| quality > current) {
+            best = Some((mimetype, quality));
+        }
+    }
+    best.map(|(mimetype, _)| mimetype)
+}
+
+
+/// Parse an `Accept-Encoding` header value into codings sorted by quality.
+pub fn parse_accept_encodings(value: &str) -> Vec<(String, f32)> {
+    let mut items = parse_quality_values(value);
+    sort_quality_values(&mut items, coding_specificity);
+    items
+}
+
+
+/// Parse an `Accept-Charset` header value into charsets sorted by quality.
+pub fn parse_accept_charsets(value: &str) -> Vec<(String, f32)> {
+    let mut items = parse_quality_values(value);
+    sort_quality_values(&mut items, coding_specificity);
+    items
+}
+
+
+/// Parse an `Accept-Language` header value into language ranges sorted
+/// by quality.
+pub fn parse_accept_languages(value: &str) -> Vec<(String, f32)> {
+    let mut items = parse_quality_values(value);
+    sort_quality_values(&mut items, language_range_specificity);
+    items
+}
+
+
+/// Whether a byte may appear unencoded in an RFC 5987 extended value.
+fn is_attr_char(b: u8) -> bool {
+    (b as char).is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b)
+}
+
+
+/// Return the `Content-Disposition` value for an attachment.  Names that
+/// are not plain tokens are quoted, non-ASCII names are sent as
+/// `filename*=UTF-8''...` per RFC 5987 with an ASCII fallback for old
+/// clients, where every other character is replaced by `_`.
+pub fn get_attachment_disposition(filename: &str) -> String {
+    if !filename.is_empty() && filename.bytes().all(is_attr_char) {
+        return format!("attachment; filename={}", filename);
+    }
+    let fallback: String = filename.chars().map(|c| {
+        if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' { c } else { '_' }
+    }).collect();
+    if filename.is_ascii() {
+        return format!("attachment; filename=\"{}\"", fallback);
+    }
+    let mut encoded = String::new();
+    for b in filename.bytes() {
+        if is_attr_char(b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
+
 #[test]
 fn test_get_name_by_http_code() {
     let status_name = get_name_by_http_code(200).unwrap();
     assert!(status_name == "OK");
 }
+
+
+
+#[test]
+fn test_parse_quality_values() {
+    let items = parse_quality_values("text/html;level=1, text/plain;q=0.5, */*;q=bogus");
+    assert!(items == vec![(String::from("text/html;level=1"), 1.0),
+                          (String::from("text/plain"), 0.5),
+                          (String::from("*/*"), 1.0)]);
+}
+
+
+#[test]
+fn test_parse_accept_mimetypes() {
+    let items = parse_accept_mimetypes("*/*;q=0.8, text/*, text/html, application/json;q=0.8");
+    let values: Vec<&str> = items.iter().map(|item| &item.0 as &str).collect();
+    assert!(values == vec!["text/html", "text/*", "application/json", "*/*"]);
+}
+
+
+#[test]
+fn test_best_mimetype_match() {
+    let offered = ["text/html", "application/json"];
+    assert!(best_mimetype_match("application/json", &offered) == Some("application/json"));
+    assert!(best_mimetype_match("text/html, application/json", &offered) == Some("text/html"));
+    assert!(best_mimetype_match("*/*", &offered) == Some("text/html"));
+    assert!(best_mimetype_match("application/*, text/html;q=0.5", &offered) == Some("application/json"));
+    assert!(best_mimetype_match("*/*, text/html;q=0", &offered) == Some("application/json"));
+    assert!(best_mimetype_match("image/png", &offered) == None);
+}
+
+
+#[test]
+fn test_parse_accept_languages() {
+    let items = parse_accept_languages("*;q=0.5, en;q=0.8, de-CH, de");
+    let values: Vec<&str> = items.iter().map(|item| &item.0 as &str).collect();
+    assert!(values == vec!["de-CH", "de", "en", "*"]);
+}
+
+
+#[test]
+fn test_get_attachment_disposition() {
+    assert!(get_attachment_disposition("report.pdf") == "attachment; filename=report.pdf");
+    assert!(get_attachment_disposition("my \"report\".pdf") == "attachment; filename=\"my _report_.pdf\"");
+    assert!(get_attachment_disposition("r\u{e9}sum\u{e9} 1.pdf") ==
+            "attachment; filename=\"r_sum_ 1.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9%201.pdf");
+}
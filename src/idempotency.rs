@@ -0,0 +1,263 @@
+//! This module implements support for idempotency keys.  Clients send an
+//! `Idempotency-Key` header with unsafe requests, the first response for
+//! every key is stored and replayed when the client retries the request.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hyper::header::Headers;
+use hyper::method::Method;
+use rustc_serialize::hex::ToHex;
+
+use crypto::{sha256, constant_time_eq};
+use http_errors::{Conflict, UnprocessableEntity};
+use types::PencilError::PenHTTPError;
+use types::PencilResult;
+use wrappers::{Request, Response, ResponseBody};
+
+
+/// A response that was stored for one idempotency key.
+#[derive(Clone, Debug)]
+pub struct StoredResponse {
+    pub status_code: u16,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// The SHA-256 hash of the request body, a retry with another body is
+    /// rejected.
+    pub request_hash: Vec<u8>,
+}
+
+impl StoredResponse {
+    /// Create a response that can be sent to the client.
+    pub fn to_response(&self) -> Response {
+        let mut response = Response::from(self.body.clone());
+        response.status_code = self.status_code;
+        response.headers = self.headers.clone();
+        response.set_content_length(self.body.len());
+        response
+    }
+}
+
+
+/// The store for responses of idempotent requests.  Implement this for your
+/// own storage backend if the responses should be shared between processes.
+pub trait IdempotencyStore: Send + Sync {
+    /// Get the stored response for a key if it did not expire yet.
+    fn get(&self, key: &str) -> Option<StoredResponse>;
+    /// Store the response for a key for the given time, this replaces the
+    /// claim of the key.
+    fn set(&self, key: &str, response: StoredResponse, ttl: Duration);
+    /// Mark the key as in flight for the given time.  This has to be
+    /// atomic, it returns `false` if the key is already claimed or has a
+    /// stored response.
+    fn claim(&self, key: &str, ttl: Duration) -> bool;
+    /// Remove the claim of a key without storing a response.
+    fn release(&self, key: &str);
+}
+
+
+/// A simple in memory store, expired entries are purged lazily.
+pub struct MemoryStore {
+    /// The entries by key, `None` marks a claimed key.
+    entries: Mutex<HashMap<String, (Instant, Option<StoredResponse>)>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> MemoryStore {
+        MemoryStore::new()
+    }
+}
+
+impl IdempotencyStore for MemoryStore {
+    fn get(&self, key: &str) -> Option<StoredResponse> {
+        let entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(_) => return None,
+        };
+        match entries.get(key) {
+            Some(&(expires, Some(ref response))) if expires > Instant::now() => Some(response.clone()),
+            _ => None,
+        }
+    }
+
+    fn set(&self, key: &str, response: StoredResponse, ttl: Duration) {
+        if let Ok(mut entries) = self.entries.lock() {
+            let now = Instant::now();
+            entries.retain(|_, entry| entry.0 > now);
+            entries.insert(key.to_string(), (now + ttl, Some(response)));
+        }
+    }
+
+    fn claim(&self, key: &str, ttl: Duration) -> bool {
+        let mut entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(_) => return false,
+        };
+        let now = Instant::now();
+        entries.retain(|_, entry| entry.0 > now);
+        if entries.contains_key(key) {
+            return false;
+        }
+        entries.insert(key.to_string(), (now + ttl, None));
+        true
+    }
+
+    fn release(&self, key: &str) {
+        if let Ok(mut entries) = self.entries.lock() {
+            let claimed = match entries.get(key) {
+                Some(&(_, None)) => true,
+                _ => false,
+            };
+            if claimed {
+                entries.remove(key);
+            }
+        }
+    }
+}
+
+
+/// The claim of the key of a request that is being processed.  The claim
+/// is released when this is dropped, so a view that fails or panics
+/// doesn't keep the key claimed, call `keep` once the response is stored.
+pub struct Claim<'a> {
+    store: &'a IdempotencyStore,
+    key: Option<String>,
+}
+
+impl<'a> Claim<'a> {
+    /// Keep the key, its response was stored.
+    pub fn keep(mut self) {
+        self.key = None;
+    }
+}
+
+impl<'a> Drop for Claim<'a> {
+    fn drop(&mut self) {
+        if let Some(ref key) = self.key {
+            self.store.release(key);
+        }
+    }
+}
+
+
+/// The idempotency settings of one application.
+pub struct Idempotency {
+    store: Box<IdempotencyStore>,
+    ttl: Duration,
+}
+
+impl Idempotency {
+    pub fn new<S: IdempotencyStore + 'static>(store: S, ttl: Duration) -> Idempotency {
+        Idempotency {
+            store: Box::new(store),
+            ttl: ttl,
+        }
+    }
+
+    /// The caller of the request, this is the `Authorization` header, the
+    /// session cookie or the remote address, in this order.
+    fn caller(request: &Request) -> String {
+        if let Some(values) = request.headers.get_raw("Authorization") {
+            if let Some(value) = values.first() {
+                return format!("auth:{}", sha256(value).to_hex());
+            }
+        }
+        let cookie_name = request.config_value("SESSION_COOKIE_NAME")
+                                 .and_then(|name| name.as_string().map(|name| name.to_string()))
+                                 .unwrap_or_else(|| String::from("session"));
+        if let Some(session) = request.cookie_map().get(&cookie_name) {
+            return format!("session:{}", sha256(session.as_bytes()).to_hex());
+        }
+        format!("addr:{}", request.remote_addr().ip())
+    }
+
+    /// The store key for the request, keys are scoped to the caller, the
+    /// method and the path so the same key can't replay a response of
+    /// another client or endpoint.
+    fn store_key(request: &Request) -> Option<String> {
+        match request.method() {
+            Method::Get | Method::Head | Method::Options | Method::Trace => return None,
+            _ => {},
+        }
+        let key = match request.headers.get_raw("Idempotency-Key") {
+            Some(values) if !values.is_empty() => String::from_utf8_lossy(&values[0]).into_owned(),
+            _ => return None,
+        };
+        if key.is_empty() {
+            return None;
+        }
+        Some(format!("{} {} {} {}", Idempotency::caller(request), request.method(), request.path(), key))
+    }
+
+    /// Get the stored response if this request is a retry.  A retry with
+    /// another body fails with `422 Unprocessable Entity`, a retry while
+    /// the first request still runs fails with `409 Conflict`.  If this
+    /// returns `Ok` the key is claimed until the claim is dropped or kept
+    /// after `remember`.  The claim expires after the
+    /// `IDEMPOTENCY_CLAIM_TIMEOUT` configuration key (seconds, defaults to
+    /// 60), so a lost claim doesn't block the key for the whole `ttl`.
+    pub fn replay(&self, request: &mut Request) -> Result<Option<Claim>, PencilResult> {
+        let key = match Idempotency::store_key(request) {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        let request_hash = sha256(request.get_data());
+        if let Some(stored) = self.store.get(&key) {
+            if !constant_time_eq(&stored.request_hash, &request_hash) {
+                let error = UnprocessableEntity.with_description("The idempotency key was used with another request body.");
+                return Err(Err(PenHTTPError(error)));
+            }
+            let mut response = stored.to_response();
+            response.headers.set_raw("Idempotent-Replayed", vec![b"true".to_vec()]);
+            return Err(Ok(response));
+        }
+        let claim_timeout = request.config_value("IDEMPOTENCY_CLAIM_TIMEOUT")
+                                   .and_then(|timeout| timeout.as_u64()).unwrap_or(60);
+        if !self.store.claim(&key, Duration::from_secs(claim_timeout)) {
+            let error = Conflict.with_description("A request with this idempotency key is still being processed.");
+            return Err(Err(PenHTTPError(error)));
+        }
+        Ok(Some(Claim { store: &*self.store, key: Some(key) }))
+    }
+
+    /// Store the response for the request if it carries an idempotency key,
+    /// this returns whether the response was stored.  Server errors are not
+    /// stored so that retries run the view again, the `Set-Cookie` headers
+    /// are never replayed.
+    pub fn remember(&self, request: &mut Request, response: &mut Response) -> bool {
+        let key = match Idempotency::store_key(request) {
+            Some(key) => key,
+            None => return false,
+        };
+        if response.status_code >= 500 {
+            return false;
+        }
+        let mut body = Vec::new();
+        if let Some(mut response_body) = response.body.take() {
+            if let Err(err) = response_body.write_body(&mut ResponseBody::new(&mut body)) {
+                error!("Error buffering response for idempotency key: {}", err);
+                return false;
+            }
+            response.body = Some(Box::new(body.clone()));
+        }
+        let mut headers = response.headers.clone();
+        headers.remove_raw("Set-Cookie");
+        let stored = StoredResponse {
+            status_code: response.status_code,
+            headers: headers,
+            body: body,
+            request_hash: sha256(request.get_data()),
+        };
+        self.store.set(&key, stored, self.ttl);
+        true
+    }
+}
@@ -1,10 +1,284 @@
 //! This module implements helpers for the JSON support in Pencil.
 
-use rustc_serialize::json;
-use rustc_serialize::Encodable;
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
+use rustc_serialize::json::{self, Json, EncoderError};
+use rustc_serialize::{Encodable, Encoder};
+use hyper::header::Headers;
+
+use app::Pencil;
 use wrappers::{Response};
-use types::{PencilResult, PenUserError, UserError};
+use types::{PencilResult, PenUserError, UserError, IntoPencilResponse};
+
+
+/// JSON encoder hook type, it gets every encoded value and returns a
+/// replacement for the values it handles, see `Pencil::json_encoder`.
+pub type JsonEncoderFunc = Arc<Fn(&Json) -> Option<Json> + Send + Sync>;
+
+
+/// Rewrites a value with the JSON encoder hooks.  The first hook that
+/// handles a value replaces it, the values nested in unhandled arrays and
+/// objects are rewritten too.
+#[doc(hidden)]
+pub fn apply_json_encoders(encoders: &[JsonEncoderFunc], value: Json) -> Json {
+    for encoder in encoders {
+        if let Some(replacement) = encoder(&value) {
+            return replacement;
+        }
+    }
+    match value {
+        Json::Array(array) => {
+            Json::Array(array.into_iter().map(|value| apply_json_encoders(encoders, value)).collect())
+        },
+        Json::Object(object) => {
+            Json::Object(object.into_iter().map(|(key, value)| (key, apply_json_encoders(encoders, value))).collect())
+        },
+        value => value,
+    }
+}
+
+
+/// An encoder that builds the `Json` value of an object, so the encoder
+/// hooks can be applied without encoding the object to a string and
+/// parsing it again.  Values are built like `json::encode` writes them.
+struct JsonValueEncoder {
+    value: Json,
+    key: Option<String>,
+}
+
+impl JsonValueEncoder {
+    fn new() -> JsonValueEncoder {
+        JsonValueEncoder { value: Json::Null, key: None }
+    }
+
+    /// Encode a nested value.
+    fn encode_child<F>(f: F) -> Result<Json, EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        let mut encoder = JsonValueEncoder::new();
+        try!(f(&mut encoder));
+        Ok(encoder.value)
+    }
+
+    /// Encode a container, the nested values are added to `value`.
+    fn encode_container<F>(&mut self, value: Json, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        let mut encoder = JsonValueEncoder { value: value, key: None };
+        try!(f(&mut encoder));
+        self.value = encoder.value;
+        Ok(())
+    }
+
+    fn push<F>(&mut self, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        let value = try!(JsonValueEncoder::encode_child(f));
+        if let Json::Array(ref mut array) = self.value {
+            array.push(value);
+        }
+        Ok(())
+    }
+
+    fn insert<F>(&mut self, key: String, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        let value = try!(JsonValueEncoder::encode_child(f));
+        if let Json::Object(ref mut object) = self.value {
+            object.insert(key, value);
+        }
+        Ok(())
+    }
+
+    fn emit_unsigned(&mut self, v: u64) -> Result<(), EncoderError> {
+        self.value = Json::U64(v);
+        Ok(())
+    }
+
+    /// Non-negative numbers are unsigned, like numbers parsed from a string.
+    fn emit_signed(&mut self, v: i64) -> Result<(), EncoderError> {
+        self.value = if v < 0 { Json::I64(v) } else { Json::U64(v as u64) };
+        Ok(())
+    }
+}
+
+impl Encoder for JsonValueEncoder {
+    type Error = EncoderError;
+
+    fn emit_nil(&mut self) -> Result<(), EncoderError> {
+        self.value = Json::Null;
+        Ok(())
+    }
+
+    fn emit_usize(&mut self, v: usize) -> Result<(), EncoderError> { self.emit_unsigned(v as u64) }
+    fn emit_u64(&mut self, v: u64) -> Result<(), EncoderError> { self.emit_unsigned(v) }
+    fn emit_u32(&mut self, v: u32) -> Result<(), EncoderError> { self.emit_unsigned(v as u64) }
+    fn emit_u16(&mut self, v: u16) -> Result<(), EncoderError> { self.emit_unsigned(v as u64) }
+    fn emit_u8(&mut self, v: u8) -> Result<(), EncoderError> { self.emit_unsigned(v as u64) }
+    fn emit_isize(&mut self, v: isize) -> Result<(), EncoderError> { self.emit_signed(v as i64) }
+    fn emit_i64(&mut self, v: i64) -> Result<(), EncoderError> { self.emit_signed(v) }
+    fn emit_i32(&mut self, v: i32) -> Result<(), EncoderError> { self.emit_signed(v as i64) }
+    fn emit_i16(&mut self, v: i16) -> Result<(), EncoderError> { self.emit_signed(v as i64) }
+    fn emit_i8(&mut self, v: i8) -> Result<(), EncoderError> { self.emit_signed(v as i64) }
+
+    fn emit_bool(&mut self, v: bool) -> Result<(), EncoderError> {
+        self.value = Json::Boolean(v);
+        Ok(())
+    }
+
+    fn emit_f64(&mut self, v: f64) -> Result<(), EncoderError> {
+        // NaN and infinities are written as `null`.
+        self.value = if v.is_finite() { Json::F64(v) } else { Json::Null };
+        Ok(())
+    }
+
+    fn emit_f32(&mut self, v: f32) -> Result<(), EncoderError> {
+        self.emit_f64(v as f64)
+    }
+
+    fn emit_char(&mut self, v: char) -> Result<(), EncoderError> {
+        self.value = Json::String(v.to_string());
+        Ok(())
+    }
+
+    fn emit_str(&mut self, v: &str) -> Result<(), EncoderError> {
+        self.value = Json::String(v.to_string());
+        Ok(())
+    }
+
+    fn emit_enum<F>(&mut self, _name: &str, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        f(self)
+    }
+
+    /// Variants without fields are strings, other variants are objects like
+    /// `{"variant": "Kangaroo", "fields": [34, "William"]}`.
+    fn emit_enum_variant<F>(&mut self, name: &str, _id: usize, cnt: usize, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        if cnt == 0 {
+            return self.emit_str(name);
+        }
+        try!(self.encode_container(Json::Array(Vec::with_capacity(cnt)), f));
+        let fields = ::std::mem::replace(&mut self.value, Json::Null);
+        let mut object = BTreeMap::new();
+        object.insert(String::from("variant"), Json::String(name.to_string()));
+        object.insert(String::from("fields"), fields);
+        self.value = Json::Object(object);
+        Ok(())
+    }
+
+    fn emit_enum_variant_arg<F>(&mut self, _idx: usize, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        self.push(f)
+    }
+
+    fn emit_enum_struct_variant<F>(&mut self, name: &str, id: usize, cnt: usize, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        self.emit_enum_variant(name, id, cnt, f)
+    }
+
+    fn emit_enum_struct_variant_field<F>(&mut self, _name: &str, idx: usize, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        self.emit_enum_variant_arg(idx, f)
+    }
+
+    fn emit_struct<F>(&mut self, _name: &str, _len: usize, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        self.encode_container(Json::Object(BTreeMap::new()), f)
+    }
+
+    fn emit_struct_field<F>(&mut self, name: &str, _idx: usize, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        self.insert(name.to_string(), f)
+    }
+
+    fn emit_tuple<F>(&mut self, len: usize, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        self.emit_seq(len, f)
+    }
+
+    fn emit_tuple_arg<F>(&mut self, idx: usize, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        self.emit_seq_elt(idx, f)
+    }
+
+    fn emit_tuple_struct<F>(&mut self, _name: &str, len: usize, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        self.emit_seq(len, f)
+    }
+
+    fn emit_tuple_struct_arg<F>(&mut self, idx: usize, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        self.emit_seq_elt(idx, f)
+    }
+
+    fn emit_option<F>(&mut self, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        f(self)
+    }
+
+    fn emit_option_none(&mut self) -> Result<(), EncoderError> {
+        self.emit_nil()
+    }
+
+    fn emit_option_some<F>(&mut self, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        f(self)
+    }
+
+    fn emit_seq<F>(&mut self, len: usize, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        self.encode_container(Json::Array(Vec::with_capacity(len)), f)
+    }
+
+    fn emit_seq_elt<F>(&mut self, _idx: usize, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        self.push(f)
+    }
+
+    fn emit_map<F>(&mut self, _len: usize, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        self.encode_container(Json::Object(BTreeMap::new()), f)
+    }
+
+    /// Keys must be strings or numbers, numbers are turned into strings.
+    fn emit_map_elt_key<F>(&mut self, _idx: usize, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        self.key = match try!(JsonValueEncoder::encode_child(f)) {
+            Json::String(key) => Some(key),
+            key @ Json::I64(_) | key @ Json::U64(_) | key @ Json::F64(_) => Some(key.to_string()),
+            _ => return Err(EncoderError::BadHashmapKey),
+        };
+        Ok(())
+    }
+
+    fn emit_map_elt_val<F>(&mut self, _idx: usize, f: F) -> Result<(), EncoderError>
+        where F: FnOnce(&mut JsonValueEncoder) -> Result<(), EncoderError>
+    {
+        match self.key.take() {
+            Some(key) => self.insert(key, f),
+            None => Err(EncoderError::BadHashmapKey),
+        }
+    }
+}
 
 
 /// Creates a view result with the JSON representation of the given object
@@ -29,16 +303,108 @@ use types::{PencilResult, PenUserError, UserError};
 ///     return jsonify(&user);
 /// }
 /// ```
+///
+/// This function doesn't know the application, so the JSON encoder hooks
+/// are not applied, use `Pencil::jsonify` or `JsonResponse::with_encoders`
+/// for that.
 pub fn jsonify<T: Encodable>(object: &T) -> PencilResult {
-    match json::encode(object) {
-        Ok(encoded) => {
-            let mut response = Response::from(encoded);
-            response.set_content_type("application/json");
-            Ok(response)
-        },
-        Err(err) => {
-            let error = UserError::new(format!("Json encoder error: {}", err));
-            Err(PenUserError(error))
-        },
+    JsonResponse::new(object).build()
+}
+
+
+/// Like `jsonify`, but with the given status code, like `201 Created`.
+pub fn jsonify_with_status<T: Encodable>(object: &T, status_code: u16) -> PencilResult {
+    JsonResponse::new(object).status(status_code).build()
+}
+
+
+/// A builder for JSON responses with a status code and extra headers:
+///
+/// ```ignore
+/// fn create_user(request: &mut Request) -> PencilResult {
+///     let user = try!(save_user(request));
+///     JsonResponse::new(&user)
+///         .status(201)
+///         .header("Location", &format!("/users/{}", user.id))
+///         .build()
+/// }
+/// ```
+pub struct JsonResponse {
+    encoded: Result<String, json::EncoderError>,
+    status_code: u16,
+    headers: Headers,
+}
+
+impl JsonResponse {
+    /// Create a `200 OK` response with the JSON representation of the
+    /// given object.
+    pub fn new<T: Encodable>(object: &T) -> JsonResponse {
+        JsonResponse {
+            encoded: json::encode(object),
+            status_code: 200,
+            headers: Headers::new(),
+        }
+    }
+
+    /// Like `new`, but the JSON encoder hooks of the application are
+    /// applied, see `Pencil::json_encoder`.
+    pub fn with_encoders<T: Encodable>(object: &T, app: &Pencil) -> JsonResponse {
+        if app.json_encoders().is_empty() {
+            return JsonResponse::new(object);
+        }
+        let mut encoder = JsonValueEncoder::new();
+        let encoded = object.encode(&mut encoder).map(|_| {
+            apply_json_encoders(app.json_encoders(), encoder.value).to_string()
+        });
+        JsonResponse {
+            encoded: encoded,
+            status_code: 200,
+            headers: Headers::new(),
+        }
+    }
+
+    /// Set the status code.
+    pub fn status(mut self, status_code: u16) -> JsonResponse {
+        self.status_code = status_code;
+        self
+    }
+
+    /// Set a header.
+    pub fn header(mut self, name: &str, value: &str) -> JsonResponse {
+        self.headers.set_raw(name.to_string(), vec![value.as_bytes().to_vec()]);
+        self
+    }
+
+    /// Create the response, this fails if the object could not be encoded.
+    pub fn build(self) -> PencilResult {
+        match self.encoded {
+            Ok(encoded) => {
+                let mut response = Response::from(encoded);
+                response.status_code = self.status_code;
+                response.headers.extend(self.headers.iter());
+                response.set_content_type("application/json");
+                Ok(response)
+            },
+            Err(err) => {
+                let error = UserError::new(format!("Json encoder error: {}", err));
+                Err(PenUserError(error))
+            },
+        }
+    }
+}
+
+
+/// A view return value that is sent as JSON, like with `jsonify`:
+///
+/// ```ignore
+/// fn get_user(_: &mut Request) -> (JsonBody<User>, u16) {
+///     (JsonBody(User { id: 1, name: String::from("admin") }), 200)
+/// }
+/// ```
+pub struct JsonBody<T: Encodable>(pub T);
+
+impl<T: Encodable> IntoPencilResponse for JsonBody<T> {
+    fn into_pencil_response(self) -> PencilResult {
+        jsonify(&self.0)
     }
 }
@@ -48,6 +48,8 @@
 
 #[macro_use]
 extern crate log;
+#[macro_use]
+extern crate lazy_static;
 extern crate hyper;
 extern crate rustc_serialize;
 extern crate regex;
@@ -57,9 +59,14 @@ extern crate handlebars;
 extern crate typemap;
 extern crate mime;
 extern crate mime_guess;
+extern crate time;
+extern crate rand;
+#[cfg(unix)]
+extern crate libc;
+extern crate openssl;
 
 /* public api */
-pub use app::Pencil;
+pub use app::{Pencil, PencilApp, RouteHooks};
 pub use types::{
     PencilError,
         PenHTTPError,
@@ -68,36 +75,59 @@ pub use types::{
     PencilResult,
     ViewArgs,
     ViewFunc,
+    IntoPencilResponse,
+    IntoViewFunc,
+    View,
     UserErrorHandler,
     HTTPErrorHandler,
     BeforeRequestFunc,
     AfterRequestFunc,
     TeardownRequestFunc,
+    MiddlewareFunc,
+    CommandFunc,
+    ConfigReloadFunc,
+    ConverterFunc,
 };
 pub use wrappers::{
     Request,
     Response,
+    RequestContext,
 };
 pub use http_errors::{
     HTTPError
 };
-pub use json::jsonify;
+pub use json::{jsonify, jsonify_with_status, JsonBody, JsonResponse};
 pub use config::{
     Config,
 };
 pub use helpers::{
     PathBound,
+    UrlFor,
     safe_join,
+    secure_filename,
     abort,
+    view_arg,
     redirect,
+    redirect_to,
+    redirect_back,
     escape,
     send_file,
     send_from_directory,
+    send_file_range,
+    send_file_auto,
+    send_file_with,
+    SendFileOptions,
+    send_from_directory_range,
+    send_directory_listing,
 };
 pub use module::Module;
+pub use serving::ServerOptions;
+pub use templating::SafeHtml;
 
 #[macro_use]
 mod utils;
+#[macro_use]
+mod macros;
 pub mod http_errors;
 pub mod datastructures;
 pub mod wrappers;
@@ -106,12 +136,29 @@ pub mod json;
 pub mod config;
 pub mod helpers;
 pub mod method;
-mod testing;
+pub mod httputils;
+pub mod webhooks;
+pub mod idempotency;
+pub mod feeds;
+pub mod sitemaps;
+pub mod freeze;
+pub mod signing;
+pub mod tls;
+pub mod pool;
+pub mod sessions;
+pub mod cookies;
+pub mod cors;
+pub mod signals;
+pub mod staticfiles;
+pub mod auth;
+pub mod testing;
 mod app;
 mod types;
 mod logging;
 mod serving;
-mod httputils;
 mod templating;
 mod formparser;
 mod module;
+mod cli;
+mod crypto;
+mod bufpool;
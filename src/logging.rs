@@ -1,19 +1,222 @@
 //! This module implements the logging support for Pencil.
 
 use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::{self, Log, LogLevelFilter, LogMetadata, LogRecord};
 use rustc_serialize::json::Json;
+use time;
 
 use app::Pencil;
 
 
-/// Set global log level based on the application's debug flag.
-/// This is only useful for `env_logger` crate.
+/// Set global log level based on the application's debug flag and
+/// environment.  This is only useful for `env_logger` crate.
 pub fn set_log_level(app: &Pencil) {
-    if let Some(value) = app.config.get("DEBUG") {
-        if let Json::Boolean(value) = *value {
-            if value {
-                env::set_var("RUST_LOG", "debug");
+    if app.is_debug() {
+        env::set_var("RUST_LOG", "debug");
+    } else if env::var("RUST_LOG").is_err() {
+        if app.env() == "production" {
+            env::set_var("RUST_LOG", "warn");
+        } else {
+            env::set_var("RUST_LOG", "info");
+        }
+    }
+}
+
+
+/// The target of the access log records, one record is logged at the
+/// `info` level for every handled request.  The file logger filters this
+/// target with `ACCESS_LOG_LEVEL` instead of `LOG_LEVEL`.
+pub const ACCESS_LOG_TARGET: &'static str = "pencil::access";
+
+
+/// The settings of the rotating file logger, read from the config:
+///
+/// - `LOG_FILE`, the path of the log file.  File logging is disabled
+///   without it.
+/// - `LOG_FILE_MAX_SIZE`, in bytes, defaults to 10 MiB.
+/// - `LOG_FILE_BACKUP_COUNT`, how many rotated files are kept as
+///   `<path>.1` to `<path>.<count>`, defaults to 5.
+/// - `LOG_LEVEL`, one of `"error"`, `"warn"`, `"info"`, `"debug"` and
+///   `"trace"`.  Defaults to `"debug"` in debug mode, to `"warn"` in the
+///   `production` environment and to `"info"` otherwise.
+/// - `ACCESS_LOG_LEVEL`, the level of the access log records, defaults to
+///   `"info"` so the access log is kept when `LOG_LEVEL` is raised.
+#[derive(Clone, Debug)]
+pub struct FileLoggerConfig {
+    pub path: PathBuf,
+    pub max_size: u64,
+    pub backup_count: usize,
+    pub level: LogLevelFilter,
+    pub access_level: LogLevelFilter,
+}
+
+impl FileLoggerConfig {
+    /// Read the settings, this returns `None` if `LOG_FILE` is not set.
+    pub fn from_app(app: &Pencil) -> Option<FileLoggerConfig> {
+        let config = &app.config;
+        let path = match config.get("LOG_FILE") {
+            Some(Json::String(path)) => PathBuf::from(path),
+            _ => return None,
+        };
+        let default_level = if app.is_debug() {
+            LogLevelFilter::Debug
+        } else if app.env() == "production" {
+            LogLevelFilter::Warn
+        } else {
+            LogLevelFilter::Info
+        };
+        let level = config.get("LOG_LEVEL").and_then(|level| level.as_string().and_then(|level| level.parse().ok())).unwrap_or(default_level);
+        let access_level = config.get("ACCESS_LOG_LEVEL").and_then(|level| level.as_string().and_then(|level| level.parse().ok())).unwrap_or(LogLevelFilter::Info);
+        Some(FileLoggerConfig {
+            path: path,
+            max_size: config.get("LOG_FILE_MAX_SIZE").and_then(|size| size.as_u64()).unwrap_or(10 * 1024 * 1024),
+            backup_count: config.get("LOG_FILE_BACKUP_COUNT").and_then(|count| count.as_u64()).unwrap_or(5) as usize,
+            level: level,
+            access_level: access_level,
+        })
+    }
+}
+
+
+/// The open log file and its current size.
+struct LogFile {
+    file: File,
+    size: u64,
+}
+
+
+/// A logger that writes access and error logs to a file.  The file is
+/// rotated once it would grow beyond the maximum size, the current file
+/// becomes `<path>.1`, `<path>.1` becomes `<path>.2` and so on, the oldest
+/// file is dropped.
+pub struct RotatingFileLogger {
+    config: FileLoggerConfig,
+    file: Mutex<LogFile>,
+}
+
+impl RotatingFileLogger {
+    pub fn new(config: FileLoggerConfig) -> io::Result<RotatingFileLogger> {
+        let file = try!(RotatingFileLogger::open(&config.path));
+        Ok(RotatingFileLogger {
+            config: config,
+            file: Mutex::new(file),
+        })
+    }
+
+    fn open(path: &Path) -> io::Result<LogFile> {
+        let file = try!(OpenOptions::new().create(true).append(true).open(path));
+        let size = try!(file.metadata()).len();
+        Ok(LogFile { file: file, size: size })
+    }
+
+    /// The path of a rotated file.
+    fn backup_path(&self, index: usize) -> PathBuf {
+        let mut path = self.config.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&self, log_file: &mut LogFile) -> io::Result<()> {
+        if self.config.backup_count == 0 {
+            try!(log_file.file.set_len(0));
+            log_file.size = 0;
+            return Ok(());
+        }
+        for index in (1..self.config.backup_count).rev() {
+            let backup = self.backup_path(index);
+            if backup.exists() {
+                try!(fs::rename(&backup, self.backup_path(index + 1)));
             }
         }
+        try!(fs::rename(&self.config.path, self.backup_path(1)));
+        *log_file = try!(RotatingFileLogger::open(&self.config.path));
+        Ok(())
+    }
+
+    /// Write one line, rotating the file first if it would grow too big.
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        let mut log_file = match self.file.lock() {
+            Ok(log_file) => log_file,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let len = line.len() as u64;
+        if log_file.size > 0 && log_file.size + len > self.config.max_size {
+            try!(self.rotate(&mut log_file));
+        }
+        try!(log_file.file.write_all(line.as_bytes()));
+        log_file.size += len;
+        Ok(())
+    }
+}
+
+impl Log for RotatingFileLogger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        if metadata.target() == ACCESS_LOG_TARGET {
+            metadata.level() <= self.config.access_level
+        } else {
+            metadata.level() <= self.config.level
+        }
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let timestamp = time::now_utc().rfc3339().to_string();
+        let line = format!("{} {} {}: {}\n", timestamp, record.level(), record.target(), record.args());
+        if let Err(err) = self.write_line(&line) {
+            let _ = writeln!(io::stderr(), "Could not write to the log file: {}", err);
+        }
+    }
+}
+
+
+/// Install the rotating file logger as the global logger if `LOG_FILE`
+/// is configured.  This fails if the file can't be opened or another
+/// logger, like `env_logger`, is already installed.
+pub fn init_file_logger(app: &Pencil) -> Result<(), String> {
+    let config = match FileLoggerConfig::from_app(app) {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+    let level = ::std::cmp::max(config.level, config.access_level);
+    let logger = try!(RotatingFileLogger::new(config).map_err(|e| format!("Could not open the log file: {}", e)));
+    log::set_logger(|max_log_level| {
+        max_log_level.set(level);
+        Box::new(logger)
+    }).map_err(|e| e.to_string())
+}
+
+
+#[test]
+fn test_rotating_file_logger() {
+    use std::io::Read;
+    let dir = env::temp_dir().join("pencil-test-logs");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let logger = RotatingFileLogger::new(FileLoggerConfig {
+        path: dir.join("app.log"),
+        max_size: 10,
+        backup_count: 2,
+        level: LogLevelFilter::Info,
+        access_level: LogLevelFilter::Info,
+    }).unwrap();
+    for line in &["first\n", "second\n", "third\n", "fourth\n"] {
+        logger.write_line(line).unwrap();
     }
+    let read = |name: &str| {
+        let mut content = String::new();
+        File::open(dir.join(name)).unwrap().read_to_string(&mut content).unwrap();
+        content
+    };
+    assert!(read("app.log") == "fourth\n");
+    assert!(read("app.log.1") == "third\n");
+    assert!(read("app.log.2") == "second\n");
+    assert!(!dir.join("app.log.3").exists());
+    fs::remove_dir_all(&dir).unwrap();
 }
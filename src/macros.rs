@@ -0,0 +1,86 @@
+//! This module implements the macros of the public api.
+
+
+/// Register many routes in one block, the endpoint of each route is the
+/// name of its view function:
+///
+/// ```rust,no_run
+/// #[macro_use]
+/// extern crate pencil;
+///
+/// use pencil::{Pencil, Request, PencilResult, Response};
+///
+///
+/// fn index(_: &mut Request) -> PencilResult {
+///     Ok(Response::from("index"))
+/// }
+///
+///
+/// fn user(_: &mut Request) -> PencilResult {
+///     Ok(Response::from("user"))
+/// }
+///
+///
+/// fn create_user(_: &mut Request) -> PencilResult {
+///     Ok(Response::from("created"))
+/// }
+///
+///
+/// fn main() {
+///     let mut app = Pencil::new("/web/demo");
+///     routes!(app, {
+///         GET "/" => index,
+///         GET "/user/<id:int>" => user,
+///         POST "/user" => create_user,
+///     });
+///     app.run("127.0.0.1:5000");
+/// }
+/// ```
+#[macro_export]
+macro_rules! routes {
+    ($app:expr, { $($method:ident $rule:expr => $view:ident),* $(,)* }) => {{
+        $(
+            $app.route($rule,
+                       &[stringify!($method).parse::<$crate::method::Method>().unwrap()],
+                       stringify!($view),
+                       $view);
+        )*
+    }};
+}
+
+
+/// Get view arguments parsed as the given types, this evaluates to a
+/// `Result` with the value, or a tuple of values for several arguments.
+/// Missing arguments give `404 Not Found` and arguments that can't be
+/// parsed give `400 Bad Request`, so the error can be returned with `try!`:
+///
+/// ```rust,no_run
+/// #[macro_use]
+/// extern crate pencil;
+///
+/// use pencil::{Pencil, Request, PencilResult, Response};
+///
+///
+/// fn post(request: &mut Request) -> PencilResult {
+///     let (user_id, slug) = try!(view_args!(request => user_id: i64, slug: String));
+///     Ok(Response::from(format!("Post {} by {}", slug, user_id)))
+/// }
+///
+///
+/// fn main() {
+///     let mut app = Pencil::new("/web/demo");
+///     app.get("/<user_id:int>/<slug:string>", "post", post);
+///     app.run("127.0.0.1:5000");
+/// }
+/// ```
+#[macro_export]
+macro_rules! view_args {
+    ($request:expr => $name:ident : $ty:ty) => {
+        $crate::helpers::view_arg::<$ty>(&$request, stringify!($name))
+    };
+    ($request:expr => $($name:ident : $ty:ty),+) => {
+        (|| -> Result<_, $crate::PencilError> {
+            Ok(($(try!($crate::helpers::view_arg::<$ty>(&$request, stringify!($name)))),+))
+        })()
+    };
+}
@@ -13,3 +13,11 @@ pub use hyper::method::Method::{
     Patch,
     Extension,
 };
+
+
+/// Get the method with the given name, like `PROPFIND` or `MKCOL` from
+/// WebDAV.  Standard names give the standard methods, so
+/// `extension("GET")` is `Get`.  Method names are case-sensitive.
+pub fn extension(name: &str) -> Method {
+    name.parse().unwrap_or_else(|_| Extension(name.to_string()))
+}
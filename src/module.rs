@@ -1,24 +1,32 @@
 //! Modules are the recommended way to implement larger or more
 //! pluggable applications.
 
+use std::any::TypeId;
 use std::collections::HashMap;
+use std::error::Error;
 use std::mem;
+use std::ops::Range;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use hyper::method::Method;
 
 use http_errors::NotFound;
 use app::Pencil;
-use routing::Matcher;
-use types::ViewFunc;
-use types::PencilResult;
+use config::Config;
+use cors::Cors;
+use routing::{Matcher, Rule};
+use types::{ViewFunc, IntoViewFunc};
+use types::{PencilError, PencilResult, UserError};
 use types::{BeforeRequestFunc, AfterRequestFunc, TeardownRequestFunc};
-use types::{HTTPErrorHandler, UserErrorHandler};
-use helpers::send_from_directory;
-use wrappers::Request;
+use types::{HTTPErrorHandler, UserErrorHandler, user_error_handler, user_error_desc_handler};
+use helpers::{send_from_directory_range, add_static_cache_headers};
+use wrappers::{Request, Response};
 
 
-/// Represents a module.
+/// Represents a module.  A module definition can be registered several
+/// times under different names and url prefixes with `register_as`.
+#[derive(Clone)]
 pub struct Module {
     /// The name of the module.
     pub name: String,
@@ -26,10 +34,20 @@ pub struct Module {
     pub root_path: String,
     /// The folder with static files that should be served at `static_url_path`.
     pub static_folder: Option<String>,
-    /// The url path for the static files on the web.
+    /// The url path for the static files on the web, defaults to the name of
+    /// the static folder below the module's url prefix.  A module without a
+    /// url prefix only serves static files if this is set.
     pub static_url_path: Option<String>,
     /// The folder that contains the templates that should be used for the module.
     pub template_folder: Option<String>,
+    /// The url path that is prepended to all routes of the module.
+    pub url_prefix: Option<String>,
+    /// Configuration values that override the application's configuration
+    /// for requests handled by this module, see `Request::config_value`.
+    pub config: Config,
+    /// The CORS configuration for requests handled by this module, see
+    /// `enable_cors`.
+    pub cors: Option<Cors>,
     #[doc(hidden)]
     pub before_request_funcs: Vec<BeforeRequestFunc>,
     #[doc(hidden)]
@@ -39,9 +57,15 @@ pub struct Module {
     #[doc(hidden)]
     pub http_error_handlers: HashMap<u16, HTTPErrorHandler>,
     #[doc(hidden)]
-    pub user_error_handlers: HashMap<String, UserErrorHandler>,
-    deferred_functions: Vec<Box<Fn(&mut Pencil) + Send + Sync>>,
+    pub http_error_range_handlers: Vec<(Range<u16>, HTTPErrorHandler)>,
+    #[doc(hidden)]
+    pub user_error_handlers: HashMap<TypeId, UserErrorHandler>,
+    #[doc(hidden)]
+    pub user_error_desc_handlers: HashMap<String, UserErrorHandler>,
+    deferred_functions: Vec<Arc<Fn(&mut Pencil) + Send + Sync>>,
     deferred_routes: Vec<(Matcher, Vec<Method>, String, ViewFunc)>,
+    children: Vec<Module>,
+    definition: Arc<()>,
 }
 
 impl Module {
@@ -52,65 +76,112 @@ impl Module {
             static_folder: None,
             static_url_path: None,
             template_folder: None,
+            url_prefix: None,
+            config: Config::new(),
+            cors: None,
             before_request_funcs: Vec::new(),
             after_request_funcs: Vec::new(),
             teardown_request_funcs: Vec::new(),
             http_error_handlers: HashMap::new(),
+            http_error_range_handlers: Vec::new(),
             user_error_handlers: HashMap::new(),
+            user_error_desc_handlers: HashMap::new(),
             deferred_functions: Vec::new(),
             deferred_routes: Vec::new(),
+            children: Vec::new(),
+            definition: Arc::new(()),
         }
     }
 
+    /// Prepend a url path to all routes of the module:
+    ///
+    /// ```rust,no_run
+    /// use pencil::Module;
+    ///
+    /// let admin = Module::new("admin", "/web/demo/admin").url_prefix("/admin");
+    /// ```
+    pub fn url_prefix(mut self, url_prefix: &str) -> Module {
+        self.url_prefix = Some(url_prefix.trim_right_matches('/').to_string());
+        self
+    }
+
+    /// Enables CORS for the requests handled by this module, this replaces
+    /// the configuration of the application:
+    ///
+    /// ```rust,no_run
+    /// use pencil::Module;
+    /// use pencil::cors::Cors;
+    ///
+    /// let mut api = Module::new("api", "/web/demo/api");
+    /// api.enable_cors(Cors::new().allow_origin("https://example.com"));
+    /// ```
+    pub fn enable_cors(&mut self, cors: Cors) {
+        self.cors = Some(cors);
+    }
+
     fn record<F: Fn(&mut Pencil) + Send + Sync + 'static>(&mut self, f: F) {
-        self.deferred_functions.push(Box::new(f));
+        self.deferred_functions.push(Arc::new(f));
     }
 
     /// The endpoint is automatically prefixed with the module's name.
-    pub fn route<M: Into<Matcher>, N: AsRef<[Method]>>(&mut self, rule: M, methods: N, endpoint: &str, view_func: ViewFunc) {
+    pub fn route<M, N, V>(&mut self, rule: M, methods: N, endpoint: &str, view_func: V)
+        where M: Into<Matcher>, N: AsRef<[Method]>, V: IntoViewFunc {
         let mut methods_vec: Vec<Method> = Vec::new();
         methods_vec.extend(methods.as_ref().iter().cloned());
         if endpoint.contains('.') {
             panic!("Module endpoint should not contain dot");
         }
-        let endpoint = format!("{}.{}", self.name, endpoint);
-        self.deferred_routes.push((rule.into(), methods_vec, endpoint, view_func));
+        self.deferred_routes.push((rule.into(), methods_vec, endpoint.to_string(), view_func.into_view_func()));
     }
 
     /// Before request for a module.  This is only executed before each request
     /// that is handled by a view function of that module.
-    pub fn before_request(&mut self, f: BeforeRequestFunc) {
-        self.before_request_funcs.push(f);
+    pub fn before_request<F>(&mut self, f: F)
+        where F: Fn(&mut Request) -> Option<PencilResult> + Send + Sync + 'static {
+        self.before_request_funcs.push(Arc::new(f));
     }
 
     /// Before request for the app that this module is registered on.  This is
     /// executed before each request, even if outside of a module.
-    pub fn before_app_request(&mut self, f: BeforeRequestFunc) {
-        self.record(move |app| app.before_request(f));
+    pub fn before_app_request<F>(&mut self, f: F)
+        where F: Fn(&mut Request) -> Option<PencilResult> + Send + Sync + 'static {
+        let f: BeforeRequestFunc = Arc::new(f);
+        self.record(move |app| {
+            let f = f.clone();
+            app.before_request(move |request| f(request));
+        });
     }
 
     /// After request for a module.  This is only executed after each request
     /// that is handled by a view function of that module.
-    pub fn after_request(&mut self, f: AfterRequestFunc) {
-        self.after_request_funcs.push(f);
+    pub fn after_request<F>(&mut self, f: F) where F: Fn(&mut Response) + Send + Sync + 'static {
+        self.after_request_funcs.push(Arc::new(f));
     }
 
     /// After request for the app that this module is registered on.  This is
     /// executed after each request, even if outside of a module.
-    pub fn after_app_request(&mut self, f: AfterRequestFunc) {
-        self.record(move |app| app.after_request(f));
+    pub fn after_app_request<F>(&mut self, f: F) where F: Fn(&mut Response) + Send + Sync + 'static {
+        let f: AfterRequestFunc = Arc::new(f);
+        self.record(move |app| {
+            let f = f.clone();
+            app.after_request(move |response| f(response));
+        });
     }
  
     /// Teardown request for a module.  This is only executed when tearing down
     /// each request that is handled by a view function of that module.
-    pub fn teardown_request(&mut self, f: TeardownRequestFunc) {
-        self.teardown_request_funcs.push(f);
+    pub fn teardown_request<F>(&mut self, f: F) where F: Fn(Option<&PencilError>) + Send + Sync + 'static {
+        self.teardown_request_funcs.push(Arc::new(f));
     }
 
     /// Teardown request for the app that this module is registered on.  This is
     /// executed when tearing down each request, even if outside of a module.
-    pub fn teardown_app_request(&mut self, f: TeardownRequestFunc) {
-        self.record(move |app| app.teardown_request(f));
+    pub fn teardown_app_request<F>(&mut self, f: F) where F: Fn(Option<&PencilError>) + Send + Sync + 'static {
+        let f: TeardownRequestFunc = Arc::new(f);
+        self.record(move |app| {
+            let f = f.clone();
+            app.teardown_request(move |e| f(e));
+        });
     }
 
     /// Registers a http error handler that becomes active for this module only.
@@ -118,9 +189,24 @@ impl Module {
         self.http_error_handlers.insert(status_code, f);
     }
 
-    /// Registers an user error handler that becomes active for this module only.
-    pub fn usererrorhandler(&mut self, error_desc: &str, f: UserErrorHandler) {
-        self.user_error_handlers.insert(error_desc.to_string(), f);
+    /// Registers a http error handler for a range of status codes that
+    /// becomes active for this module only.
+    pub fn httperrorhandler_range(&mut self, status_codes: Range<u16>, f: HTTPErrorHandler) {
+        self.http_error_range_handlers.push((status_codes, f));
+    }
+
+    /// Registers an user error handler for errors of type `E` that becomes
+    /// active for this module only, see `Pencil::register_error_handler`.
+    pub fn register_error_handler<E, F>(&mut self, f: F)
+        where E: Error + 'static, F: Fn(&E) -> PencilResult + Send + Sync + 'static {
+        self.user_error_handlers.insert(TypeId::of::<E>(), user_error_handler(f));
+    }
+
+    /// Registers an user error handler for user errors with the given
+    /// description that becomes active for this module only.
+    #[deprecated(note = "use `register_error_handler` instead")]
+    pub fn usererrorhandler(&mut self, error_desc: &str, f: fn(UserError) -> PencilResult) {
+        self.user_error_desc_handlers.insert(error_desc.to_string(), user_error_desc_handler(f));
     }
 
     /// Registers a http error handler for all requests of the application.
@@ -128,23 +214,79 @@ impl Module {
         self.record(move |app| app.httperrorhandler(status_code, f));
     }
 
-    /// Registers an user error handler for all requests of the application.
-    pub fn app_usererrorhandler(&mut self, error_desc: &str, f: UserErrorHandler) {
+    /// Registers an user error handler for errors of type `E` for all
+    /// requests of the application.
+    pub fn register_app_error_handler<E, F>(&mut self, f: F)
+        where E: Error + 'static, F: Fn(&E) -> PencilResult + Send + Sync + 'static {
+        let handler = user_error_handler(f);
+        self.record(move |app| {
+            app.add_user_error_handler(TypeId::of::<E>(), handler.clone());
+        });
+    }
+
+    /// Registers an user error handler for user errors with the given
+    /// description for all requests of the application.
+    #[deprecated(note = "use `register_app_error_handler` instead")]
+    #[allow(deprecated)]
+    pub fn app_usererrorhandler(&mut self, error_desc: &str, f: fn(UserError) -> PencilResult) {
         let desc = error_desc.to_string();
-        self.record(move |app| app.register_user_error_handler(&desc, f));
+        self.record(move |app| app.usererrorhandler(&desc, f));
+    }
+
+    /// Register a child module, it is registered with this module.  The
+    /// child is named like `parent.child`, so its endpoints look like
+    /// `parent.child.index`, and its routes are mounted below the url
+    /// prefix of this module.  The hooks and error handlers of this module
+    /// apply to the child too, after the child's own.
+    pub fn register_module(&mut self, child: Module) {
+        if child.name.contains('.') {
+            panic!("Module name should not contain dot");
+        }
+        self.children.push(child);
     }
 
     /// Register this module.
-    pub fn register(mut self, app: &mut Pencil) {
+    pub fn register(self, app: &mut Pencil) {
+        self.register_under(app, "");
+    }
+
+    /// Register a copy of this module under another name with all of its
+    /// routes mounted under a url prefix, so the same module can be served
+    /// several times.  The module's own `url_prefix` comes after it:
+    ///
+    /// ```rust,ignore
+    /// blog.register_as(&mut app, "blog_en", "/en");
+    /// blog.register_as(&mut app, "blog_de", "/de");
+    /// ```
+    ///
+    /// The endpoints are prefixed with the given name, like `blog_de.index`.
+    /// Functions registered for the whole application, like
+    /// `before_app_request`, only run on the first registration.
+    pub fn register_as(&self, app: &mut Pencil, name: &str, url_prefix: &str) {
+        let mut module = self.clone();
+        module.name = name.to_string();
+        module.register_under(app, url_prefix);
+    }
+
+    fn register_under(mut self, app: &mut Pencil, url_prefix: &str) {
         if app.modules.contains_key(&self.name) {
             panic!("A module that is named {} already exists, name collision occurred.", self.name);
         }
+        let first_registration = !app.modules.values().any(|module| Arc::ptr_eq(&module.definition, &self.definition));
 
+        let url_prefix = format!("{}{}", url_prefix.trim_right_matches('/'),
+                                 self.url_prefix.as_ref().map_or("", |prefix| &prefix[..]));
+        // The default static route is only added under a prefix, without
+        // one it would shadow the static files of the application.
         let static_url_path = match self.static_folder {
-            Some(_) => {
+            Some(ref static_folder) => {
                 match self.static_url_path {
                     Some(ref static_url_path) => Some(static_url_path.clone()),
-                    None => None,
+                    None if url_prefix.is_empty() => None,
+                    None => {
+                        let folder_name = static_folder.trim_right_matches('/').rsplit('/').next().unwrap_or("static");
+                        Some(format!("/{}", folder_name))
+                    },
                 }
             },
             None => None
@@ -156,11 +298,19 @@ impl Module {
         }
         let deferred_routes = mem::replace(&mut self.deferred_routes, Vec::new());
         for (matcher, methods, endpoint, view_func) in deferred_routes {
-            app.add_url_rule(matcher, methods.as_ref(), &endpoint, view_func);
+            let matcher = if url_prefix.is_empty() { matcher } else { matcher.prefixed(&url_prefix) };
+            let endpoint = format!("{}.{}", self.name, endpoint);
+            app.add_view_func(Rule::new(matcher, methods.as_ref(), &endpoint), view_func);
+        }
+        if first_registration {
+            for deferred in &self.deferred_functions {
+                deferred(app);
+            }
         }
-        let deferred_functions = mem::replace(&mut self.deferred_functions, Vec::new());
-        for deferred in deferred_functions {
-            deferred(app);
+        self.deferred_functions.clear();
+        for mut child in mem::replace(&mut self.children, Vec::new()) {
+            child.name = format!("{}.{}", self.name, child.name);
+            child.register_under(app, &url_prefix);
         }
 
         app.modules.insert(self.name.clone(), self);
@@ -177,7 +327,9 @@ fn send_module_static_file(request: &mut Request) -> PencilResult {
                 static_path.push(module_static_folder);
                 let static_path_str = static_path.to_str().unwrap();
                 let filename = request.view_args.get("filename").unwrap();
-                return send_from_directory(static_path_str, filename, false);
+                let mut response = try!(send_from_directory_range(request, static_path_str, filename, false));
+                add_static_cache_headers(request, filename, &mut response);
+                return Ok(response);
             }
         }
     }
@@ -0,0 +1,186 @@
+//! This module implements the integration point for database connection
+//! pools.  A pool is configured from the application config and stored as
+//! managed state of the application, every request checks out at most one
+//! connection lazily and returns it when the request is torn down.
+//!
+//! An implementation for `r2d2` with `r2d2_postgres` looks like this:
+//!
+//! ```rust,ignore
+//! extern crate r2d2;
+//! extern crate r2d2_postgres;
+//!
+//! use pencil::{Config, Pencil, Request, PencilResult, Response};
+//! use pencil::pool::{Pool, connection};
+//! use r2d2_postgres::{PostgresConnectionManager, TlsMode};
+//!
+//!
+//! struct Postgres(r2d2::Pool<PostgresConnectionManager>);
+//!
+//! impl Pool for Postgres {
+//!     type Connection = r2d2::PooledConnection<PostgresConnectionManager>;
+//!
+//!     fn from_config(config: &Config) -> Result<Postgres, String> {
+//!         let url = config.get("DATABASE_URL").and_then(|url| url.as_string().map(|url| url.to_string()))
+//!                         .unwrap_or_else(|| String::from("postgres://localhost"));
+//!         let manager = try!(PostgresConnectionManager::new(&url[..], TlsMode::None).map_err(|e| e.to_string()));
+//!         r2d2::Pool::new(r2d2::Config::default(), manager).map(Postgres).map_err(|e| e.to_string())
+//!     }
+//!
+//!     fn get_connection(&self) -> Result<Self::Connection, String> {
+//!         self.0.get().map_err(|e| e.to_string())
+//!     }
+//! }
+//!
+//!
+//! fn users(request: &mut Request) -> PencilResult {
+//!     let conn = try!(connection::<Postgres>(request));
+//!     let rows = conn.query("SELECT name FROM users", &[]).unwrap();
+//!     Ok(Response::from(format!("{} users", rows.len())))
+//! }
+//!
+//!
+//! fn main() {
+//!     let mut app = Pencil::new("/web/demo");
+//!     app.enable_pool::<Postgres>();
+//!     app.get("/users", "users", users);
+//!     app.run("127.0.0.1:5000");
+//! }
+//! ```
+
+use std::marker::PhantomData;
+
+use typemap::Key;
+
+use config::Config;
+use http_errors::InternalServerError;
+use types::{PencilError, PenHTTPError};
+use wrappers::Request;
+
+
+/// A connection pool, like an `r2d2::Pool`.
+pub trait Pool: Sized + Send + Sync + 'static {
+    /// The connection checked out of the pool.
+    type Connection: 'static;
+
+    /// Create the pool from the application config.
+    fn from_config(config: &Config) -> Result<Self, String>;
+
+    /// Check out a connection.
+    fn get_connection(&self) -> Result<Self::Connection, String>;
+
+    /// Return a connection to the pool after the request.  By default the
+    /// connection is dropped, which returns pooled connections like
+    /// r2d2's to their pool.
+    fn release_connection(&self, connection: Self::Connection) {
+        drop(connection);
+    }
+}
+
+
+/// The key of the pool in the managed state of the application.
+pub struct PoolKey<P: Pool>(PhantomData<P>);
+
+impl<P: Pool> Key for PoolKey<P> {
+    type Value = P;
+}
+
+
+/// The key of the checked out connection in `request.extensions_data`.
+pub struct ConnectionKey<P: Pool>(PhantomData<P>);
+
+impl<P: Pool> Key for ConnectionKey<P> {
+    type Value = P::Connection;
+}
+
+
+/// Get the connection of the request, the first call checks out a
+/// connection from the pool of the application.  This fails with
+/// `500 Internal Server Error` if the pool is not enabled or no
+/// connection can be checked out.
+pub fn connection<'r, 'a, 'b, 'c, P: Pool>(request: &'c mut Request<'r, 'a, 'b>)
+                                           -> Result<&'c mut P::Connection, PencilError> {
+    if !request.extensions_data.contains::<ConnectionKey<P>>() {
+        let pool = match request.app.state.get::<PoolKey<P>>() {
+            Some(pool) => pool,
+            None => {
+                error!("No pool enabled for the connection");
+                return Err(PenHTTPError(InternalServerError));
+            },
+        };
+        match pool.get_connection() {
+            Ok(connection) => {
+                request.extensions_data.insert::<ConnectionKey<P>>(connection);
+            },
+            Err(err) => {
+                error!("Could not check out a connection: {}", err);
+                return Err(PenHTTPError(InternalServerError));
+            },
+        }
+    }
+    Ok(request.extensions_data.get_mut::<ConnectionKey<P>>().unwrap())
+}
+
+
+/// Return the connection of the request to the pool, this is registered
+/// as release function when the pool is enabled.
+pub fn release_connection<P: Pool>(request: &mut Request) {
+    if let Some(connection) = request.extensions_data.remove::<ConnectionKey<P>>() {
+        if let Some(pool) = request.app.state.get::<PoolKey<P>>() {
+            pool.release_connection(connection);
+        }
+    }
+}
+
+
+#[cfg(test)]
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+
+#[cfg(test)]
+#[allow(deprecated)]
+static CHECKED_OUT: AtomicUsize = atomic::ATOMIC_USIZE_INIT;
+#[cfg(test)]
+#[allow(deprecated)]
+static RELEASED: AtomicUsize = atomic::ATOMIC_USIZE_INIT;
+
+#[cfg(test)]
+struct CountingPool;
+
+#[cfg(test)]
+impl Pool for CountingPool {
+    type Connection = usize;
+
+    fn from_config(_: &Config) -> Result<CountingPool, String> {
+        Ok(CountingPool)
+    }
+
+    fn get_connection(&self) -> Result<usize, String> {
+        Ok(CHECKED_OUT.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    fn release_connection(&self, _: usize) {
+        RELEASED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+
+#[test]
+fn test_pool_connection_per_request() {
+    use app::Pencil;
+    use hyper::method::Method;
+    use testing::PencilClient;
+    use types::PencilResult;
+    use wrappers::Response;
+    fn view(request: &mut Request) -> PencilResult {
+        let first = *try!(connection::<CountingPool>(request));
+        let second = *try!(connection::<CountingPool>(request));
+        assert!(first == second);
+        Ok(Response::from("ok"))
+    }
+    let mut app = Pencil::new("/test");
+    app.enable_pool::<CountingPool>();
+    app.get("/", "index", view);
+    let response = PencilClient::new(&app).request(Method::Get, "/").unwrap();
+    assert!(response.status_code == 200);
+    assert!(CHECKED_OUT.load(Ordering::SeqCst) == 1);
+    assert!(RELEASED.load(Ordering::SeqCst) == 1);
+}
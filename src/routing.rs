@@ -1,20 +1,81 @@
 //! This module implements the dispatcher.
 
+use std::cmp;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::slice;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use regex::Regex;
 use regex::quote as regex_quote;
 
 use hyper::method::Method;
+use url::form_urlencoded;
+use url::percent_encoding::{percent_decode, utf8_percent_encode, DEFAULT_ENCODE_SET, PATH_SEGMENT_ENCODE_SET};
 
-use http_errors::{HTTPError, MethodNotAllowed, NotFound};
-use types::ViewArgs;
+use http_errors::{HTTPError, BadRequest, MethodNotAllowed, NotFound};
+use method;
+use types::{ViewArgs, ConverterFunc};
 use utils::join_string;
 
+/// The error type for invalid URL rules.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuleError {
+    /// The rule doesn't start with a slash.
+    MissingLeadingSlash(String),
+    /// The rule has unbalanced `<` or `>` or a variable without a name.
+    Malformed(String),
+    /// A variable name is used twice in the rule.
+    DuplicateVariable(String),
+    /// The rule uses a converter that doesn't exist.
+    UnknownConverter(String),
+    /// The endpoint already has a view function.
+    EndpointCollision(String),
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RuleError::MissingLeadingSlash(ref rule) => write!(f, "urls must start with a leading slash: {}", rule),
+            RuleError::Malformed(ref rule) => write!(f, "malformed url rule: {}", rule),
+            RuleError::DuplicateVariable(ref variable) => write!(f, "variable name {} used twice.", variable),
+            RuleError::UnknownConverter(ref converter) => write!(f, "the converter {} does not exist", converter),
+            RuleError::EndpointCollision(ref endpoint) => write!(f, "the endpoint {} already has a view function", endpoint),
+        }
+    }
+}
+
+impl error::Error for RuleError {
+    fn description(&self) -> &str {
+        match *self {
+            RuleError::MissingLeadingSlash(_) => "url rule without leading slash",
+            RuleError::Malformed(_) => "malformed url rule",
+            RuleError::DuplicateVariable(_) => "duplicate variable name",
+            RuleError::UnknownConverter(_) => "unknown converter",
+            RuleError::EndpointCollision(_) => "endpoint collision",
+        }
+    }
+}
+
+
 /// Parse a rule and return a list of tuples in the form
 /// `(Option<converter>, variable)`.  If the converter
 /// is `None`, it's a static url part.
+///
+/// # Panics
+///
+/// Panics if the rule is malformed, see `try_parse_rule`.
 fn parse_rule(rule: &str) -> Vec<(Option<&str>, &str)> {
+    try_parse_rule(rule).unwrap_or_else(|err| panic!("{}", err))
+}
+
+
+/// Parse a rule like `parse_rule`, malformed rules are an error.
+fn try_parse_rule(rule: &str) -> Result<Vec<(Option<&str>, &str)>, RuleError> {
     let rule_re = Regex::new(r"(?x)
         (?P<static>[^<]*)                            # static rule data
         <
@@ -35,13 +96,16 @@ fn parse_rule(rule: &str) -> Vec<(Option<&str>, &str)> {
                 if static_part.is_some() {
                     rule_parts.push((None, static_part.unwrap()));
                 }
-                let variable = caps.name("variable").unwrap();
+                let variable = match caps.name("variable") {
+                    Some(variable) => variable,
+                    None => return Err(RuleError::Malformed(rule.to_string())),
+                };
                 let converter = match caps.name("converter") {
                     Some(converter) => { converter },
                     None => { "default" },
                 };
                 if used_names.contains(variable) {
-                    panic!("variable name {} used twice.", variable);
+                    return Err(RuleError::DuplicateVariable(variable.to_string()));
                 }
                 used_names.insert(variable);
                 rule_parts.push((Some(converter), variable));
@@ -56,61 +120,102 @@ fn parse_rule(rule: &str) -> Vec<(Option<&str>, &str)> {
     }
     if !remaining.is_empty() {
         if remaining.contains('>') || remaining.contains('<') {
-            panic!("malformed url rule: {}", rule);
+            return Err(RuleError::Malformed(rule.to_string()));
         }
         rule_parts.push((None, remaining));
     }
-    rule_parts
+    Ok(rule_parts)
+}
+
+/// The regular expressions of the built-in converters.
+fn builtin_converter(converter: &str) -> Option<&'static str> {
+    match converter {
+        "string" | "default" => Some("[^/]{1,}"),
+        "int" => Some(r"\d+"),
+        "float" => Some(r"\d+\.\d+"),
+        "path" => Some("[^/].*?"),
+        "slug" => Some("[a-z0-9]+(?:-[a-z0-9]+)*"),
+        "uuid" => Some("[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}"),
+        _ => None,
+    }
+}
+
+
+/// A custom converter, see `Map::add_converter`.
+#[derive(Clone)]
+pub struct Converter {
+    /// The regular expression a value has to match, it should not contain
+    /// capturing groups.
+    pub regex: String,
+    /// Parses the matched value.
+    pub parse: ConverterFunc,
 }
 
+
 /// The matcher holds the url regex object.
 #[derive(Clone)]
 pub struct Matcher {
-    pub regex: Regex
+    pub regex: Regex,
+    /// The rule string the regex was compiled from, this is `None` for
+    /// matchers created from a regular expression directly.
+    pub rule: Option<String>,
+    /// The parse functions of the custom converters by variable.
+    parsers: Vec<(String, ConverterFunc)>,
+    /// The variables of the `path` converter, only they can contain a
+    /// percent-encoded slash.
+    path_variables: Vec<String>,
+    /// Whether the rule uses converters that are not built in, they are
+    /// resolved when the rule is added to a map.
+    unresolved: bool,
 }
 
 impl Matcher {
     pub fn new(regex: Regex) -> Matcher {
         Matcher {
-            regex: regex
+            regex: regex,
+            rule: None,
+            parsers: Vec::new(),
+            path_variables: Vec::new(),
+            unresolved: false,
         }
     }
-}
 
-/// Rule strings basically are just normal URL paths with placeholders in
-/// the format `<name:converter>` where the converter are optional.
-/// Currently we support following converters:
-///
-/// - string(default)
-/// - int
-/// - float
-/// - path
-///
-/// If no converter is defined the `default` converter is used which means `string`.
-///
-/// URL rules that end with a slash are branch URLs, others are leaves.
-/// All branch URLs that are matched without a trailing slash will trigger a
-/// redirect to the same URL with the missing slash appended.
-/// We have a url without a trailing slash for branch url rule.
-/// So we redirect to the same url but with a trailing slash.
-impl<'a> From<&'a str> for Matcher {
-    fn from(rule: &'a str) -> Matcher {
+    /// Compile a rule string.  Converters that are neither built in nor in
+    /// `converters` match like `string` and mark the matcher unresolved,
+    /// unless `strict` is set, then they panic.
+    fn compile(rule: &str, converters: &HashMap<String, Converter>, strict: bool) -> Matcher {
+        Matcher::try_compile(rule, converters, strict).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Compile a rule string like `compile`, invalid rules are an error.
+    fn try_compile(rule: &str, converters: &HashMap<String, Converter>, strict: bool) -> Result<Matcher, RuleError> {
         if !rule.starts_with('/') {
-            panic!("urls must start with a leading slash");
+            return Err(RuleError::MissingLeadingSlash(rule.to_string()));
         }
         let is_branch = rule.ends_with('/');
 
         // Compiles the regular expression
         let mut regex_parts: Vec<String> = Vec::new();
-        for (converter, variable) in parse_rule(rule.trim_right_matches('/')) {
+        let mut parsers = Vec::new();
+        let mut path_variables = Vec::new();
+        let mut unresolved = false;
+        for (converter, variable) in try!(try_parse_rule(rule.trim_right_matches('/'))) {
             match converter {
                 Some(converter) => {
-                    let re = match converter {
-                        "string" | "default" => "[^/]{1,}",
-                        "int" => r"\d+",
-                        "float" => r"\d+\.\d+",
-                        "path" => "[^/].*?",
-                        _ => { panic!("the converter {} does not exist", converter); }
+                    if converter == "path" {
+                        path_variables.push(variable.to_string());
+                    }
+                    let re = match (builtin_converter(converter), converters.get(converter)) {
+                        (Some(re), _) => re,
+                        (None, Some(custom)) => {
+                            parsers.push((variable.to_string(), custom.parse));
+                            &custom.regex
+                        },
+                        (None, None) if strict => return Err(RuleError::UnknownConverter(converter.to_string())),
+                        (None, None) => {
+                            unresolved = true;
+                            "[^/]{1,}"
+                        },
                     };
                     regex_parts.push(format!("(?P<{}>{})", variable, re));
                 },
@@ -124,7 +229,88 @@ impl<'a> From<&'a str> for Matcher {
             regex_parts.push(String::from("(?P<__suffix__>/?)"));
         }
         let regex = format!(r"^{}$", join_string(regex_parts, ""));
-        Matcher::new(Regex::new(&regex).unwrap())
+        let mut matcher = Matcher::new(Regex::new(&regex).unwrap());
+        matcher.rule = Some(rule.to_string());
+        matcher.parsers = parsers;
+        matcher.path_variables = path_variables;
+        matcher.unresolved = unresolved;
+        Ok(matcher)
+    }
+
+    /// Build the URL path for this matcher from the given values, the values
+    /// are percent-encoded.  This returns `None` if the matcher has no rule
+    /// string or a variable of the rule is missing in the values.  The names
+    /// of the used values are returned along with the path.
+    fn build(&self, values: &ViewArgs) -> Option<(String, Vec<String>)> {
+        let rule = match self.rule {
+            Some(ref rule) => rule,
+            None => return None,
+        };
+        let mut path = String::new();
+        let mut used = Vec::new();
+        for (converter, variable) in parse_rule(rule) {
+            match converter {
+                Some(converter) => {
+                    let value = match values.get(variable) {
+                        Some(value) => value,
+                        None => return None,
+                    };
+                    if converter == "path" {
+                        path.extend(utf8_percent_encode(value, DEFAULT_ENCODE_SET));
+                    } else {
+                        path.extend(utf8_percent_encode(value, PATH_SEGMENT_ENCODE_SET));
+                    }
+                    used.push(variable.to_string());
+                },
+                None => {
+                    path.push_str(variable);
+                }
+            }
+        }
+        if self.regex.is_match(&path) {
+            Some((path, used))
+        } else {
+            None
+        }
+    }
+
+    /// The same matcher mounted under a url prefix like `/en`.
+    #[doc(hidden)]
+    pub fn prefixed(&self, prefix: &str) -> Matcher {
+        let prefix = prefix.trim_right_matches('/');
+        match self.rule {
+            Some(ref rule) => Matcher::from(format!("{}{}", prefix, rule)),
+            None => {
+                let regex = self.regex.as_str().trim_left_matches('^');
+                Matcher::new(Regex::new(&format!("^{}{}", regex_quote(prefix), regex)).unwrap())
+            }
+        }
+    }
+}
+
+/// Rule strings basically are just normal URL paths with placeholders in
+/// the format `<name:converter>` where the converter are optional.
+/// Currently we support following converters:
+///
+/// - string(default)
+/// - int
+/// - float
+/// - path
+/// - slug (lowercase letters and digits separated by single dashes)
+/// - uuid (like `5f3e6a2c-8d1b-4c7e-9f0a-2b6d4e8c1a3f`)
+///
+/// If no converter is defined the `default` converter is used which means `string`.
+/// Other converters have to be registered with `Map::add_converter` before
+/// the rule is added to the map.
+///
+/// URL rules that end with a slash are branch URLs, others are leaves.
+/// All branch URLs that are matched without a trailing slash will trigger a
+/// redirect to the same URL with the missing slash appended.
+/// We have a url without a trailing slash for branch url rule.
+/// So we redirect to the same url but with a trailing slash.
+impl<'a> From<&'a str> for Matcher {
+    fn from(rule: &'a str) -> Matcher {
+        Matcher::compile(rule, &HashMap::new(), false)
     }
 }
 
@@ -143,15 +329,21 @@ impl From<Regex> for Matcher {
 }
 
 
-/// Request Slash error.
-/// This is for example the case if you request `/foo`
-/// although the correct URL is `/foo/`.
-pub struct RequestSlashError;
+/// The errors of a path that matches the pattern of a rule.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MatchError {
+    /// The path lacks the trailing slash of the rule, this is for example
+    /// the case if you request `/foo` although the correct URL is `/foo/`.
+    RequestSlash,
+    /// The variable with this name is not valid UTF-8 after
+    /// percent-decoding.
+    InvalidVariable(String),
+}
 
 
 /// The map adapter matched value.
 pub enum MapAdapterMatched {
-    MatchedRule((Rule, ViewArgs)),
+    MatchedRule((Arc<Rule>, ViewArgs)),
     MatchedRedirect((String, u16)),
     MatchedError(HTTPError)
 }
@@ -167,6 +359,15 @@ pub struct Rule {
     /// The endpoint for this rule.
     pub endpoint: String,
     pub provide_automatic_options: bool,
+    /// The subdomain the rule applies to, `None` for the domain that is
+    /// configured with `SERVER_NAME` itself.
+    pub subdomain: Option<String>,
+    /// Requests matching the rule are redirected instead of dispatched, the
+    /// view arguments are used to build the target URL.
+    pub redirect_to: Option<Redirect>,
+    /// Whether a missing or extra trailing slash is an error, `None` uses
+    /// the default of the map the rule is added to.
+    pub strict_slashes: Option<bool>,
 }
 
 impl Rule {
@@ -174,11 +375,13 @@ impl Rule {
     /// regular expressions.  Rule endpoint is a string that is used for
     /// URL generation.  Rule methods is an array of http methods this rule
     /// applies to, if `GET` is present in it and `HEAD` is not, `HEAD` is
-    /// added automatically.
+    /// added automatically.  Extension methods like `PROPFIND` are matched
+    /// like the standard ones.
     pub fn new(matcher: Matcher, methods: &[Method], endpoint: &str) -> Rule {
         let mut all_methods = HashSet::new();
         for method in methods.iter() {
-            all_methods.insert(method.clone());
+            // `Extension("GET")` must match a parsed `GET` request.
+            all_methods.insert(method::extension(method.as_ref()));
         }
         if all_methods.contains(&Method::Get) {
             all_methods.insert(Method::Head);
@@ -194,26 +397,115 @@ impl Rule {
             endpoint: endpoint.to_string(),
             methods: all_methods,
             provide_automatic_options: provide_automatic_options,
+            subdomain: None,
+            redirect_to: None,
+            strict_slashes: None,
+        }
+    }
+
+    /// Restrict the rule to a subdomain of the `SERVER_NAME`:
+    ///
+    /// ```rust,ignore
+    /// app.add_rule(Rule::new("/users".into(), &[Method::Get], "api_users").subdomain("api"), api_users);
+    /// ```
+    pub fn subdomain(mut self, subdomain: &str) -> Rule {
+        self.subdomain = Some(subdomain.to_string());
+        self
+    }
+
+    /// Redirect requests that match the rule, see `Pencil::redirect_rule`.
+    /// Rules with a redirect are not used for URL building.
+    pub fn redirect_to(mut self, redirect: Redirect) -> Rule {
+        self.redirect_to = Some(redirect);
+        self
+    }
+
+    /// With strict slashes, which is the default, a request for `/foo` to
+    /// the rule `/foo/` is redirected to `/foo/` and a request for `/foo/`
+    /// doesn't match the rule `/foo`.  Without them both paths match the
+    /// rule directly.
+    pub fn strict_slashes(mut self, flag: bool) -> Rule {
+        self.strict_slashes = Some(flag);
+        self
+    }
+
+    /// The rule string like `/users/<id:int>`, or the regular expression
+    /// for rules that were created from one.
+    pub fn rule_str(&self) -> String {
+        match self.matcher.rule {
+            Some(ref rule) => rule.clone(),
+            None => self.matcher.regex.as_str().to_string(),
         }
     }
 
-    /// Check if the rule matches a given path.
-    pub fn matched(&self, path: String) -> Option<Result<ViewArgs, RequestSlashError>> {
-        match self.matcher.regex.captures(&path) {
+    /// The names of the methods of the rule, sorted.
+    pub fn method_names(&self) -> Vec<String> {
+        let mut methods: Vec<String> = self.methods.iter().map(|method| method.to_string()).collect();
+        methods.sort();
+        methods
+    }
+
+    /// The variables of the rule with the names of their converters, like
+    /// `("id", "int")`, in the order they appear in the rule.
+    pub fn converters(&self) -> Vec<(String, String)> {
+        match self.matcher.rule {
+            Some(ref rule) => parse_rule(rule).into_iter().filter_map(|(converter, variable)| {
+                converter.map(|converter| (variable.to_string(), converter.to_string()))
+            }).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Check if the rule applies to the subdomain of a request, `None` is
+    /// used for hosts that don't belong to the `SERVER_NAME`.
+    pub fn matches_subdomain(&self, subdomain: Option<&str>) -> bool {
+        subdomain == Some(self.subdomain.as_ref().map_or("", |subdomain| &subdomain[..]))
+    }
+
+    /// The name of the module the endpoint belongs to.
+    pub fn module_name(&self) -> Option<&str> {
+        self.endpoint.rfind('.').map(|pos| &self.endpoint[..pos])
+    }
+
+    /// Check if the rule matches a given path.  The variables are
+    /// percent-decoded, so `/users/J%C3%BCrgen` gives `Jürgen`.  A decoded
+    /// slash only matches the `path` converter, like a literal one.
+    pub fn matched<P: AsRef<str>>(&self, path: P) -> Option<Result<ViewArgs, MatchError>> {
+        let path = path.as_ref();
+        let strict_slashes = self.strict_slashes.unwrap_or(true);
+        let mut captures = self.matcher.regex.captures(path);
+        if captures.is_none() && !strict_slashes && path.len() > 1 && path.ends_with('/') {
+            captures = self.matcher.regex.captures(&path[..path.len() - 1]);
+        }
+        match captures {
             Some(caps) => {
                 if let Some(suffix) = caps.name("__suffix__") {
-                    if suffix.is_empty() {
-                        return Some(Err(RequestSlashError));
+                    if suffix.is_empty() && strict_slashes {
+                        return Some(Err(MatchError::RequestSlash));
                     }
                 }
                 let mut view_args: HashMap<String, String> = HashMap::new();
                 for variable in self.matcher.regex.capture_names() {
                     if let Some(variable) = variable {
                         if variable != "__suffix__" {
-                            view_args.insert(variable.to_string(), caps.name(variable).unwrap().to_string());
+                            let value = caps.name(variable).unwrap();
+                            if !self.matcher.path_variables.iter().any(|path_variable| path_variable == variable) &&
+                               value.to_lowercase().contains("%2f") {
+                                return None;
+                            }
+                            match percent_decode(value.as_bytes()).decode_utf8() {
+                                Ok(value) => { view_args.insert(variable.to_string(), value.into_owned()); },
+                                Err(_) => return Some(Err(MatchError::InvalidVariable(variable.to_string()))),
+                            }
                         }
                     }
                 }
+                for &(ref variable, parse) in &self.matcher.parsers {
+                    match view_args.get(variable).and_then(|value| parse(value)) {
+                        Some(value) => { view_args.insert(variable.clone(), value); },
+                        None => return None,
+                    }
+                }
                 Some(Ok(view_args))
             },
             None => None,
@@ -222,10 +514,327 @@ impl Rule {
 }
 
 
+/// The target of a registered redirect.
+#[derive(Clone, Debug)]
+pub enum RedirectTarget {
+    /// Redirect to a path or a full URL.
+    Location(String),
+    /// Redirect to the URL of an endpoint, the endpoint's rule must not
+    /// have any variables that the redirect can't provide.
+    Endpoint(String),
+}
+
+
+/// A redirect from an old URL path, it's used when no rule matches the path.
+#[derive(Clone, Debug)]
+pub struct Redirect {
+    pub target: RedirectTarget,
+    pub code: u16,
+}
+
+impl Redirect {
+    /// Redirect to a path or a full URL.
+    pub fn to_location(location: &str, code: u16) -> Redirect {
+        Redirect {
+            target: RedirectTarget::Location(location.to_string()),
+            code: code,
+        }
+    }
+
+    /// Redirect to the URL of an endpoint.
+    pub fn to_endpoint(endpoint: &str, code: u16) -> Redirect {
+        Redirect {
+            target: RedirectTarget::Endpoint(endpoint.to_string()),
+            code: code,
+        }
+    }
+}
+
+
+/// Options for normalizing request paths before they are matched.
+#[derive(Clone, Debug)]
+pub struct PathNormalization {
+    /// Collapse duplicate slashes, `//api//users` becomes `/api/users`.
+    pub merge_slashes: bool,
+    /// Resolve `.` and `..` segments.
+    pub remove_dot_segments: bool,
+    /// Lowercase the path.
+    pub lowercase: bool,
+    /// Redirect to the normalized URL instead of rewriting the path in place.
+    pub redirect: bool,
+}
+
+impl PathNormalization {
+    /// Create the default options, slashes are merged and dot segments are
+    /// removed, the client is redirected to the normalized URL.
+    pub fn new() -> PathNormalization {
+        PathNormalization {
+            merge_slashes: true,
+            remove_dot_segments: true,
+            lowercase: false,
+            redirect: true,
+        }
+    }
+
+    /// Normalize a path with these options.
+    pub fn normalize(&self, path: &str) -> String {
+        let mut segments: Vec<&str> = Vec::new();
+        let mut directory = false;
+        for segment in path.split('/').skip(1) {
+            directory = false;
+            if self.remove_dot_segments && (segment == "." || segment == "..") {
+                if segment == ".." {
+                    segments.pop();
+                }
+                directory = true;
+                continue;
+            }
+            if self.merge_slashes && segment.is_empty() {
+                directory = true;
+                continue;
+            }
+            segments.push(segment);
+        }
+        let mut normalized = String::from("/") + &segments.join("/");
+        if directory && !normalized.ends_with('/') {
+            normalized.push('/');
+        }
+        if self.lowercase {
+            normalized = normalized.to_lowercase();
+        }
+        normalized
+    }
+}
+
+impl Default for PathNormalization {
+    fn default() -> PathNormalization {
+        PathNormalization::new()
+    }
+}
+
+
+/// Options for routing with a locale prefix, like `/de/about`.  The prefix
+/// is validated against the supported locales and stripped before the path
+/// is matched.
+#[derive(Clone, Debug)]
+pub struct LocalePrefix {
+    /// The supported locales, like `en` or `pt-BR`.
+    pub supported: Vec<String>,
+    /// The locale used for paths without a locale prefix.
+    pub default: String,
+}
+
+impl LocalePrefix {
+    pub fn new(supported: &[&str], default: &str) -> LocalePrefix {
+        LocalePrefix {
+            supported: supported.iter().map(|locale| locale.to_string()).collect(),
+            default: default.to_string(),
+        }
+    }
+
+    /// Split the locale prefix from a path, this returns the locale if the
+    /// path starts with a supported one and the remaining path.
+    pub fn split(&self, path: &str) -> (Option<String>, String) {
+        let trimmed = path.trim_left_matches('/');
+        let (first, rest) = match trimmed.find('/') {
+            Some(pos) => (&trimmed[..pos], &trimmed[pos..]),
+            None => (trimmed, "/"),
+        };
+        if self.supported.iter().any(|locale| locale == first) {
+            (Some(first.to_string()), rest.to_string())
+        } else {
+            (None, path.to_string())
+        }
+    }
+
+    /// Choose the supported locale for the languages of an `Accept-Language`
+    /// header, sorted by quality.  A language matches a supported locale
+    /// exactly or by its primary subtag, so `de-AT` matches `de`.
+    pub fn negotiate(&self, languages: &[(String, f32)]) -> Option<String> {
+        let primary = |tag: &str| tag.split('-').next().unwrap_or("").to_lowercase();
+        for &(ref language, quality) in languages {
+            if quality <= 0.0 || language == "*" {
+                continue;
+            }
+            let exact = self.supported.iter().find(|locale| locale.eq_ignore_ascii_case(language));
+            let matched = exact.or_else(|| {
+                self.supported.iter().find(|locale| primary(locale) == primary(language))
+            });
+            if let Some(locale) = matched {
+                return Some(locale.clone());
+            }
+        }
+        None
+    }
+}
+
+
+/// The path without one trailing slash, `/` becomes an empty string.
+fn without_trailing_slash(path: &str) -> &str {
+    if path.ends_with('/') { &path[..path.len() - 1] } else { path }
+}
+
+
+/// The first segment of a path, like `users` for `/users/42`.
+fn first_segment(path: &str) -> &str {
+    path.trim_left_matches('/').split('/').next().unwrap_or("")
+}
+
+
+/// An index of rules by their static parts, so matching only runs the
+/// regular expressions of the rules that can match a path instead of all
+/// of them.
+#[derive(Clone, Default)]
+struct RuleIndex {
+    /// Rules without variables by their path without the trailing slash.
+    static_rules: HashMap<String, Vec<usize>>,
+    /// Rules with variables by their first path segment.
+    prefix_rules: HashMap<String, Vec<usize>>,
+    /// Rules with a variable in the first segment, or that were created
+    /// from a regular expression.
+    other_rules: Vec<usize>,
+}
+
+impl RuleIndex {
+    fn add(&mut self, index: usize, rule: &Rule) {
+        match rule.matcher.rule {
+            Some(ref rule_str) if !rule_str.contains('<') => {
+                let key = without_trailing_slash(rule_str).to_string();
+                self.static_rules.entry(key).or_insert_with(Vec::new).push(index);
+            },
+            Some(ref rule_str) if !first_segment(rule_str).contains('<') => {
+                let key = first_segment(rule_str).to_string();
+                self.prefix_rules.entry(key).or_insert_with(Vec::new).push(index);
+            },
+            _ => self.other_rules.push(index),
+        }
+    }
+
+    /// The indices of the rules that may match the path, in the order the
+    /// rules were added.
+    fn candidates(&self, path: &str) -> Vec<usize> {
+        let mut candidates = Vec::new();
+        if let Some(indices) = self.static_rules.get(without_trailing_slash(path)) {
+            candidates.extend(indices.iter().cloned());
+        }
+        if let Some(indices) = self.prefix_rules.get(first_segment(path)) {
+            candidates.extend(indices.iter().cloned());
+        }
+        candidates.extend(self.other_rules.iter().cloned());
+        candidates.sort();
+        candidates
+    }
+}
+
+
+/// The key of a cached match, the method, the subdomain and the path.
+type MatchKey = (Method, Option<String>, String);
+
+
+/// A cached match, stamped with the last use so the least recently used
+/// entry of a shard can be found.
+struct CachedMatch {
+    rule: Arc<Rule>,
+    view_args: ViewArgs,
+    used: AtomicUsize,
+}
+
+
+/// One shard of a `MatchCache`, hits only take the read lock.
+#[derive(Default)]
+struct MatchCacheShard {
+    entries: RwLock<HashMap<MatchKey, CachedMatch>>,
+    counter: AtomicUsize,
+}
+
+
+/// A cache of matched rules for hot URLs.  Only successful matches are
+/// cached, redirects and errors are matched again.  The cache is split into
+/// shards by the hash of the key, a full shard drops its least recently
+/// used entry.
+struct MatchCache {
+    capacity: usize,
+    shard_capacity: usize,
+    shards: Vec<MatchCacheShard>,
+}
+
+impl MatchCache {
+    fn new(capacity: usize) -> MatchCache {
+        // Small caches use a single shard so they stay exact LRU caches.
+        let shard_count = cmp::max(1, cmp::min(16, capacity / 64));
+        MatchCache {
+            capacity: capacity,
+            shard_capacity: (capacity + shard_count - 1) / shard_count,
+            shards: (0..shard_count).map(|_| MatchCacheShard::default()).collect(),
+        }
+    }
+
+    fn shard(&self, key: &MatchKey) -> &MatchCacheShard {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    fn get(&self, key: &MatchKey) -> Option<(Arc<Rule>, ViewArgs)> {
+        let shard = self.shard(key);
+        let entries = shard.entries.read().unwrap();
+        entries.get(key).map(|entry| {
+            entry.used.store(shard.counter.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+            (entry.rule.clone(), entry.view_args.clone())
+        })
+    }
+
+    fn insert(&self, key: MatchKey, rule: Arc<Rule>, view_args: ViewArgs) {
+        if self.capacity == 0 {
+            return;
+        }
+        let shard = self.shard(&key);
+        let mut entries = shard.entries.write().unwrap();
+        if !entries.contains_key(&key) && entries.len() >= self.shard_capacity {
+            let oldest = entries.iter()
+                .min_by_key(|&(_, entry)| entry.used.load(Ordering::Relaxed))
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        let used = AtomicUsize::new(shard.counter.fetch_add(1, Ordering::Relaxed));
+        entries.insert(key, CachedMatch { rule: rule, view_args: view_args, used: used });
+    }
+
+    fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.entries.read().unwrap().len()).sum()
+    }
+}
+
+impl Clone for MatchCache {
+    /// Clones start with an empty cache of the same capacity.
+    fn clone(&self) -> MatchCache {
+        MatchCache::new(self.capacity)
+    }
+}
+
+
 /// The map stores all the URL rules.
 #[derive(Clone)]
 pub struct Map {
-    rules: Vec<Rule>,
+    rules: Vec<Arc<Rule>>,
+    /// The rules indexed by their static parts.
+    rule_index: RuleIndex,
+    /// One rule for every distinct pattern and subdomain with the methods of
+    /// all rules with that pattern, so allowed methods need one match per
+    /// pattern.
+    patterns: Vec<(Arc<Rule>, HashSet<Method>)>,
+    /// The positions of the patterns by regular expression and subdomain.
+    pattern_positions: HashMap<(String, Option<String>), usize>,
+    /// The patterns indexed by their static parts.
+    pattern_index: RuleIndex,
+    redirects: HashMap<String, Redirect>,
+    normalization: Option<PathNormalization>,
+    locale_prefix: Option<LocalePrefix>,
+    converters: HashMap<String, Converter>,
+    strict_slashes: bool,
+    match_cache: Option<MatchCache>,
 }
 
 impl Default for Map {
@@ -236,13 +845,159 @@ impl Default for Map {
 
 impl Map {
     pub fn new() -> Map {
-        Map { rules: vec![] }
+        Map {
+            rules: vec![],
+            rule_index: RuleIndex::default(),
+            patterns: vec![],
+            pattern_positions: HashMap::new(),
+            pattern_index: RuleIndex::default(),
+            redirects: HashMap::new(),
+            normalization: None,
+            locale_prefix: None,
+            converters: HashMap::new(),
+            strict_slashes: true,
+            match_cache: None,
+        }
+    }
+
+    /// Register a custom converter for rule variables like `<year:year>`.
+    /// The regular expression should not contain capturing groups, the
+    /// parse function gets the matched value and returns the view argument
+    /// or `None` to reject the value.  Converters have to be registered
+    /// before the rules that use them are added.
+    pub fn add_converter(&mut self, name: &str, regex: &str, parse: ConverterFunc) {
+        if builtin_converter(name).is_some() {
+            panic!("the converter {} is built in and can't be replaced", name);
+        }
+        if let Err(e) = Regex::new(regex) {
+            panic!("invalid regular expression for the converter {}: {}", name, e);
+        }
+        self.converters.insert(name.to_string(), Converter {
+            regex: regex.to_string(),
+            parse: parse,
+        });
+    }
+
+    /// Compile a rule string with the converters of this map.  Unlike
+    /// `Matcher::from`, invalid rules and unknown converters are an error
+    /// instead of a panic.
+    pub fn compile_rule(&self, rule: &str) -> Result<Matcher, RuleError> {
+        Matcher::try_compile(rule, &self.converters, true)
+    }
+
+    /// Cache the matched rules of up to `capacity` URLs, the least recently
+    /// used URL of a cache shard is dropped when the shard is full.  Cache
+    /// hits only take a read lock, so concurrent requests don't wait on
+    /// each other.  This helps applications with many rules, where matching
+    /// runs several regular expressions per request.  Adding a rule clears
+    /// the cache.
+    pub fn enable_match_cache(&mut self, capacity: usize) {
+        self.match_cache = Some(MatchCache::new(capacity));
+    }
+
+    /// The number of cached matches.
+    pub fn match_cache_len(&self) -> usize {
+        self.match_cache.as_ref().map_or(0, |cache| cache.len())
+    }
+
+    /// Set the default of `Rule::strict_slashes` for the rules that are
+    /// added after this, it defaults to `true`.
+    pub fn set_strict_slashes(&mut self, flag: bool) {
+        self.strict_slashes = flag;
+    }
+
+    /// Set the options for routing with a locale prefix, `None` disables
+    /// the locale prefix.
+    pub fn set_locale_prefix(&mut self, locale_prefix: Option<LocalePrefix>) {
+        self.locale_prefix = locale_prefix;
+    }
+
+    /// The options for routing with a locale prefix.
+    pub fn locale_prefix(&self) -> Option<&LocalePrefix> {
+        self.locale_prefix.as_ref()
+    }
+
+    /// Set the options for normalizing request paths before matching,
+    /// `None` disables the normalization.
+    pub fn set_normalization(&mut self, normalization: Option<PathNormalization>) {
+        self.normalization = normalization;
+    }
+
+    /// The options for normalizing request paths.
+    pub fn normalization(&self) -> Option<&PathNormalization> {
+        self.normalization.as_ref()
     }
 
-    pub fn add(&mut self, rule: Rule) {
+    pub fn add(&mut self, mut rule: Rule) {
+        if rule.matcher.unresolved {
+            let source = rule.matcher.rule.clone().unwrap();
+            rule.matcher = Matcher::compile(&source, &self.converters, true);
+        }
+        if rule.strict_slashes.is_none() {
+            rule.strict_slashes = Some(self.strict_slashes);
+        }
+        let rule = Arc::new(rule);
+        let index = self.rules.len();
+        self.rule_index.add(index, &rule);
+        if let Some(ref mut cache) = self.match_cache {
+            *cache = MatchCache::new(cache.capacity);
+        }
+        let key = (rule.matcher.regex.as_str().to_string(), rule.subdomain.clone());
+        match self.pattern_positions.get(&key) {
+            Some(&position) => self.patterns[position].1.extend(rule.methods.iter().cloned()),
+            None => {
+                let position = self.patterns.len();
+                self.pattern_index.add(position, &rule);
+                self.pattern_positions.insert(key, position);
+                self.patterns.push((rule.clone(), rule.methods.clone()));
+            },
+        }
         self.rules.push(rule);
     }
 
+    /// An iterator over all rules in the order they were added.
+    pub fn iter_rules(&self) -> slice::Iter<Arc<Rule>> {
+        self.rules.iter()
+    }
+
+    /// Add a redirect for an old path.  Redirects are only used if no
+    /// rule matches the path.
+    pub fn add_redirect(&mut self, path: &str, redirect: Redirect) {
+        self.redirects.insert(path.to_string(), redirect);
+    }
+
+    /// Build the URL path for an endpoint.  Values that are not used by
+    /// the rule are appended as query string.  This returns `None` if no
+    /// rule for the endpoint can be built with the given values.
+    pub fn build(&self, endpoint: &str, values: &ViewArgs) -> Option<String> {
+        self.build_with_subdomain(endpoint, values).map(|(path, _)| path)
+    }
+
+    /// Like `build`, but also returns the subdomain of the rule that built
+    /// the path, this is an empty string for rules without a subdomain.
+    pub fn build_with_subdomain(&self, endpoint: &str, values: &ViewArgs) -> Option<(String, String)> {
+        for rule in &self.rules {
+            if rule.endpoint != endpoint || rule.redirect_to.is_some() {
+                continue;
+            }
+            if let Some((mut path, used)) = rule.matcher.build(values) {
+                let mut extra: Vec<(&String, &String)> = values.iter()
+                    .filter(|&(key, _)| !used.contains(key))
+                    .collect();
+                if !extra.is_empty() {
+                    extra.sort();
+                    let query = form_urlencoded::Serializer::new(String::new())
+                        .extend_pairs(extra)
+                        .finish();
+                    path = path + "?" + &query;
+                }
+                let subdomain = rule.subdomain.clone().unwrap_or_default();
+                return Some((path, subdomain));
+            }
+        }
+        None
+    }
+
     pub fn bind(&self, host: String, path: String, query_string: Option<String>, method: Method) -> MapAdapter {
         MapAdapter::new(self, host, path, query_string, method)
     }
@@ -255,8 +1010,10 @@ pub struct MapAdapter<'m> {
     url_scheme: String,
     host: String,
     path: String,
+    path_prefix: String,
     query_string: Option<String>,
     method: Method,
+    subdomain: Option<String>,
 }
 
 impl<'m> MapAdapter<'m> {
@@ -266,11 +1023,30 @@ impl<'m> MapAdapter<'m> {
             url_scheme: "http".to_owned(),
             host: host,
             path: path,
+            path_prefix: String::new(),
             query_string: query_string,
             method: method,
+            subdomain: Some(String::new()),
         }
     }
 
+    /// Set the subdomain of the request, only rules for that subdomain
+    /// match.  `None` means the host doesn't belong to the server name, then
+    /// no rule matches.  Defaults to no subdomain.
+    pub fn set_subdomain(&mut self, subdomain: Option<String>) {
+        self.subdomain = subdomain;
+    }
+
+    fn matches_subdomain(&self, rule: &Rule) -> bool {
+        rule.matches_subdomain(self.subdomain.as_ref().map(|subdomain| &subdomain[..]))
+    }
+
+    /// Set a prefix that was stripped from the path before matching, like a
+    /// locale prefix.  It is prepended to the URLs of redirects.
+    pub fn set_path_prefix(&mut self, path_prefix: &str) {
+        self.path_prefix = path_prefix.trim_right_matches('/').to_string();
+    }
+
     fn make_redirect_url(&self) -> String {
         let mut redirect_path = String::from("");
         redirect_path = redirect_path + &self.path.trim_left_matches('/') + "/";
@@ -278,62 +1054,205 @@ impl<'m> MapAdapter<'m> {
         if let Some(ref query_string) = self.query_string {
             suffix = suffix + "?" + query_string;
         }
-        format!("{}://{}/{}{}", self.url_scheme, self.host, redirect_path, suffix)
+        format!("{}://{}{}/{}{}", self.url_scheme, self.host, self.path_prefix, redirect_path, suffix)
     }
 
     pub fn matched(&self) -> MapAdapterMatched {
-        let mut have_match_for = HashSet::new();
-        for rule in &self.map.rules {
-            let rule_view_args: ViewArgs;
-            match rule.matched(self.path.clone()) {
-                Some(result) => {
-                    match result {
-                        Ok(view_args) => {
-                            rule_view_args = view_args;
-                        },
-                        // RequestSlashError, redirect here
-                        Err(_) => {
-                            let redirect_url = self.make_redirect_url();
-                            return MapAdapterMatched::MatchedRedirect((redirect_url, 301));
+        let cache_key = match self.map.match_cache {
+            Some(ref cache) => {
+                let key = (self.method.clone(), self.subdomain.clone(), self.path.clone());
+                if let Some(matched) = cache.get(&key) {
+                    return MapAdapterMatched::MatchedRule(matched);
+                }
+                Some(key)
+            },
+            None => None,
+        };
+        for index in self.map.rule_index.candidates(&self.path) {
+            let rule = &self.map.rules[index];
+            if !rule.methods.contains(&self.method) || !self.matches_subdomain(rule) {
+                continue;
+            }
+            match rule.matched(&self.path) {
+                Some(Ok(view_args)) => {
+                    if let Some(ref redirect) = rule.redirect_to {
+                        match self.make_registered_redirect_url(redirect, &view_args) {
+                            Some(redirect_url) => return MapAdapterMatched::MatchedRedirect((redirect_url, redirect.code)),
+                            None => continue,
                         }
                     }
+                    if let (Some(cache), Some(key)) = (self.map.match_cache.as_ref(), cache_key) {
+                        cache.insert(key, rule.clone(), view_args.clone());
+                    }
+                    return MapAdapterMatched::MatchedRule((rule.clone(), view_args));
+                },
+                Some(Err(MatchError::RequestSlash)) => {
+                    let redirect_url = self.make_redirect_url();
+                    return MapAdapterMatched::MatchedRedirect((redirect_url, 301));
+                },
+                Some(Err(MatchError::InvalidVariable(variable))) => {
+                    let description = format!("The {} parameter is not valid UTF-8.", variable);
+                    return MapAdapterMatched::MatchedError(BadRequest.with_description(description));
                 },
                 None => { continue; },
             }
-            if !rule.methods.contains(&self.method) {
-                for method in &rule.methods {
-                    have_match_for.insert(method.clone());
-                }
+        }
+        let mut have_match_for = HashSet::new();
+        for index in self.map.pattern_index.candidates(&self.path) {
+            let (ref rule, ref methods) = self.map.patterns[index];
+            if !self.matches_subdomain(rule) {
                 continue;
             }
-            return MapAdapterMatched::MatchedRule((rule.clone(), rule_view_args))
+            match rule.matched(&self.path) {
+                Some(Ok(_)) | Some(Err(MatchError::InvalidVariable(_))) => {
+                    have_match_for.extend(methods.iter().cloned());
+                },
+                Some(Err(MatchError::RequestSlash)) => {
+                    let redirect_url = self.make_redirect_url();
+                    return MapAdapterMatched::MatchedRedirect((redirect_url, 301));
+                },
+                None => { continue; },
+            }
         }
         if !have_match_for.is_empty() {
             let mut allowed_methods = Vec::new();
             allowed_methods.extend(have_match_for.into_iter());
+            // Keep the `Allow` header stable.
+            allowed_methods.sort_by_key(|method| method.to_string());
             return MapAdapterMatched::MatchedError(MethodNotAllowed(Some(allowed_methods)))
         }
+        if let Some(redirect) = self.map.redirects.get(&self.path) {
+            if let Some(redirect_url) = self.make_registered_redirect_url(redirect, &HashMap::new()) {
+                return MapAdapterMatched::MatchedRedirect((redirect_url, redirect.code));
+            }
+        }
         MapAdapterMatched::MatchedError(NotFound)
     }
 
+    /// The URL for a registered redirect, the query string is kept if the
+    /// target doesn't have one.  The values are used to build the URL of an
+    /// endpoint target.
+    fn make_registered_redirect_url(&self, redirect: &Redirect, values: &ViewArgs) -> Option<String> {
+        let location = match redirect.target {
+            RedirectTarget::Location(ref location) => location.clone(),
+            RedirectTarget::Endpoint(ref endpoint) => {
+                match self.map.build(endpoint, values) {
+                    Some(location) => location,
+                    None => {
+                        error!("Could not build the URL for redirect endpoint {}", endpoint);
+                        return None;
+                    }
+                }
+            },
+        };
+        let mut url = if location.contains("://") {
+            location
+        } else {
+            format!("{}://{}{}/{}", self.url_scheme, self.host, self.path_prefix, location.trim_left_matches('/'))
+        };
+        if let Some(ref query_string) = self.query_string {
+            if !url.contains('?') {
+                url = url + "?" + query_string;
+            }
+        }
+        Some(url)
+    }
+
     /// Get the valid methods that match for the given path.
     pub fn allowed_methods(&self) -> Vec<Method> {
         let mut have_match_for = HashSet::new();
-        for rule in &self.map.rules {
-            match rule.matched(self.path.clone()) {
-                Some(_) => {
-                    for method in &rule.methods {
-                        have_match_for.insert(method.clone());
-                    }
-                    continue;
-                },
-                None => { continue; },
+        for index in self.map.pattern_index.candidates(&self.path) {
+            let (ref rule, ref methods) = self.map.patterns[index];
+            if self.matches_subdomain(rule) && rule.matched(&self.path).is_some() {
+                have_match_for.extend(methods.iter().cloned());
             }
         }
         let mut allowed_methods = Vec::new();
         allowed_methods.extend(have_match_for.into_iter());
+        allowed_methods.sort_by_key(|method| method.to_string());
         allowed_methods
     }
+
+    /// Rules that nearly match the path, this helps finding typos when
+    /// nothing matched.  Rules are suggested if they match with or without
+    /// a trailing slash, if their static parts are within a small edit
+    /// distance of the path or if only their converters reject the path.
+    /// Rules that don't allow the method list their methods.
+    pub fn near_misses(&self) -> Vec<String> {
+        let mut near_misses = Vec::new();
+        for rule in &self.map.rules {
+            if !self.matches_subdomain(rule) {
+                continue;
+            }
+            let rule_str = match rule.matcher.rule {
+                Some(ref rule_str) => rule_str,
+                None => continue,
+            };
+            let toggled_slash = if self.path.ends_with('/') {
+                self.path.trim_right_matches('/').to_string()
+            } else {
+                format!("{}/", self.path)
+            };
+            let reason = if rule.matched(toggled_slash).is_some() {
+                "trailing slash"
+            } else {
+                match path_distance(rule_str, &self.path) {
+                    Some(0) => "arguments rejected by the converters",
+                    Some(distance) if distance <= 2 => "similar path",
+                    _ => continue,
+                }
+            };
+            let mut near_miss = format!("{} ({}): {}", rule_str, rule.endpoint, reason);
+            if !rule.methods.contains(&self.method) {
+                let mut methods: Vec<String> = rule.methods.iter().filter(|method| {
+                    **method != Method::Head && **method != Method::Options
+                }).map(|method| method.to_string()).collect();
+                methods.sort();
+                near_miss = format!("{}, only {}", near_miss, methods.join(", "));
+            }
+            near_misses.push(near_miss);
+        }
+        near_misses
+    }
+}
+
+
+/// The edit distance between a rule and a path.  Static rules are
+/// compared as a whole, rules with variables segment by segment where a
+/// variable matches any non-empty segment.  This is `None` if the number of segments
+/// differs for a rule with variables.
+fn path_distance(rule: &str, path: &str) -> Option<usize> {
+    if !rule.contains('<') {
+        return Some(edit_distance(rule, path));
+    }
+    let rule_segments: Vec<&str> = rule.split('/').collect();
+    let path_segments: Vec<&str> = path.split('/').collect();
+    if rule_segments.len() != path_segments.len() {
+        return None;
+    }
+    Some(rule_segments.iter().zip(path_segments.iter()).map(|(rule_segment, path_segment)| {
+        if rule_segment.contains('<') {
+            if path_segment.is_empty() { 1 } else { 0 }
+        } else {
+            edit_distance(rule_segment, path_segment)
+        }
+    }).sum())
+}
+
+
+/// The Levenshtein distance of two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
 }
 
 
@@ -354,3 +1273,264 @@ fn test_basic_routing() {
         _ => { panic!("Basic routing failed!"); }
     }
 }
+
+
+#[test]
+fn test_url_building() {
+    let mut map = Map::new();
+    map.add(Rule::new("/".into(), &[Method::Get], "index"));
+    map.add(Rule::new("/user/<user_id:int>".into(), &[Method::Get], "user"));
+    map.add(Rule::new("/files/<name:path>".into(), &[Method::Get], "files"));
+    let mut values = HashMap::new();
+    assert!(map.build("index", &values) == Some(String::from("/")));
+    assert!(map.build("user", &values).is_none());
+    values.insert(String::from("user_id"), String::from("42"));
+    values.insert(String::from("page"), String::from("2"));
+    assert!(map.build("user", &values) == Some(String::from("/user/42?page=2")));
+    values.insert(String::from("user_id"), String::from("foo"));
+    assert!(map.build("user", &values).is_none());
+    let mut values = HashMap::new();
+    values.insert(String::from("name"), String::from("a b/c.txt"));
+    assert!(map.build("files", &values) == Some(String::from("/files/a%20b/c.txt")));
+}
+
+
+#[test]
+fn test_percent_decoded_view_args() {
+    let rule = Rule::new("/users/<name:string>/".into(), &[Method::Get], "user");
+    let view_args = rule.matched(String::from("/users/J%C3%BCrgen%20M/")).unwrap().unwrap();
+    assert!(view_args["name"] == "Jürgen M");
+    assert!(rule.matched(String::from("/users/J%FCrgen/")).unwrap() == Err(MatchError::InvalidVariable(String::from("name"))));
+    assert!(rule.matched(String::from("/users/a")).unwrap() == Err(MatchError::RequestSlash));
+    assert!(rule.matched(String::from("/users/a%2Fb/")).is_none());
+    let rule = Rule::new("/files/<file:path>".into(), &[Method::Get], "file");
+    assert!(rule.matched(String::from("/files/a%2Fb")).unwrap().unwrap()["file"] == "a/b");
+}
+
+
+#[test]
+fn test_rule_introspection() {
+    let rule = Rule::new("/users/<id:int>/files/<name:path>".into(), &[Method::Post], "user_file");
+    assert!(rule.rule_str() == "/users/<id:int>/files/<name:path>");
+    assert!(rule.method_names() == vec!["OPTIONS", "POST"]);
+    assert!(rule.converters() == vec![(String::from("id"), String::from("int")),
+                                      (String::from("name"), String::from("path"))]);
+    let rule = Rule::new(Matcher::new(Regex::new("^/raw$").unwrap()), &[Method::Get], "raw");
+    assert!(rule.rule_str() == "^/raw$");
+    assert!(rule.converters().is_empty());
+}
+
+#[test]
+fn test_registered_redirects() {
+    let mut map = Map::new();
+    map.add(Rule::new("/users/".into(), &[Method::Get], "users"));
+    map.add_redirect("/members", Redirect::to_endpoint("users", 301));
+    map.add_redirect("/old", Redirect::to_location("/new", 302));
+    let adapter = map.bind(String::from("localhost"), String::from("/members"), Some(String::from("page=2")), Method::Get);
+    match adapter.matched() {
+        MapAdapterMatched::MatchedRedirect((url, code)) => {
+            assert!(url == "http://localhost/users/?page=2");
+            assert!(code == 301);
+        },
+        _ => { panic!("Registered redirect failed!"); }
+    }
+    let adapter = map.bind(String::from("localhost"), String::from("/old"), None, Method::Get);
+    match adapter.matched() {
+        MapAdapterMatched::MatchedRedirect((url, code)) => {
+            assert!(url == "http://localhost/new");
+            assert!(code == 302);
+        },
+        _ => { panic!("Registered redirect failed!"); }
+    }
+}
+
+
+#[test]
+fn test_path_normalization() {
+    let normalization = PathNormalization::new();
+    assert!(normalization.normalize("/") == "/");
+    assert!(normalization.normalize("//api//users") == "/api/users");
+    assert!(normalization.normalize("/api//users/") == "/api/users/");
+    assert!(normalization.normalize("/a/./b/../c") == "/a/c");
+    assert!(normalization.normalize("/a/b/..") == "/a/");
+    assert!(normalization.normalize("/../a") == "/a");
+    let mut normalization = PathNormalization::new();
+    normalization.merge_slashes = false;
+    normalization.lowercase = true;
+    assert!(normalization.normalize("/A//B") == "/a//b");
+}
+
+
+#[test]
+fn test_slug_converter() {
+    let rule = Rule::new("/post/<title:slug>".into(), &[Method::Get], "post");
+    assert!(rule.matched(String::from("/post/hello-world-2")).is_some());
+    assert!(rule.matched(String::from("/post/hello")).is_some());
+    assert!(rule.matched(String::from("/post/Hello-World")).is_none());
+    assert!(rule.matched(String::from("/post/hello--world")).is_none());
+    assert!(rule.matched(String::from("/post/-hello")).is_none());
+    assert!(rule.matched(String::from("/post/hello_world")).is_none());
+}
+
+
+#[test]
+fn test_uuid_converter() {
+    let rule = Rule::new("/items/<id:uuid>".into(), &[Method::Get], "item");
+    assert!(rule.matched(String::from("/items/5f3e6a2c-8d1b-4c7e-9f0a-2b6d4e8c1a3f")).is_some());
+    assert!(rule.matched(String::from("/items/5F3E6A2C-8D1B-4C7E-9F0A-2B6D4E8C1A3F")).is_some());
+    assert!(rule.matched(String::from("/items/5f3e6a2c8d1b4c7e9f0a2b6d4e8c1a3f")).is_none());
+    assert!(rule.matched(String::from("/items/5f3e6a2c-8d1b-4c7e-9f0a-2b6d4e8c1a3g")).is_none());
+    assert!(rule.matched(String::from("/items/42")).is_none());
+}
+
+
+#[test]
+fn test_custom_converter() {
+    fn parse_year(value: &str) -> Option<String> {
+        match value.parse::<u32>() {
+            Ok(year) if year >= 1970 => Some(format!("{}", year)),
+            _ => None,
+        }
+    }
+    let mut map = Map::new();
+    map.add_converter("year", r"\d{4}", parse_year);
+    map.add(Rule::new("/archive/<year:year>".into(), &[Method::Get], "archive"));
+    let matched = |path: &str| map.bind(String::from("localhost"), String::from(path), None, Method::Get).matched();
+    match matched("/archive/2016") {
+        MapAdapterMatched::MatchedRule((_, view_args)) => assert!(view_args.get("year").unwrap() == "2016"),
+        _ => panic!("/archive/2016 should match"),
+    }
+    match matched("/archive/1969") {
+        MapAdapterMatched::MatchedError(NotFound) => (),
+        _ => panic!("/archive/1969 should be rejected by the converter"),
+    }
+    match matched("/archive/16") {
+        MapAdapterMatched::MatchedError(NotFound) => (),
+        _ => panic!("/archive/16 should not match"),
+    }
+}
+
+
+#[test]
+#[should_panic(expected = "the converter year does not exist")]
+fn test_unknown_converter() {
+    let mut map = Map::new();
+    map.add(Rule::new("/archive/<year:year>".into(), &[Method::Get], "archive"));
+}
+
+
+#[test]
+fn test_locale_prefix() {
+    let locale_prefix = LocalePrefix::new(&["en", "de"], "en");
+    assert!(locale_prefix.split("/de/about") == (Some(String::from("de")), String::from("/about")));
+    assert!(locale_prefix.split("/de") == (Some(String::from("de")), String::from("/")));
+    assert!(locale_prefix.split("/de/") == (Some(String::from("de")), String::from("/")));
+    assert!(locale_prefix.split("/fr/about") == (None, String::from("/fr/about")));
+    assert!(locale_prefix.split("/about") == (None, String::from("/about")));
+
+    let mut map = Map::new();
+    map.add(Rule::new("/about/".into(), &[Method::Get], "about"));
+    let mut adapter = map.bind(String::from("localhost"), String::from("/about"), None, Method::Get);
+    adapter.set_path_prefix("/de");
+    match adapter.matched() {
+        MapAdapterMatched::MatchedRedirect((url, _)) => {
+            assert!(url == "http://localhost/de/about/");
+        },
+        _ => { panic!("Locale prefixed redirect failed!"); }
+    }
+}
+
+
+#[test]
+fn test_near_misses() {
+    let mut map = Map::new();
+    map.add(Rule::new("/users".into(), &[Method::Get], "users"));
+    map.add(Rule::new("/users/<id:int>".into(), &[Method::Get], "user"));
+    map.add(Rule::new("/login".into(), &[Method::Post], "login"));
+    let near_misses = |path: &str| {
+        map.bind(String::from("localhost"), String::from(path), None, Method::Get).near_misses()
+    };
+    assert!(near_misses("/users/") == vec!["/users (users): trailing slash", "/users/<id:int> (user): similar path"]);
+    assert!(near_misses("/usres") == vec!["/users (users): similar path"]);
+    assert!(near_misses("/users/abc") == vec!["/users/<id:int> (user): arguments rejected by the converters"]);
+    assert!(near_misses("/logon") == vec!["/login (login): similar path, only POST"]);
+    assert!(near_misses("/about").is_empty());
+}
+
+
+#[test]
+fn test_rule_index() {
+    let mut map = Map::new();
+    map.add(Rule::new("/".into(), &[Method::Get], "index"));
+    map.add(Rule::new("/users/<name:string>".into(), &[Method::Get], "user"));
+    map.add(Rule::new("/users/new".into(), &[Method::Get], "new_user"));
+    map.add(Rule::new("/<lang:string>/about/".into(), &[Method::Get], "about"));
+    map.add(Rule::new(Matcher::new(Regex::new("^/raw/[0-9]+$").unwrap()), &[Method::Get], "raw"));
+    assert!(map.rule_index.candidates("/") == vec![0, 3, 4]);
+    assert!(map.rule_index.candidates("/users/new") == vec![1, 2, 3, 4]);
+    assert!(map.rule_index.candidates("/en/about") == vec![3, 4]);
+    let endpoint = |path: &str| match map.bind(String::from("localhost"), String::from(path), None, Method::Get).matched() {
+        MapAdapterMatched::MatchedRule((rule, _)) => rule.endpoint.clone(),
+        MapAdapterMatched::MatchedRedirect(_) => String::from("redirect"),
+        MapAdapterMatched::MatchedError(_) => String::from("error"),
+    };
+    assert!(endpoint("/") == "index");
+    assert!(endpoint("/users/new") == "user");
+    assert!(endpoint("/en/about/") == "about");
+    assert!(endpoint("/en/about") == "redirect");
+    assert!(endpoint("/raw/42") == "raw");
+    assert!(endpoint("/missing") == "error");
+}
+
+#[test]
+fn test_match_cache() {
+    let mut map = Map::new();
+    map.enable_match_cache(2);
+    map.add(Rule::new("/users/<id:int>".into(), &[Method::Get], "user"));
+    map.add(Rule::new("/about/".into(), &[Method::Get], "about"));
+    let matched = |map: &Map, path: &str| match map.bind(String::from("localhost"), String::from(path), None, Method::Get).matched() {
+        MapAdapterMatched::MatchedRule((rule, view_args)) => Some((rule.endpoint.clone(), view_args)),
+        _ => None,
+    };
+    assert!(matched(&map, "/users/1").unwrap().1["id"] == "1");
+    assert!(matched(&map, "/users/1").unwrap().0 == "user");
+    assert!(map.match_cache_len() == 1);
+    assert!(matched(&map, "/about").is_none());
+    assert!(matched(&map, "/missing").is_none());
+    assert!(map.match_cache_len() == 1);
+    assert!(matched(&map, "/about/").is_some());
+    assert!(matched(&map, "/users/1").is_some());
+    assert!(matched(&map, "/users/2").unwrap().1["id"] == "2");
+    assert!(map.match_cache_len() == 2);
+    // `/about/` was the least recently used URL.
+    let key = (Method::Get, Some(String::new()), String::from("/about/"));
+    assert!(map.match_cache.as_ref().unwrap().get(&key).is_none());
+    map.add(Rule::new("/contact".into(), &[Method::Get], "contact"));
+    assert!(map.match_cache_len() == 0);
+}
+
+#[test]
+fn test_method_indexed_routing() {
+    let mut map = Map::new();
+    map.add(Rule::new("/users".into(), &[Method::Get], "users"));
+    map.add(Rule::new("/users".into(), &[Method::Post], "create_user"));
+    map.add(Rule::new("/users/<id:int>".into(), &[Method::Delete], "delete_user"));
+    let matched = |path: &str, method: Method| map.bind(String::from("localhost"), String::from(path), None, method).matched();
+    match matched("/users", Method::Post) {
+        MapAdapterMatched::MatchedRule((rule, _)) => assert!(rule.endpoint == "create_user"),
+        _ => panic!("POST /users should match"),
+    }
+    match matched("/users", Method::Put) {
+        MapAdapterMatched::MatchedError(MethodNotAllowed(Some(methods))) => {
+            let methods: HashSet<Method> = methods.into_iter().collect();
+            let expected: HashSet<Method> = vec![Method::Get, Method::Head, Method::Post, Method::Options].into_iter().collect();
+            assert!(methods == expected);
+        },
+        _ => panic!("PUT /users should not be allowed"),
+    }
+    match matched("/users/1", Method::Get) {
+        MapAdapterMatched::MatchedError(MethodNotAllowed(_)) => (),
+        _ => panic!("GET /users/1 should not be allowed"),
+    }
+    assert!(map.patterns.len() == 2);
+}
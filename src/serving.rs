@@ -1,14 +1,171 @@
 //! This module implements the http server support for our application.
 
 use std::net::ToSocketAddrs;
+use std::time::Duration;
 
+use hyper::net::NetworkListener;
 use hyper::server::Server;
 
-use app::Pencil;
+use app::PencilApp;
+use config::Config;
+#[cfg(feature = "ssl")]
+use tls::TlsConfig;
+
+
+/// The options of the http server:
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use pencil::{Pencil, ServerOptions};
+///
+/// let app = Pencil::new("/web/demo");
+/// let options = ServerOptions::new().threads(16).read_timeout(Some(Duration::from_secs(30)));
+/// app.run_with_options("127.0.0.1:5000", options);
+/// ```
+///
+/// The server handles one connection per thread, so the number of threads
+/// is also the number of connections that are served at the same time.
+/// The options can also be set with configuration keys, see `from_config`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerOptions {
+    threads: Option<usize>,
+    keep_alive: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    max_connections: Option<usize>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> ServerOptions {
+        ServerOptions::new()
+    }
+}
+
+impl ServerOptions {
+    /// The default options: hyper's default number of threads, keep-alive
+    /// with a 5 second timeout and no read and write timeouts.
+    pub fn new() -> ServerOptions {
+        ServerOptions {
+            threads: None,
+            keep_alive: Some(Duration::from_secs(5)),
+            read_timeout: None,
+            write_timeout: None,
+            max_connections: None,
+        }
+    }
+
+    /// Read the options from the configuration, missing keys keep the
+    /// default:
+    ///
+    /// - `SERVER_THREADS`
+    /// - `SERVER_KEEP_ALIVE`, in seconds, `0` disables keep-alive.
+    /// - `SERVER_READ_TIMEOUT`, in seconds.
+    /// - `SERVER_WRITE_TIMEOUT`, in seconds.
+    /// - `SERVER_MAX_CONNECTIONS`
+    pub fn from_config(config: &Config) -> ServerOptions {
+        let get_u64 = |key: &str| config.get(key).and_then(|value| value.as_u64());
+        let mut options = ServerOptions::new();
+        if let Some(threads) = get_u64("SERVER_THREADS") {
+            options = options.threads(threads as usize);
+        }
+        if let Some(keep_alive) = get_u64("SERVER_KEEP_ALIVE") {
+            options = options.keep_alive(if keep_alive == 0 { None } else { Some(Duration::from_secs(keep_alive)) });
+        }
+        if let Some(read_timeout) = get_u64("SERVER_READ_TIMEOUT") {
+            options = options.read_timeout(Some(Duration::from_secs(read_timeout)));
+        }
+        if let Some(write_timeout) = get_u64("SERVER_WRITE_TIMEOUT") {
+            options = options.write_timeout(Some(Duration::from_secs(write_timeout)));
+        }
+        if let Some(max_connections) = get_u64("SERVER_MAX_CONNECTIONS") {
+            options = options.max_connections(max_connections as usize);
+        }
+        options
+    }
+
+    /// Set the number of threads.
+    pub fn threads(mut self, threads: usize) -> ServerOptions {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Set the keep-alive timeout, `None` disables keep-alive.
+    pub fn keep_alive(mut self, keep_alive: Option<Duration>) -> ServerOptions {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Set the timeout for reading requests.
+    pub fn read_timeout(mut self, read_timeout: Option<Duration>) -> ServerOptions {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    /// Set the timeout for writing responses.
+    pub fn write_timeout(mut self, write_timeout: Option<Duration>) -> ServerOptions {
+        self.write_timeout = write_timeout;
+        self
+    }
+
+    /// Limit the number of connections that are served at the same time,
+    /// this caps the number of threads.
+    pub fn max_connections(mut self, max_connections: usize) -> ServerOptions {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    /// The number of threads the server uses, `None` for hyper's default.
+    pub fn thread_count(&self) -> Option<usize> {
+        match (self.threads, self.max_connections) {
+            (Some(threads), Some(max_connections)) => Some(threads.min(max_connections)),
+            (threads, None) => threads,
+            (None, max_connections) => max_connections,
+        }
+    }
+
+    /// Apply the options to the server and start handling connections.
+    fn serve<L: NetworkListener + Send + 'static>(&self, mut server: Server<L>, application: PencilApp) {
+        server.keep_alive(self.keep_alive);
+        server.set_read_timeout(self.read_timeout);
+        server.set_write_timeout(self.write_timeout);
+        let _guard = match self.thread_count() {
+            Some(threads) => server.handle_threads(application, threads.max(1)).unwrap(),
+            None => server.handle(application).unwrap(),
+        };
+    }
+}
 
 
 /// Run the `Pencil` application.
-pub fn run_server<A: ToSocketAddrs>(application: Pencil, addr: A) {
+pub fn run_server<A: ToSocketAddrs>(application: PencilApp, addr: A, options: &ServerOptions) {
     let server = Server::http(addr).unwrap();
-    let _guard = server.handle(application).unwrap();
+    options.serve(server, application);
+}
+
+
+/// Run the `Pencil` application over TLS.
+#[cfg(feature = "ssl")]
+pub fn run_tls_server<A: ToSocketAddrs>(application: PencilApp, addr: A, tls: TlsConfig, options: &ServerOptions) {
+    let ssl = match tls.openssl() {
+        Ok(ssl) => ssl,
+        Err(e) => panic!("Invalid TLS configuration: {}", e),
+    };
+    let server = Server::https(addr, ssl).unwrap();
+    options.serve(server, application);
+}
+
+
+#[test]
+fn test_server_options_from_config() {
+    use rustc_serialize::json::ToJson;
+    let mut config = Config::new();
+    assert!(ServerOptions::from_config(&config) == ServerOptions::new());
+    config.set("SERVER_THREADS", 16.to_json());
+    config.set("SERVER_KEEP_ALIVE", 0.to_json());
+    config.set("SERVER_READ_TIMEOUT", 30.to_json());
+    config.set("SERVER_MAX_CONNECTIONS", 8.to_json());
+    let options = ServerOptions::from_config(&config);
+    assert!(options == ServerOptions::new().threads(16).keep_alive(None)
+                                           .read_timeout(Some(Duration::from_secs(30))).max_connections(8));
+    assert!(options.thread_count() == Some(8));
 }
@@ -0,0 +1,240 @@
+//! This module implements sessions stored in signed cookies.  The session
+//! data is visible to the client but can't be modified without the secret
+//! key.
+
+use std::collections::BTreeMap;
+
+use hyper::header::CookiePair;
+use rustc_serialize::base64::{self, ToBase64, FromBase64};
+use rustc_serialize::json::{Json, ToJson};
+use time::{self, Duration};
+use typemap::Key;
+
+use config::Config;
+use signing::Signer;
+use wrappers::{Request, Response};
+
+
+/// The key for the permanent flag in the serialized data.
+const PERMANENT_KEY: &'static str = "_permanent";
+
+
+/// The session of one request, it is available with `request.session()`:
+///
+/// ```rust,ignore
+/// fn login(request: &mut Request) -> PencilResult {
+///     let session = request.session();
+///     session.set("user_id", 42.to_json());
+///     session.permanent = true;
+///     Ok(Response::from("Logged in"))
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Session {
+    data: BTreeMap<String, Json>,
+    /// Permanent sessions outlive the browser session, they expire after
+    /// `PERMANENT_SESSION_LIFETIME`.  Otherwise the cookie is deleted when
+    /// the browser is closed.
+    pub permanent: bool,
+    was_permanent: bool,
+    modified: bool,
+}
+
+impl Key for Session {
+    type Value = Session;
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    /// Get a value.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        self.data.get(key)
+    }
+
+    /// Set a value.
+    pub fn set(&mut self, key: &str, value: Json) {
+        self.data.insert(key.to_string(), value);
+        self.modified = true;
+    }
+
+    /// Remove a value.
+    pub fn remove(&mut self, key: &str) -> Option<Json> {
+        let value = self.data.remove(key);
+        if value.is_some() {
+            self.modified = true;
+        }
+        value
+    }
+
+    /// Remove all values, like on logout.
+    pub fn clear(&mut self) {
+        if !self.data.is_empty() {
+            self.data.clear();
+            self.modified = true;
+        }
+    }
+
+    /// Whether the session has no values.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Whether the session was changed during the request.
+    pub fn is_modified(&self) -> bool {
+        self.modified || self.permanent != self.was_permanent
+    }
+}
+
+
+/// The session cookie settings, read from the config:
+///
+/// - `SESSION_COOKIE_NAME`, defaults to `"session"`.
+/// - `SESSION_COOKIE_DOMAIN`, defaults to no domain.
+/// - `SESSION_COOKIE_PATH`, defaults to `"/"`.
+/// - `SESSION_COOKIE_SECURE`, defaults to `false`.
+/// - `SESSION_COOKIE_HTTPONLY`, defaults to `true`.
+/// - `SESSION_COOKIE_SAMESITE`, `"Lax"` or `"Strict"`, defaults to none.
+/// - `PERMANENT_SESSION_LIFETIME`, in seconds, defaults to 31 days.
+#[derive(Clone, Debug)]
+pub struct SessionCookieConfig {
+    pub name: String,
+    pub domain: Option<String>,
+    pub path: String,
+    pub secure: bool,
+    pub httponly: bool,
+    pub samesite: Option<String>,
+    pub lifetime: u64,
+}
+
+impl SessionCookieConfig {
+    pub fn from_config(config: &Config) -> SessionCookieConfig {
+        let get_string = |key: &str| config.get(key).and_then(|value| value.as_string().map(|value| value.to_string()));
+        SessionCookieConfig {
+            name: get_string("SESSION_COOKIE_NAME").unwrap_or_else(|| String::from("session")),
+            domain: get_string("SESSION_COOKIE_DOMAIN"),
+            path: get_string("SESSION_COOKIE_PATH").unwrap_or_else(|| String::from("/")),
+            secure: config.get_boolean("SESSION_COOKIE_SECURE", false),
+            httponly: config.get_boolean("SESSION_COOKIE_HTTPONLY", true),
+            samesite: get_string("SESSION_COOKIE_SAMESITE"),
+            lifetime: config.get("PERMANENT_SESSION_LIFETIME").and_then(|value| value.as_u64())
+                            .unwrap_or(31 * 24 * 60 * 60),
+        }
+    }
+
+    /// Create the cookie with the configured attributes.
+    fn cookie(&self, value: String) -> CookiePair {
+        let mut cookie = CookiePair::new(self.name.clone(), value);
+        cookie.domain = self.domain.clone();
+        cookie.path = Some(self.path.clone());
+        cookie.secure = self.secure;
+        cookie.httponly = self.httponly;
+        if let Some(ref samesite) = self.samesite {
+            cookie.custom.insert(String::from("SameSite"), samesite.clone());
+        }
+        cookie
+    }
+}
+
+
+/// Load the session from the cookie of the request.  Missing, tampered
+/// and expired cookies give an empty session.
+pub fn open_session(signer: &Signer, config: &SessionCookieConfig, request: &Request) -> Session {
+    let mut session = Session::new();
+    let value = match request.get_cookie(&config.name) {
+        Some(value) => value,
+        None => return session,
+    };
+    let payload = match signer.unsign_with_max_age(&value, config.lifetime) {
+        Ok(payload) => payload,
+        Err(_) => return session,
+    };
+    let data = match payload.from_base64().ok().and_then(|data| String::from_utf8(data).ok()) {
+        Some(data) => data,
+        None => return session,
+    };
+    if let Ok(Json::Object(mut data)) = Json::from_str(&data) {
+        session.permanent = match data.remove(PERMANENT_KEY) {
+            Some(Json::Boolean(permanent)) => permanent,
+            _ => false,
+        };
+        session.was_permanent = session.permanent;
+        session.data = data;
+    }
+    session
+}
+
+
+/// Save the session in the cookie of the response.  Permanent sessions
+/// are saved on every request to refresh the expiry, other sessions only
+/// if they were modified.  Empty sessions delete the cookie.
+pub fn save_session(signer: &Signer, config: &SessionCookieConfig, session: &Session, response: &mut Response) {
+    if !session.is_modified() && !session.permanent {
+        return;
+    }
+    if session.is_empty() {
+        if session.is_modified() {
+            let mut cookie = config.cookie(String::new());
+            cookie.max_age = Some(0);
+            cookie.expires = Some(time::at_utc(time::Timespec::new(0, 0)));
+            response.add_cookie(cookie);
+        }
+        return;
+    }
+    let mut data = session.data.clone();
+    if session.permanent {
+        data.insert(PERMANENT_KEY.to_string(), true.to_json());
+    }
+    let payload = Json::Object(data).to_string().as_bytes().to_base64(base64::URL_SAFE);
+    let mut cookie = config.cookie(signer.sign_with_timestamp(&payload));
+    if session.permanent {
+        cookie.max_age = Some(config.lifetime);
+        cookie.expires = Some(time::now_utc() + Duration::seconds(config.lifetime as i64));
+    }
+    response.add_cookie(cookie);
+}
+
+
+#[test]
+fn test_session_cookie_roundtrip() {
+    use app::Pencil;
+    use hyper::header::{Cookie, Headers, SetCookie};
+    use hyper::method::Method;
+    use testing::{PencilClient, TestResponse};
+    use types::PencilResult;
+    fn login(request: &mut Request) -> PencilResult {
+        let session = request.session();
+        session.set("user_id", 42.to_json());
+        session.permanent = true;
+        Ok(Response::from("ok"))
+    }
+    fn whoami(request: &mut Request) -> PencilResult {
+        let user_id = request.session().get("user_id").and_then(|id| id.as_u64()).unwrap_or(0);
+        Ok(Response::from(user_id.to_string()))
+    }
+    let mut app = Pencil::new("/test");
+    app.config.set("SECRET_KEY", "secret".to_json());
+    app.config.set("SESSION_COOKIE_SAMESITE", "Lax".to_json());
+    app.config.set("PERMANENT_SESSION_LIFETIME", 3600.to_json());
+    app.enable_sessions();
+    app.get("/login", "login", login);
+    app.get("/whoami", "whoami", whoami);
+    let client = PencilClient::new(&app);
+    let response = client.request(Method::Get, "/login").unwrap();
+    let cookie = response.headers.get::<SetCookie>().unwrap().0[0].clone();
+    assert!(cookie.name == "session");
+    assert!(cookie.max_age == Some(3600));
+    assert!(cookie.custom.get("SameSite") == Some(&String::from("Lax")));
+    let mut headers = Headers::new();
+    headers.set(Cookie(vec![CookiePair::new(cookie.name.clone(), cookie.value.clone())]));
+    let mut response = client.request_with_headers(Method::Get, "/whoami", &headers).unwrap();
+    let body = response.body_bytes();
+    assert!(body == b"42");
+    let mut headers = Headers::new();
+    headers.set(Cookie(vec![CookiePair::new(cookie.name.clone(), format!("x{}", cookie.value))]));
+    let mut response = client.request_with_headers(Method::Get, "/whoami", &headers).unwrap();
+    let body = response.body_bytes();
+    assert!(body == b"0");
+}
@@ -0,0 +1,118 @@
+//! This module implements signals, they notify subscribers about things
+//! that happen in the application, like a request that started or a
+//! template that was rendered.  Subscribers can't change what happens,
+//! which makes signals a good fit for instrumentation like metrics or
+//! tracing in extensions:
+//!
+//! ```rust,no_run
+//! use pencil::Pencil;
+//!
+//! let mut app = Pencil::new("/web/demo");
+//! app.signals.connect_request_finished(|request, response| {
+//!     println!("{} {}", request.path(), response.status_code);
+//! });
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+
+use rustc_serialize::json::Json;
+
+use types::PencilError;
+use wrappers::{Request, Response};
+
+
+/// Receiver type of the `request_started` signal.
+pub type RequestStartedFunc = Arc<Fn(&Request) + Send + Sync>;
+/// Receiver type of the `request_finished` signal.
+pub type RequestFinishedFunc = Arc<Fn(&Request, &Response) + Send + Sync>;
+/// Receiver type of the `got_request_exception` signal.
+pub type GotRequestExceptionFunc = Arc<Fn(&Request, &PencilError) + Send + Sync>;
+/// Receiver type of the `template_rendered` signal, it gets the template
+/// name, `None` for templates rendered from a string, and the context.
+pub type TemplateRenderedFunc = Arc<Fn(Option<&str>, &Json) + Send + Sync>;
+
+
+/// The signals of an application with their subscribers.
+#[derive(Clone, Default)]
+pub struct Signals {
+    request_started: Vec<RequestStartedFunc>,
+    request_finished: Vec<RequestFinishedFunc>,
+    got_request_exception: Vec<GotRequestExceptionFunc>,
+    template_rendered: Vec<TemplateRenderedFunc>,
+}
+
+impl Signals {
+    pub fn new() -> Signals {
+        Signals::default()
+    }
+
+    /// Subscribes to `request_started`, it is sent after the request is
+    /// matched and before any `before_request` hook runs.
+    pub fn connect_request_started<F>(&mut self, f: F) where F: Fn(&Request) + Send + Sync + 'static {
+        self.request_started.push(Arc::new(f));
+    }
+
+    /// Subscribes to `request_finished`, it is sent with the final
+    /// response, before the request is torn down.
+    pub fn connect_request_finished<F>(&mut self, f: F)
+        where F: Fn(&Request, &Response) + Send + Sync + 'static {
+        self.request_finished.push(Arc::new(f));
+    }
+
+    /// Subscribes to `got_request_exception`, it is sent when an error is
+    /// not handled by any error handler, before the error page is created.
+    pub fn connect_got_request_exception<F>(&mut self, f: F)
+        where F: Fn(&Request, &PencilError) + Send + Sync + 'static {
+        self.got_request_exception.push(Arc::new(f));
+    }
+
+    /// Subscribes to `template_rendered`, it is sent after a template is
+    /// rendered successfully.
+    pub fn connect_template_rendered<F>(&mut self, f: F)
+        where F: Fn(Option<&str>, &Json) + Send + Sync + 'static {
+        self.template_rendered.push(Arc::new(f));
+    }
+
+    #[doc(hidden)]
+    pub fn send_request_started(&self, request: &Request) {
+        for func in &self.request_started {
+            func(request);
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn send_request_finished(&self, request: &Request, response: &Response) {
+        for func in &self.request_finished {
+            func(request, response);
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn send_got_request_exception(&self, request: &Request, e: &PencilError) {
+        for func in &self.got_request_exception {
+            func(request, e);
+        }
+    }
+
+    /// Whether anyone subscribed to `template_rendered`, the context only
+    /// needs to be converted to json then.
+    #[doc(hidden)]
+    pub fn has_template_rendered_receivers(&self) -> bool {
+        !self.template_rendered.is_empty()
+    }
+
+    #[doc(hidden)]
+    pub fn send_template_rendered(&self, template_name: Option<&str>, context: &Json) {
+        for func in &self.template_rendered {
+            func(template_name, context);
+        }
+    }
+}
+
+impl fmt::Debug for Signals {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<Signals: {} receivers>", self.request_started.len() + self.request_finished.len() +
+               self.got_request_exception.len() + self.template_rendered.len())
+    }
+}
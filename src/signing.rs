@@ -0,0 +1,197 @@
+//! This module implements signing of values with the application's secret
+//! key, it is the base for sessions and other signed tokens.
+
+use std::error;
+use std::fmt;
+
+use rustc_serialize::base64::{self, ToBase64, FromBase64};
+use rustc_serialize::json::Json;
+use time;
+
+use config::Config;
+use crypto::{Digest, hmac, constant_time_eq};
+
+
+/// The secret keys of an application.  The current key signs new values,
+/// values signed with one of the fallback keys still validate so that keys
+/// can be rotated without invalidating every session at once.
+///
+/// The keys are read from the `SECRET_KEY` configuration key and the
+/// `SECRET_KEY_FALLBACKS` configuration key, which is a list of old keys.
+#[derive(Clone)]
+pub struct SecretKeys {
+    pub current: Vec<u8>,
+    pub fallbacks: Vec<Vec<u8>>,
+}
+
+impl SecretKeys {
+    pub fn new<S: AsRef<[u8]>>(current: S) -> SecretKeys {
+        SecretKeys {
+            current: current.as_ref().to_vec(),
+            fallbacks: Vec::new(),
+        }
+    }
+
+    /// Add an old key that is still accepted.
+    pub fn fallback<S: AsRef<[u8]>>(mut self, key: S) -> SecretKeys {
+        self.fallbacks.push(key.as_ref().to_vec());
+        self
+    }
+
+    /// Read the keys from the configuration, this returns `None` if there
+    /// is no `SECRET_KEY` or it is empty.
+    pub fn from_config(config: &Config) -> Option<SecretKeys> {
+        let mut keys = match config.get("SECRET_KEY") {
+            Some(Json::String(ref key)) if !key.is_empty() => SecretKeys::new(key),
+            _ => return None,
+        };
+        if let Some(Json::Array(fallbacks)) = config.get("SECRET_KEY_FALLBACKS") {
+            for key in fallbacks {
+                if let Json::String(ref key) = key {
+                    keys = keys.fallback(key);
+                }
+            }
+        }
+        Some(keys)
+    }
+
+    /// All keys, the current key first.
+    fn all(&self) -> Vec<&[u8]> {
+        let mut keys = vec![&self.current as &[u8]];
+        keys.extend(self.fallbacks.iter().map(|key| key as &[u8]));
+        keys
+    }
+}
+
+impl fmt::Debug for SecretKeys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<SecretKeys with {} fallbacks>", self.fallbacks.len())
+    }
+}
+
+
+/// The error returned if a signed value doesn't validate.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BadSignature;
+
+impl fmt::Display for BadSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Signature does not match")
+    }
+}
+
+impl error::Error for BadSignature {
+    fn description(&self) -> &str {
+        "signature does not match"
+    }
+}
+
+
+/// Signs values with HMAC-SHA256, the signed value looks like
+/// `<value>.<signature>`.  The salt separates the signatures of
+/// different features so a session cookie can't be used as another token:
+///
+/// ```rust
+/// use pencil::signing::{SecretKeys, Signer};
+///
+/// let signer = Signer::new(SecretKeys::new("secret"), "session");
+/// let signed = signer.sign("user-42");
+/// assert!(signer.unsign(&signed).unwrap() == "user-42");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Signer {
+    keys: SecretKeys,
+    salt: String,
+}
+
+impl Signer {
+    pub fn new(keys: SecretKeys, salt: &str) -> Signer {
+        Signer {
+            keys: keys,
+            salt: salt.to_string(),
+        }
+    }
+
+    /// Compute the signature of the value with one key.
+    fn signature(&self, key: &[u8], value: &str) -> Vec<u8> {
+        let derived_key = hmac(Digest::Sha256, key, self.salt.as_bytes());
+        hmac(Digest::Sha256, &derived_key, value.as_bytes())
+    }
+
+    /// Sign the value with the current key.
+    pub fn sign(&self, value: &str) -> String {
+        let signature = self.signature(&self.keys.current, value).to_base64(base64::URL_SAFE);
+        format!("{}.{}", value, signature)
+    }
+
+    /// Sign the value together with the current time, so that the age can
+    /// be checked with `unsign_with_max_age`.
+    pub fn sign_with_timestamp(&self, value: &str) -> String {
+        self.sign(&format!("{}.{}", value, time::get_time().sec))
+    }
+
+    /// Validate a value signed with `sign_with_timestamp` that must not be
+    /// older than `max_age` seconds.
+    pub fn unsign_with_max_age<'s>(&self, signed: &'s str, max_age: u64) -> Result<&'s str, BadSignature> {
+        let value = try!(self.unsign(signed));
+        let pos = match value.rfind('.') {
+            Some(pos) => pos,
+            None => return Err(BadSignature),
+        };
+        let timestamp: i64 = match value[pos + 1..].parse() {
+            Ok(timestamp) => timestamp,
+            Err(_) => return Err(BadSignature),
+        };
+        let age = time::get_time().sec - timestamp;
+        if age < 0 || age as u64 > max_age {
+            return Err(BadSignature);
+        }
+        Ok(&value[..pos])
+    }
+
+    /// Validate the signed value with the current key and all fallback
+    /// keys, this returns the value without signature.
+    pub fn unsign<'s>(&self, signed: &'s str) -> Result<&'s str, BadSignature> {
+        let pos = match signed.rfind('.') {
+            Some(pos) => pos,
+            None => return Err(BadSignature),
+        };
+        let (value, signature) = (&signed[..pos], &signed[pos + 1..]);
+        let signature = match signature.from_base64() {
+            Ok(signature) => signature,
+            Err(_) => return Err(BadSignature),
+        };
+        for key in self.keys.all() {
+            if constant_time_eq(&signature, &self.signature(key, value)) {
+                return Ok(value);
+            }
+        }
+        Err(BadSignature)
+    }
+}
+
+
+#[test]
+fn test_signer_key_rotation() {
+    let old_signer = Signer::new(SecretKeys::new("old"), "session");
+    let signed = old_signer.sign("value.with.dots");
+    assert!(old_signer.unsign(&signed) == Ok("value.with.dots"));
+    let signer = Signer::new(SecretKeys::new("new").fallback("old"), "session");
+    assert!(signer.unsign(&signed) == Ok("value.with.dots"));
+    assert!(signer.sign("value.with.dots") != signed);
+    let signer = Signer::new(SecretKeys::new("new"), "session");
+    assert!(signer.unsign(&signed) == Err(BadSignature));
+    let other_salt = Signer::new(SecretKeys::new("old"), "remember");
+    assert!(other_salt.unsign(&signed) == Err(BadSignature));
+    assert!(signer.unsign("no-signature") == Err(BadSignature));
+}
+
+
+#[test]
+fn test_signer_max_age() {
+    let signer = Signer::new(SecretKeys::new("secret"), "session");
+    let signed = signer.sign_with_timestamp("value");
+    assert!(signer.unsign_with_max_age(&signed, 60) == Ok("value"));
+    let old = signer.sign(&format!("value.{}", time::get_time().sec - 120));
+    assert!(signer.unsign_with_max_age(&old, 60) == Err(BadSignature));
+}
@@ -0,0 +1,254 @@
+//! This module implements helpers to generate sitemaps.
+
+use std::fmt;
+
+use hyper::method::Method;
+use time::Tm;
+
+use app::Pencil;
+use helpers::escape;
+use wrappers::{Request, Response};
+use types::{PencilResult, PenHTTPError};
+use http_errors::NotFound;
+
+
+/// Sitemap function type, it returns the dynamic entries of the sitemap.
+pub type SitemapFunc = fn(&mut Request) -> Vec<SitemapEntry>;
+
+
+/// The maximum number of URLs in one sitemap file.
+pub const MAX_SITEMAP_URLS: usize = 50000;
+
+
+/// How frequently the page is likely to change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChangeFreq {
+    Always,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    Never,
+}
+
+impl fmt::Display for ChangeFreq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            ChangeFreq::Always => "always",
+            ChangeFreq::Hourly => "hourly",
+            ChangeFreq::Daily => "daily",
+            ChangeFreq::Weekly => "weekly",
+            ChangeFreq::Monthly => "monthly",
+            ChangeFreq::Yearly => "yearly",
+            ChangeFreq::Never => "never",
+        })
+    }
+}
+
+
+/// One URL of a sitemap.
+#[derive(Clone, Debug)]
+pub struct SitemapEntry {
+    /// The URL of the page, paths starting with `/` are joined with the
+    /// host URL of the request when the sitemap is served.
+    pub loc: String,
+    pub lastmod: Option<Tm>,
+    pub changefreq: Option<ChangeFreq>,
+    pub priority: Option<f32>,
+}
+
+impl SitemapEntry {
+    pub fn new(loc: &str) -> SitemapEntry {
+        SitemapEntry {
+            loc: loc.to_string(),
+            lastmod: None,
+            changefreq: None,
+            priority: None,
+        }
+    }
+
+    /// Set the time the page was last modified.
+    pub fn lastmod(mut self, lastmod: Tm) -> SitemapEntry {
+        self.lastmod = Some(lastmod);
+        self
+    }
+
+    /// Set how frequently the page is likely to change.
+    pub fn changefreq(mut self, changefreq: ChangeFreq) -> SitemapEntry {
+        self.changefreq = Some(changefreq);
+        self
+    }
+
+    /// Set the priority of the page relative to other pages, between 0 and 1.
+    pub fn priority(mut self, priority: f32) -> SitemapEntry {
+        self.priority = Some(priority.max(0.0).min(1.0));
+        self
+    }
+}
+
+
+/// A sitemap, sitemaps with more than 50,000 URLs are split into multiple
+/// pages that are listed by a sitemap index.
+#[derive(Clone, Debug, Default)]
+pub struct Sitemap {
+    pub entries: Vec<SitemapEntry>,
+}
+
+impl Sitemap {
+    pub fn new() -> Sitemap {
+        Sitemap { entries: Vec::new() }
+    }
+
+    /// Add an entry.
+    pub fn add(&mut self, entry: SitemapEntry) {
+        self.entries.push(entry);
+    }
+
+    /// Add all rules of the application that listen for `GET` and have
+    /// no variables, like `/` or `/about`.  Static file rules and redirect
+    /// rules are skipped.
+    pub fn add_rules(&mut self, app: &Pencil) {
+        for rule in app.url_map.iter_rules() {
+            if !rule.methods.contains(&Method::Get) || rule.redirect_to.is_some() || rule.endpoint == "static" ||
+               rule.endpoint.ends_with(".static") || rule.endpoint == "sitemap" {
+                continue;
+            }
+            if let Some(ref rule_str) = rule.matcher.rule {
+                if !rule_str.contains('<') {
+                    self.add(SitemapEntry::new(rule_str));
+                }
+            }
+        }
+    }
+
+    /// The number of sitemap files needed for the entries.
+    pub fn pages(&self) -> usize {
+        if self.entries.is_empty() {
+            1
+        } else {
+            (self.entries.len() + MAX_SITEMAP_URLS - 1) / MAX_SITEMAP_URLS
+        }
+    }
+
+    /// Render the `urlset` of one page, pages start at 1.  This returns
+    /// `None` if the page doesn't exist.
+    pub fn page_xml(&self, page: usize, base_url: &str) -> Option<String> {
+        if page < 1 || page > self.pages() {
+            return None;
+        }
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+        let start = (page - 1) * MAX_SITEMAP_URLS;
+        for entry in self.entries.iter().skip(start).take(MAX_SITEMAP_URLS) {
+            xml.push_str("  <url>\n");
+            xml.push_str(&format!("    <loc>{}</loc>\n", escape(absolute_url(base_url, &entry.loc))));
+            if let Some(lastmod) = entry.lastmod {
+                xml.push_str(&format!("    <lastmod>{}</lastmod>\n", lastmod.to_utc().rfc3339()));
+            }
+            if let Some(changefreq) = entry.changefreq {
+                xml.push_str(&format!("    <changefreq>{}</changefreq>\n", changefreq));
+            }
+            if let Some(priority) = entry.priority {
+                xml.push_str(&format!("    <priority>{:.1}</priority>\n", priority));
+            }
+            xml.push_str("  </url>\n");
+        }
+        xml.push_str("</urlset>\n");
+        Some(xml)
+    }
+
+    /// Render the sitemap index that lists all pages, the pages are served
+    /// at `/sitemap<page>.xml`.
+    pub fn index_xml(&self, base_url: &str) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+        for page in 1..self.pages() + 1 {
+            let loc = absolute_url(base_url, &format!("/sitemap{}.xml", page));
+            xml.push_str(&format!("  <sitemap>\n    <loc>{}</loc>\n  </sitemap>\n", escape(loc)));
+        }
+        xml.push_str("</sitemapindex>\n");
+        xml
+    }
+}
+
+
+/// Join relative paths with the base URL.
+fn absolute_url(base_url: &str, loc: &str) -> String {
+    if loc.starts_with('/') {
+        format!("{}/{}", base_url.trim_right_matches('/'), loc.trim_left_matches('/'))
+    } else {
+        loc.to_string()
+    }
+}
+
+
+/// Create an XML response.
+fn xml_response(xml: String) -> Response {
+    let mut response = Response::from(xml);
+    response.set_content_type("application/xml");
+    response
+}
+
+
+/// Collect the sitemap for the request from the rules of the application and
+/// the entries returned by the registered sitemap function.
+fn build_sitemap(request: &mut Request) -> Sitemap {
+    let mut sitemap = Sitemap::new();
+    sitemap.add_rules(request.app);
+    if let Some(sitemap_func) = request.app.sitemap_func() {
+        for entry in sitemap_func(request) {
+            sitemap.add(entry);
+        }
+    }
+    sitemap
+}
+
+
+/// View function used internally to serve `/sitemap.xml`, this is a sitemap
+/// index if the entries don't fit into one sitemap.
+pub fn send_sitemap(request: &mut Request) -> PencilResult {
+    let sitemap = build_sitemap(request);
+    let base_url = request.host_url();
+    if sitemap.pages() > 1 {
+        Ok(xml_response(sitemap.index_xml(&base_url)))
+    } else {
+        Ok(xml_response(sitemap.page_xml(1, &base_url).unwrap()))
+    }
+}
+
+
+/// View function used internally to serve the pages of a sitemap index.
+pub fn send_sitemap_page(request: &mut Request) -> PencilResult {
+    let page: usize = match request.view_args.get("page").and_then(|page| page.parse().ok()) {
+        Some(page) => page,
+        None => return Err(PenHTTPError(NotFound)),
+    };
+    let sitemap = build_sitemap(request);
+    let base_url = request.host_url();
+    match sitemap.page_xml(page, &base_url) {
+        Some(xml) => Ok(xml_response(xml)),
+        None => Err(PenHTTPError(NotFound)),
+    }
+}
+
+
+#[test]
+fn test_sitemap_pages() {
+    let mut sitemap = Sitemap::new();
+    sitemap.add(SitemapEntry::new("/").changefreq(ChangeFreq::Daily).priority(0.8));
+    sitemap.add(SitemapEntry::new("http://other.example.com/about"));
+    assert!(sitemap.pages() == 1);
+    let xml = sitemap.page_xml(1, "http://example.com/").unwrap();
+    assert!(xml.contains("<loc>http://example.com/</loc>"));
+    assert!(xml.contains("<changefreq>daily</changefreq>"));
+    assert!(xml.contains("<priority>0.8</priority>"));
+    assert!(xml.contains("<loc>http://other.example.com/about</loc>"));
+    assert!(sitemap.page_xml(2, "http://example.com/").is_none());
+    for i in 0..MAX_SITEMAP_URLS {
+        sitemap.add(SitemapEntry::new(&format!("/post/{}", i)));
+    }
+    assert!(sitemap.pages() == 2);
+    let index = sitemap.index_xml("http://example.com/");
+    assert!(index.contains("<loc>http://example.com/sitemap2.xml</loc>"));
+}
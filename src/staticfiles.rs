@@ -0,0 +1,100 @@
+//! This module implements the providers of static files.  The static
+//! handler reads files from the static folder on disk by default, an
+//! application that should be deployed as a single binary can embed its
+//! files instead:
+//!
+//! ```rust,ignore
+//! use pencil::Pencil;
+//! use pencil::staticfiles::EmbeddedProvider;
+//!
+//! let mut app = Pencil::new("/web/demo");
+//! app.set_static_provider(EmbeddedProvider::new()
+//!     .add("app.js", include_bytes!("../static/app.js"))
+//!     .add("css/site.css", include_bytes!("../static/css/site.css")));
+//! app.enable_static_file_handling();
+//! ```
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use hyper::header::{ContentType, ETag, EntityTag};
+use mime_guess::guess_mime_type;
+use rustc_serialize::hex::ToHex;
+
+use crypto::sha256;
+use helpers::send_from_directory_range;
+use http_errors::NotFound;
+use types::{PenHTTPError, PencilResult};
+use wrappers::{Request, Response};
+
+
+/// A source of static files.  Implement this to serve static files from
+/// somewhere else than the filesystem.
+pub trait StaticProvider: Send + Sync {
+    /// Send the file with the given name, relative to the root of the
+    /// provider.  Returns `NotFound` if there is no such file.
+    fn send(&self, request: &Request, filename: &str) -> PencilResult;
+}
+
+
+/// Provides the files of a folder on disk, see `send_from_directory_range`.
+#[derive(Clone, Debug)]
+pub struct FilesystemProvider {
+    directory: String,
+}
+
+impl FilesystemProvider {
+    pub fn new(directory: &str) -> FilesystemProvider {
+        FilesystemProvider {
+            directory: directory.to_string(),
+        }
+    }
+}
+
+impl StaticProvider for FilesystemProvider {
+    fn send(&self, request: &Request, filename: &str) -> PencilResult {
+        send_from_directory_range(request, &self.directory, filename, false)
+    }
+}
+
+
+/// Provides files that are embedded in the binary, usually with
+/// `include_bytes!`.  Responses have a strong `ETag` computed from the
+/// contents, so clients can revalidate their cached copies.
+#[derive(Clone, Debug, Default)]
+pub struct EmbeddedProvider {
+    files: HashMap<String, (&'static [u8], EntityTag)>,
+}
+
+impl EmbeddedProvider {
+    pub fn new() -> EmbeddedProvider {
+        EmbeddedProvider::default()
+    }
+
+    /// Add a file with the given name, like `css/site.css`.
+    pub fn add(mut self, filename: &str, contents: &'static [u8]) -> EmbeddedProvider {
+        let etag = EntityTag::strong(sha256(contents)[..16].to_hex());
+        self.files.insert(filename.trim_left_matches('/').to_string(), (contents, etag));
+        self
+    }
+
+    /// Check if a file with the given name was added.
+    pub fn contains(&self, filename: &str) -> bool {
+        self.files.contains_key(filename.trim_left_matches('/'))
+    }
+}
+
+impl StaticProvider for EmbeddedProvider {
+    fn send(&self, request: &Request, filename: &str) -> PencilResult {
+        match self.files.get(filename.trim_left_matches('/')) {
+            Some(&(contents, ref etag)) => {
+                let mut response = Response::from(contents);
+                response.headers.set(ContentType(guess_mime_type(Path::new(filename))));
+                response.headers.set(ETag(etag.clone()));
+                response.make_conditional(request);
+                Ok(response)
+            },
+            None => Err(PenHTTPError(NotFound)),
+        }
+    }
+}
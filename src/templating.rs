@@ -1,17 +1,105 @@
 //! This module implements the bridge to handlebars.
 use std::convert;
+use std::fmt;
 use std::io::Read;
 use std::io::Result as IOResult;
 use std::fs::File;
 use std::path::PathBuf;
 use std::error::Error;
 
-use rustc_serialize::json::ToJson;
-use handlebars::{RenderError, TemplateRenderError};
+use std::collections::BTreeMap;
+
+use rustc_serialize::json::{Json, ToJson};
+use handlebars::{Context, Handlebars, Helper, JsonRender, RenderContext, RenderError, TemplateRenderError};
 
 use app::Pencil;
 use types::{PencilResult, PenUserError, UserError, PencilError};
 use wrappers::Response;
+use json::apply_json_encoders;
+
+
+/// The key of the JSON object that `SafeHtml` values are converted to.
+const SAFE_HTML_KEY: &'static str = "__html__";
+
+
+/// A trusted HTML fragment, like rendered markdown or a widget.  Values in
+/// template contexts are escaped when they are inserted with `{{name}}`,
+/// `SafeHtml` values are inserted as they are with the `html` helper:
+///
+/// ```rust,ignore
+/// let mut context = BTreeMap::new();
+/// context.insert("title".to_string(), post.title.to_json());
+/// context.insert("body".to_string(), SafeHtml::new(markdown_to_html(&post.body)).to_json());
+/// request.app.render_template("post.html", &context)
+/// ```
+///
+/// ```html
+/// <h1>{{title}}</h1>
+/// {{html body}}
+/// ```
+///
+/// The value is converted to a JSON object `{"__html__": "..."}`, so user
+/// input, which is a string, can't mark itself as safe.  The `html` helper
+/// escapes strings like `{{name}}` does.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SafeHtml(String);
+
+impl SafeHtml {
+    pub fn new<S: Into<String>>(html: S) -> SafeHtml {
+        SafeHtml(html.into())
+    }
+
+    /// The HTML fragment.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl ToJson for SafeHtml {
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        object.insert(SAFE_HTML_KEY.to_string(), Json::String(self.0.clone()));
+        Json::Object(object)
+    }
+}
+
+impl fmt::Display for SafeHtml {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+
+/// The escape function of the handlebars registry.  This escapes single
+/// quotes too, so values are safe in single quoted attributes.
+fn escape_html(data: &str) -> String {
+    data.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;")
+        .replace("\"", "&quot;").replace("'", "&#x27;")
+}
+
+
+/// The `html` helper, `{{html body}}` inserts `SafeHtml` values as they
+/// are and escapes everything else.
+fn html_helper(c: &Context, h: &Helper, _: &Handlebars, rc: &mut RenderContext) -> Result<(), RenderError> {
+    let param = try!(h.param(0).ok_or_else(|| RenderError::new("Param not found for helper \"html\"")));
+    let value = c.navigate(rc.get_path(), param);
+    let rendered = match value.find(SAFE_HTML_KEY) {
+        Some(&Json::String(ref html)) => html.clone(),
+        _ => escape_html(&value.render()),
+    };
+    try!(rc.writer.write(rendered.as_bytes()));
+    Ok(())
+}
+
+
+/// Create the handlebars registry with the escape function and the `html`
+/// helper for `SafeHtml`.
+pub fn create_registry() -> Handlebars {
+    let mut registry = Handlebars::new();
+    registry.register_escape_fn(escape_html);
+    registry.register_helper("html", Box::new(html_helper));
+    registry
+}
 
 impl convert::From<RenderError> for PencilError {
     fn from(err: RenderError) -> PencilError {
@@ -31,7 +119,15 @@ pub fn render_template<T: ToJson>(app: &Pencil, template_name: &str, context: &T
         return Err(PenUserError(UserError::new("Can't acquire handlebars registry")));
     }
     let registry = registry_read_rv.unwrap();
-    let rv = try!(registry.render(template_name, context));
+    let rv = if app.json_encoders().is_empty() {
+        try!(registry.render(template_name, context))
+    } else {
+        let context = apply_json_encoders(app.json_encoders(), context.to_json());
+        try!(registry.render(template_name, &context))
+    };
+    if app.signals.has_template_rendered_receivers() {
+        app.signals.send_template_rendered(Some(template_name), &context.to_json());
+    }
     Ok(Response::from(rv))
 }
 
@@ -41,7 +137,15 @@ pub fn render_template_string<T: ToJson>(app: &Pencil, source: &str, context: &T
         return Err(PenUserError(UserError::new("Can't acquire handlebars registry")));
     }
     let registry = registry_read_rv.unwrap();
-    let rv = try!(registry.template_render(source, context));
+    let rv = if app.json_encoders().is_empty() {
+        try!(registry.template_render(source, context))
+    } else {
+        let context = apply_json_encoders(app.json_encoders(), context.to_json());
+        try!(registry.template_render(source, &context))
+    };
+    if app.signals.has_template_rendered_receivers() {
+        app.signals.send_template_rendered(None, &context.to_json());
+    }
     Ok(Response::from(rv))
 }
 
@@ -109,3 +213,20 @@ pub fn load_template(app: &Pencil, template_name: &str) -> Option<IOResult<Strin
     }
     None
 }
+
+
+#[test]
+fn test_safe_html_escaping() {
+    use testing::TestResponse;
+    let app = Pencil::new("/test");
+    let mut context = BTreeMap::new();
+    context.insert("name".to_string(), "<script>'x'</script>".to_json());
+    context.insert("body".to_string(), SafeHtml::new("<p>Hi</p>").to_json());
+    let mut response = app.render_template_string("{{name}} {{html name}} {{html body}}", &context).unwrap();
+    let rendered = response.body_text();
+    assert!(rendered == "&lt;script&gt;&#x27;x&#x27;&lt;/script&gt; &lt;script&gt;&#x27;x&#x27;&lt;/script&gt; <p>Hi</p>");
+    // A string that looks like a marker is still escaped.
+    context.insert("name".to_string(), "{\"__html__\":\"<b>\"}".to_json());
+    let mut response = app.render_template_string("{{html name}}", &context).unwrap();
+    assert!(response.body_text() == "{&quot;__html__&quot;:&quot;&lt;b&gt;&quot;}");
+}
@@ -1,11 +1,83 @@
 //! This module implements test support helpers.
 
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write, Cursor};
+use std::net::SocketAddr;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::time::Duration;
+
+use hyper::buffer::BufReader;
+use hyper::header::{Headers, ContentLength, ContentType, Host, TransferEncoding};
+use hyper::method::Method;
+use hyper::net::NetworkStream;
+use hyper::server::Request as HTTPRequest;
+
+use rand;
+use rustc_serialize::json::Json;
+use url::form_urlencoded;
+
 use app::Pencil;
-use wrappers::{Request, Response};
+use module::Module;
+use wrappers::{Request, Response, ResponseBody};
+
+
+/// An in memory stream that reads a raw HTTP request, everything written
+/// to it is dropped.
+struct MockStream {
+    data: Cursor<Vec<u8>>,
+}
+
+impl MockStream {
+    fn new(data: Vec<u8>) -> MockStream {
+        MockStream { data: Cursor::new(data) }
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.data.read(buf)
+    }
+}
 
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 
-/// This type allows to send requests to a wrapped application.
-#[allow(dead_code)]
+impl NetworkStream for MockStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok("127.0.0.1:1337".parse().unwrap())
+    }
+
+    fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+/// This type allows to send requests to a wrapped application:
+///
+/// ```rust,ignore
+/// let client = app.test_client();
+/// let response = client.post("/upload")
+///                      .file("avatar", "cat.png", png_bytes, "image/png")
+///                      .field("name", "cat")
+///                      .send()
+///                      .unwrap();
+/// assert!(response.status_code == 200);
+/// ```
 pub struct PencilClient<'c> {
     application: &'c Pencil,
 }
@@ -17,22 +89,456 @@ impl<'c> PencilClient<'c> {
     }
 
     /// Get wrapped application.
-    #[allow(dead_code)]
     pub fn get_application(&self) -> &Pencil {
         self.application
     }
 
+    /// Start building a request with the method for the path.
+    pub fn open_request(&self, method: Method, path: &str) -> TestRequest<'c> {
+        TestRequest::new(self.application, method, path)
+    }
+
+    /// Start building a `GET` request.
+    pub fn get_path(&self, path: &str) -> TestRequest<'c> {
+        self.open_request(Method::Get, path)
+    }
+
     /// Runs the wrapped pencil app with the given request.
-    fn run_pencil_app(&self, request: &mut Request) -> Response {
-        self.application.handle_request(request)
+    #[deprecated(note = "use `get_path` to build the request instead")]
+    pub fn get(&self, mut request: Request) -> Response {
+        self.application.handle_request(&mut request)
+    }
+
+    /// Start building a `POST` request.
+    pub fn post(&self, path: &str) -> TestRequest<'c> {
+        self.open_request(Method::Post, path)
+    }
+
+    /// Start building a `PUT` request.
+    pub fn put(&self, path: &str) -> TestRequest<'c> {
+        self.open_request(Method::Put, path)
+    }
+
+    /// Start building a `PATCH` request.
+    pub fn patch(&self, path: &str) -> TestRequest<'c> {
+        self.open_request(Method::Patch, path)
+    }
+
+    /// Start building a `DELETE` request.
+    pub fn delete(&self, path: &str) -> TestRequest<'c> {
+        self.open_request(Method::Delete, path)
+    }
+
+    /// Send a request without body for the path to the wrapped application,
+    /// the request never touches the network.
+    pub fn request(&self, method: Method, path: &str) -> Result<Response, String> {
+        self.request_with_headers(method, path, &Headers::new())
+    }
+
+    /// Send a request without body with additional headers.
+    pub fn request_with_headers(&self, method: Method, path: &str, headers: &Headers) -> Result<Response, String> {
+        self.request_raw(method, path, headers, &[])
     }
 
-    fn open(&self, mut request: Request) -> Response {
-        self.run_pencil_app(&mut request)
+    /// Send a request with the body, the `Content-Length` header is set
+    /// for non-empty bodies unless they are sent with `Transfer-Encoding`.
+    fn request_raw(&self, method: Method, path: &str, headers: &Headers, body: &[u8]) -> Result<Response, String> {
+        let application = self.application;
+        with_raw_request(application, method, path, headers, body, |request| application.handle_request(request))
     }
+}
 
-    #[allow(dead_code)]
-    pub fn get(&self, request: Request) -> Response {
-        self.open(request)
+
+/// Build a request in memory and pass it to the function.
+fn with_raw_request<T, F>(application: &Pencil, method: Method, path: &str, headers: &Headers, body: &[u8],
+                          f: F) -> Result<T, String> where F: FnOnce(&mut Request) -> T {
+    let mut headers = headers.clone();
+    if !body.is_empty() && !headers.has::<TransferEncoding>() {
+        headers.set(ContentLength(body.len() as u64));
+    }
+    if !headers.has::<Host>() {
+        headers.set(Host { hostname: String::from("localhost"), port: None });
     }
+    let mut raw = format!("{} {} HTTP/1.1\r\n{}\r\n", method, path, headers).into_bytes();
+    raw.extend_from_slice(body);
+    let mut stream = MockStream::new(raw);
+    let remote_addr = try!(stream.peer_addr().map_err(|e| e.to_string()));
+    let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+    let http_request = try!(HTTPRequest::new(&mut reader, remote_addr).map_err(|e| e.to_string()));
+    let mut request = try!(Request::new(application, http_request));
+    Ok(f(&mut request))
+}
+
+
+/// A throwaway application with one module registered on it, so that a
+/// module can be tested without the application that uses it.  The
+/// module's routes, hooks and error handlers are applied like on a real
+/// application and testing mode is enabled:
+///
+/// ```rust,ignore
+/// let tester = ModuleTestApp::new(users_module());
+/// let response = tester.client().get_path("/users/42").send().unwrap();
+/// assert!(response.status_code == 200);
+/// ```
+///
+/// The application is available through `Deref`, so config keys the
+/// module needs can be set before sending requests.
+pub struct ModuleTestApp {
+    app: Pencil,
+}
+
+impl ModuleTestApp {
+    /// Register the module on a new application with the module's root path.
+    pub fn new(module: Module) -> ModuleTestApp {
+        let mut app = Pencil::new(&module.root_path);
+        app.set_testing(true);
+        app.register_module(module);
+        ModuleTestApp { app: app }
+    }
+
+    /// Create a test client for the application.
+    pub fn client(&self) -> PencilClient {
+        self.app.test_client()
+    }
+}
+
+impl Deref for ModuleTestApp {
+    type Target = Pencil;
+
+    fn deref(&self) -> &Pencil {
+        &self.app
+    }
+}
+
+impl DerefMut for ModuleTestApp {
+    fn deref_mut(&mut self) -> &mut Pencil {
+        &mut self.app
+    }
+}
+
+
+/// One file of a multipart request.
+struct TestFile {
+    name: String,
+    filename: String,
+    data: Vec<u8>,
+    content_type: String,
+}
+
+
+/// A request that is built for the test client.  Form fields are sent
+/// url encoded, as soon as a file is added the body is encoded as
+/// `multipart/form-data`.
+pub struct TestRequest<'c> {
+    application: &'c Pencil,
+    method: Method,
+    path: String,
+    headers: Headers,
+    body: Vec<u8>,
+    fields: Vec<(String, String)>,
+    files: Vec<TestFile>,
+}
+
+impl<'c> TestRequest<'c> {
+    /// Start building a request, see `PencilClient::open_request` and
+    /// `Pencil::test_request_context`.
+    pub fn new(application: &'c Pencil, method: Method, path: &str) -> TestRequest<'c> {
+        TestRequest {
+            application: application,
+            method: method,
+            path: path.to_string(),
+            headers: Headers::new(),
+            body: Vec::new(),
+            fields: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Set a header.
+    pub fn header(mut self, name: &str, value: &str) -> TestRequest<'c> {
+        self.headers.set_raw(name.to_string(), vec![value.as_bytes().to_vec()]);
+        self
+    }
+
+    /// Set the raw body with its content type.
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B, content_type: &str) -> TestRequest<'c> {
+        self.body = body.into();
+        self.header("Content-Type", content_type)
+    }
+
+    /// Add a form field.
+    pub fn field(mut self, name: &str, value: &str) -> TestRequest<'c> {
+        self.fields.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Add a file upload.
+    pub fn file<B: Into<Vec<u8>>>(mut self, name: &str, filename: &str, data: B, content_type: &str) -> TestRequest<'c> {
+        self.files.push(TestFile {
+            name: name.to_string(),
+            filename: filename.to_string(),
+            data: data.into(),
+            content_type: content_type.to_string(),
+        });
+        self
+    }
+
+    /// Create a boundary that doesn't occur in any part.
+    fn boundary(&self) -> String {
+        loop {
+            let boundary = format!("------------------------{:016x}", rand::random::<u64>());
+            let used = self.fields.iter().any(|&(ref name, ref value)| {
+                name.contains(&boundary as &str) || value.contains(&boundary as &str)
+            }) || self.files.iter().any(|file| {
+                contains_bytes(&file.data, boundary.as_bytes())
+            });
+            if !used {
+                return boundary;
+            }
+        }
+    }
+
+    /// Encode the fields and files as multipart body.
+    fn multipart_body(&self, boundary: &str) -> Vec<u8> {
+        let mut body = Vec::new();
+        for &(ref name, ref value) in &self.fields {
+            body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                                boundary, quote(name), value).into_bytes());
+        }
+        for file in &self.files {
+            body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\
+                                 Content-Type: {}\r\n\r\n",
+                                boundary, quote(&file.name), quote(&file.filename), file.content_type).into_bytes());
+            body.extend_from_slice(&file.data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend(format!("--{}--\r\n", boundary).into_bytes());
+        body
+    }
+
+    /// Encode the fields and files as body.
+    fn encode_body(mut self) -> TestRequest<'c> {
+        if !self.files.is_empty() {
+            let boundary = self.boundary();
+            self.body = self.multipart_body(&boundary);
+            self = self.header("Content-Type", &format!("multipart/form-data; boundary={}", boundary));
+        } else if !self.fields.is_empty() {
+            let mut serializer = form_urlencoded::Serializer::new(String::new());
+            for &(ref name, ref value) in &self.fields {
+                serializer.append_pair(name, value);
+            }
+            self.body = serializer.finish().into_bytes();
+            self = self.header("Content-Type", "application/x-www-form-urlencoded");
+        }
+        self
+    }
+
+    /// Send the request to the application.
+    pub fn send(self) -> Result<Response, String> {
+        let request = self.encode_body();
+        let client = PencilClient::new(request.application);
+        client.request_raw(request.method, &request.path, &request.headers, &request.body)
+    }
+
+    /// Build the request in memory and pass it to the function instead of
+    /// sending it.  The URL is matched, so `url_rule` and `view_args` are
+    /// set, but no hooks or views run.  This is useful for testing hooks
+    /// and helpers that take a request:
+    ///
+    /// ```rust,ignore
+    /// let result = app.test_request_context(Method::Get, "/admin")
+    ///                 .header("Authorization", "Bearer secret")
+    ///                 .with_request(|request| require_token(request))
+    ///                 .unwrap();
+    /// assert!(result.is_none());
+    /// ```
+    pub fn with_request<T, F>(self, f: F) -> Result<T, String> where F: FnOnce(&mut Request) -> T {
+        let request = self.encode_body();
+        with_raw_request(request.application, request.method, &request.path, &request.headers, &request.body, |request| {
+            request.match_request();
+            f(request)
+        })
+    }
+}
+
+
+/// Accessors for the responses of the test client:
+///
+/// ```rust,ignore
+/// use pencil::testing::TestResponse;
+///
+/// let mut response = app.test_client().get_path("/").send().unwrap();
+/// assert!(response.body_text() == "Hello World!");
+/// ```
+pub trait TestResponse {
+    /// The body of the response.  It is read into memory, so it can be
+    /// read again.
+    fn body_bytes(&mut self) -> Vec<u8>;
+    /// The body of the response as text, invalid UTF-8 is replaced.
+    fn body_text(&mut self) -> String;
+}
+
+impl TestResponse for Response {
+    fn body_bytes(&mut self) -> Vec<u8> {
+        let mut body = Vec::new();
+        if let Some(mut response_body) = self.body.take() {
+            response_body.write_body(&mut ResponseBody::new(&mut body)).unwrap();
+        }
+        self.body = Some(Box::new(body.clone()));
+        body
+    }
+
+    fn body_text(&mut self) -> String {
+        String::from_utf8_lossy(&self.body_bytes()).into_owned()
+    }
+}
+
+
+/// Escape quotes in a header parameter.
+fn quote(value: &str) -> String {
+    value.replace("\\", "\\\\").replace("\"", "\\\"")
+}
+
+
+/// Whether the needle occurs in the data.
+fn contains_bytes(data: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty() || data.windows(needle.len()).any(|window| window == needle)
+}
+
+
+/// A snapshot of a response for regression tests.  It keeps the status
+/// code, the selected headers and the body, JSON bodies are pretty printed
+/// with sorted keys and line endings are normalized so that snapshots are
+/// stable and easy to review:
+///
+/// ```rust,ignore
+/// let response = app.test_client().get_path("/users/42").send().unwrap();
+/// ResponseSnapshot::new(response, &["Content-Type"]).assert_matches("tests/snapshots/user.txt");
+/// ```
+///
+/// The snapshot file is written if it doesn't exist yet, set the
+/// `PENCIL_UPDATE_SNAPSHOTS` environment variable to overwrite snapshots
+/// after an intended change.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResponseSnapshot {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl ResponseSnapshot {
+    /// Take the snapshot, the body of the response is consumed.
+    pub fn new(mut response: Response, headers: &[&str]) -> ResponseSnapshot {
+        let mut body = Vec::new();
+        if let Some(mut response_body) = response.body.take() {
+            response_body.write_body(&mut ResponseBody::new(&mut body)).unwrap();
+        }
+        let body = String::from_utf8_lossy(&body).into_owned();
+        let is_json = match response.headers.get::<ContentType>() {
+            Some(&ContentType(ref mimetype)) => mimetype.to_string().contains("json"),
+            None => false,
+        };
+        let body = match Json::from_str(&body) {
+            Ok(ref json) if is_json => json.pretty().to_string(),
+            _ => normalize_text(&body),
+        };
+        let headers = headers.iter().filter_map(|name| {
+            response.headers.get_raw(name).map(|values| {
+                let values: Vec<String> = values.iter().map(|value| String::from_utf8_lossy(value).into_owned()).collect();
+                (name.to_string(), values.join(", "))
+            })
+        }).collect();
+        ResponseSnapshot {
+            status_code: response.status_code,
+            headers: headers,
+            body: body,
+        }
+    }
+
+    /// Compare the snapshot with the snapshot file, this panics with a
+    /// line diff if they differ.
+    pub fn assert_matches<P: AsRef<Path>>(&self, path: P) {
+        let path = path.as_ref();
+        let actual = self.to_string();
+        if !path.exists() || env::var_os("PENCIL_UPDATE_SNAPSHOTS").is_some() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).unwrap();
+            }
+            File::create(path).and_then(|mut file| file.write_all(actual.as_bytes())).unwrap();
+            return;
+        }
+        let mut expected = String::new();
+        File::open(path).and_then(|mut file| file.read_to_string(&mut expected)).unwrap();
+        let expected = normalize_text(&expected);
+        if expected != actual {
+            panic!("Response does not match the snapshot {}:\n{}", path.display(), diff_lines(&expected, &actual));
+        }
+    }
+}
+
+impl fmt::Display for ResponseSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "{}", self.status_code));
+        for &(ref name, ref value) in &self.headers {
+            try!(writeln!(f, "{}: {}", name, value));
+        }
+        try!(writeln!(f, ""));
+        try!(f.write_str(&self.body));
+        if !self.body.is_empty() && !self.body.ends_with('\n') {
+            try!(writeln!(f, ""));
+        }
+        Ok(())
+    }
+}
+
+
+/// Use `\n` line endings and strip trailing whitespace from lines.
+fn normalize_text(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().map(|line| line.trim_right()).collect();
+    let mut text = lines.join("\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    text
+}
+
+
+/// A line diff of two texts, removed lines start with `-` and added lines
+/// with `+`.
+fn diff_lines(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    // The length of the longest common subsequence of the remaining lines.
+    let mut lengths = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lengths[i][j] = if expected[i] == actual[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            diff.push_str(&format!("  {}\n", expected[i]));
+            i += 1;
+            j += 1;
+        } else if i < expected.len() && (j == actual.len() || lengths[i + 1][j] >= lengths[i][j + 1]) {
+            diff.push_str(&format!("- {}\n", expected[i]));
+            i += 1;
+        } else {
+            diff.push_str(&format!("+ {}\n", actual[j]));
+            j += 1;
+        }
+    }
+    diff
+}
+
+
+#[test]
+fn test_diff_lines() {
+    assert!(diff_lines("a\nb\nc\n", "a\nx\nc\n") == "  a\n- b\n+ x\n  c\n");
 }
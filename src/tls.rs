@@ -0,0 +1,184 @@
+//! This module implements serving over TLS, optionally with client
+//! certificate authentication for service-to-service requests.
+
+#[cfg(feature = "ssl")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "ssl")]
+use std::sync::Arc;
+
+#[cfg(feature = "ssl")]
+use hyper::net::{HttpStream, Openssl};
+use hyper::server::Request as HTTPRequest;
+#[cfg(feature = "ssl")]
+use openssl::nid::Nid;
+#[cfg(feature = "ssl")]
+use openssl::ssl::{SslContext, SslMethod, SslStream, SSL_VERIFY_NONE, SSL_VERIFY_PEER,
+                   SSL_VERIFY_FAIL_IF_NO_PEER_CERT, SSL_OP_NO_SSLV2, SSL_OP_NO_SSLV3};
+#[cfg(feature = "ssl")]
+use openssl::ssl::error::SslError;
+#[cfg(feature = "ssl")]
+use openssl::x509::X509FileType;
+use typemap::Key;
+
+
+/// The subject of the client certificate that was verified during the TLS
+/// handshake.  It is stored in `request.extensions_data` for every request
+/// on a connection with a client certificate:
+///
+/// ```rust,ignore
+/// fn internal(request: &mut Request) -> PencilResult {
+///     match request.extensions_data.get::<ClientCertificate>() {
+///         Some(cert) => Ok(Response::from(format!("Hello {:?}", cert.common_name))),
+///         None => Err(PenHTTPError(Forbidden)),
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientCertificate {
+    /// The common name (`CN`) of the subject.
+    pub common_name: Option<String>,
+    /// The organization (`O`) of the subject.
+    pub organization: Option<String>,
+    /// The organizational unit (`OU`) of the subject.
+    pub organizational_unit: Option<String>,
+}
+
+impl Key for ClientCertificate {
+    type Value = ClientCertificate;
+}
+
+
+/// The cipher suites offered by the server, forward secret AEAD ciphers
+/// first, without anonymous, export, DES, RC4 and MD5 based suites.
+#[cfg(feature = "ssl")]
+const TLS_CIPHERS: &'static str = "ECDHE-ECDSA-AES128-GCM-SHA256:ECDHE-RSA-AES128-GCM-SHA256:\
+                                   ECDHE-ECDSA-AES256-GCM-SHA384:ECDHE-RSA-AES256-GCM-SHA384:\
+                                   DHE-RSA-AES128-GCM-SHA256:DHE-RSA-AES256-GCM-SHA384:\
+                                   ECDHE-ECDSA-AES128-SHA256:ECDHE-RSA-AES128-SHA256:\
+                                   ECDHE-RSA-AES128-SHA:ECDHE-RSA-AES256-SHA:\
+                                   !aNULL:!eNULL:!EXPORT:!DES:!RC4:!MD5";
+
+
+/// The TLS settings of the server:
+///
+/// ```rust,no_run
+/// use pencil::Pencil;
+/// use pencil::tls::TlsConfig;
+///
+/// let app = Pencil::new("/web/internal");
+/// let tls = TlsConfig::new("/etc/internal/cert.pem", "/etc/internal/key.pem")
+///     .client_ca("/etc/internal/ca.pem", true);
+/// app.run_tls("0.0.0.0:8443", tls);
+/// ```
+#[cfg(feature = "ssl")]
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    client_ca_file: Option<PathBuf>,
+    require_client_cert: bool,
+}
+
+#[cfg(feature = "ssl")]
+impl TlsConfig {
+    /// Create the settings with the PEM encoded certificate chain and
+    /// private key of the server.
+    pub fn new<C: AsRef<Path>, K: AsRef<Path>>(cert_file: C, key_file: K) -> TlsConfig {
+        TlsConfig {
+            cert_file: cert_file.as_ref().to_path_buf(),
+            key_file: key_file.as_ref().to_path_buf(),
+            client_ca_file: None,
+            require_client_cert: false,
+        }
+    }
+
+    /// Verify client certificates against the PEM encoded CA bundle.
+    /// If `required` is true, connections without a valid client
+    /// certificate fail during the handshake, otherwise only presented
+    /// certificates are verified.
+    pub fn client_ca<P: AsRef<Path>>(mut self, ca_file: P, required: bool) -> TlsConfig {
+        self.client_ca_file = Some(ca_file.as_ref().to_path_buf());
+        self.require_client_cert = required;
+        self
+    }
+
+    /// Build the OpenSSL context for hyper.
+    pub fn openssl(&self) -> Result<Openssl, SslError> {
+        let ctx = try!(self.context());
+        Ok(Openssl { context: Arc::new(ctx) })
+    }
+
+    /// Build the OpenSSL context.  `Sslv23` negotiates the highest version
+    /// both sides support, SSLv2 and SSLv3 are disabled.
+    fn context(&self) -> Result<SslContext, SslError> {
+        let mut ctx = try!(SslContext::new(SslMethod::Sslv23));
+        ctx.set_options(SSL_OP_NO_SSLV2 | SSL_OP_NO_SSLV3);
+        try!(ctx.set_cipher_list(TLS_CIPHERS));
+        try!(ctx.set_certificate_chain_file(&self.cert_file, X509FileType::PEM));
+        try!(ctx.set_private_key_file(&self.key_file, X509FileType::PEM));
+        try!(ctx.check_private_key());
+        match self.client_ca_file {
+            Some(ref ca_file) => {
+                try!(ctx.set_CA_file(ca_file));
+                let mut mode = SSL_VERIFY_PEER;
+                if self.require_client_cert {
+                    mode = mode | SSL_VERIFY_FAIL_IF_NO_PEER_CERT;
+                }
+                ctx.set_verify(mode, None);
+            },
+            None => ctx.set_verify(SSL_VERIFY_NONE, None),
+        }
+        Ok(ctx)
+    }
+}
+
+
+/// Read the verified client certificate of the connection.
+#[cfg(feature = "ssl")]
+pub fn client_certificate(request: &HTTPRequest) -> Option<ClientCertificate> {
+    let stream = match request.ssl::<SslStream<HttpStream>>() {
+        Some(stream) => stream,
+        None => return None,
+    };
+    stream.ssl().peer_certificate().map(|cert| {
+        let subject = cert.subject_name();
+        ClientCertificate {
+            common_name: subject.text_by_nid(Nid::CN).map(|s| s.to_string()),
+            organization: subject.text_by_nid(Nid::O).map(|s| s.to_string()),
+            organizational_unit: subject.text_by_nid(Nid::OU).map(|s| s.to_string()),
+        }
+    })
+}
+
+
+/// Without TLS support there are no client certificates.
+#[cfg(not(feature = "ssl"))]
+pub fn client_certificate(_: &HTTPRequest) -> Option<ClientCertificate> {
+    None
+}
+
+
+#[cfg(all(test, feature = "ssl"))]
+fn write_test_certificate(dir: &Path) {
+    use std::fs::File;
+    use openssl::x509::X509Generator;
+    let (cert, key) = X509Generator::new().set_bitlength(2048).set_valid_period(1)
+        .add_name("CN".to_string(), "localhost".to_string()).generate().unwrap();
+    cert.write_pem(&mut File::create(dir.join("cert.pem")).unwrap()).unwrap();
+    key.write_pem(&mut File::create(dir.join("key.pem")).unwrap()).unwrap();
+}
+
+
+#[cfg(feature = "ssl")]
+#[test]
+fn test_tls_context() {
+    use std::env;
+    use std::fs;
+    let dir = env::temp_dir().join(format!("pencil-tls-{}", ::rand::random::<u32>()));
+    fs::create_dir_all(&dir).unwrap();
+    write_test_certificate(&dir);
+    let mut ctx = TlsConfig::new(dir.join("cert.pem"), dir.join("key.pem")).context().unwrap();
+    assert!(ctx.get_options().contains(SSL_OP_NO_SSLV2 | SSL_OP_NO_SSLV3));
+    assert!(TlsConfig::new(dir.join("key.pem"), dir.join("cert.pem")).context().is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
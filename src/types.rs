@@ -1,11 +1,17 @@
 //! This module implements a number of types.
 
+use std::any::TypeId;
 use std::collections::HashMap;
 use std::error;
 use std::convert;
 use std::error::Error;
 use std::fmt;
+use std::sync::Arc;
 
+use hyper::header::Headers;
+
+use app::Pencil;
+use config::Config;
 use wrappers::{Request, Response};
 use http_errors::HTTPError;
 
@@ -15,18 +21,54 @@ pub use self::PencilError::{
 };
 
 
-/// The Pencil User Error type.
+/// The Pencil User Error type.  It can carry the error that caused it,
+/// errors with a source can be handled by type with
+/// `Pencil::register_error_handler`.
 #[derive(Clone, Debug)]
 pub struct UserError {
     pub desc: String,
+    source: Option<(TypeId, Arc<Error + Send + Sync>)>,
 }
 
 impl UserError {
     pub fn new<T>(desc: T) -> UserError where T: AsRef<str> {
         UserError {
             desc: desc.as_ref().to_owned(),
+            source: None,
+        }
+    }
+
+    /// Creates an user error from another error, the description is taken
+    /// from the error:
+    ///
+    /// ```rust,ignore
+    /// impl From<MyErr> for PencilError {
+    ///     fn from(err: MyErr) -> PencilError {
+    ///         UserError::from_error(err).into()
+    ///     }
+    /// }
+    /// ```
+    pub fn from_error<E: Error + Send + Sync + 'static>(err: E) -> UserError {
+        UserError {
+            desc: err.to_string(),
+            source: Some((TypeId::of::<E>(), Arc::new(err))),
         }
     }
+
+    /// The error this error was created from.
+    pub fn source(&self) -> Option<&(Error + Send + Sync + 'static)> {
+        self.source.as_ref().map(|&(_, ref err)| &**err)
+    }
+
+    /// The source error if it has the type `E`.
+    pub fn downcast_ref<E: Error + 'static>(&self) -> Option<&E> {
+        self.source().and_then(|err| err.downcast_ref::<E>())
+    }
+
+    #[doc(hidden)]
+    pub fn source_type(&self) -> Option<TypeId> {
+        self.source.as_ref().map(|&(type_id, _)| type_id)
+    }
 }
 
 impl fmt::Display for UserError {
@@ -39,6 +81,10 @@ impl error::Error for UserError {
     fn description(&self) -> &str {
         &self.desc
     }
+
+    fn cause(&self) -> Option<&error::Error> {
+        self.source().map(|err| err as &error::Error)
+    }
 }
 
 
@@ -82,7 +128,7 @@ impl error::Error for PencilError {
     fn cause(&self) -> Option<&error::Error> {
         match *self {
             PenHTTPError(ref err) => Some(&*err as &error::Error),
-            PenUserError(_) => None,
+            PenUserError(ref err) => Some(&*err as &error::Error),
         }
     }
 }
@@ -94,23 +140,173 @@ pub type PencilResult = Result<Response, PencilError>;
 
 /// View arguments type.
 pub type ViewArgs = HashMap<String, String>;
-/// View function type.
-pub type ViewFunc = fn(&mut Request) -> PencilResult;
+/// View function type.  Views are registered as any function or closure
+/// that returns an `IntoPencilResponse` value or as a `View`.
+pub type ViewFunc = Arc<Fn(&mut Request) -> PencilResult + Send + Sync>;
+
+
+/// Values that view functions can return.  Besides `PencilResult` these
+/// are responses, strings, `(body, status)` and `(body, status, headers)`
+/// tuples and `JsonBody<T>`:
+///
+/// ```rust,ignore
+/// fn hello(_: &mut Request) -> &'static str {
+///     "Hello World!"
+/// }
+///
+/// fn create_user(_: &mut Request) -> (String, u16) {
+///     (String::from("created"), 201)
+/// }
+/// ```
+pub trait IntoPencilResponse {
+    fn into_pencil_response(self) -> PencilResult;
+}
+
+impl IntoPencilResponse for Response {
+    fn into_pencil_response(self) -> PencilResult {
+        Ok(self)
+    }
+}
+
+impl IntoPencilResponse for String {
+    fn into_pencil_response(self) -> PencilResult {
+        Ok(Response::from(self))
+    }
+}
+
+impl IntoPencilResponse for &'static str {
+    fn into_pencil_response(self) -> PencilResult {
+        Ok(Response::from(self))
+    }
+}
+
+impl IntoPencilResponse for Vec<u8> {
+    fn into_pencil_response(self) -> PencilResult {
+        Ok(Response::from(self))
+    }
+}
+
+impl<T: IntoPencilResponse> IntoPencilResponse for (T, u16) {
+    fn into_pencil_response(self) -> PencilResult {
+        let mut response = try!(self.0.into_pencil_response());
+        response.status_code = self.1;
+        Ok(response)
+    }
+}
+
+impl<T: IntoPencilResponse> IntoPencilResponse for (T, u16, Headers) {
+    fn into_pencil_response(self) -> PencilResult {
+        let mut response = try!(self.0.into_pencil_response());
+        response.status_code = self.1;
+        response.headers.extend(self.2.iter());
+        Ok(response)
+    }
+}
+
+impl<T: IntoPencilResponse> IntoPencilResponse for Result<T, PencilError> {
+    fn into_pencil_response(self) -> PencilResult {
+        self.and_then(|value| value.into_pencil_response())
+    }
+}
+
+impl<T: IntoPencilResponse> IntoPencilResponse for Result<T, HTTPError> {
+    fn into_pencil_response(self) -> PencilResult {
+        self.map_err(PenHTTPError).and_then(|value| value.into_pencil_response())
+    }
+}
+
+
+/// A view.  Functions and closures are views, other types can implement
+/// this to hold state like configuration, clients or compiled templates:
+///
+/// ```rust,ignore
+/// struct Greeting {
+///     greeting: String,
+/// }
+///
+/// impl View for Greeting {
+///     fn call(&self, request: &mut Request) -> PencilResult {
+///         let name = request.view_args.get("name").cloned().unwrap_or_default();
+///         Ok(Response::from(format!("{}, {}!", self.greeting, name)))
+///     }
+/// }
+///
+/// app.get("/hello/<name:string>", "hello", Greeting { greeting: String::from("Hello") });
+/// ```
+pub trait View: Send + Sync + 'static {
+    fn call(&self, request: &mut Request) -> PencilResult;
+}
+
+impl<F, R> View for F where F: Fn(&mut Request) -> R + Send + Sync + 'static, R: IntoPencilResponse {
+    fn call(&self, request: &mut Request) -> PencilResult {
+        self(request).into_pencil_response()
+    }
+}
+
+
+/// Conversion of views into a `ViewFunc`, the return value is converted
+/// to a `PencilResult` when the view is dispatched.
+pub trait IntoViewFunc {
+    fn into_view_func(self) -> ViewFunc;
+}
+
+impl<V: View> IntoViewFunc for V {
+    fn into_view_func(self) -> ViewFunc {
+        Arc::new(move |request: &mut Request| self.call(request))
+    }
+}
 
 
 /// HTTP Error handler type.
 pub type HTTPErrorHandler = fn(HTTPError) -> PencilResult;
-/// User Error handler type.
-pub type UserErrorHandler = fn(UserError) -> PencilResult;
+/// User Error handler type.  Handlers are registered by the type of the
+/// error source, see `Pencil::register_error_handler`.
+pub type UserErrorHandler = Arc<Fn(&UserError) -> PencilResult + Send + Sync>;
+
+/// Wraps a handler that gets the user error itself, like the handlers
+/// registered by their description with the deprecated `usererrorhandler`.
+pub fn user_error_desc_handler(f: fn(UserError) -> PencilResult) -> UserErrorHandler {
+    Arc::new(move |e: &UserError| f(e.clone()))
+}
+
+/// Wraps a handler for the error type `E` into an user error handler.
+pub fn user_error_handler<E, F>(f: F) -> UserErrorHandler
+    where E: Error + 'static, F: Fn(&E) -> PencilResult + Send + Sync + 'static {
+    Arc::new(move |e: &UserError| {
+        match e.downcast_ref::<E>() {
+            Some(err) => f(err),
+            None => Err(PenUserError(e.clone())),
+        }
+    })
+}
 
 
-/// Before request func type.
-pub type BeforeRequestFunc = fn(&mut Request) -> Option<PencilResult>;
+/// Before request func type.  Hooks are shared trait objects, so closures
+/// that capture state like counters or connection pools can be used.
+pub type BeforeRequestFunc = Arc<Fn(&mut Request) -> Option<PencilResult> + Send + Sync>;
 
 
 /// After request func type.
-pub type AfterRequestFunc = fn(&mut Response);
+pub type AfterRequestFunc = Arc<Fn(&mut Response) + Send + Sync>;
 
 
 /// Teardown request func type.
-pub type TeardownRequestFunc = fn(Option<&PencilError>);
+pub type TeardownRequestFunc = Arc<Fn(Option<&PencilError>) + Send + Sync>;
+
+
+/// Middleware func type, it gets the request and the next handler, which
+/// runs the inner middlewares and the view.
+pub type MiddlewareFunc = Arc<Fn(&mut Request, &Fn(&mut Request) -> PencilResult) -> PencilResult + Send + Sync>;
+
+
+/// Command func type, it gets the arguments after the command name.
+pub type CommandFunc = fn(&Pencil, &[String]) -> Result<(), String>;
+
+
+/// Config reload listener type.
+pub type ConfigReloadFunc = fn(&Config);
+
+
+/// URL converter parse function type.  It gets the matched value and
+/// returns the view argument, or `None` to reject the value.
+pub type ConverterFunc = fn(&str) -> Option<String>;
@@ -0,0 +1,226 @@
+//! This module implements verification of signed webhook requests.
+
+use std::str;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rustc_serialize::hex::FromHex;
+use rustc_serialize::json::Json;
+
+use crypto::{Digest, hmac, constant_time_eq};
+use http_errors::{HTTPError, Unauthorized, Forbidden, InternalServerError};
+use types::{MiddlewareFunc, PencilResult, PenHTTPError};
+use wrappers::Request;
+
+
+/// The HMAC algorithm used to sign the webhook requests.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SignatureAlgorithm {
+    HmacSha1,
+    HmacSha256,
+}
+
+impl SignatureAlgorithm {
+    fn digest(&self) -> Digest {
+        match *self {
+            SignatureAlgorithm::HmacSha1 => Digest::Sha1,
+            SignatureAlgorithm::HmacSha256 => Digest::Sha256,
+        }
+    }
+}
+
+
+/// Verifies the HMAC signature of webhook requests over the raw request body.
+/// The signature is read from a configurable header as hex digest.  For
+/// example GitHub style webhooks can be verified like this:
+///
+/// ```rust,no_run
+/// use pencil::{Request, PencilResult, Response};
+/// use pencil::webhooks::WebhookVerifier;
+///
+///
+/// fn github_hook(request: &mut Request) -> PencilResult {
+///     let verifier = WebhookVerifier::new("secret", "X-Hub-Signature-256").prefix("sha256=");
+///     try!(verifier.verify(request));
+///     Ok(Response::from("OK"))
+/// }
+/// ```
+///
+/// Timestamped schemes like Stripe's (`t=1492774577,v1=5257a869...`) sign the
+/// timestamp together with the body, they are supported with `timestamped`,
+/// signatures older than the tolerance are rejected to prevent replays.
+#[derive(Clone, Debug)]
+pub struct WebhookVerifier {
+    secret: Vec<u8>,
+    header_name: String,
+    algorithm: SignatureAlgorithm,
+    prefix: String,
+    tolerance: Option<u64>,
+}
+
+impl WebhookVerifier {
+    /// Create a verifier for the secret and the header that carries the
+    /// signature.  The algorithm defaults to HMAC-SHA256.
+    pub fn new<S: AsRef<[u8]>>(secret: S, header_name: &str) -> WebhookVerifier {
+        WebhookVerifier {
+            secret: secret.as_ref().to_vec(),
+            header_name: header_name.to_string(),
+            algorithm: SignatureAlgorithm::HmacSha256,
+            prefix: String::new(),
+            tolerance: None,
+        }
+    }
+
+    /// Set the HMAC algorithm.
+    pub fn algorithm(mut self, algorithm: SignatureAlgorithm) -> WebhookVerifier {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Set a prefix that the header value starts with, like `sha256=`.
+    pub fn prefix(mut self, prefix: &str) -> WebhookVerifier {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Use the timestamped scheme, the header looks like
+    /// `t=<timestamp>,v1=<signature>` and the signed payload is
+    /// `<timestamp>.<body>`.  The tolerance is in seconds.
+    pub fn timestamped(mut self, tolerance: u64) -> WebhookVerifier {
+        self.tolerance = Some(tolerance);
+        self
+    }
+
+    /// Compute the signature for the payload.
+    fn sign(&self, payload: &[u8]) -> Vec<u8> {
+        hmac(self.algorithm.digest(), &self.secret, payload)
+    }
+
+    /// Check whether one hex encoded signature matches.
+    fn matches(&self, signature: &str, payload: &[u8]) -> bool {
+        match signature.trim().from_hex() {
+            Ok(signature) => constant_time_eq(&signature, &self.sign(payload)),
+            Err(_) => false,
+        }
+    }
+
+    /// Verify the signature of the payload against the header value.
+    fn verify_header(&self, header: &str, body: &[u8]) -> Result<(), HTTPError> {
+        let header = header.trim();
+        if !header.starts_with(&self.prefix as &str) {
+            return Err(Unauthorized.with_description("The webhook signature is malformed."));
+        }
+        let header = &header[self.prefix.len()..];
+        let tolerance = match self.tolerance {
+            Some(tolerance) => tolerance,
+            None => {
+                if self.matches(header, body) {
+                    return Ok(());
+                }
+                return Err(Unauthorized.with_description("The webhook signature does not match."));
+            }
+        };
+        let mut timestamp = None;
+        let mut signatures = Vec::new();
+        for part in header.split(',') {
+            let mut kv = part.trim().splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some("t"), Some(value)) => timestamp = value.parse::<u64>().ok(),
+                (Some("v1"), Some(value)) => signatures.push(value),
+                _ => {},
+            }
+        }
+        let timestamp = match timestamp {
+            Some(timestamp) => timestamp,
+            None => return Err(Unauthorized.with_description("The webhook signature has no timestamp.")),
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let age = if now > timestamp { now - timestamp } else { timestamp - now };
+        if age > tolerance {
+            return Err(Unauthorized.with_description("The webhook timestamp is outside the tolerance zone."));
+        }
+        let mut payload = timestamp.to_string().into_bytes();
+        payload.push(b'.');
+        payload.extend_from_slice(body);
+        if signatures.iter().any(|signature| self.matches(signature, &payload)) {
+            Ok(())
+        } else {
+            Err(Unauthorized.with_description("The webhook signature does not match."))
+        }
+    }
+
+    /// Verify the request, this fails with `401 Unauthorized` if the
+    /// signature is missing or doesn't match.  The body stays available
+    /// for parsing after the verification.
+    pub fn verify(&self, request: &mut Request) -> Result<(), HTTPError> {
+        let header = match request.headers.get_raw(&self.header_name) {
+            Some(values) if !values.is_empty() => {
+                match str::from_utf8(&values[0]) {
+                    Ok(value) => value.to_owned(),
+                    Err(_) => return Err(Unauthorized.with_description("The webhook signature is malformed.")),
+                }
+            },
+            _ => return Err(Unauthorized.with_description("The webhook signature is missing.")),
+        };
+        self.verify_header(&header, request.get_data())
+    }
+}
+
+
+/// Verifies a GitHub style signature with the secret configured as
+/// `WEBHOOK_SECRET` (a module can override it).  The signature is read from
+/// `X-Hub-Signature-256: sha256=<hex>`.  The older
+/// `X-Hub-Signature: sha1=<hex>` is only accepted if `WEBHOOK_ALLOW_SHA1` is
+/// set to `true`.  This fails with `403 Forbidden` if the signature is
+/// missing or doesn't match.
+pub fn verify_signature(request: &mut Request) -> Result<(), HTTPError> {
+    let secret = match request.config_value("WEBHOOK_SECRET") {
+        Some(Json::String(secret)) => secret,
+        _ => return Err(InternalServerError.with_description("WEBHOOK_SECRET is not configured.")),
+    };
+    let allow_sha1 = request.config_value("WEBHOOK_ALLOW_SHA1").and_then(|allow| allow.as_boolean()).unwrap_or(false);
+    let verifier = if !allow_sha1 || request.headers.get_raw("X-Hub-Signature-256").is_some() {
+        WebhookVerifier::new(secret, "X-Hub-Signature-256").prefix("sha256=")
+    } else {
+        WebhookVerifier::new(secret, "X-Hub-Signature").prefix("sha1=").algorithm(SignatureAlgorithm::HmacSha1)
+    };
+    verifier.verify(request).map_err(|err| Forbidden.with_description(err.to_string()))
+}
+
+
+/// A middleware that only passes requests with a valid signature to the
+/// view, see `verify_signature`:
+///
+/// ```rust,ignore
+/// app.config.set("WEBHOOK_SECRET", "secret".to_json());
+/// app.post_with("/hooks/github", "github_hook", github_hook).wrap_with(require_signature());
+/// ```
+pub fn require_signature() -> MiddlewareFunc {
+    Arc::new(|request: &mut Request, next: &Fn(&mut Request) -> PencilResult| {
+        match verify_signature(request) {
+            Ok(()) => next(request),
+            Err(err) => Err(PenHTTPError(err)),
+        }
+    })
+}
+
+
+#[test]
+fn test_verify_webhook_signature() {
+    use rustc_serialize::hex::ToHex;
+    let verifier = WebhookVerifier::new("secret", "X-Hub-Signature-256").prefix("sha256=");
+    let signature = hmac(Digest::Sha256, b"secret", b"payload").to_hex();
+    assert!(verifier.verify_header(&format!("sha256={}", signature), b"payload").is_ok());
+    assert!(verifier.verify_header(&format!("sha256={}", signature), b"tampered").is_err());
+    assert!(verifier.verify_header(&signature, b"payload").is_err());
+
+    let verifier = WebhookVerifier::new("secret", "Stripe-Signature").timestamped(300);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let payload = format!("{}.payload", now);
+    let signature = hmac(Digest::Sha256, b"secret", payload.as_bytes()).to_hex();
+    assert!(verifier.verify_header(&format!("t={},v1={}", now, signature), b"payload").is_ok());
+    let old = now - 600;
+    let payload = format!("{}.payload", old);
+    let signature = hmac(Digest::Sha256, b"secret", payload.as_bytes()).to_hex();
+    assert!(verifier.verify_header(&format!("t={},v1={}", old, signature), b"payload").is_err());
+}
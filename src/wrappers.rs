@@ -4,15 +4,21 @@ use std::fmt;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::io;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{Read, Write};
 use std::convert;
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
 
 use hyper;
 use hyper::server::request::Request as HttpRequest;
 use hyper::uri::RequestUri::{AbsolutePath, AbsoluteUri, Authority, Star};
-use hyper::header::{Headers, ContentLength, ContentType, Cookie};
-use hyper::mime::Mime;
+use hyper::header::{Header, Headers, ContentLength, ContentType, Cookie};
+use hyper::header::{Authorization, Basic, Bearer};
+use hyper::header::{ETag, EntityTag, HttpDate, IfModifiedSince, IfNoneMatch, LastModified};
+use hyper::mime::{Mime, TopLevel, SubLevel};
 use hyper::method::Method;
 use hyper::http::h1::HttpReader;
 use hyper::net::NetworkStream;
@@ -21,16 +27,29 @@ use url::Url;
 use url::form_urlencoded;
 use formdata::FilePart;
 use rustc_serialize::json;
-use typemap::TypeMap;
+use rustc_serialize::json::Json;
+use typemap::{TypeMap, ShareCloneMap, Key};
+use rand;
+use rustc_serialize::hex::ToHex;
+use time;
 
 use app::Pencil;
+use cookies;
+use module::Module;
 use datastructures::MultiDict;
 use httputils::{get_name_by_http_code, get_content_type, get_host_value};
 use httputils::get_status_from_code;
+use httputils::{parse_accept_mimetypes, parse_accept_charsets, parse_accept_languages, parse_accept_encodings};
+use httputils::best_mimetype_match;
+use utils::join_string;
 use routing::{Rule, MapAdapterMatched, MapAdapter};
-use types::ViewArgs;
-use http_errors::HTTPError;
-use formparser::FormDataParser;
+use types::{ViewArgs, PencilError, PenHTTPError, UserError};
+use http_errors::{HTTPError, BadRequest, NotFound, RequestEntityTooLarge};
+use formparser::{FormDataParser, parse_nested_form};
+use helpers::{UrlFor, escape, secure_filename};
+use sessions::{Session, SessionCookieConfig, open_session};
+use bufpool::{PooledBuffer, default_headers};
+use crypto::sha256;
 
 
 /// Request type.
@@ -46,7 +65,7 @@ pub struct Request<'r, 'a, 'b: 'a> {
     pub url: Url,
     /// The URL rule that matched the request.  This is
     /// going to be `None` if nothing matched.
-    pub url_rule: Option<Rule>,
+    pub url_rule: Option<Arc<Rule>>,
     /// A dict of view arguments that matched the request.
     pub view_args: ViewArgs,
     /// If matching the URL requests a redirect, this will be the redirect.
@@ -55,27 +74,56 @@ pub struct Request<'r, 'a, 'b: 'a> {
     pub routing_error: Option<HTTPError>,
     /// Storage for data of extensions.
     pub extensions_data: TypeMap,
+    locale: Option<String>,
     body: HttpReader<&'a mut BufReader<&'b mut NetworkStream>>,
     host: hyper::header::Host,
     args: Option<MultiDict<String>>,
     form: Option<MultiDict<String>>,
+    values: Option<MultiDict<String>>,
     files: Option<MultiDict<FilePart>>,
-    cached_json: Option<Option<json::Json>>
+    form_error: Option<HTTPError>,
+    form_nested: Option<json::Json>,
+    cached_json: Option<Option<json::Json>>,
+    json_error: Option<String>,
+    cached_data: Option<io::Cursor<Vec<u8>>>,
+    body_read: u64,
+    body_too_large: bool,
+    started: Instant,
+    request_id: String,
+    after_this_request: Vec<Box<FnOnce(&mut Response)>>,
+    scheme: String,
 }
 
 impl<'r, 'a, 'b: 'a> Request<'r, 'a, 'b> {
     /// Create a `Request`.
     pub fn new(app: &'r Pencil, http_request: HttpRequest<'a, 'b>) -> Result<Request<'r, 'a, 'b>, String> {
-        let (remote_addr, method, headers, uri, _, body) = http_request.deconstruct();
-        let host = match headers.get::<hyper::header::Host>() {
+        let (mut remote_addr, method, headers, uri, _, body) = http_request.deconstruct();
+        let mut host = match headers.get::<hyper::header::Host>() {
             Some(host) => host.clone(),
             None => {
                 return Err("No host specified in your request".into());
             }
         };
+        let mut scheme = String::from("http");
+        let trusted_proxies = app.trusted_proxies();
+        if trusted_proxies > 0 {
+            if let Some(ip) = forwarded_value(&headers, "X-Forwarded-For", trusted_proxies).and_then(|ip| ip.parse().ok()) {
+                remote_addr = SocketAddr::new(ip, 0);
+            }
+            if let Some(proto) = forwarded_value(&headers, "X-Forwarded-Proto", trusted_proxies) {
+                if proto.eq_ignore_ascii_case("http") || proto.eq_ignore_ascii_case("https") {
+                    scheme = proto.to_lowercase();
+                }
+            }
+            if let Some(forwarded_host) = forwarded_value(&headers, "X-Forwarded-Host", trusted_proxies) {
+                if let Ok(forwarded_host) = hyper::header::Host::parse_header(&[forwarded_host.into_bytes()]) {
+                    host = forwarded_host;
+                }
+            }
+        }
         let url = match uri {
             AbsolutePath(ref path) => {
-                let url_string = format!("http://{}{}", get_host_value(&host), path);
+                let url_string = format!("{}://{}{}", scheme, get_host_value(&host), path);
                 match Url::parse(&url_string) {
                     Ok(url) => url,
                     Err(e) => return Err(format!("Couldn't parse requested URL: {}", e))
@@ -88,6 +136,12 @@ impl<'r, 'a, 'b: 'a> Request<'r, 'a, 'b> {
                 return Err("Unsupported request URI".into());
             }
         };
+        let request_id = match headers.get_raw("X-Request-Id") {
+            Some(values) if values.len() == 1 && !values[0].is_empty() => {
+                String::from_utf8_lossy(&values[0]).into_owned()
+            },
+            _ => format!("{:016x}", rand::random::<u64>()),
+        };
         Ok(Request {
             app: app,
             remote_addr: remote_addr,
@@ -99,23 +153,90 @@ impl<'r, 'a, 'b: 'a> Request<'r, 'a, 'b> {
             routing_redirect: None,
             routing_error: None,
             extensions_data: TypeMap::new(),
+            locale: None,
             body: body,
             host: host,
             args: None,
             form: None,
+            values: None,
             files: None,
+            form_error: None,
+            form_nested: None,
             cached_json: None,
+            json_error: None,
+            cached_data: None,
+            body_read: 0,
+            body_too_large: false,
+            started: Instant::now(),
+            request_id: request_id,
+            after_this_request: Vec::new(),
+            scheme: scheme,
         })
     }
 
     /// Get the url adapter for this request.
-    pub fn url_adapter(&self) -> MapAdapter {
-        self.app.url_map.bind(self.host(), self.path(), self.query_string(), self.method())
+    pub fn url_adapter(&self) -> MapAdapter<'r> {
+        let app = self.app;
+        let mut path = self.path();
+        let mut path_prefix = String::new();
+        if let Some(locale_prefix) = app.url_map.locale_prefix() {
+            if let (Some(locale), rest) = locale_prefix.split(&path) {
+                path_prefix = format!("/{}", locale);
+                path = rest;
+            }
+        }
+        let mut url_adapter = app.url_map.bind(self.host(), path, self.query_string(), self.method());
+        url_adapter.set_path_prefix(&path_prefix);
+        url_adapter.set_subdomain(self.subdomain());
+        url_adapter
+    }
+
+    /// The subdomain of the request relative to the `SERVER_NAME`
+    /// configuration key, like `api` for `api.example.com` if the server
+    /// name is `example.com`.  This is an empty string for the server name
+    /// itself or if no server name is configured, and `None` if the host
+    /// doesn't belong to the server name.
+    pub fn subdomain(&self) -> Option<String> {
+        let server_name = match self.app.config.get("SERVER_NAME") {
+            Some(Json::String(server_name)) => server_name.to_lowercase(),
+            _ => return Some(String::new()),
+        };
+        let host = self.host().to_lowercase();
+        if host == server_name {
+            Some(String::new())
+        } else if host.ends_with(&format!(".{}", server_name)) {
+            Some(host[..host.len() - server_name.len() - 1].to_string())
+        } else {
+            None
+        }
     }
 
     /// Match the request, set the `url_rule` and `view_args` field.
     pub fn match_request(&mut self) {
-        let url_adapter = self.app.url_map.bind(self.host(), self.path(), self.query_string(), self.method());
+        if let Some(normalization) = self.app.url_map.normalization() {
+            let path = self.path();
+            let normalized = normalization.normalize(&path);
+            if normalized != path {
+                if normalization.redirect {
+                    let mut redirect_url = self.host_url() + normalized.trim_left_matches('/');
+                    if let Some(query_string) = self.query_string() {
+                        redirect_url = redirect_url + "?" + &query_string;
+                    }
+                    // 308 keeps the method and body of unsafe requests.
+                    let code = match self.method {
+                        Method::Get | Method::Head => 301,
+                        _ => 308,
+                    };
+                    self.routing_redirect = Some((redirect_url, code));
+                    return;
+                }
+                self.url.set_path(&normalized);
+            }
+        }
+        if let Some(locale_prefix) = self.app.url_map.locale_prefix() {
+            self.locale = locale_prefix.split(&self.path()).0;
+        }
+        let url_adapter = self.url_adapter();
         match url_adapter.matched() {
             MapAdapterMatched::MatchedRule((rule, view_args)) => {
                 self.url_rule = Some(rule);
@@ -124,12 +245,109 @@ impl<'r, 'a, 'b: 'a> Request<'r, 'a, 'b> {
             MapAdapterMatched::MatchedRedirect((redirect_url, redirect_code)) => {
                 self.routing_redirect = Some((redirect_url, redirect_code));
             },
+            MapAdapterMatched::MatchedError(NotFound) if self.app.is_debug() => {
+                let near_misses = url_adapter.near_misses();
+                if near_misses.is_empty() {
+                    self.routing_error = Some(NotFound);
+                } else {
+                    let description = format!("The requested URL was not found on the server.  Close matches: {}.",
+                                              near_misses.join("; "));
+                    info!("No URL rule matched {}, close matches: {}", self.path(), near_misses.join("; "));
+                    self.routing_error = Some(NotFound.with_description(escape(description)));
+                }
+            },
             MapAdapterMatched::MatchedError(routing_error) => {
                 self.routing_error = Some(routing_error);
             },
         }
     }
 
+    /// The locale of the request.  With locale prefixed routing enabled this
+    /// is the locale from the path prefix.  If the path has no prefix the
+    /// locale is negotiated with the `Accept-Language` header, falling back
+    /// to the default locale.  Use this to select the locale for
+    /// translations.
+    pub fn locale(&self) -> Option<String> {
+        match self.locale {
+            Some(ref locale) => Some(locale.clone()),
+            None => self.app.url_map.locale_prefix().map(|locale_prefix| {
+                locale_prefix.negotiate(&self.accept_languages()).unwrap_or_else(|| locale_prefix.default.clone())
+            }),
+        }
+    }
+
+    /// The id of the request, taken from the `X-Request-Id` header or
+    /// generated if the header is missing.  Use it to correlate logs.
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    /// Registers a function to run on the response of this request only,
+    /// before the `after_request` hooks.  This lets a view or hook change
+    /// the response after it is created:
+    ///
+    /// ```rust,ignore
+    /// fn set_theme(request: &mut Request) -> Option<PencilResult> {
+    ///     if let Some(theme) = request.args().get("theme").cloned() {
+    ///         request.call_after(move |response| response.set_cookie(Cookie::new("theme", &theme)));
+    ///     }
+    ///     None
+    /// }
+    /// ```
+    pub fn call_after<F: FnOnce(&mut Response) + 'static>(&mut self, f: F) {
+        self.after_this_request.push(Box::new(f));
+    }
+
+    #[doc(hidden)]
+    pub fn take_after_this_request(&mut self) -> Vec<Box<FnOnce(&mut Response)>> {
+        mem::replace(&mut self.after_this_request, Vec::new())
+    }
+
+    /// A snapshot of the request that can be sent to other threads, see
+    /// `RequestContext`.
+    pub fn context(&self) -> RequestContext {
+        RequestContext {
+            request_id: self.request_id.clone(),
+            endpoint: self.endpoint(),
+            remote_addr: self.remote_addr,
+            locale: self.locale(),
+            extensions_data: ShareCloneMap::custom(),
+        }
+    }
+
+    /// Build the URL path for an endpoint like `Pencil::url_for`, the locale
+    /// prefix of this request is added to the path.  If the rule belongs to
+    /// another subdomain than this request, an absolute URL with the scheme
+    /// of this request is built.
+    pub fn url_for(&self, endpoint: &str, values: &ViewArgs) -> Option<String> {
+        let path_prefix = match self.locale {
+            Some(ref locale) => format!("/{}", locale),
+            None => String::new(),
+        };
+        let subdomain = self.subdomain();
+        self.app.build_url(endpoint, values, subdomain.as_ref().map(|s| &s[..]),
+                           &self.scheme(), &path_prefix)
+    }
+
+    /// The session of the request, it is loaded from the session cookie
+    /// on first access and saved when the response is processed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if sessions are not enabled with `app.enable_sessions()`.
+    pub fn session(&mut self) -> &mut Session {
+        if !self.extensions_data.contains::<Session>() {
+            let app = self.app;
+            let signer = match app.session_signer() {
+                Some(signer) => signer,
+                None => panic!("Sessions are not enabled, call `app.enable_sessions()` first."),
+            };
+            let session = open_session(signer, &SessionCookieConfig::from_config(&app.config), self);
+            self.extensions_data.insert::<Session>(session);
+        }
+        self.extensions_data.get_mut::<Session>().unwrap()
+    }
+
     /// The endpoint that matched the request.
     pub fn endpoint(&self) -> Option<String> {
         match self.url_rule {
@@ -140,13 +358,41 @@ impl<'r, 'a, 'b: 'a> Request<'r, 'a, 'b> {
 
     /// The current module name.
     pub fn module_name(&self) -> Option<String> {
-        if let Some(endpoint) = self.endpoint() {
-            if endpoint.contains('.') {
-                let v: Vec<&str> = endpoint.rsplitn(2, '.').collect();
-                return Some(v[1].to_string());
+        self.url_rule.as_ref().and_then(|rule| rule.module_name()).map(|name| name.to_string())
+    }
+
+    /// Get a configuration value for this request.  If the request is
+    /// handled by a module, the configs of the module and its parents are
+    /// consulted first, so a module can override application settings like
+    /// `REQUEST_TIMEOUT`.
+    pub fn config_value(&self, key: &str) -> Option<Json> {
+        let module_value = self.modules().into_iter().filter_map(|module| module.config.get(key)).next();
+        module_value.or_else(|| self.app.config.get(key))
+    }
+
+    /// The modules that handle the request, the module of the endpoint
+    /// first and then its parent modules.
+    pub fn modules(&self) -> Vec<&'r Module> {
+        let app = self.app;
+        let mut modules = Vec::new();
+        let mut name = self.url_rule.as_ref().and_then(|rule| rule.module_name());
+        while let Some(current) = name {
+            if let Some(module) = app.modules.get(current) {
+                modules.push(module);
             }
+            name = current.rfind('.').map(|pos| &current[..pos]);
+        }
+        modules
+    }
+
+    /// Get a boolean configuration value for this request, see
+    /// `config_value`.  If the key doesn't exist or the value is not a
+    /// `Json::Boolean`, the default value will be returned.
+    pub fn config_boolean(&self, key: &str, default: bool) -> bool {
+        match self.config_value(key) {
+            Some(Json::Boolean(value)) => value,
+            _ => default,
         }
-        None
     }
 
     /// The parsed URL parameters.
@@ -170,18 +416,79 @@ impl<'r, 'a, 'b: 'a> Request<'r, 'a, 'b> {
         content_type.cloned()
     }
 
+    /// The raw request body.  The body is read once and cached, JSON and
+    /// form data are parsed from the cached data, so verifying a signature
+    /// over the body, parsing it and reading it again all see the same
+    /// bytes in any order.  Multipart uploads are streamed to temporary
+    /// files instead, call this before `files` if you need their raw bytes.
+    /// The data is empty if the body is larger than `max_content_length`,
+    /// use `try_get_data` to fail with the error instead.
+    pub fn get_data(&mut self) -> &[u8] {
+        if self.cached_data.is_none() {
+            let mut data = Vec::new();
+            if let Err(err) = Read::read_to_end(self, &mut data) {
+                warn!("Error reading request body: {}", err);
+                if self.body_too_large {
+                    data.clear();
+                }
+            }
+            self.cached_data = Some(io::Cursor::new(data));
+        }
+        self.cached_data.as_ref().unwrap().get_ref()
+    }
+
+    /// The raw request body like `get_data`, a body that is larger than
+    /// `max_content_length` is a `413 Request Entity Too Large`:
+    ///
+    /// ```rust,ignore
+    /// fn upload(request: &mut Request) -> PencilResult {
+    ///     let size = try!(request.try_get_data()).len();
+    ///     Ok(Response::from(format!("{} bytes", size)))
+    /// }
+    /// ```
+    pub fn try_get_data(&mut self) -> Result<&[u8], HTTPError> {
+        self.get_data();
+        if self.body_too_large {
+            return Err(RequestEntityTooLarge);
+        }
+        Ok(self.cached_data.as_ref().unwrap().get_ref())
+    }
+
+    /// The maximum size of the request body in bytes, configured with the
+    /// `MAX_CONTENT_LENGTH` configuration key (a module can override it).
+    /// Requests with a larger `Content-Length` are answered with
+    /// `413 Request Entity Too Large` before any `before_request` hook
+    /// runs, reading a larger body without `Content-Length` fails.
+    pub fn max_content_length(&self) -> Option<u64> {
+        self.config_value("MAX_CONTENT_LENGTH").and_then(|limit| limit.as_u64())
+    }
+
+    /// Whether reading the body failed because it is larger than
+    /// `max_content_length`.
+    pub fn body_too_large(&self) -> bool {
+        self.body_too_large
+    }
+
     /// Parses the incoming JSON request data.
     pub fn get_json(&mut self) -> &Option<json::Json> {
         if self.cached_json.is_none() {
-            let mut data = String::from("");
-            let rv = match self.read_to_string(&mut data) {
-                Ok(_) => {
+            let data = String::from_utf8(self.get_data().to_vec());
+            let rv = match data {
+                Ok(_) if self.body_too_large => {
+                    self.json_error = Some(String::from("request body too large"));
+                    None
+                },
+                Ok(data) => {
                     match json::Json::from_str(&data) {
                         Ok(json) => Some(json),
-                        Err(_) => None
+                        Err(err) => {
+                            self.json_error = Some(err.to_string());
+                            None
+                        }
                     }
                 },
-                Err(_) => {
+                Err(err) => {
+                    self.json_error = Some(err.to_string());
                     None
                 }
             };
@@ -190,17 +497,87 @@ impl<'r, 'a, 'b: 'a> Request<'r, 'a, 'b> {
         self.cached_json.as_ref().unwrap()
     }
 
-    /// This method is used internally to retrieve submitted data.
+    /// Parses the incoming JSON request data strictly.  Unlike `get_json`
+    /// this requires an `application/json` content type and fails with a
+    /// `400 Bad Request` describing the problem if the data is not valid
+    /// JSON, so client bugs don't look like empty request bodies:
+    ///
+    /// ```rust,no_run
+    /// use pencil::{Request, PencilResult, Response};
+    ///
+    ///
+    /// fn create_user(request: &mut Request) -> PencilResult {
+    ///     let name = {
+    ///         let data = try!(request.get_json_strict());
+    ///         data.find("name").and_then(|name| name.as_string()).unwrap_or("").to_owned()
+    ///     };
+    ///     Ok(Response::from(format!("Created {}", name)))
+    /// }
+    /// ```
+    pub fn get_json_strict(&mut self) -> Result<&json::Json, HTTPError> {
+        let is_json = match self.content_type() {
+            Some(ContentType(Mime(TopLevel::Application, SubLevel::Json, _))) => true,
+            Some(ContentType(Mime(TopLevel::Application, SubLevel::Ext(ref sub), _))) => sub.ends_with("+json"),
+            _ => false,
+        };
+        if !is_json {
+            return Err(BadRequest.with_description("The request content type must be application/json."));
+        }
+        self.get_json();
+        if self.body_too_large {
+            return Err(RequestEntityTooLarge);
+        }
+        match *self.cached_json.as_ref().unwrap() {
+            Some(ref json) => Ok(json),
+            None => {
+                let error = match self.json_error {
+                    Some(ref error) => format!("The request body is not valid JSON: {}", error),
+                    None => String::from("The request body is not valid JSON."),
+                };
+                Err(BadRequest.with_description(error))
+            }
+        }
+    }
+
+    /// This method is used internally to retrieve submitted data.  The
+    /// size of the form data is limited by the `MAX_CONTENT_LENGTH`,
+    /// `MAX_FORM_FILE_SIZE` and `MAX_FORM_FIELD_SIZE` configuration keys
+    /// (bytes), see `form_error`.
     fn load_form_data(&mut self) {
         if self.form.is_some() {
             return
         }
-        let (form, files) = match self.content_type() {
+        let limit = |key: &str| self.config_value(key).and_then(|limit| limit.as_u64());
+        let parser = FormDataParser::new()
+            .max_content_length(self.max_content_length())
+            .max_file_size(limit("MAX_FORM_FILE_SIZE"))
+            .max_field_size(limit("MAX_FORM_FIELD_SIZE"));
+        let content_type = self.content_type();
+        // Only multipart bodies are streamed, everything else is cached so
+        // it can be read again, for example to verify a signature.
+        let is_multipart = match content_type {
+            Some(ContentType(Mime(TopLevel::Multipart, _, _))) => true,
+            _ => false,
+        };
+        if content_type.is_some() && !is_multipart {
+            self.get_data();
+        }
+        let result = match content_type {
+            Some(_) if self.body_too_large => Err(RequestEntityTooLarge),
             Some(ContentType(mimetype)) => {
-                let parser = FormDataParser::new();
-                parser.parse(&mut self.body, &self.headers, &mimetype)
+                match self.cached_data {
+                    Some(ref data) => parser.parse(&mut &data.get_ref()[..], &self.headers, &mimetype),
+                    None => parser.parse(&mut self.body, &self.headers, &mimetype),
+                }
             },
             None => {
+                Ok((MultiDict::new(), MultiDict::new()))
+            }
+        };
+        let (form, files) = match result {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                self.form_error = Some(error);
                 (MultiDict::new(), MultiDict::new())
             }
         };
@@ -208,23 +585,254 @@ impl<'r, 'a, 'b: 'a> Request<'r, 'a, 'b> {
         self.files = Some(files);
     }
 
-    /// The form parameters.
+    /// The error of parsing the form data, the form and the files are
+    /// empty then.  This is `413 Request Entity Too Large` if a size
+    /// limit was exceeded, see `try_form`.
+    pub fn form_error(&mut self) -> Option<HTTPError> {
+        self.load_form_data();
+        self.form_error.clone()
+    }
+
+    /// The form parameters, they are empty if the form data can't be
+    /// parsed, see `form_error`.
     pub fn form(&mut self) -> &MultiDict<String> {
         self.load_form_data();
         self.form.as_ref().unwrap()
     }
 
+    /// The form parameters like `form`, form data that can't be parsed or
+    /// exceeds a size limit is an error:
+    ///
+    /// ```rust,ignore
+    /// fn login(request: &mut Request) -> PencilResult {
+    ///     let name = try!(request.try_form()).get("name").cloned().unwrap_or_default();
+    ///     Ok(Response::from(format!("Hello {}", name)))
+    /// }
+    /// ```
+    pub fn try_form(&mut self) -> Result<&MultiDict<String>, HTTPError> {
+        if let Some(error) = self.form_error() {
+            return Err(error);
+        }
+        Ok(self.form.as_ref().unwrap())
+    }
+
+    /// The form parameters combined with the URL parameters.  Form fields
+    /// take precedence, so `get` returns the form value if the key is present
+    /// in both, and `getlist` returns the form values followed by the URL
+    /// parameter values.
+    pub fn values(&mut self) -> &MultiDict<String> {
+        if self.values.is_none() {
+            self.load_form_data();
+            self.args();
+            let mut values = MultiDict::new();
+            for (key, list) in self.form.as_ref().unwrap().listiter() {
+                for value in list {
+                    values.add(key.clone(), value.clone());
+                }
+            }
+            for (key, list) in self.args.as_ref().unwrap().listiter() {
+                for value in list {
+                    values.add(key.clone(), value.clone());
+                }
+            }
+            self.values = Some(values);
+        }
+        self.values.as_ref().unwrap()
+    }
+
+    /// The form parameters parsed into a nested structure.  Keys like
+    /// `user[name]=x` become objects and keys like `tags[]=a&tags[]=b`
+    /// become arrays, all values are strings.  The result can be decoded
+    /// into your own types:
+    ///
+    /// ```rust,ignore
+    /// #[derive(RustcDecodable)]
+    /// struct User {
+    ///     name: String,
+    ///     age: u8,
+    /// }
+    ///
+    /// let nested = request.form_nested().clone();
+    /// let mut decoder = json::Decoder::new(nested.find("user").unwrap().clone());
+    /// let user: User = Decodable::decode(&mut decoder).unwrap();
+    /// ```
+    pub fn form_nested(&mut self) -> &json::Json {
+        if self.form_nested.is_none() {
+            self.load_form_data();
+            let nested = parse_nested_form(self.form.as_ref().unwrap());
+            self.form_nested = Some(nested);
+        }
+        self.form_nested.as_ref().unwrap()
+    }
+
     /// All uploaded files.
     pub fn files(&mut self) -> &MultiDict<FilePart> {
         self.load_form_data();
         self.files.as_ref().unwrap()
     }
 
+    /// All uploaded files like `files`, form data that can't be parsed or
+    /// exceeds a size limit is an error, see `try_form`.
+    pub fn try_files(&mut self) -> Result<&MultiDict<FilePart>, HTTPError> {
+        if let Some(error) = self.form_error() {
+            return Err(error);
+        }
+        Ok(self.files.as_ref().unwrap())
+    }
+
+    /// Saves the file uploaded in a form field to the given directory and
+    /// returns the path of the saved file.  The file is named after the
+    /// uploaded filename made safe with `secure_filename`, a number is
+    /// appended if a file with that name exists already.  The file is
+    /// written to a temporary file first and linked to a free name, so it
+    /// never appears half written and concurrent uploads never replace each
+    /// other:
+    ///
+    /// ```rust,ignore
+    /// fn upload(request: &mut Request) -> PencilResult {
+    ///     let path = try!(request.save_file("avatar", "/var/uploads"));
+    ///     Ok(Response::from(format!("saved {}", path.display())))
+    /// }
+    /// ```
+    ///
+    /// The upload is validated against the `UPLOAD_MAX_FILE_SIZE` (bytes)
+    /// and `UPLOAD_ALLOWED_EXTENSIONS` (like `["png", "jpg"]`)
+    /// configuration keys, a module can override them.  A missing or
+    /// disallowed file is a `400 Bad Request`, a file that is too large a
+    /// `413 Request Entity Too Large`.
+    pub fn save_file(&mut self, field: &str, dest_dir: &str) -> Result<PathBuf, PencilError> {
+        let max_size = self.config_value("UPLOAD_MAX_FILE_SIZE").and_then(|size| size.as_u64());
+        let allowed_extensions: Option<Vec<String>> = match self.config_value("UPLOAD_ALLOWED_EXTENSIONS") {
+            Some(Json::Array(extensions)) => Some(extensions.iter()
+                .filter_map(|extension| extension.as_string())
+                .map(|extension| extension.trim_left_matches('.').to_lowercase())
+                .collect()),
+            _ => None,
+        };
+        if let Some(error) = self.form_error() {
+            return Err(PenHTTPError(error));
+        }
+        let file = match self.files().get(field) {
+            Some(file) => file,
+            None => return Err(PenHTTPError(BadRequest.with_description(format!("No file uploaded in {}.", field)))),
+        };
+        let filename = secure_filename(&file.filename().ok().and_then(|filename| filename).unwrap_or_default());
+        let size = match file.size {
+            Some(size) => size as u64,
+            None => try!(fs::metadata(&file.path).map_err(|e| UserError::from_error(e))).len(),
+        };
+        if let Some(max_size) = max_size {
+            if size > max_size {
+                return Err(PenHTTPError(RequestEntityTooLarge));
+            }
+        }
+        let extension = Path::new(&filename).extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        if let Some(ref allowed_extensions) = allowed_extensions {
+            if !extension.as_ref().map_or(false, |extension| allowed_extensions.contains(extension)) {
+                return Err(PenHTTPError(BadRequest.with_description(format!("File type not allowed in {}.", field))));
+            }
+        }
+        let filename = if filename.is_empty() {
+            let random_name = rand::random::<[u8; 16]>().to_hex();
+            match extension {
+                Some(extension) => format!("{}.{}", random_name, extension),
+                None => random_name,
+            }
+        } else {
+            filename
+        };
+        let dest_dir = Path::new(dest_dir);
+        let temp_path = dest_dir.join(format!(".{}.upload", rand::random::<[u8; 16]>().to_hex()));
+        if let Err(e) = fs::copy(&file.path, &temp_path) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(UserError::from_error(e).into());
+        }
+        // The hard link claims the name atomically, it fails if another
+        // upload took the name in the meantime.
+        let mut dest_path = dest_dir.join(&filename);
+        let mut counter = 1;
+        loop {
+            match fs::hard_link(&temp_path, &dest_path) {
+                Ok(()) => break,
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {},
+                Err(e) => {
+                    let _ = fs::remove_file(&temp_path);
+                    return Err(UserError::from_error(e).into());
+                },
+            }
+            let stem = Path::new(&filename).file_stem().and_then(|stem| stem.to_str()).unwrap_or("");
+            dest_path = match Path::new(&filename).extension().and_then(|extension| extension.to_str()) {
+                Some(extension) => dest_dir.join(format!("{}-{}.{}", stem, counter, extension)),
+                None => dest_dir.join(format!("{}-{}", stem, counter)),
+            };
+            counter += 1;
+        }
+        let _ = fs::remove_file(&temp_path);
+        Ok(dest_path)
+    }
+
     /// The headers.
     pub fn headers(&self) -> &Headers {
         &self.headers
     }
 
+    /// Whether this is a `HEAD` request.  The body of the response is never
+    /// sent for `HEAD` requests, so views with an expensive body can return
+    /// `Response::headers_only` instead:
+    ///
+    /// ```rust,ignore
+    /// fn report(request: &mut Request) -> PencilResult {
+    ///     if request.is_head() {
+    ///         return Ok(Response::headers_only(Some(report_size())));
+    ///     }
+    ///     Ok(Response::from(render_report()))
+    /// }
+    /// ```
+    pub fn is_head(&self) -> bool {
+        self.method == Method::Head
+    }
+
+    /// The time since the request was received.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// The time by which the response should be ready.  This is `None`
+    /// unless a timeout in seconds is configured with the `REQUEST_TIMEOUT`
+    /// configuration key (a module can override it), the deadline is counted from the time the
+    /// request was received.
+    pub fn deadline(&self) -> Option<Instant> {
+        let timeout = match self.config_value("REQUEST_TIMEOUT").and_then(|timeout| timeout.as_f64()) {
+            Some(timeout) if timeout > 0.0 => timeout,
+            _ => return None,
+        };
+        let timeout = Duration::new(timeout.trunc() as u64, (timeout.fract() * 1e9) as u32);
+        Some(self.started + timeout)
+    }
+
+    /// The time left until the deadline, this is zero once the deadline
+    /// has passed.  Pass it on as timeout of downstream calls, or stop early
+    /// when there is no time left:
+    ///
+    /// ```rust,ignore
+    /// fn search(request: &mut Request) -> PencilResult {
+    ///     let timeout = request.time_remaining().unwrap_or(Duration::from_secs(30));
+    ///     if timeout == Duration::from_secs(0) {
+    ///         return Err(PenHTTPError(ServiceUnavailable));
+    ///     }
+    ///     let results = try!(search_backend.query_with_timeout(request.args().get("q"), timeout));
+    ///     ...
+    /// }
+    /// ```
+    pub fn time_remaining(&self) -> Option<Duration> {
+        self.deadline().map(|deadline| {
+            let now = Instant::now();
+            if deadline > now { deadline - now } else { Duration::from_secs(0) }
+        })
+    }
+
     /// Requested path.
     pub fn path(&self) -> String {
         self.url.path().to_owned()
@@ -251,11 +859,101 @@ impl<'r, 'a, 'b: 'a> Request<'r, 'a, 'b> {
         self.url.query().map(|q| q.to_owned())
     }
 
-    /// The retrieved cookies.
+    /// The `Cookie` header of the request, see `get_cookie` and `cookie_map`
+    /// for the parsed values.
     pub fn cookies(&self) -> Option<&Cookie> {
         self.headers.get()
     }
 
+    /// The value of the cookie with the name, if a cookie is sent several
+    /// times the first one is used.
+    pub fn get_cookie(&self, name: &str) -> Option<String> {
+        self.cookies()
+            .and_then(|cookies| cookies.iter().find(|cookie| cookie.name == name))
+            .map(|cookie| cookie.value.clone())
+    }
+
+    /// The values of all cookies by name.
+    pub fn cookie_map(&self) -> HashMap<String, String> {
+        let mut cookie_map = HashMap::new();
+        if let Some(cookies) = self.cookies() {
+            for cookie in cookies.iter() {
+                cookie_map.entry(cookie.name.clone()).or_insert_with(|| cookie.value.clone());
+            }
+        }
+        cookie_map
+    }
+
+    /// The username and password of HTTP Basic authentication, a missing
+    /// password is empty.  See `auth::basic_auth` for a middleware that
+    /// checks them.
+    pub fn basic_auth(&self) -> Option<(String, String)> {
+        self.headers.get::<Authorization<Basic>>().map(|auth| {
+            (auth.0.username.clone(), auth.0.password.clone().unwrap_or_default())
+        })
+    }
+
+    /// The token of bearer authentication, see `auth::token_auth` for a
+    /// middleware that validates it.
+    pub fn bearer_token(&self) -> Option<String> {
+        self.headers.get::<Authorization<Bearer>>().map(|auth| auth.0.token.clone())
+    }
+
+    /// The value of a header, several header lines are joined by commas.
+    fn joined_header(&self, name: &str) -> Option<String> {
+        self.headers.get_raw(name).map(|values| {
+            let values: Vec<String> = values.iter().map(|value| String::from_utf8_lossy(value).into_owned()).collect();
+            join_string(values, ", ")
+        })
+    }
+
+    /// The media ranges of the `Accept` header, sorted by quality with the
+    /// most preferred first.
+    pub fn accept_mimetypes(&self) -> Vec<(String, f32)> {
+        self.joined_header("Accept").map_or(Vec::new(), |value| parse_accept_mimetypes(&value))
+    }
+
+    /// The charsets of the `Accept-Charset` header, sorted by quality.
+    pub fn accept_charsets(&self) -> Vec<(String, f32)> {
+        self.joined_header("Accept-Charset").map_or(Vec::new(), |value| parse_accept_charsets(&value))
+    }
+
+    /// The languages of the `Accept-Language` header, sorted by quality.
+    pub fn accept_languages(&self) -> Vec<(String, f32)> {
+        self.joined_header("Accept-Language").map_or(Vec::new(), |value| parse_accept_languages(&value))
+    }
+
+    /// The codings of the `Accept-Encoding` header, sorted by quality.
+    pub fn accept_encodings(&self) -> Vec<(String, f32)> {
+        self.joined_header("Accept-Encoding").map_or(Vec::new(), |value| parse_accept_encodings(&value))
+    }
+
+    /// Choose the best of the offered mimetypes for the `Accept` header,
+    /// ties go to the earlier offer.  Without an `Accept` header the first
+    /// offer is used:
+    ///
+    /// ```rust,ignore
+    /// fn user(request: &mut Request) -> PencilResult {
+    ///     match request.best_mimetype(&["text/html", "application/json"]) {
+    ///         Some("application/json") => jsonify(&load_user()),
+    ///         Some(_) => request.app.render_template("user.html", &load_user()),
+    ///         None => Err(NotAcceptable.into()),
+    ///     }
+    /// }
+    /// ```
+    pub fn best_mimetype<'o>(&self, offered: &[&'o str]) -> Option<&'o str> {
+        match self.joined_header("Accept") {
+            Some(value) => best_mimetype_match(&value, offered),
+            None => offered.first().cloned(),
+        }
+    }
+
+    /// Whether the client prefers `application/json` over `text/html`.
+    pub fn prefers_json(&self) -> bool {
+        self.headers.get_raw("Accept").is_some() &&
+            self.best_mimetype(&["text/html", "application/json"]) == Some("application/json")
+    }
+
     /// The request method.
     pub fn method(&self) -> Method {
         self.method.clone()
@@ -266,9 +964,10 @@ impl<'r, 'a, 'b: 'a> Request<'r, 'a, 'b> {
         self.remote_addr
     }
 
-    /// URL scheme (http or https)
+    /// URL scheme (http or https), behind trusted proxies this is taken
+    /// from `X-Forwarded-Proto`, see `Pencil::enable_proxy_fix`.
     pub fn scheme(&self) -> String {
-        String::from("http")
+        self.scheme.clone()
     }
 
     /// Just the host with scheme.
@@ -292,6 +991,75 @@ impl<'r, 'a, 'b: 'a> Request<'r, 'a, 'b> {
     }
 }
 
+/// The value a header got from the outermost of the trusted proxies, every
+/// proxy appends its value to the comma separated list.  If there are fewer
+/// values than trusted proxies the header can't be trusted.
+fn forwarded_value(headers: &Headers, name: &str, trusted_proxies: usize) -> Option<String> {
+    let raw = match headers.get_raw(name) {
+        Some(raw) => raw,
+        None => return None,
+    };
+    let values: Vec<String> = raw.iter()
+        .flat_map(|line| String::from_utf8_lossy(line).split(',').map(|value| value.trim().to_string()).collect::<Vec<_>>())
+        .filter(|value| !value.is_empty())
+        .collect();
+    if values.len() < trusted_proxies {
+        return None;
+    }
+    Some(values[values.len() - trusted_proxies].clone())
+}
+
+/// A cheap snapshot of a request that can be passed to threads spawned by
+/// a view, so that logs written off the request thread can still be
+/// correlated with the request:
+///
+/// ```rust,ignore
+/// fn import(request: &mut Request) -> PencilResult {
+///     let context = request.context().with_extension::<CurrentUser>(request);
+///     thread::spawn(move || {
+///         info!("[{}] import started by {:?}", context.request_id, context.extensions_data.get::<CurrentUser>());
+///     });
+///     Ok(Response::from("Import started"))
+/// }
+/// ```
+#[derive(Clone)]
+pub struct RequestContext {
+    /// The id of the request, see `Request::request_id`.
+    pub request_id: String,
+    /// The endpoint that matched the request.
+    pub endpoint: Option<String>,
+    /// The IP address of the remote connection.
+    pub remote_addr: SocketAddr,
+    /// The locale of the request.
+    pub locale: Option<String>,
+    /// The extension data copied from the request with `with_extension`.
+    pub extensions_data: ShareCloneMap,
+}
+
+impl RequestContext {
+    /// Copy the extension data for a key from the request, if the request
+    /// has it.
+    pub fn with_extension<K: Key>(mut self, request: &Request) -> RequestContext
+        where K::Value: Clone + Send + Sync {
+        if let Some(value) = request.extensions_data.get::<K>() {
+            self.extensions_data.insert::<K>(value.clone());
+        }
+        self
+    }
+}
+
+impl fmt::Debug for RequestContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<Pencil RequestContext {}>", self.request_id)
+    }
+}
+
+impl<'r, 'a, 'b: 'a> UrlFor for Request<'r, 'a, 'b> {
+    fn url_for(&self, endpoint: &str, values: &ViewArgs) -> Option<String> {
+        Request::url_for(self, endpoint, values)
+    }
+}
+
 impl<'r, 'a, 'b: 'a> fmt::Debug for Request<'r, 'a, 'b> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "<Pencil Request '{}' {}>", self.url(), self.method())
@@ -300,7 +1068,18 @@ impl<'r, 'a, 'b: 'a> fmt::Debug for Request<'r, 'a, 'b> {
 
 impl<'r, 'a, 'b: 'a> Read for Request<'r, 'a, 'b> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.body.read(buf)
+        if let Some(ref mut data) = self.cached_data {
+            return data.read(buf);
+        }
+        let size = try!(self.body.read(buf));
+        self.body_read += size as u64;
+        if let Some(limit) = self.max_content_length() {
+            if self.body_read > limit {
+                self.body_too_large = true;
+                return Err(io::Error::new(io::ErrorKind::Other, "request body too large"));
+            }
+        }
+        Ok(size)
     }
 }
 
@@ -384,15 +1163,11 @@ impl Response {
     /// let response = Response::from("Hello");
     /// ```
     pub fn new<T: 'static + BodyWrite>(body: T) -> Response {
-        let mut response = Response {
+        Response {
             status_code: 200,
-            headers: Headers::new(),
+            headers: default_headers(),
             body: Some(Box::new(body)),
-        };
-        let mime: Mime = "text/html; charset=UTF-8".parse().unwrap();
-        let content_type = ContentType(mime);
-        response.headers.set(content_type);
-        response
+        }
     }
 
     /// Create an empty response without body.
@@ -404,6 +1179,18 @@ impl Response {
         }
     }
 
+    /// Create a response without body for `HEAD` requests.  The headers are
+    /// the same as for a `GET` request, set the content length the body
+    /// would have if it is known cheaply.  Other requests get an empty body.
+    pub fn headers_only(content_length: Option<usize>) -> Response {
+        let mut response = Response::new_empty();
+        response.headers = default_headers();
+        if let Some(content_length) = content_length {
+            response.set_content_length(content_length);
+        }
+        response
+    }
+
     /// Get status name.
     pub fn status_name(&self) -> &str {
         match get_name_by_http_code(self.status_code) {
@@ -442,9 +1229,94 @@ impl Response {
         self.headers.set(content_length);
     }
 
-    /// Sets cookie.
-    pub fn set_cookie(&mut self, cookie: hyper::header::SetCookie) {
-        self.headers.set(cookie);
+    /// Sets a cookie, a cookie with the same name and path that is set
+    /// already is replaced.
+    pub fn set_cookie(&mut self, cookie: cookies::Cookie) {
+        let cookie = cookie.into_pair();
+        if let Some(set_cookie) = self.headers.get_mut::<hyper::header::SetCookie>() {
+            set_cookie.0.retain(|other| other.name != cookie.name || other.path != cookie.path);
+        }
+        self.add_cookie(cookie);
+    }
+
+    /// Deletes a cookie in the browser by setting an expired cookie with
+    /// the path `/`.
+    pub fn delete_cookie(&mut self, name: &str) {
+        self.set_cookie(cookies::Cookie::removal(name));
+    }
+
+    /// Adds one cookie, keeping the cookies that are set already.
+    pub fn add_cookie(&mut self, cookie: hyper::header::CookiePair) {
+        if let Some(set_cookie) = self.headers.get_mut::<hyper::header::SetCookie>() {
+            set_cookie.0.push(cookie);
+            return;
+        }
+        self.headers.set(hyper::header::SetCookie(vec![cookie]));
+    }
+
+    /// Sets the `ETag` header to a hash of the body, unless the response
+    /// has one already.  The body is buffered for this.
+    pub fn add_etag(&mut self) -> io::Result<()> {
+        if self.headers.has::<ETag>() {
+            return Ok(());
+        }
+        let mut body = Vec::new();
+        if let Some(mut response_body) = self.body.take() {
+            try!(response_body.write_body(&mut ResponseBody::new(&mut body)));
+        }
+        let hash = sha256(&body)[..16].to_hex();
+        self.body = Some(Box::new(body));
+        self.headers.set(ETag(EntityTag::strong(hash)));
+        Ok(())
+    }
+
+    /// Sets the `Last-Modified` header.
+    pub fn set_last_modified(&mut self, last_modified: time::Tm) {
+        self.headers.set(LastModified(HttpDate(last_modified)));
+    }
+
+    /// Turns a successful response to a `GET` or `HEAD` request into
+    /// `304 Not Modified` without body if the `ETag` or `Last-Modified`
+    /// header of the response matches the `If-None-Match` or
+    /// `If-Modified-Since` header of the request, so the client can use its
+    /// cached copy:
+    ///
+    /// ```rust,ignore
+    /// fn report(request: &mut Request) -> PencilResult {
+    ///     let mut response = Response::from(render_report());
+    ///     try!(response.add_etag().map_err(|err| UserError::new(err.to_string())));
+    ///     response.make_conditional(request);
+    ///     Ok(response)
+    /// }
+    /// ```
+    pub fn make_conditional(&mut self, request: &Request) {
+        if request.method() != Method::Get && request.method() != Method::Head {
+            return;
+        }
+        if self.status_code < 200 || self.status_code >= 300 {
+            return;
+        }
+        let not_modified = if let Some(if_none_match) = request.headers().get::<IfNoneMatch>() {
+            // `If-None-Match` takes precedence over `If-Modified-Since`.
+            match (if_none_match, self.headers.get::<ETag>()) {
+                (&IfNoneMatch::Any, _) => true,
+                (&IfNoneMatch::Items(ref etags), Some(&ETag(ref etag))) => etags.iter().any(|item| item.weak_eq(etag)),
+                (&IfNoneMatch::Items(_), None) => false,
+            }
+        } else {
+            match (request.headers().get::<IfModifiedSince>(), self.headers.get::<LastModified>()) {
+                (Some(&IfModifiedSince(HttpDate(ref since))), Some(&LastModified(HttpDate(ref last_modified)))) => {
+                    last_modified.to_timespec() <= since.to_timespec()
+                },
+                _ => false,
+            }
+        };
+        if not_modified {
+            self.status_code = 304;
+            self.body = None;
+            self.headers.remove::<ContentLength>();
+            self.headers.remove::<ContentType>();
+        }
     }
 
     /// Write the response out.  Mostly you shouldn't use this directly.
@@ -458,8 +1330,13 @@ impl Response {
         *res.headers_mut() = self.headers;
 
         // write data.
-        if request_method == Method::Head ||
-           (100 <= status_code && status_code < 200) || status_code == 204 || status_code == 304 {
+        if request_method == Method::Head {
+            // Keep the content length of the body that is not sent.
+            if !res.headers().has::<ContentLength>() {
+                res.headers_mut().set(ContentLength(0));
+            }
+            try_return!(res.start().and_then(|w| w.end()));
+        } else if (100 <= status_code && status_code < 200) || status_code == 204 || status_code == 304 {
             res.headers_mut().set(ContentLength(0));
             try_return!(res.start().and_then(|w| w.end()));
         } else {
@@ -499,7 +1376,11 @@ impl<'a> convert::From<&'a [u8]> for Response {
     /// Convert to response body.  The content length is set
     /// automatically.
     fn from(bytes: &'a [u8]) -> Response {
-        bytes.to_vec().into()
+        let mut buffer = PooledBuffer::new();
+        buffer.extend_from_slice(bytes);
+        let mut response = Response::new(buffer);
+        response.set_content_length(bytes.len());
+        response
     }
 }
 
@@ -507,7 +1388,7 @@ impl<'a> convert::From<&'a str> for Response {
     /// Convert to response body.  The content length is set
     /// automatically.
     fn from(s: &'a str) -> Response {
-        s.to_owned().into()
+        s.as_bytes().into()
     }
 }
 
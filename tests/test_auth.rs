@@ -0,0 +1,67 @@
+// Test the authentication helpers.
+
+extern crate pencil;
+extern crate typemap;
+
+use typemap::Key;
+
+use pencil::{Pencil, Request, PencilResult, Response};
+use pencil::auth::{basic_auth, token_auth};
+use pencil::testing::TestResponse;
+
+
+fn admin(request: &mut Request) -> PencilResult {
+    let (username, _) = request.basic_auth().unwrap();
+    Ok(Response::from(username))
+}
+
+
+#[test]
+fn test_basic_auth() {
+    let mut app = Pencil::new("/test");
+    app.get_with("/admin", "admin", admin)
+       .wrap_with(basic_auth("Admin area", |username, password| username == "admin" && password == "secret"));
+    let client = app.test_client();
+    let response = client.get_path("/admin").send().unwrap();
+    assert!(response.status_code == 401);
+    assert!(response.headers.get_raw("WWW-Authenticate") ==
+            Some(&[b"Basic realm=\"Admin area\", charset=\"UTF-8\"".to_vec()][..]));
+    // admin:wrong
+    let response = client.get_path("/admin").header("Authorization", "Basic YWRtaW46d3Jvbmc=").send().unwrap();
+    assert!(response.status_code == 401);
+    // admin:secret
+    let response = client.get_path("/admin").header("Authorization", "Basic YWRtaW46c2VjcmV0").send().unwrap();
+    assert!(response.status_code == 200);
+}
+
+
+struct CurrentUser;
+
+impl Key for CurrentUser {
+    type Value = String;
+}
+
+
+fn profile(request: &mut Request) -> PencilResult {
+    let user = request.extensions_data.get::<CurrentUser>().unwrap().clone();
+    Ok(Response::from(user))
+}
+
+
+#[test]
+fn test_token_auth() {
+    let mut app = Pencil::new("/test");
+    app.get_with("/profile", "profile", profile)
+       .wrap_with(token_auth::<CurrentUser, _>(|token| if token == "t0ken" { Some(String::from("alice")) } else { None }));
+    let client = app.test_client();
+    let response = client.get_path("/profile").send().unwrap();
+    assert!(response.status_code == 401);
+    assert!(response.headers.get_raw("WWW-Authenticate") == Some(&[b"Bearer".to_vec()][..]));
+    let response = client.get_path("/profile").header("Authorization", "Bearer wrong").send().unwrap();
+    assert!(response.status_code == 401);
+    assert!(response.headers.get_raw("WWW-Authenticate") == Some(&[b"Bearer error=\"invalid_token\"".to_vec()][..]));
+    let mut response = client.get_path("/profile").header("Authorization", "Bearer t0ken").send().unwrap();
+    assert!(response.status_code == 200);
+    let body = response.body_bytes();
+    assert!(body == b"alice");
+}
@@ -0,0 +1,793 @@
+// Test the test client.
+
+extern crate pencil;
+extern crate rustc_serialize as serialize;
+extern crate typemap;
+extern crate time;
+extern crate hyper;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::panic;
+use std::thread;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use serialize::json::ToJson;
+use typemap::Key;
+
+use pencil::{Pencil, PencilApp, Request, PencilResult, Response};
+use pencil::{Module, HTTPError, JsonBody, View};
+use pencil::method::{Get, Head};
+use pencil::testing::{ModuleTestApp, ResponseSnapshot, TestResponse};
+use hyper::header::Headers;
+
+
+fn upload(request: &mut Request) -> PencilResult {
+    let name = request.form().get("name").cloned().unwrap_or_default();
+    let mut data = String::new();
+    if let Some(avatar) = request.files().get("avatar") {
+        File::open(&avatar.path).unwrap().read_to_string(&mut data).unwrap();
+    }
+    Ok(Response::from(format!("{}:{}", name, data)))
+}
+
+
+#[test]
+fn test_client_multipart_upload() {
+    let mut app = Pencil::new("/test");
+    app.post("/upload", "upload", upload);
+    let client = app.test_client();
+    let mut response = client.post("/upload")
+                         .file("avatar", "cat.png", "meow", "image/png")
+                         .field("name", "cat")
+                         .send()
+                         .unwrap();
+    assert!(response.status_code == 200);
+    assert!(response.body_text() == "cat:meow");
+}
+
+
+#[test]
+fn test_client_form_fields() {
+    let mut app = Pencil::new("/test");
+    app.post("/upload", "upload", upload);
+    let client = app.test_client();
+    let mut response = client.post("/upload").field("name", "a b&c").send().unwrap();
+    assert!(response.body_text() == "a b&c:");
+}
+
+
+#[test]
+fn test_request_values() {
+    let app = Pencil::new("/test");
+    let client = app.test_client();
+    let (q, all_q, page, tag) = client.post("/search?q=url&page=2").field("q", "form").field("tag", "cat")
+        .with_request(|request| {
+            let values = request.values();
+            (values.get("q").cloned(), values.getlist("q").cloned(), values.get("page").cloned(), values.get("tag").cloned())
+        }).unwrap();
+    assert!(q == Some(String::from("form")));
+    assert!(all_q == Some(vec![String::from("form"), String::from("url")]));
+    assert!(page == Some(String::from("2")));
+    assert!(tag == Some(String::from("cat")));
+}
+
+
+fn user(_: &mut Request) -> PencilResult {
+    let mut response = Response::from("{\"name\": \"cat\", \"id\": 42}");
+    response.set_content_type("application/json");
+    Ok(response)
+}
+
+
+#[test]
+fn test_client_response_snapshot() {
+    let mut app = Pencil::new("/test");
+    app.get("/user", "user", user);
+    let client = app.test_client();
+    let path = env::temp_dir().join("pencil-test-snapshot.txt");
+    let _ = fs::remove_file(&path);
+    let snapshot = ResponseSnapshot::new(client.get_path("/user").send().unwrap(), &["Content-Type"]);
+    snapshot.assert_matches(&path);
+    let mut written = String::new();
+    File::open(&path).unwrap().read_to_string(&mut written).unwrap();
+    assert!(written == "200\nContent-Type: application/json\n\n{\n  \"id\": 42,\n  \"name\": \"cat\"\n}\n");
+    ResponseSnapshot::new(client.get_path("/user").send().unwrap(), &["Content-Type"]).assert_matches(&path);
+    let mut changed = snapshot.clone();
+    changed.status_code = 404;
+    assert!(panic::catch_unwind(|| changed.assert_matches(&path)).is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+
+fn module_index(request: &mut Request) -> PencilResult {
+    Ok(Response::from(request.endpoint().unwrap()))
+}
+
+
+fn module_not_found(_: HTTPError) -> PencilResult {
+    let mut response = Response::from("module not found");
+    response.status_code = 404;
+    Ok(response)
+}
+
+
+#[test]
+fn test_module_test_app() {
+    let mut module = Module::new("users", "/test");
+    module.route("/users", &[Get], "index", module_index);
+    module.app_httperrorhandler(404, module_not_found);
+    let tester = ModuleTestApp::new(module);
+    assert!(tester.is_testing());
+    let client = tester.client();
+    assert!(client.get_path("/users").send().unwrap().body_text() == "users.index");
+    let mut response = client.get_path("/missing").send().unwrap();
+    assert!(response.status_code == 404);
+    assert!(response.body_text() == "module not found");
+}
+
+
+fn remaining(request: &mut Request) -> PencilResult {
+    let remaining = match request.time_remaining() {
+        Some(remaining) => remaining.as_secs().to_string(),
+        None => String::from("none"),
+    };
+    Ok(Response::from(remaining))
+}
+
+
+#[test]
+fn test_request_deadline() {
+    let mut app = Pencil::new("/test");
+    app.get("/remaining", "remaining", remaining);
+    assert!(app.test_client().get_path("/remaining").send().unwrap().body_text() == "none");
+    app.config.set("REQUEST_TIMEOUT", 30.to_json());
+    let remaining = app.test_client().get_path("/remaining").send().unwrap().body_text();
+    assert!(remaining == "29" || remaining == "30");
+}
+
+
+fn report(request: &mut Request) -> PencilResult {
+    if request.is_head() {
+        return Ok(Response::headers_only(Some(6)));
+    }
+    Ok(Response::from("report"))
+}
+
+
+#[test]
+fn test_head_headers_only() {
+    let mut app = Pencil::new("/test");
+    app.get("/report", "report", report);
+    let client = app.test_client();
+    let get = client.get_path("/report").send().unwrap();
+    let head = client.open_request(Head, "/report").send().unwrap();
+    assert!(head.status_code == 200);
+    assert!(head.body.is_none());
+    assert!(head.content_length() == get.content_length());
+    assert!(head.content_type() == get.content_type());
+}
+
+
+#[test]
+fn test_module_config_overrides_app_config() {
+    let mut app = Pencil::new("/test");
+    app.config.set("REQUEST_TIMEOUT", 30.to_json());
+    app.get("/remaining", "remaining", remaining);
+    let mut module = Module::new("slow", "/test");
+    module.config.set("REQUEST_TIMEOUT", 300.to_json());
+    module.route("/slow/remaining", &[Get], "remaining", remaining);
+    module.register(&mut app);
+    let client = app.test_client();
+    let remaining = client.get_path("/remaining").send().unwrap().body_text();
+    assert!(remaining == "29" || remaining == "30");
+    let remaining = client.get_path("/slow/remaining").send().unwrap().body_text();
+    assert!(remaining == "299" || remaining == "300");
+}
+
+
+#[test]
+fn test_module_registered_twice() {
+    let mut blog = Module::new("blog", "/test");
+    blog.route("/posts", &[Get], "index", module_index);
+    let mut app = Pencil::new("/test");
+    app.register_module_as(&blog, "blog_en", "/en");
+    app.register_module_as(&blog, "blog_de", "/de/");
+    blog.register(&mut app);
+    let client = app.test_client();
+    assert!(client.get_path("/en/posts").send().unwrap().body_text() == "blog_en.index");
+    assert!(client.get_path("/de/posts").send().unwrap().body_text() == "blog_de.index");
+    assert!(client.get_path("/posts").send().unwrap().body_text() == "blog.index");
+}
+
+
+fn config_name(request: &mut Request) -> PencilResult {
+    let name = request.config_value("NAME").and_then(|name| name.as_string().map(|name| name.to_string())).unwrap_or_default();
+    Ok(Response::from(name))
+}
+
+
+fn create_app(name: &str) -> PencilApp {
+    let mut app = Pencil::new("/test");
+    app.config.set("NAME", name.to_json());
+    app.get("/name", "name", config_name);
+    app.build()
+}
+
+
+#[test]
+fn test_built_app_shared_across_threads() {
+    let first = create_app("first");
+    let second = create_app("second");
+    let shared = first.clone();
+    let handle = thread::spawn(move || shared.test_client().get_path("/name").send().unwrap().body_text());
+    assert!(handle.join().unwrap() == "first");
+    assert!(second.test_client().get_path("/name").send().unwrap().body_text() == "second");
+}
+
+
+struct CurrentUser;
+
+impl Key for CurrentUser {
+    type Value = String;
+}
+
+
+fn background(request: &mut Request) -> PencilResult {
+    request.extensions_data.insert::<CurrentUser>(String::from("cat"));
+    let context = request.context().with_extension::<CurrentUser>(request);
+    let handle = thread::spawn(move || {
+        format!("{} {} {}", context.request_id, context.endpoint.unwrap(),
+                context.extensions_data.get::<CurrentUser>().unwrap())
+    });
+    Ok(Response::from(handle.join().unwrap()))
+}
+
+
+#[test]
+fn test_request_context_in_thread() {
+    let mut app = Pencil::new("/test");
+    app.get("/background", "background", background);
+    let client = app.test_client();
+    let text = client.get_path("/background").header("X-Request-Id", "abc123").send().unwrap().body_text();
+    assert!(text == "abc123 background cat");
+    let text = client.get_path("/background").send().unwrap().body_text();
+    let request_id = text.split(' ').next().unwrap();
+    assert!(request_id.len() == 16 && request_id != "abc123");
+}
+
+
+#[test]
+fn test_module_url_prefix() {
+    let mut admin = Module::new("admin", "/test").url_prefix("/admin/");
+    admin.route("/users", &[Get], "users", module_index);
+    let mut app = Pencil::new("/test");
+    app.register_module_as(&admin, "admin_en", "/en");
+    admin.register(&mut app);
+    let client = app.test_client();
+    assert!(client.get_path("/admin/users").send().unwrap().body_text() == "admin.users");
+    assert!(client.get_path("/en/admin/users").send().unwrap().body_text() == "admin_en.users");
+    assert!(client.get_path("/users").send().unwrap().status_code == 404);
+}
+
+
+#[test]
+fn test_module_static_files() {
+    let mut shop = Module::new("shop", "./tests/modules/shop").url_prefix("/shop");
+    shop.static_folder = Some(String::from("assets"));
+    let mut app = Pencil::new("./tests");
+    app.enable_static_file_handling();
+    shop.register(&mut app);
+    let client = app.test_client();
+    assert!(client.get_path("/shop/assets/shop.css").send().unwrap().body_text() == "shop.css");
+    assert!(client.get_path("/shop/assets/digits.txt").send().unwrap().status_code == 404);
+    assert!(client.get_path("/static/digits.txt").send().unwrap().body_text() == "0123456789");
+
+    // Without a url prefix the module's default static route would shadow
+    // the application's static files.
+    let mut shop = Module::new("shop", "./tests/modules/shop");
+    shop.static_folder = Some(String::from("static"));
+    let mut app = Pencil::new("./tests");
+    app.enable_static_file_handling();
+    shop.register(&mut app);
+    let mut values = HashMap::new();
+    values.insert(String::from("filename"), String::from("digits.txt"));
+    assert!(app.url_for("shop.static", &values).is_none());
+    assert!(app.test_client().get_path("/static/digits.txt").send().unwrap().body_text() == "0123456789");
+}
+
+
+#[test]
+fn test_nested_modules() {
+    let mut users = Module::new("users", "/test").url_prefix("/users");
+    users.route("/", &[Get], "index", module_index);
+    users.route("/name", &[Get], "name", config_name);
+    let mut admin = Module::new("admin", "/test").url_prefix("/admin");
+    admin.config.set("NAME", "admin".to_json());
+    admin.register_module(users);
+    let mut app = Pencil::new("/test");
+    admin.register(&mut app);
+    let client = app.test_client();
+    assert!(client.get_path("/admin/users/").send().unwrap().body_text() == "admin.users.index");
+    assert!(client.get_path("/admin/users/name").send().unwrap().body_text() == "admin");
+    assert!(app.modules.contains_key("admin.users"));
+}
+
+
+fn require_token(request: &mut Request) -> Option<PencilResult> {
+    match request.headers().get_raw("X-Token") {
+        Some(_) => None,
+        None => Some(Err(HTTPError::Unauthorized.into())),
+    }
+}
+
+
+#[test]
+fn test_request_context_without_server() {
+    let mut app = Pencil::new("/test");
+    app.get("/users", "index", module_index);
+    let denied = app.test_request_context(Get, "/users").with_request(|request| require_token(request)).unwrap();
+    assert!(denied.is_some());
+    let allowed = app.test_request_context(Get, "/users").header("X-Token", "secret")
+                     .with_request(|request| (request.endpoint(), require_token(request).is_none())).unwrap();
+    assert!(allowed == (Some(String::from("index")), true));
+    let mut response = app.test_request_context(Get, "/users").with_request(|request| app.handle_request(request)).unwrap();
+    assert!(response.body_text() == "index");
+}
+
+
+#[test]
+fn test_closure_hooks() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let mut app = Pencil::new("/test");
+    app.get("/users", "index", module_index);
+    let requests = counter.clone();
+    app.before_request(move |_| {
+        requests.fetch_add(1, Ordering::SeqCst);
+        None
+    });
+    let header = String::from("counted");
+    app.after_request(move |response| response.headers.set_raw("X-Hook", vec![header.clone().into_bytes()]));
+    let client = app.test_client();
+    client.get_path("/users").send().unwrap();
+    let response = client.get_path("/users").send().unwrap();
+    assert!(counter.load(Ordering::SeqCst) == 2);
+    assert!(response.headers.get_raw("X-Hook").unwrap()[0] == b"counted".to_vec());
+}
+
+
+fn admin(_: &mut Request) -> PencilResult {
+    Ok(Response::from("admin"))
+}
+
+
+#[test]
+fn test_route_hooks() {
+    let mut app = Pencil::new("/test");
+    app.get("/users", "index", module_index);
+    app.get_with("/admin", "admin", admin)
+       .before(require_token)
+       .after(|response| response.headers.set_raw("X-Admin", vec![b"1".to_vec()]))
+       .wrap(|request, next| {
+           let result = next(request);
+           result.map(|mut response| Response::from(format!("[{}]", response.body_text())))
+       });
+    let client = app.test_client();
+    assert!(client.get_path("/admin").send().unwrap().status_code == 401);
+    let mut response = client.get_path("/admin").header("X-Token", "secret").send().unwrap();
+    assert!(response.headers.get_raw("X-Admin").is_some());
+    assert!(response.body_text() == "[admin]");
+    let mut response = client.get_path("/users").send().unwrap();
+    assert!(response.headers.get_raw("X-Admin").is_none());
+    assert!(response.body_text() == "index");
+}
+
+
+#[test]
+fn test_before_first_request() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let mut app = Pencil::new("/test");
+    app.get("/users", "index", module_index);
+    let runs = counter.clone();
+    app.before_first_request(move |app| {
+        assert!(app.url_map.iter_rules().count() == 1);
+        runs.fetch_add(1, Ordering::SeqCst);
+    });
+    let app = app.build();
+    assert!(counter.load(Ordering::SeqCst) == 0);
+    let handles: Vec<_> = (0..4).map(|_| {
+        let app = app.clone();
+        thread::spawn(move || app.test_client().get_path("/users").send().unwrap().status_code)
+    }).collect();
+    for handle in handles {
+        assert!(handle.join().unwrap() == 200);
+    }
+    assert!(counter.load(Ordering::SeqCst) == 1);
+}
+
+
+#[test]
+fn test_before_first_request_panics() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let mut app = Pencil::new("/test");
+    app.get("/users", "index", module_index);
+    let runs = counter.clone();
+    app.before_first_request(move |_| {
+        if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+            panic!("warm-up failed");
+        }
+    });
+    let client = app.test_client();
+    assert!(client.get_path("/users").send().unwrap().status_code == 500);
+    assert!(client.get_path("/users").send().unwrap().status_code == 200);
+    assert!(client.get_path("/users").send().unwrap().status_code == 200);
+    assert!(counter.load(Ordering::SeqCst) == 2);
+}
+
+
+fn choose_theme(request: &mut Request) -> PencilResult {
+    if let Some(theme) = request.args().get("theme").cloned() {
+        request.call_after(move |response| response.headers.set_raw("X-Theme", vec![theme.into_bytes()]));
+    }
+    Ok(Response::from("theme"))
+}
+
+
+#[test]
+fn test_call_after_this_request() {
+    let mut app = Pencil::new("/test");
+    app.get("/theme", "choose_theme", choose_theme);
+    app.after_request(|response| {
+        let seen = response.headers.get_raw("X-Theme").is_some();
+        response.headers.set_raw("X-Theme-Seen", vec![seen.to_string().into_bytes()]);
+    });
+    let client = app.test_client();
+    let response = client.get_path("/theme?theme=dark").send().unwrap();
+    assert!(response.headers.get_raw("X-Theme") == Some(&[b"dark".to_vec()][..]));
+    assert!(response.headers.get_raw("X-Theme-Seen") == Some(&[b"true".to_vec()][..]));
+    let response = client.get_path("/theme").send().unwrap();
+    assert!(response.headers.get_raw("X-Theme").is_none());
+}
+
+
+#[test]
+fn test_response_time_header() {
+    let mut app = Pencil::new("/test");
+    app.get("/users", "index", module_index);
+    assert!(app.test_client().get_path("/users").send().unwrap().headers.get_raw("X-Response-Time").is_none());
+    app.enable_response_time_header();
+    let response = app.test_client().get_path("/missing").send().unwrap();
+    let value = String::from_utf8(response.headers.get_raw("X-Response-Time").unwrap()[0].clone()).unwrap();
+    assert!(value.ends_with("ms"));
+    assert!(value.trim_right_matches("ms").parse::<f64>().unwrap() >= 0.0);
+}
+
+
+fn client_info(request: &mut Request) -> PencilResult {
+    Ok(Response::from(format!("{} {} {} {}", request.remote_addr.ip(), request.scheme(), request.host(), request.url())))
+}
+
+
+#[test]
+fn test_proxy_fix() {
+    let mut app = Pencil::new("/test");
+    app.get("/info", "info", client_info);
+    let send = |app: &Pencil| {
+        app.test_client().get_path("/info")
+            .header("X-Forwarded-For", "10.0.0.1, 203.0.113.7")
+            .header("X-Forwarded-Proto", "https")
+            .header("X-Forwarded-Host", "example.com")
+            .send().unwrap()
+    };
+    assert!(send(&app).body_text() == "127.0.0.1 http localhost http://localhost/info");
+    app.enable_proxy_fix(1);
+    assert!(send(&app).body_text() == "203.0.113.7 https example.com https://example.com/info");
+    app.enable_proxy_fix(2);
+    assert!(send(&app).body_text() == "10.0.0.1 http localhost http://localhost/info");
+}
+
+
+#[test]
+fn test_content_negotiation() {
+    let app = Pencil::new("/test");
+    let request = app.test_request_context(Get, "/")
+        .header("Accept", "text/html;q=0.5, application/json")
+        .header("Accept-Language", "de;q=0.7, en-US")
+        .header("Accept-Charset", "*;q=0.1, utf-8");
+    let (mimetypes, languages, charsets, best, prefers_json) = request.with_request(|request| {
+        (request.accept_mimetypes(), request.accept_languages(), request.accept_charsets(),
+         request.best_mimetype(&["text/html", "application/json"]), request.prefers_json())
+    }).unwrap();
+    assert!(mimetypes == vec![(String::from("application/json"), 1.0), (String::from("text/html"), 0.5)]);
+    assert!(languages == vec![(String::from("en-US"), 1.0), (String::from("de"), 0.7)]);
+    assert!(charsets == vec![(String::from("utf-8"), 1.0), (String::from("*"), 0.1)]);
+    assert!(best == Some("application/json"));
+    assert!(prefers_json);
+    let (best, prefers_json) = app.test_request_context(Get, "/").with_request(|request| {
+        (request.best_mimetype(&["text/html", "application/json"]), request.prefers_json())
+    }).unwrap();
+    assert!(best == Some("text/html"));
+    assert!(!prefers_json);
+}
+
+
+fn cached_report(request: &mut Request) -> PencilResult {
+    let mut response = Response::from("report");
+    response.add_etag().unwrap();
+    response.make_conditional(request);
+    Ok(response)
+}
+
+
+#[test]
+fn test_response_make_conditional() {
+    let mut app = Pencil::new("/test");
+    app.get("/report", "report", cached_report);
+    let client = app.test_client();
+    let mut response = client.get_path("/report").send().unwrap();
+    assert!(response.status_code == 200);
+    let etag = String::from_utf8(response.headers.get_raw("ETag").unwrap()[0].clone()).unwrap();
+    assert!(response.body_text() == "report");
+    let response = client.get_path("/report").header("If-None-Match", &etag).send().unwrap();
+    assert!(response.status_code == 304);
+    let response = client.post("/report").header("If-None-Match", &etag).send().unwrap();
+    assert!(response.status_code == 405);
+
+    let mut response = Response::from("report");
+    response.set_last_modified(time::at_utc(time::Timespec::new(784111777, 0)));
+    assert!(response.headers.get_raw("Last-Modified") == Some(&[b"Sun, 06 Nov 1994 08:49:37 GMT".to_vec()][..]));
+    let modified = app.test_request_context(Get, "/report")
+        .header("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT")
+        .with_request(|request| { response.make_conditional(request); response.status_code }).unwrap();
+    assert!(modified == 304);
+}
+
+
+#[test]
+fn test_save_uploaded_file() {
+    let dest_dir = env::temp_dir().join(format!("pencil-uploads-{}", std::process::id()));
+    fs::create_dir_all(&dest_dir).unwrap();
+    let dest_dir_str = dest_dir.to_str().unwrap();
+    let mut app = Pencil::new("/test");
+    app.config.set("UPLOAD_MAX_FILE_SIZE", 10.to_json());
+    app.config.set("UPLOAD_ALLOWED_EXTENSIONS", vec![String::from("png")].to_json());
+    let client = app.test_client();
+    let save = |filename: &str, data: &str| {
+        client.post("/upload").file("avatar", filename, data, "image/png")
+              .with_request(|request| request.save_file("avatar", dest_dir_str)).unwrap()
+    };
+    let path = save("../cat.png", "meow").unwrap();
+    assert!(path == dest_dir.join("cat.png"));
+    let mut data = String::new();
+    File::open(&path).unwrap().read_to_string(&mut data).unwrap();
+    assert!(data == "meow");
+    assert!(save("cat.png", "purr").unwrap() == dest_dir.join("cat-1.png"));
+    assert!(save("cat.gif", "meow").unwrap_err().to_string() == "File type not allowed in avatar.");
+    assert!(save("big.png", "meow meow meow").is_err());
+    let missing = client.post("/upload").field("name", "cat")
+                        .with_request(|request| request.save_file("avatar", dest_dir_str)).unwrap();
+    assert!(missing.is_err());
+    // No temporary files are left behind.
+    let mut names: Vec<String> = fs::read_dir(&dest_dir).unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+    names.sort();
+    assert!(names == vec![String::from("cat-1.png"), String::from("cat.png")]);
+    fs::remove_dir_all(&dest_dir).unwrap();
+}
+
+
+#[test]
+fn test_form_size_limits() {
+    let mut app = Pencil::new("/test");
+    app.config.set("MAX_CONTENT_LENGTH", 1000.to_json());
+    app.config.set("MAX_FORM_FILE_SIZE", 10.to_json());
+    app.config.set("MAX_FORM_FIELD_SIZE", 5.to_json());
+    let client = app.test_client();
+    let form_error = |request: pencil::testing::TestRequest| {
+        request.with_request(|request| request.form_error().map(|error| error.code())).unwrap()
+    };
+    assert!(form_error(client.post("/upload").field("name", "cat").file("avatar", "cat.png", "meow", "image/png")) == None);
+    assert!(form_error(client.post("/upload").file("avatar", "cat.png", "meow meow meow", "image/png")) == Some(413));
+    assert!(form_error(client.post("/upload").field("name", "catcatcat")) == Some(413));
+    assert!(form_error(client.post("/upload").body(vec![b'a'; 2000], "application/x-www-form-urlencoded")) == Some(413));
+    assert!(form_error(client.post("/upload").body("--x\r\nbroken", "multipart/form-data; boundary=x")) == Some(400));
+}
+
+
+fn form_names(request: &mut Request) -> PencilResult {
+    let names: Vec<String> = try!(request.try_form()).listiter().map(|(name, _)| name.clone()).collect();
+    Ok(Response::from(names.join(",")))
+}
+
+
+fn file_names(request: &mut Request) -> PencilResult {
+    let names: Vec<String> = request.files().listiter().map(|(name, _)| name.clone()).collect();
+    Ok(Response::from(names.join(",")))
+}
+
+
+#[test]
+fn test_form_size_limits_fail_dispatch() {
+    let mut app = Pencil::new("/test");
+    app.config.set("MAX_FORM_FILE_SIZE", 10.to_json());
+    app.post("/upload", "upload", form_names);
+    app.post("/files", "files", file_names);
+    let client = app.test_client();
+    let mut response = client.post("/upload").field("name", "cat").file("avatar", "cat.png", "meow", "image/png").send().unwrap();
+    assert!(response.status_code == 200);
+    assert!(response.body_text() == "name");
+    let response = client.post("/upload").field("name", "cat").file("avatar", "cat.png", "meow meow meow", "image/png").send().unwrap();
+    assert!(response.status_code == 413);
+    let code = client.post("/upload").file("avatar", "cat.png", "meow meow meow", "image/png")
+        .with_request(|request| request.try_files().err().map(|error| error.code())).unwrap();
+    assert!(code == Some(413));
+    // A view that reads the form without checking the error keeps its response.
+    let mut response = client.post("/files").file("avatar", "cat.png", "meow meow meow", "image/png").send().unwrap();
+    assert!(response.status_code == 200);
+    assert!(response.body_text() == "");
+}
+
+
+fn echo(request: &mut Request) -> PencilResult {
+    let data = try!(request.try_get_data()).to_vec();
+    Ok(Response::from(data))
+}
+
+
+fn data_length(request: &mut Request) -> PencilResult {
+    Ok(Response::from(request.get_data().len().to_string()))
+}
+
+
+#[test]
+fn test_max_content_length() {
+    let mut app = Pencil::new("/test");
+    app.post("/echo", "echo", echo);
+    app.config.set("MAX_CONTENT_LENGTH", 10.to_json());
+    let client = app.test_client();
+    let mut response = client.post("/echo").body("hello", "text/plain").send().unwrap();
+    assert!(response.status_code == 200);
+    assert!(response.body_text() == "hello");
+    let response = client.post("/echo").body("hello hello hello", "text/plain").send().unwrap();
+    assert!(response.status_code == 413);
+    let (data_len, too_large, code) = client.post("/echo").body("[1, 2, 3, 4, 5, 6]", "application/json")
+        .with_request(|request| {
+            let code = request.get_json_strict().err().map(|error| error.code());
+            (request.get_data().len(), request.body_too_large(), code)
+        }).unwrap();
+    assert!(data_len == 0);
+    assert!(too_large);
+    assert!(code == Some(413));
+}
+
+
+#[test]
+fn test_max_content_length_chunked() {
+    let mut app = Pencil::new("/test");
+    app.post("/echo", "echo", echo);
+    app.post("/length", "length", data_length);
+    app.config.set("MAX_CONTENT_LENGTH", 10.to_json());
+    let client = app.test_client();
+    let chunked = |body: &str| client.post("/echo").header("Transfer-Encoding", "chunked").body(body, "text/plain");
+    let mut response = chunked("5\r\nhello\r\n0\r\n\r\n").send().unwrap();
+    assert!(response.status_code == 200);
+    assert!(response.body_text() == "hello");
+    let response = chunked("6\r\nhello \r\n6\r\nhello \r\n0\r\n\r\n").send().unwrap();
+    assert!(response.status_code == 413);
+    let (data_len, code) = chunked("6\r\nhello \r\n6\r\nhello \r\n0\r\n\r\n")
+        .with_request(|request| {
+            let code = request.try_get_data().err().map(|error| error.code());
+            (request.get_data().len(), code)
+        }).unwrap();
+    assert!(data_len == 0);
+    assert!(code == Some(413));
+    // The response of a view that ignores the error is kept.
+    let mut response = client.post("/length").header("Transfer-Encoding", "chunked")
+        .body("6\r\nhello \r\n6\r\nhello \r\n0\r\n\r\n", "text/plain").send().unwrap();
+    assert!(response.status_code == 200);
+    assert!(response.body_text() == "0");
+}
+
+
+fn hello(_: &mut Request) -> &'static str {
+    "hello"
+}
+
+
+fn created(_: &mut Request) -> (String, u16) {
+    (String::from("created"), 201)
+}
+
+
+fn moved(_: &mut Request) -> (&'static str, u16, Headers) {
+    let mut headers = Headers::new();
+    headers.set_raw("Location", vec![b"/new".to_vec()]);
+    ("moved", 301, headers)
+}
+
+
+fn numbers(_: &mut Request) -> JsonBody<Vec<u32>> {
+    JsonBody(vec![1, 2, 3])
+}
+
+
+fn forbidden(_: &mut Request) -> Result<&'static str, HTTPError> {
+    Err(HTTPError::new(403))
+}
+
+
+#[test]
+fn test_view_return_values() {
+    let mut app = Pencil::new("/test");
+    app.get("/hello", "hello", hello);
+    app.get("/created", "created", created);
+    app.get("/moved", "moved", moved);
+    app.get("/numbers", "numbers", numbers);
+    app.get("/forbidden", "forbidden", forbidden);
+    app.get("/closure", "closure", |request: &mut Request| format!("path {}", request.path()));
+    let client = app.test_client();
+    assert!(client.get_path("/hello").send().unwrap().body_text() == "hello");
+    let mut response = client.get_path("/created").send().unwrap();
+    assert!(response.status_code == 201);
+    assert!(response.body_text() == "created");
+    let response = client.get_path("/moved").send().unwrap();
+    assert!(response.status_code == 301);
+    assert!(response.headers.get_raw("Location") == Some(&[b"/new".to_vec()][..]));
+    let mut response = client.get_path("/numbers").send().unwrap();
+    assert!(response.headers.get_raw("Content-Type") == Some(&[b"application/json".to_vec()][..]));
+    assert!(response.body_text() == "[1,2,3]");
+    assert!(client.get_path("/forbidden").send().unwrap().status_code == 403);
+    assert!(client.get_path("/closure").send().unwrap().body_text() == "path /closure");
+}
+
+
+struct Greeting {
+    greeting: String,
+}
+
+impl View for Greeting {
+    fn call(&self, request: &mut Request) -> PencilResult {
+        let name = request.view_args.get("name").cloned().unwrap_or_default();
+        Ok(Response::from(format!("{}, {}!", self.greeting, name)))
+    }
+}
+
+
+#[test]
+fn test_stateful_view() {
+    let mut app = Pencil::new("/test");
+    app.get("/hello/<name:string>", "hello", Greeting { greeting: String::from("Hello") });
+    let mut module = Module::new("french", "/test");
+    module.route("/bonjour/<name:string>", &[Get], "hello", Greeting { greeting: String::from("Bonjour") });
+    module.register(&mut app);
+    let client = app.test_client();
+    assert!(client.get_path("/hello/cat").send().unwrap().body_text() == "Hello, cat!");
+    assert!(client.get_path("/bonjour/chat").send().unwrap().body_text() == "Bonjour, chat!");
+}
+
+
+#[test]
+fn test_cached_request_body() {
+    let app = Pencil::new("/test");
+    let client = app.test_client();
+    let (name, data) = client.post("/").body("{\"name\": \"cat\"}", "application/json").with_request(|request| {
+        let name = request.get_json().as_ref().and_then(|json| json.find("name")).and_then(|name| name.as_string()).map(|name| name.to_string());
+        (name, request.get_data().to_vec())
+    }).unwrap();
+    assert!(name == Some(String::from("cat")));
+    assert!(data == b"{\"name\": \"cat\"}");
+    let (data, name) = client.post("/").field("name", "cat").with_request(|request| {
+        let data = request.get_data().to_vec();
+        (data, request.form().get("name").cloned())
+    }).unwrap();
+    assert!(data == b"name=cat");
+    assert!(name == Some(String::from("cat")));
+    let (name, data) = client.post("/").field("name", "cat").with_request(|request| {
+        let name = request.form().get("name").cloned();
+        (name, request.get_data().to_vec())
+    }).unwrap();
+    assert!(name == Some(String::from("cat")));
+    assert!(data == b"name=cat");
+}
@@ -5,10 +5,13 @@ extern crate rustc_serialize as serialize;
 
 use std::env;
 use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::sync::atomic::{self, AtomicUsize, Ordering};
 use serialize::json;
 use serialize::json::ToJson;
 
-use pencil::Pencil;
+use pencil::{Config, Pencil};
 
 
 fn config_test(app: Pencil) {
@@ -56,3 +59,126 @@ fn test_config_from_envvar() {
     config_test(app);
     env::remove_var("PENCIL_TEST_APP_SETTINGS");
 }
+
+
+#[test]
+fn test_config_from_env_profile() {
+    let mut app = Pencil::new("/test");
+    env::set_var("PENCIL_ENV", "production");
+    app.config.from_env_profile("./tests/config");
+    env::remove_var("PENCIL_ENV");
+    assert!(app.env() == "production");
+    assert!(!app.is_debug());
+    assert!(app.config.get("SECRET_KEY").unwrap().as_string().unwrap() == "default");
+    assert!(app.config.get("DATABASE_URL").unwrap().as_string().unwrap() == "postgres://db/prod");
+
+    let mut app = Pencil::new("/test");
+    app.config.set("ENV", "development".to_json());
+    app.config.from_env_profile("./tests/config");
+    assert!(app.is_debug());
+    assert!(app.config.get("DATABASE_URL").unwrap().as_string().unwrap() == "sqlite://dev.db");
+
+    let mut app = Pencil::new("/test");
+    env::set_var("PENCIL_ENV", "development");
+    app.config.from_env_profile("./tests/config");
+    assert!(app.is_debug());
+    assert!(!app.is_testing());
+    let mut app = Pencil::new("/test");
+    app.set_debug(false);
+    app.config.from_env_profile("./tests/config");
+    env::remove_var("PENCIL_ENV");
+    assert!(!app.is_debug());
+}
+
+
+#[test]
+fn test_config_secret_keys() {
+    let mut app = Pencil::new("/test");
+    assert!(app.secret_keys().is_none());
+    app.config.set("SECRET_KEY", "new".to_json());
+    app.config.set("SECRET_KEY_FALLBACKS", vec!["old".to_string()].to_json());
+    let keys = app.secret_keys().unwrap();
+    assert!(keys.current == b"new".to_vec());
+    assert!(keys.fallbacks == vec![b"old".to_vec()]);
+    env::set_var("PENCIL_TEST_SECRET_KEY", "fromenv");
+    app.config.secret_key_from_envvar("PENCIL_TEST_SECRET_KEY");
+    env::remove_var("PENCIL_TEST_SECRET_KEY");
+    assert!(app.secret_keys().unwrap().current == b"fromenv".to_vec());
+}
+
+
+#[test]
+#[should_panic(expected = "A SECRET_KEY is required for session")]
+fn test_config_missing_secret_key() {
+    let app = Pencil::new("/test");
+    app.signer("session");
+}
+
+
+#[test]
+fn test_secret_key_from_file() {
+    let path = env::temp_dir().join("pencil-test-secret-key");
+    File::create(&path).unwrap().write_all(b"  s3cr3t\n").unwrap();
+    let mut config = Config::new();
+    config.secret_key_from_file(path.to_str().unwrap());
+    assert!(config.get("SECRET_KEY").unwrap().as_string() == Some("s3cr3t"));
+    fs::remove_file(&path).unwrap();
+}
+
+
+#[test]
+#[should_panic(expected = "Could not read the secret key file")]
+fn test_secret_key_from_missing_file() {
+    let mut config = Config::new();
+    config.secret_key_from_file("/nonexistent/pencil-secret-key");
+}
+
+
+#[allow(deprecated)]
+static RELOADS: AtomicUsize = atomic::ATOMIC_USIZE_INIT;
+
+
+fn count_reload(config: &Config) {
+    assert!(config.get("FEATURE").is_some());
+    RELOADS.fetch_add(1, Ordering::SeqCst);
+}
+
+
+#[test]
+fn test_config_reload() {
+    let path = env::temp_dir().join("pencil-test-reload.json");
+    let path = path.to_str().unwrap();
+    File::create(path).unwrap().write_all(b"{\"FEATURE\": false}").unwrap();
+    let mut app = Pencil::new("/test");
+    app.config.set("TEST_KEY", "foo".to_json());
+    app.enable_config_reload(path);
+    app.on_config_reload(count_reload);
+    app.post("/reload", "reload_config", pencil::config::reload_config);
+    assert!(app.config.get_boolean("FEATURE", true) == false);
+    File::create(path).unwrap().write_all(b"{\"FEATURE\": true}").unwrap();
+    let response = app.test_client().post("/reload").send().unwrap();
+    assert!(response.status_code == 200);
+    assert!(RELOADS.load(Ordering::SeqCst) == 1);
+    let live_config = app.live_config();
+    assert!(live_config.get_boolean("FEATURE", false));
+    assert!(live_config.get("TEST_KEY").unwrap().as_string() == Some("foo"));
+    assert!(app.config.get_boolean("FEATURE", false));
+    assert!(app.config.get("TEST_KEY").unwrap().as_string() == Some("foo"));
+    File::create(path).unwrap().write_all(b"not json").unwrap();
+    assert!(app.reload_config().is_err());
+    assert!(app.live_config().get_boolean("FEATURE", false));
+    assert!(app.config.get_boolean("FEATURE", false));
+    fs::remove_file(path).unwrap();
+}
+
+
+#[test]
+#[should_panic(expected = "Config reloading is already enabled.")]
+fn test_config_reload_enabled_twice() {
+    let path = env::temp_dir().join("pencil-test-reload-twice.json");
+    let path = path.to_str().unwrap();
+    File::create(path).unwrap().write_all(b"{}").unwrap();
+    let mut app = Pencil::new("/test");
+    app.enable_config_reload(path);
+    app.enable_config_reload(path);
+}
@@ -0,0 +1,58 @@
+// Test cookies.
+
+extern crate pencil;
+extern crate hyper;
+
+use hyper::header::SetCookie;
+
+use pencil::{Pencil, Request, PencilResult, Response};
+use pencil::testing::TestResponse;
+use pencil::cookies::{Cookie, SameSite};
+
+
+fn set_cookies(response: &Response) -> Vec<String> {
+    response.headers.get::<SetCookie>().unwrap().0.iter().map(|cookie| cookie.to_string()).collect()
+}
+
+
+#[test]
+fn test_set_cookie() {
+    let mut response = Response::from("Hello");
+    response.set_cookie(Cookie::new("theme", "light"));
+    response.set_cookie(Cookie::new("theme", "dark").max_age(3600).domain("example.com")
+                                                    .secure(true).http_only(true).same_site(SameSite::Strict));
+    response.set_cookie(Cookie::new("lang", "en").path("/docs"));
+    let cookies = set_cookies(&response);
+    assert!(cookies.len() == 2);
+    assert!(cookies[0] == "theme=dark; HttpOnly; Secure; Path=/; Domain=example.com; Max-Age=3600; SameSite=Strict");
+    assert!(cookies[1] == "lang=en; Path=/docs");
+}
+
+
+#[test]
+fn test_delete_cookie() {
+    let mut response = Response::from("Bye");
+    response.set_cookie(Cookie::new("theme", "dark"));
+    response.delete_cookie("theme");
+    let cookies = set_cookies(&response);
+    assert!(cookies.len() == 1);
+    assert!(cookies[0].starts_with("theme=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00"));
+}
+
+
+fn greet(request: &mut Request) -> PencilResult {
+    let cookie_map = request.cookie_map();
+    let theme = request.get_cookie("theme").unwrap_or_default();
+    Ok(Response::from(format!("{} {} {}", theme, cookie_map.len(), cookie_map.get("lang").unwrap())))
+}
+
+
+#[test]
+fn test_request_cookies() {
+    let mut app = Pencil::new("/test");
+    app.get("/", "greet", greet);
+    let client = app.test_client();
+    let mut response = client.get_path("/").header("Cookie", "theme=dark; lang=en; theme=light").send().unwrap();
+    let body = response.body_bytes();
+    assert!(body == b"dark 2 en");
+}
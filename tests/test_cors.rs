@@ -0,0 +1,87 @@
+// Test CORS.
+
+extern crate pencil;
+
+use pencil::{Pencil, Module, Request, PencilResult, Response};
+use pencil::cors::Cors;
+use pencil::method::{Get, Post, Options};
+
+
+fn users(_: &mut Request) -> PencilResult {
+    Ok(Response::from("users"))
+}
+
+
+fn header(response: &Response, name: &str) -> Option<String> {
+    response.headers.get_raw(name).map(|values| String::from_utf8(values[0].clone()).unwrap())
+}
+
+
+#[test]
+fn test_cors_preflight_and_actual_requests() {
+    let mut app = Pencil::new("/test");
+    app.route("/users", &[Get, Post], "users", users);
+    app.enable_cors(Cors::new().allow_origin("https://example.com").supports_credentials(true).max_age(600));
+    let client = app.test_client();
+
+    let response = client.open_request(Options, "/users")
+        .header("Origin", "https://example.com")
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "X-Token")
+        .send().unwrap();
+    assert!(header(&response, "Access-Control-Allow-Origin") == Some(String::from("https://example.com")));
+    assert!(header(&response, "Access-Control-Allow-Credentials") == Some(String::from("true")));
+    assert!(header(&response, "Access-Control-Allow-Headers") == Some(String::from("X-Token")));
+    assert!(header(&response, "Access-Control-Max-Age") == Some(String::from("600")));
+    assert!(header(&response, "Access-Control-Allow-Methods").unwrap().contains("POST"));
+
+    let response = client.open_request(Options, "/users")
+        .header("Origin", "https://example.com")
+        .header("Access-Control-Request-Method", "DELETE")
+        .send().unwrap();
+    assert!(header(&response, "Access-Control-Allow-Methods").is_none());
+
+    let response = client.get_path("/users").header("Origin", "https://example.com").send().unwrap();
+    assert!(header(&response, "Access-Control-Allow-Origin") == Some(String::from("https://example.com")));
+    assert!(header(&response, "Vary") == Some(String::from("Origin")));
+
+    let response = client.get_path("/users").header("Origin", "https://evil.com").send().unwrap();
+    assert!(header(&response, "Access-Control-Allow-Origin").is_none());
+    let response = client.get_path("/users").send().unwrap();
+    assert!(header(&response, "Access-Control-Allow-Origin").is_none());
+}
+
+
+#[test]
+fn test_module_cors() {
+    let mut app = Pencil::new("/test");
+    app.get("/users", "users", users);
+    let mut api = Module::new("api", "/test");
+    api.route("/api/users", &[Get], "users", users);
+    api.enable_cors(Cors::new().expose_headers(&["X-Total"]));
+    api.register(&mut app);
+    let client = app.test_client();
+    let response = client.get_path("/api/users").header("Origin", "https://example.com").send().unwrap();
+    assert!(header(&response, "Access-Control-Allow-Origin") == Some(String::from("*")));
+    assert!(header(&response, "Access-Control-Expose-Headers") == Some(String::from("X-Total")));
+    let response = client.get_path("/users").header("Origin", "https://example.com").send().unwrap();
+    assert!(header(&response, "Access-Control-Allow-Origin").is_none());
+}
+
+
+#[test]
+fn test_credentials_require_allowed_origins() {
+    let mut app = Pencil::new("/test");
+    app.route("/users", &[Get, Post], "users", users);
+    app.enable_cors(Cors::new().supports_credentials(true));
+    let client = app.test_client();
+    let response = client.get_path("/users").header("Origin", "https://evil.com").send().unwrap();
+    assert!(header(&response, "Access-Control-Allow-Origin").is_none());
+    assert!(header(&response, "Access-Control-Allow-Credentials").is_none());
+    let response = client.open_request(Options, "/users")
+        .header("Origin", "https://evil.com")
+        .header("Access-Control-Request-Method", "POST")
+        .send().unwrap();
+    assert!(header(&response, "Access-Control-Allow-Origin").is_none());
+    assert!(header(&response, "Access-Control-Allow-Credentials").is_none());
+}
@@ -29,3 +29,37 @@ fn test_multi_dict_basic_interface() {
     }
     assert!(all_values.len() == 2);
 }
+
+
+#[test]
+fn test_multi_dict_parsed_values() {
+    let mut multi_dict = MultiDict::new();
+    multi_dict.add("page".to_string(), "3".to_string());
+    multi_dict.add("limit".to_string(), "many".to_string());
+    assert!(multi_dict.get_parsed::<u32>("page") == Some(3));
+    assert!(multi_dict.get_parsed::<u32>("limit") == None);
+    assert!(multi_dict.get_parsed::<u32>("offset") == None);
+    assert!(multi_dict.get_or("page", 1) == 3);
+    assert!(multi_dict.get_or("limit", 20) == 20);
+    assert!(multi_dict.get_or("offset", 0) == 0);
+}
+
+
+#[test]
+fn test_multi_dict_editing() {
+    let mut multi_dict: MultiDict<String> = MultiDict::new();
+    assert!(multi_dict.is_empty());
+    multi_dict.add("tag", "a".to_string());
+    multi_dict.set(String::from("page"), "1".to_string());
+    multi_dict.extend(vec![("tag", "b".to_string()), ("sort", "name".to_string())]);
+    assert!(multi_dict.len() == 3);
+    assert!(multi_dict.getlist("tag").unwrap() == &vec!["a".to_string(), "b".to_string()]);
+    assert!(multi_dict.setdefault("sort", "date".to_string()) == "name");
+    multi_dict.setdefault("order", "asc".to_string()).push_str("ending");
+    assert!(multi_dict.get("order").unwrap() == "ascending");
+    assert!(multi_dict.contains_key("tag"));
+    assert!(multi_dict.remove("tag") == Some(vec!["a".to_string(), "b".to_string()]));
+    assert!(!multi_dict.contains_key("tag"));
+    assert!(multi_dict.remove("tag") == None);
+    assert!(multi_dict.len() == 3);
+}
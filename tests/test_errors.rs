@@ -2,8 +2,16 @@
 
 extern crate pencil;
 extern crate hyper;
+extern crate rustc_serialize as serialize;
 
-use pencil::http_errors::NotFound;
+use std::error::Error;
+use std::fmt;
+use serialize::json::Json;
+
+use pencil::{Pencil, Module, Request, PencilResult, Response, UserError, HTTPError};
+use pencil::method::Get;
+use pencil::http_errors::{NotFound, BadRequest, ImATeapot, ServiceUnavailable, TooManyRequests};
+use pencil::testing::TestResponse;
 use hyper::header::ContentType;
 
 
@@ -42,3 +50,220 @@ fn test_http_error_to_response() {
     assert!(*response.content_type().unwrap() ==
             ContentType::html());
 }
+
+
+#[test]
+fn test_http_error_with_description() {
+    let error = BadRequest.with_description("Missing page parameter.");
+    assert!(error.code() == 400);
+    assert!(error.name() == "Bad Request");
+    assert!(error.to_string() == "Missing page parameter.");
+    let error = error.with_description("Invalid page parameter.");
+    assert!(error.to_string() == "Invalid page parameter.");
+    assert!(error.to_response().status_code == 400);
+}
+
+
+#[test]
+fn test_error_template() {
+    let mut app = Pencil::new("./tests");
+    app.error_template(404, "errors/404.html");
+    let mut response = app.test_client().get_path("/missing").send().unwrap();
+    assert!(response.status_code == 404);
+    let body = response.body_bytes();
+    assert!(body == b"<h1>404 Not Found</h1>\n<p>Nothing at /missing.</p>\n");
+}
+
+
+#[derive(Debug)]
+struct OutOfStock(u32);
+
+impl fmt::Display for OutOfStock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "item {} is out of stock", self.0)
+    }
+}
+
+impl Error for OutOfStock {
+    fn description(&self) -> &str {
+        "out of stock"
+    }
+}
+
+
+fn buy(_: &mut Request) -> PencilResult {
+    Err(UserError::from_error(OutOfStock(7)).into())
+}
+
+
+fn fail(_: &mut Request) -> PencilResult {
+    Err(UserError::new("no source").into())
+}
+
+
+#[test]
+fn test_typed_user_error_handlers() {
+    let error = UserError::from_error(OutOfStock(7));
+    assert!(error.desc == "item 7 is out of stock");
+    assert!(error.downcast_ref::<OutOfStock>().unwrap().0 == 7);
+    assert!(error.cause().is_some());
+    assert!(UserError::new("no source").source().is_none());
+
+    let mut app = Pencil::new("/test");
+    app.get("/buy", "buy", buy);
+    app.get("/fail", "fail", fail);
+    app.register_error_handler(|err: &OutOfStock| {
+        let mut response = Response::from(format!("sold out: {}", err.0));
+        response.status_code = 409;
+        Ok(response)
+    });
+    let mut shop = Module::new("shop", "/test");
+    shop.route("/shop/buy", &[Get], "buy", buy);
+    shop.register_error_handler(|_: &OutOfStock| Ok(Response::from("shop")));
+    shop.register(&mut app);
+    let client = app.test_client();
+    assert!(client.get_path("/buy").send().unwrap().status_code == 409);
+    assert!(client.get_path("/shop/buy").send().unwrap().status_code == 200);
+    assert!(client.get_path("/fail").send().unwrap().status_code == 500);
+}
+
+
+fn no_source_handler(error: UserError) -> PencilResult {
+    Ok(Response::from(format!("handled: {}", error.desc)))
+}
+
+
+#[test]
+#[allow(deprecated)]
+fn test_user_error_handlers_by_description() {
+    let mut app = Pencil::new("/test");
+    app.get("/fail", "fail", fail);
+    app.usererrorhandler("no source", no_source_handler);
+    let client = app.test_client();
+    let mut response = client.get_path("/fail").send().unwrap();
+    assert!(response.status_code == 200);
+    assert!(response.body_text() == "handled: no source");
+}
+
+
+#[test]
+fn test_json_error_responses() {
+    let error = NotFound;
+    let body = Json::from_str(&error.get_json_body()).unwrap();
+    assert!(body["code"] == Json::U64(404));
+    assert!(body["name"] == Json::String(String::from("Not Found")));
+    assert!(*error.to_json_response().content_type().unwrap() == ContentType::json());
+
+    let mut app = Pencil::new("/test");
+    let client = app.test_client();
+    let response = client.get_path("/missing").header("Accept", "application/json").send().unwrap();
+    assert!(response.status_code == 404);
+    assert!(*response.content_type().unwrap() == ContentType::json());
+    let response = client.get_path("/missing").header("Accept", "text/html, */*;q=0.8").send().unwrap();
+    assert!(*response.content_type().unwrap() == ContentType::html());
+    let response = client.get_path("/missing").send().unwrap();
+    assert!(*response.content_type().unwrap() == ContentType::html());
+
+    app.config.set("ERROR_FORMAT", Json::String(String::from("json")));
+    let response = app.test_client().get_path("/missing").send().unwrap();
+    assert!(*response.content_type().unwrap() == ContentType::json());
+    app.config.set("ERROR_FORMAT", Json::String(String::from("html")));
+    let response = app.test_client().get_path("/missing").header("Accept", "application/json").send().unwrap();
+    assert!(*response.content_type().unwrap() == ContentType::html());
+}
+
+
+fn client_error(error: HTTPError) -> PencilResult {
+    Ok(Response::from(format!("client {}", error.code())))
+}
+
+
+fn any_error(error: HTTPError) -> PencilResult {
+    Ok(Response::from(format!("any {}", error.code())))
+}
+
+
+fn not_found(_: HTTPError) -> PencilResult {
+    Ok(Response::from("not found"))
+}
+
+
+fn teapot(_: &mut Request) -> PencilResult {
+    Err(ImATeapot.into())
+}
+
+
+fn unavailable(_: &mut Request) -> PencilResult {
+    Err(ServiceUnavailable.into())
+}
+
+
+#[test]
+fn test_range_error_handlers() {
+    let mut app = Pencil::new("/test");
+    app.get("/teapot", "teapot", teapot);
+    app.get("/unavailable", "unavailable", unavailable);
+    app.any_error_handler(any_error);
+    app.httperrorhandler_range(400..500, client_error);
+    app.httperrorhandler(404, not_found);
+    let client = app.test_client();
+    let body = |path: &str| {
+        let mut response = client.get_path(path).send().unwrap();
+        response.body_text()
+    };
+    assert!(body("/missing") == "not found");
+    assert!(body("/teapot") == "client 418");
+    assert!(body("/unavailable") == "any 503");
+}
+
+
+#[test]
+fn test_additional_status_codes() {
+    for &(code, name) in &[(421, "Misdirected Request"), (423, "Locked"), (424, "Failed Dependency"),
+                           (426, "Upgrade Required"), (451, "Unavailable For Legal Reasons")] {
+        let error = HTTPError::new(code);
+        assert!(error.code() == code);
+        assert!(error.name() == name);
+    }
+}
+
+
+#[test]
+fn test_retry_after() {
+    let error = TooManyRequests.with_retry_after(60).with_description("Slow down.");
+    assert!(error.code() == 429);
+    assert!(error.retry_after() == Some(60));
+    assert!(error.to_string() == "Slow down.");
+    let response = error.to_response();
+    assert!(response.status_code == 429);
+    assert!(response.headers.get_raw("Retry-After") == Some(&[b"60".to_vec()][..]));
+    let response = ServiceUnavailable.with_retry_after(5).with_retry_after(120).to_json_response();
+    assert!(response.headers.get_raw("Retry-After") == Some(&[b"120".to_vec()][..]));
+    assert!(NotFound.to_response().headers.get_raw("Retry-After").is_none());
+}
+
+
+fn users(_: &mut Request) -> PencilResult {
+    Ok(Response::from("users"))
+}
+
+
+fn method_not_allowed(_: HTTPError) -> PencilResult {
+    let mut response = Response::from("nope");
+    response.status_code = 405;
+    Ok(response)
+}
+
+
+#[test]
+fn test_allow_header_on_405() {
+    let mut app = Pencil::new("/test");
+    app.get("/users", "users", users);
+    let response = app.test_client().delete("/users").send().unwrap();
+    assert!(response.status_code == 405);
+    assert!(response.headers.get_raw("Allow") == Some(&[b"GET, HEAD, OPTIONS".to_vec()][..]));
+    app.httperrorhandler(405, method_not_allowed);
+    let response = app.test_client().delete("/users").send().unwrap();
+    assert!(response.status_code == 405);
+    assert!(response.headers.get_raw("Allow") == Some(&[b"GET, HEAD, OPTIONS".to_vec()][..]));
+}
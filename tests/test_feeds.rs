@@ -0,0 +1,42 @@
+// Test the feed helpers.
+
+extern crate pencil;
+extern crate time;
+
+use pencil::feeds::{AtomFeed, RssFeed, FeedEntry};
+
+
+fn updated() -> time::Tm {
+    time::at_utc(time::Timespec::new(1476014400, 0))
+}
+
+
+#[test]
+fn test_atom_feed() {
+    let feed = AtomFeed::new("Blog & News", "http://example.com/")
+        .feed_url("http://example.com/feed.atom")
+        .entry(FeedEntry::new("First <post>", "http://example.com/1", updated())
+               .author("Pencil")
+               .content("<p>Hello</p>"));
+    let xml = feed.to_xml();
+    assert!(xml.contains("<title>Blog &amp; News</title>"));
+    assert!(xml.contains("<id>http://example.com/feed.atom</id>"));
+    assert!(xml.contains("<updated>2016-10-09T12:00:00Z</updated>"));
+    assert!(xml.contains("<title>First &lt;post&gt;</title>"));
+    assert!(xml.contains("<content type=\"html\">&lt;p&gt;Hello&lt;/p&gt;</content>"));
+    let response = feed.to_response();
+    assert!(response.content_type().unwrap().to_string() == "application/atom+xml; charset=utf-8");
+}
+
+
+#[test]
+fn test_rss_feed() {
+    let feed = RssFeed::new("Blog", "http://example.com/", "All posts")
+        .entry(FeedEntry::new("First", "http://example.com/1", updated()).summary("Hello"));
+    let xml = feed.to_xml();
+    assert!(xml.contains("<lastBuildDate>Sun, 09 Oct 2016 12:00:00 GMT</lastBuildDate>"));
+    assert!(xml.contains("<guid isPermaLink=\"true\">http://example.com/1</guid>"));
+    assert!(xml.contains("<description>Hello</description>"));
+    let response = feed.to_response();
+    assert!(response.content_type().unwrap().to_string() == "application/rss+xml; charset=utf-8");
+}
@@ -0,0 +1,52 @@
+// Test freezing an application into a static site.
+
+extern crate pencil;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+
+use pencil::{Pencil, Request, PencilResult, Response, ViewArgs};
+use pencil::freeze::Freezer;
+
+
+fn index(_: &mut Request) -> PencilResult {
+    Ok(Response::from("index"))
+}
+
+
+fn post(request: &mut Request) -> PencilResult {
+    let slug = request.view_args.get("slug").unwrap().clone();
+    Ok(Response::from(format!("post {}", slug)))
+}
+
+
+fn post_urls(_: &Pencil) -> Vec<ViewArgs> {
+    let mut args = HashMap::new();
+    args.insert("slug".to_string(), "hello".to_string());
+    vec![args]
+}
+
+
+fn read_file(path: &std::path::Path) -> String {
+    let mut content = String::new();
+    File::open(path).unwrap().read_to_string(&mut content).unwrap();
+    content
+}
+
+
+#[test]
+fn test_freeze() {
+    let mut app = Pencil::new("/test");
+    app.get("/", "index", index);
+    app.get("/posts/<slug:string>/", "post", post);
+    let freezer = Freezer::new(&app).register_generator("post", post_urls);
+    assert!(freezer.urls() == vec!["/".to_string(), "/posts/hello/".to_string()]);
+    let destination = env::temp_dir().join("pencil-test-freeze");
+    let _ = fs::remove_dir_all(&destination);
+    freezer.freeze(&destination).unwrap();
+    assert!(read_file(&destination.join("index.html")) == "index");
+    assert!(read_file(&destination.join("posts/hello/index.html")) == "post hello");
+    fs::remove_dir_all(&destination).unwrap();
+}
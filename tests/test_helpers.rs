@@ -3,13 +3,21 @@
 extern crate pencil;
 extern crate url;
 extern crate hyper;
+extern crate rustc_serialize as serialize;
 
+use std::env;
+use std::fs::{self, File};
 use std::path::PathBuf;
 
-use hyper::header::Location;
+use hyper::header::{Location, ETag};
+use serialize::json::Json;
 
 use pencil::{PenHTTPError, PenUserError};
-use pencil::{abort, redirect, safe_join, escape};
+use pencil::{abort, redirect, redirect_to, redirect_back, safe_join, secure_filename, escape};
+use pencil::{Pencil, Request, PencilResult, ViewArgs};
+use pencil::{send_file_auto, send_file_with, SendFileOptions};
+use pencil::testing::TestResponse;
+use pencil::staticfiles::EmbeddedProvider;
 
 
 #[test]
@@ -41,11 +49,66 @@ fn test_redirect() {
 }
 
 
+fn create_user(request: &mut Request) -> PencilResult {
+    let mut values = ViewArgs::new();
+    values.insert(String::from("user_id"), String::from("42"));
+    redirect_to(request, "user", &values, 303)
+}
+
+
+fn missing_endpoint(request: &mut Request) -> PencilResult {
+    redirect_to(request, "missing", &ViewArgs::new(), 302)
+}
+
+
+fn go_back(request: &mut Request) -> PencilResult {
+    redirect_back(request, "/")
+}
+
+
+#[test]
+fn test_redirect_to_endpoint() {
+    let mut app = Pencil::new("/test");
+    app.get("/user/<user_id:int>", "user", |_: &mut Request| "user");
+    app.post("/users", "create_user", create_user);
+    app.get("/missing", "missing_endpoint", missing_endpoint);
+    app.get("/back", "go_back", go_back);
+    let mut values = ViewArgs::new();
+    values.insert(String::from("user_id"), String::from("7"));
+    let response = redirect_to(&app, "user", &values, 302).ok().unwrap();
+    assert!(*response.headers.get::<Location>().unwrap() == Location("/user/7".to_owned()));
+
+    let client = app.test_client();
+    let response = client.post("/users").send().unwrap();
+    assert!(response.status_code == 303);
+    assert!(*response.headers.get::<Location>().unwrap() == Location("/user/42".to_owned()));
+    assert!(client.get_path("/missing").send().unwrap().status_code == 500);
+
+    let location = |referer: Option<&str>| {
+        let request = client.get_path("/back").header("Host", "example.com");
+        let request = match referer {
+            Some(referer) => request.header("Referer", referer),
+            None => request,
+        };
+        let response = request.send().unwrap();
+        assert!(response.status_code == 302);
+        response.headers.get::<Location>().unwrap().0.clone()
+    };
+    assert!(location(None) == "/");
+    assert!(location(Some("http://example.com/users?page=2")) == "http://example.com/users?page=2");
+    assert!(location(Some("http://evil.com/users")) == "/");
+    assert!(location(Some("https://example.com/users")) == "/");
+    assert!(location(Some("//evil.com/users")) == "/");
+    assert!(location(Some("/users")) == "/users");
+}
+
 #[test]
 fn test_safe_join() {
     let path = safe_join("foo", "bar/baz").unwrap();
     assert!(path == PathBuf::from("foo/bar/baz"));
     assert!(safe_join("foo", "../bar/baz").is_none());
+    assert!(safe_join("foo", "bar/../../baz").is_none());
+    assert!(safe_join("foo", "/etc/passwd").is_none());
 }
 
 
@@ -55,3 +118,209 @@ fn test_escape() {
     assert!(escape(String::from("<>")) == "&lt;&gt;");
     assert!(escape(String::from("\"foo\"")) == "&quot;foo&quot;");
 }
+
+
+#[test]
+fn test_send_file_ranges() {
+    let mut app = Pencil::new("./tests");
+    app.enable_static_file_handling();
+    let client = app.test_client();
+    let mut response = client.get_path("/static/digits.txt").send().unwrap();
+    assert!(response.status_code == 200);
+    assert!(response.body_text() == "0123456789");
+
+    let mut response = client.get_path("/static/digits.txt").header("Range", "bytes=2-4").send().unwrap();
+    assert!(response.status_code == 206);
+    assert!(response.content_length() == Some(3));
+    assert!(response.body_text() == "234");
+
+    let mut response = client.get_path("/static/digits.txt").header("Range", "bytes=0-1,-2").send().unwrap();
+    assert!(response.status_code == 206);
+    let content_type = response.content_type().unwrap().to_string();
+    assert!(content_type.starts_with("multipart/byteranges; boundary="));
+    let boundary = content_type.splitn(2, "boundary=").nth(1).unwrap().to_string();
+    let length = response.content_length().unwrap();
+    let body = response.body_text();
+    assert!(body.len() == length);
+    assert!(body == format!("--{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\r\n\
+                             --{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\r\n\
+                             --{0}--\r\n", boundary));
+
+    let response = client.get_path("/static/digits.txt").header("Range", "bytes=20-30").send().unwrap();
+    assert!(response.status_code == 416);
+}
+
+
+#[test]
+fn test_send_file_conditional() {
+    let mut app = Pencil::new("./tests");
+    app.enable_static_file_handling();
+    let client = app.test_client();
+    let response = client.get_path("/static/digits.txt").send().unwrap();
+    assert!(response.status_code == 200);
+    let etag = String::from_utf8(response.headers.get_raw("ETag").unwrap()[0].clone()).unwrap();
+    let last_modified = String::from_utf8(response.headers.get_raw("Last-Modified").unwrap()[0].clone()).unwrap();
+    let response = client.get_path("/static/digits.txt").header("If-None-Match", &etag).send().unwrap();
+    assert!(response.status_code == 304);
+    assert!(response.body.is_none());
+    let response = client.get_path("/static/digits.txt").header("If-None-Match", "\"other\"").send().unwrap();
+    assert!(response.status_code == 200);
+    let response = client.get_path("/static/digits.txt").header("If-Modified-Since", &last_modified).send().unwrap();
+    assert!(response.status_code == 304);
+    let response = client.get_path("/static/digits.txt").header("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT").send().unwrap();
+    assert!(response.status_code == 200);
+}
+
+
+#[test]
+fn test_send_precompressed_file() {
+    let mut app = Pencil::new("./tests");
+    app.enable_static_file_handling();
+    let client = app.test_client();
+    let response = client.get_path("/static/app.js").header("Accept-Encoding", "gzip, deflate").send().unwrap();
+    assert!(response.status_code == 200);
+    assert!(response.headers.get_raw("Content-Encoding") == Some(&[b"gzip".to_vec()][..]));
+    assert!(response.headers.get_raw("Vary") == Some(&[b"Accept-Encoding".to_vec()][..]));
+    assert!(response.content_type().unwrap().to_string().starts_with("application/javascript"));
+    assert!(response.content_length() == Some(42));
+
+    let mut response = client.get_path("/static/app.js").header("Accept-Encoding", "br").send().unwrap();
+    assert!(response.headers.get_raw("Content-Encoding").is_none());
+    assert!(response.headers.get_raw("Vary") == Some(&[b"Accept-Encoding".to_vec()][..]));
+    assert!(response.body_text() == "console.log(\"hello\");\n");
+
+    let response = client.get_path("/static/digits.txt").header("Accept-Encoding", "gzip").send().unwrap();
+    assert!(response.headers.get_raw("Content-Encoding").is_none());
+    assert!(response.headers.get_raw("Vary").is_none());
+}
+
+
+#[test]
+fn test_static_cache_max_age() {
+    let mut app = Pencil::new("./tests");
+    app.enable_static_file_handling();
+    assert!(app.test_client().get_path("/static/digits.txt").send().unwrap().headers.get_raw("Cache-Control").is_none());
+    app.config.set("STATIC_CACHE_MAX_AGE", Json::U64(3600));
+    app.config.set("STATIC_CACHE_MAX_AGE_BY_EXTENSION", Json::from_str(r#"{"js": 31536000}"#).unwrap());
+    let client = app.test_client();
+    let response = client.get_path("/static/digits.txt").send().unwrap();
+    assert!(response.headers.get_raw("Cache-Control") == Some(&[b"public, max-age=3600".to_vec()][..]));
+    let response = client.get_path("/static/app.js").send().unwrap();
+    assert!(response.headers.get_raw("Cache-Control") == Some(&[b"public, max-age=31536000".to_vec()][..]));
+    assert!(client.get_path("/static/missing.txt").send().unwrap().headers.get_raw("Cache-Control").is_none());
+}
+
+
+#[test]
+fn test_spa_fallback() {
+    let mut app = Pencil::new("./tests");
+    app.enable_static_file_handling();
+    assert!(app.test_client().get_path("/users/1").send().unwrap().status_code == 404);
+    app.enable_spa("static/index.html");
+    let client = app.test_client();
+    let mut response = client.get_path("/users/1").send().unwrap();
+    assert!(response.status_code == 200);
+    let body = response.body_bytes();
+    assert!(body == b"<div id=\"app\"></div>\n");
+    assert!(client.get_path("/users/1").header("Accept", "application/json").send().unwrap().status_code == 404);
+    assert!(client.get_path("/static/missing.js").send().unwrap().status_code == 404);
+    assert!(client.post("/users/1").send().unwrap().status_code == 404);
+}
+
+
+#[test]
+fn test_static_directory_listing() {
+    let mut app = Pencil::new("./tests");
+    app.enable_static_file_handling();
+    assert!(app.test_client().get_path("/static/docs/").send().unwrap().status_code == 404);
+    app.config.set("STATIC_DIRECTORY_LISTING", Json::Boolean(true));
+    let client = app.test_client();
+    let response = client.get_path("/static/docs").send().unwrap();
+    assert!(response.status_code == 301);
+    assert!(response.headers.get::<Location>().unwrap().0 == "/static/docs/");
+    let mut response = client.get_path("/static/docs/").send().unwrap();
+    assert!(response.status_code == 200);
+    let body = response.body_text();
+    assert!(body.contains("<title>Index of /static/docs/</title>"));
+    assert!(body.contains("<a href=\"../\">../</a>"));
+    assert!(body.contains("<tr><td><a href=\"readme.txt\">readme.txt</a></td><td>9</td>"));
+}
+
+
+#[test]
+fn test_static_root_listing_escapes_names() {
+    let root = env::temp_dir().join("pencil-test-listing");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("static")).unwrap();
+    File::create(root.join("static").join("<img src=x onerror=alert(1)>.txt")).unwrap();
+    File::create(root.join("static").join("a\"b&c.txt")).unwrap();
+    let mut app = Pencil::new(root.to_str().unwrap());
+    app.enable_static_file_handling();
+    app.config.set("STATIC_DIRECTORY_LISTING", Json::Boolean(true));
+    let client = app.test_client();
+    let response = client.get_path("/static").send().unwrap();
+    assert!(response.status_code == 301);
+    let mut response = client.get_path("/static/").send().unwrap();
+    assert!(response.status_code == 200);
+    let body = response.body_text();
+    assert!(body.contains("<title>Index of /static/</title>"));
+    assert!(!body.contains("../"));
+    assert!(!body.contains("<img"));
+    assert!(body.contains(">&lt;img src=x onerror=alert(1)&gt;.txt</a>"));
+    assert!(body.contains("<a href=\"a%22b&amp;c.txt\">a&quot;b&amp;c.txt</a>"));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+
+#[test]
+fn test_embedded_static_provider() {
+    let mut app = Pencil::new("/test");
+    app.set_static_provider(EmbeddedProvider::new().add("js/app.js", b"alert(1);\n"));
+    app.enable_static_file_handling();
+    let client = app.test_client();
+    let mut response = client.get_path("/static/js/app.js").send().unwrap();
+    assert!(response.status_code == 200);
+    assert!(response.headers.get_raw("Content-Type") == Some(&[b"application/javascript".to_vec()][..]));
+    let body = response.body_bytes();
+    assert!(body == b"alert(1);\n");
+    let etag = response.headers.get::<ETag>().unwrap().clone();
+    let response = client.get_path("/static/js/app.js").header("If-None-Match", &etag.to_string()).send().unwrap();
+    assert!(response.status_code == 304);
+    assert!(client.get_path("/static/missing.js").send().unwrap().status_code == 404);
+}
+
+
+fn digits(request: &mut Request) -> PencilResult {
+    send_file_with(request, PathBuf::from("./tests/static/digits.txt"), SendFileOptions::new()
+        .as_attachment(true)
+        .conditional(true))
+}
+
+
+#[test]
+fn test_send_file_options() {
+    let response = send_file_auto("./tests/static/digits.txt", false).unwrap();
+    assert!(response.headers.get_raw("Content-Type") == Some(&[b"text/plain".to_vec()][..]));
+    assert!(response.headers.get_raw("Content-Disposition").is_none());
+
+    let mut app = Pencil::new("/test");
+    app.get("/digits", "digits", digits);
+    let client = app.test_client();
+    let mut response = client.get_path("/digits").header("Range", "bytes=2-4").send().unwrap();
+    assert!(response.status_code == 206);
+    assert!(response.headers.get_raw("Content-Disposition") == Some(&[b"attachment; filename=digits.txt".to_vec()][..]));
+    assert!(response.body_text() == "234");
+}
+
+
+#[test]
+fn test_secure_filename() {
+    assert!(secure_filename("report.pdf") == "report.pdf");
+    assert!(secure_filename("../../etc/passwd") == "etc_passwd");
+    assert!(secure_filename("/var/www/index.html") == "var_www_index.html");
+    assert!(secure_filename("..\\..\\boot.ini") == "boot.ini");
+    assert!(secure_filename("...hidden") == "hidden");
+    assert!(secure_filename("a\u{0}b\nc.txt") == "abc.txt");
+    assert!(secure_filename("NUL.txt") == "_NUL.txt");
+    assert!(secure_filename("../..") == "");
+}
@@ -0,0 +1,116 @@
+// Test the idempotency key support.
+
+extern crate pencil;
+
+use std::panic;
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use pencil::{Pencil, Request, PencilResult, Response};
+use pencil::idempotency::{IdempotencyStore, MemoryStore, StoredResponse};
+use pencil::testing::TestResponse;
+
+
+#[test]
+fn test_memory_store() {
+    let store = MemoryStore::new();
+    let response = Response::from("created");
+    let stored = StoredResponse {
+        status_code: 201,
+        headers: response.headers.clone(),
+        body: b"created".to_vec(),
+        request_hash: Vec::new(),
+    };
+    store.set("POST /orders abc", stored, Duration::from_secs(60));
+    let replayed = store.get("POST /orders abc").unwrap().to_response();
+    assert!(replayed.status_code == 201);
+    assert!(replayed.content_length() == Some(7));
+    assert!(store.get("POST /orders other").is_none());
+
+    let stored = StoredResponse {
+        status_code: 201,
+        headers: response.headers.clone(),
+        body: Vec::new(),
+        request_hash: Vec::new(),
+    };
+    store.set("POST /orders expired", stored, Duration::from_secs(0));
+    assert!(store.get("POST /orders expired").is_none());
+}
+
+
+#[test]
+fn test_memory_store_claims() {
+    let store = MemoryStore::new();
+    assert!(store.claim("POST /orders abc", Duration::from_secs(60)));
+    assert!(!store.claim("POST /orders abc", Duration::from_secs(60)));
+    assert!(store.get("POST /orders abc").is_none());
+    store.release("POST /orders abc");
+    assert!(store.claim("POST /orders abc", Duration::from_secs(60)));
+    assert!(store.claim("POST /orders expired", Duration::from_secs(0)));
+    assert!(store.claim("POST /orders expired", Duration::from_secs(60)));
+}
+
+
+#[allow(deprecated)]
+static ORDERS: AtomicUsize = atomic::ATOMIC_USIZE_INIT;
+
+fn create_order(_: &mut Request) -> PencilResult {
+    let order = ORDERS.fetch_add(1, Ordering::SeqCst) + 1;
+    let mut response = Response::from(format!("order {}", order));
+    response.status_code = 201;
+    response.headers.set_raw("Set-Cookie", vec![b"cart=; Max-Age=0".to_vec()]);
+    Ok(response)
+}
+
+
+#[test]
+fn test_replay() {
+    let mut app = Pencil::new("/test");
+    app.enable_idempotency(MemoryStore::new(), Duration::from_secs(60));
+    app.post("/orders", "create_order", create_order);
+    let client = app.test_client();
+    let send = |authorization: &str, body: &str| {
+        client.post("/orders").header("Idempotency-Key", "abc").header("Authorization", authorization)
+              .body(body, "application/json").send().unwrap()
+    };
+    let mut response = send("Bearer alice", "{\"item\":1}");
+    assert!(response.status_code == 201);
+    assert!(response.headers.get_raw("Set-Cookie").is_some());
+    let order = response.body_text();
+    let mut response = send("Bearer alice", "{\"item\":1}");
+    assert!(response.status_code == 201);
+    assert!(response.headers.get_raw("Idempotent-Replayed") == Some(&[b"true".to_vec()][..]));
+    assert!(response.headers.get_raw("Set-Cookie").is_none());
+    assert!(response.body_text() == order);
+    assert!(send("Bearer alice", "{\"item\":2}").status_code == 422);
+    // Another caller with the same key runs the view.
+    let mut response = send("Bearer bob", "{\"item\":1}");
+    assert!(response.headers.get_raw("Idempotent-Replayed").is_none());
+    assert!(response.body_text() != order);
+}
+
+
+#[allow(deprecated)]
+static PAYMENTS: AtomicUsize = atomic::ATOMIC_USIZE_INIT;
+
+fn create_payment(_: &mut Request) -> PencilResult {
+    if PAYMENTS.fetch_add(1, Ordering::SeqCst) == 0 {
+        panic!("payment backend down");
+    }
+    Ok(Response::from("paid"))
+}
+
+
+#[test]
+fn test_claim_released_on_panic() {
+    let mut app = Pencil::new("/test");
+    app.enable_idempotency(MemoryStore::new(), Duration::from_secs(60));
+    app.post("/payments", "create_payment", create_payment);
+    let client = app.test_client();
+    let send = || client.post("/payments").header("Idempotency-Key", "abc").send().unwrap();
+    assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| send())).is_err());
+    let mut response = send();
+    assert!(response.status_code == 200);
+    assert!(response.body_text() == "paid");
+    assert!(send().headers.get_raw("Idempotent-Replayed").is_some());
+}
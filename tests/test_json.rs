@@ -0,0 +1,111 @@
+// Test the JSON helpers.
+
+extern crate pencil;
+extern crate rustc_serialize as serialize;
+
+use std::collections::BTreeMap;
+use serialize::json::{Json, ToJson};
+
+use pencil::{Pencil, Request, PencilResult, Response, jsonify, jsonify_with_status, JsonResponse};
+use pencil::testing::TestResponse;
+
+
+fn user() -> BTreeMap<String, u32> {
+    let mut user = BTreeMap::new();
+    user.insert(String::from("id"), 7);
+    user
+}
+
+
+#[test]
+fn test_jsonify() {
+    let mut response = jsonify(&user()).unwrap();
+    assert!(response.status_code == 200);
+    assert!(response.headers.get_raw("Content-Type") == Some(&[b"application/json".to_vec()][..]));
+    assert!(response.body_text() == "{\"id\":7}");
+}
+
+
+#[test]
+fn test_jsonify_with_status() {
+    let mut response = jsonify_with_status(&user(), 201).unwrap();
+    assert!(response.status_code == 201);
+    assert!(response.body_text() == "{\"id\":7}");
+}
+
+
+#[test]
+fn test_json_response_builder() {
+    let mut response = JsonResponse::new(&user())
+        .status(202)
+        .header("Location", "/users/7")
+        .header("Content-Type", "text/plain")
+        .build()
+        .unwrap();
+    assert!(response.status_code == 202);
+    assert!(response.headers.get_raw("Location") == Some(&[b"/users/7".to_vec()][..]));
+    assert!(response.headers.get_raw("Content-Type") == Some(&[b"application/json".to_vec()][..]));
+    assert!(response.body_text() == "{\"id\":7}");
+}
+
+
+#[test]
+fn test_json_encoder_hooks() {
+    let mut app = Pencil::new("/test");
+    let mut price = BTreeMap::new();
+    price.insert(String::from("cents"), 1250);
+    let mut item = BTreeMap::new();
+    item.insert(String::from("price"), price.clone());
+    assert!(app.jsonify(&item).unwrap().body_text() == "{\"price\":{\"cents\":1250}}");
+    app.json_encoder(|value| {
+        value.find("cents").and_then(|cents| cents.as_i64())
+             .map(|cents| Json::String(format!("{}.{:02}", cents / 100, cents % 100)))
+    });
+    assert!(app.jsonify(&item).unwrap().body_text() == "{\"price\":\"12.50\"}");
+    assert!(jsonify(&item).unwrap().body_text() == "{\"price\":{\"cents\":1250}}");
+    let mut context = BTreeMap::new();
+    context.insert(String::from("price"), price.to_json());
+    let mut response = app.render_template_string("Total: {{price}}", &context).unwrap();
+    assert!(response.body_text() == "Total: 12.50");
+}
+
+
+#[test]
+fn test_json_encoder_hooks_values() {
+    use std::collections::HashMap;
+    use serialize::json;
+    let mut app = Pencil::new("/test");
+    app.json_encoder(|_| None);
+    let mut scores = HashMap::new();
+    scores.insert(3u32, (-2i32, 1.5f64, Some('x')));
+    scores.insert(10u32, (7i32, 2.0f64, None));
+    let value = (vec![true, false], scores, String::from("a \"quoted\" name"), -1i64);
+    let expected = Json::from_str(&json::encode(&value).unwrap()).unwrap().to_string();
+    assert!(app.jsonify(&value).unwrap().body_text() == expected);
+}
+
+
+fn create_user(request: &mut Request) -> PencilResult {
+    let name = {
+        let data = try!(request.get_json_strict());
+        data.find("name").and_then(|name| name.as_string()).unwrap_or("").to_owned()
+    };
+    Ok(Response::from(format!("Created {}", name)))
+}
+
+
+#[test]
+fn test_get_json_strict() {
+    let mut app = Pencil::new("/test");
+    app.post("/users", "create_user", create_user);
+    let client = app.test_client();
+    let response = client.post("/users").body("{\"name\": \"cat\"}", "text/plain").send().unwrap();
+    assert!(response.status_code == 400);
+    let response = client.post("/users").body("{\"name\": ", "application/json").send().unwrap();
+    assert!(response.status_code == 400);
+    let mut response = client.post("/users").body("{\"name\": \"cat\"}", "application/json").send().unwrap();
+    assert!(response.status_code == 200);
+    assert!(response.body_text() == "Created cat");
+    let mut response = client.post("/users").body("{\"name\": \"dog\"}", "application/vnd.api+json").send().unwrap();
+    assert!(response.body_text() == "Created dog");
+}
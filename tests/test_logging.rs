@@ -0,0 +1,38 @@
+// Test the file logging.
+
+extern crate pencil;
+extern crate rustc_serialize as serialize;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+
+use serialize::json::Json;
+
+use pencil::{Pencil, Request, PencilResult, Response};
+
+
+fn index(_: &mut Request) -> PencilResult {
+    Ok(Response::from("index"))
+}
+
+
+#[test]
+fn test_access_log_reaches_file() {
+    let dir = env::temp_dir().join("pencil-test-access-log");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("app.log");
+    let mut app = Pencil::new("/test");
+    app.config.set("LOG_FILE", Json::String(path.to_str().unwrap().to_string()));
+    app.config.set("LOG_LEVEL", Json::String(String::from("warn")));
+    app.get("/", "index", index);
+    app.init_file_logger().unwrap();
+    let client = app.test_client();
+    assert!(client.get_path("/").send().unwrap().status_code == 200);
+    let mut content = String::new();
+    File::open(&path).unwrap().read_to_string(&mut content).unwrap();
+    assert!(content.contains("INFO pencil::access: "));
+    assert!(content.contains("\"GET /\" 200"));
+    fs::remove_dir_all(&dir).unwrap();
+}
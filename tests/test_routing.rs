@@ -0,0 +1,303 @@
+// Test the routing.
+
+#[macro_use]
+extern crate pencil;
+extern crate rustc_serialize as serialize;
+
+use serialize::json::Json;
+
+use pencil::{Pencil, Request, PencilResult, Response, ViewArgs};
+use pencil::method::{Get, Put, Options, Extension, extension};
+use pencil::routing::{MapAdapterMatched, Rule, RuleError, PathNormalization};
+use pencil::testing::TestResponse;
+
+
+fn index(_: &mut Request) -> PencilResult {
+    Ok(Response::from("index"))
+}
+
+
+fn matched_redirect(app: &Pencil, path: &str) -> Option<(String, u16)> {
+    let adapter = app.url_map.bind(String::from("localhost"), String::from(path), None, Get);
+    match adapter.matched() {
+        MapAdapterMatched::MatchedRedirect(redirect) => Some(redirect),
+        _ => None,
+    }
+}
+
+
+#[test]
+fn test_redirects_from_config() {
+    let mut app = Pencil::new("/test");
+    app.get("/", "index", index);
+    let redirects = Json::from_str(r#"{
+        "/home": "/",
+        "/start": {"endpoint": "index", "code": 302},
+        "/elsewhere": {"location": "https://example.com/"}
+    }"#).unwrap();
+    app.config.set("REDIRECTS", redirects);
+    app.add_redirects_from_config();
+    assert!(matched_redirect(&app, "/home") == Some((String::from("http://localhost/"), 301)));
+    assert!(matched_redirect(&app, "/start") == Some((String::from("http://localhost/"), 302)));
+    assert!(matched_redirect(&app, "/elsewhere") == Some((String::from("https://example.com/"), 301)));
+    assert!(matched_redirect(&app, "/") == None);
+}
+
+
+#[test]
+fn test_robots_and_favicon_routes() {
+    let mut app = Pencil::new("./tests");
+    app.serve_robots("User-agent: *\nDisallow: /admin/\n");
+    app.serve_favicon("static/favicon.ico");
+    assert!(app.url_for("robots_txt", &ViewArgs::new()) == Some(String::from("/robots.txt")));
+    assert!(app.url_for("favicon", &ViewArgs::new()) == Some(String::from("/favicon.ico")));
+    let client = app.test_client();
+    let mut response = client.get_path("/robots.txt").send().unwrap();
+    assert!(response.status_code == 200);
+    assert!(response.headers.get_raw("Content-Type") == Some(&[b"text/plain; charset=utf-8".to_vec()][..]));
+    assert!(response.headers.get_raw("Cache-Control") == Some(&[b"public, max-age=86400".to_vec()][..]));
+    assert!(response.body_text() == "User-agent: *\nDisallow: /admin/\n");
+    let mut response = client.get_path("/favicon.ico").send().unwrap();
+    assert!(response.status_code == 200);
+    assert!(response.headers.get_raw("Content-Type") == Some(&[b"image/x-icon".to_vec()][..]));
+    assert!(response.headers.get_raw("Cache-Control") == Some(&[b"public, max-age=604800".to_vec()][..]));
+    assert!(response.body_bytes() == vec![0, 0, 1, 0, 0, 0]);
+}
+
+
+fn create_user(_: &mut Request) -> PencilResult {
+    Ok(Response::from("created"))
+}
+
+
+#[test]
+fn test_routes_macro() {
+    let mut app = Pencil::new("/test");
+    routes!(app, {
+        GET "/" => index,
+        PUT "/user" => create_user,
+    });
+    let rules: Vec<(String, bool, bool)> = app.url_map.iter_rules().map(|rule| {
+        (rule.endpoint.clone(), rule.methods.contains(&Get), rule.methods.contains(&Put))
+    }).collect();
+    assert!(rules == vec![(String::from("index"), true, false), (String::from("create_user"), false, true)]);
+}
+
+
+fn post(request: &mut Request) -> PencilResult {
+    let (user_id, slug) = try!(view_args!(request => user_id: u32, slug: String));
+    let page = try!(view_args!(request => page: u8));
+    Ok(Response::from(format!("{} {} {}", user_id, slug, page)))
+}
+
+
+#[test]
+fn test_view_args_macro() {
+    let mut app = Pencil::new("/test");
+    app.get("/<user_id:int>/<slug:string>/<page:int>", "post", post);
+    app.get("/<user_id:int>/<slug:string>", "post_without_page", post);
+    let client = app.test_client();
+    assert!(client.get_path("/42/hello/2").send().unwrap().status_code == 200);
+    assert!(client.get_path("/42/hello/300").send().unwrap().status_code == 400);
+    assert!(client.get_path("/42/hello").send().unwrap().status_code == 404);
+}
+
+
+fn api_index(_: &mut Request) -> PencilResult {
+    Ok(Response::from("api"))
+}
+
+
+#[test]
+fn test_subdomain_routing() {
+    let mut app = Pencil::new("/test");
+    app.config.set("SERVER_NAME", Json::String(String::from("example.com")));
+    app.get("/", "index", index);
+    app.add_rule(Rule::new("/".into(), &[Get], "api_index").subdomain("api"), api_index);
+    let client = app.test_client();
+    assert!(client.get_path("/").header("Host", "example.com").send().unwrap().body_text() == "index");
+    assert!(client.get_path("/").header("Host", "API.example.com").send().unwrap().body_text() == "api");
+    assert!(client.get_path("/").header("Host", "www.example.com").send().unwrap().status_code == 404);
+    assert!(client.get_path("/").header("Host", "example.org").send().unwrap().status_code == 404);
+}
+
+
+fn subdomain_urls(request: &mut Request) -> PencilResult {
+    let values = ViewArgs::new();
+    let index_url = request.url_for("index", &values).unwrap();
+    let api_url = request.url_for("api_index", &values).unwrap();
+    Ok(Response::from(format!("{} {}", index_url, api_url)))
+}
+
+
+#[test]
+fn test_subdomain_url_for() {
+    let mut app = Pencil::new("/test");
+    app.config.set("SERVER_NAME", Json::String(String::from("example.com")));
+    app.get("/", "index", index);
+    app.add_rule(Rule::new("/".into(), &[Get], "api_index").subdomain("api"), api_index);
+    app.add_rule(Rule::new("/urls".into(), &[Get], "urls").subdomain("api"), subdomain_urls);
+    app.get("/urls", "urls", subdomain_urls);
+    let values = ViewArgs::new();
+    assert!(app.url_for("index", &values) == Some(String::from("/")));
+    assert!(app.url_for("api_index", &values) == Some(String::from("http://api.example.com/")));
+    let client = app.test_client();
+    let mut response = client.get_path("/urls").header("Host", "example.com").send().unwrap();
+    assert!(response.body_text() == "/ http://api.example.com/");
+    let mut response = client.get_path("/urls").header("Host", "api.example.com").send().unwrap();
+    assert!(response.body_text() == "http://example.com/ /");
+}
+
+
+fn propfind(request: &mut Request) -> PencilResult {
+    Ok(Response::from(format!("{} {}", request.method(), request.view_args["name"])))
+}
+
+
+#[test]
+fn test_extension_methods() {
+    assert!(extension("GET") == Get);
+    assert!(extension("PROPFIND") == Extension(String::from("PROPFIND")));
+    let mut app = Pencil::new("/test");
+    app.route("/dav/<name:path>", &[extension("PROPFIND"), Extension(String::from("MKCOL"))], "dav", propfind);
+    app.route("/dav/<name:path>", &[Extension(String::from("GET"))], "dav_get", propfind);
+    let client = app.test_client();
+    assert!(client.open_request(extension("PROPFIND"), "/dav/docs").send().unwrap().body_text() == "PROPFIND docs");
+    assert!(client.get_path("/dav/docs").send().unwrap().body_text() == "GET docs");
+    let response = client.open_request(Options, "/dav/docs").send().unwrap();
+    assert!(response.status_code == 200);
+    assert!(response.headers.get_raw("Allow") == Some(&[b"GET, HEAD, MKCOL, OPTIONS, PROPFIND".to_vec()][..]));
+    let response = client.open_request(extension("LOCK"), "/dav/docs").send().unwrap();
+    assert!(response.status_code == 405);
+    assert!(response.headers.get_raw("Allow") == Some(&[b"GET, HEAD, MKCOL, OPTIONS, PROPFIND".to_vec()][..]));
+}
+
+
+fn user(request: &mut Request) -> PencilResult {
+    Ok(Response::from(format!("user {}", request.view_args["user_id"])))
+}
+
+
+#[test]
+fn test_redirect_rules() {
+    let mut app = Pencil::new("/test");
+    app.redirect_rule("/members/<user_id:int>", "user", 301);
+    app.redirect_rule("/people", "users", 308);
+    app.get("/users/<user_id:int>", "user", user);
+    app.get("/users", "users", index);
+    let mut values = ViewArgs::new();
+    values.insert(String::from("user_id"), String::from("7"));
+    assert!(app.url_for("user", &values) == Some(String::from("/users/7")));
+    let client = app.test_client();
+    let response = client.get_path("/members/7").send().unwrap();
+    assert!(response.status_code == 301);
+    assert!(response.headers.get_raw("Location") == Some(&[b"http://localhost/users/7".to_vec()][..]));
+    let response = client.get_path("/people?page=2").send().unwrap();
+    assert!(response.status_code == 308);
+    assert!(response.headers.get_raw("Location") == Some(&[b"http://localhost/users?page=2".to_vec()][..]));
+    assert!(client.get_path("/members/abc").send().unwrap().status_code == 404);
+    assert!(client.get_path("/users/7").send().unwrap().body_text() == "user 7");
+}
+
+
+#[test]
+fn test_strict_slashes() {
+    let mut app = Pencil::new("/test");
+    app.get("/users/", "users", index);
+    app.get("/about", "about", index);
+    app.add_rule(Rule::new("/teams/".into(), &[Get], "teams").strict_slashes(false), index);
+    app.set_strict_slashes(false);
+    app.get("/posts/", "posts", index);
+    app.get("/contact", "contact", index);
+    app.add_rule(Rule::new("/tags/".into(), &[Get], "tags").strict_slashes(true), index);
+    let client = app.test_client();
+    assert!(client.get_path("/users").send().unwrap().status_code == 301);
+    assert!(client.get_path("/about/").send().unwrap().status_code == 404);
+    assert!(client.get_path("/teams").send().unwrap().status_code == 200);
+    assert!(client.get_path("/posts").send().unwrap().status_code == 200);
+    assert!(client.get_path("/posts/").send().unwrap().status_code == 200);
+    assert!(client.get_path("/contact/").send().unwrap().status_code == 200);
+    assert!(client.get_path("/tags").send().unwrap().status_code == 301);
+    assert!(app.url_for("posts", &ViewArgs::new()) == Some(String::from("/posts/")));
+}
+
+
+#[test]
+fn test_url_normalization() {
+    let mut app = Pencil::new("/test");
+    app.get("/api/users", "users", index);
+    app.post("/api/users", "create_user", index);
+    app.enable_url_normalization(PathNormalization::new());
+    let client = app.test_client();
+    let response = client.get_path("//api//users?page=2").send().unwrap();
+    assert!(response.status_code == 301);
+    assert!(response.headers.get_raw("Location") == Some(&[b"http://localhost/api/users?page=2".to_vec()][..]));
+    assert!(client.post("/api//users").send().unwrap().status_code == 308);
+    assert!(client.get_path("/api/users").send().unwrap().status_code == 200);
+
+    let mut normalization = PathNormalization::new();
+    normalization.redirect = false;
+    app.enable_url_normalization(normalization);
+    assert!(app.test_client().get_path("//api//users").send().unwrap().body_text() == "index");
+}
+
+
+fn greet(request: &mut Request) -> PencilResult {
+    Ok(Response::from(format!("Hello {}!", request.view_args["name"])))
+}
+
+
+#[test]
+fn test_percent_decoded_view_args() {
+    let mut app = Pencil::new("/test");
+    app.get("/hello/<name:string>", "greet", greet);
+    let client = app.test_client();
+    assert!(client.get_path("/hello/J%C3%BCrgen").send().unwrap().body_text() == "Hello Jürgen!");
+    assert!(client.get_path("/hello/a%2Fb").send().unwrap().status_code == 404);
+    assert!(client.get_path("/hello/J%FCrgen").send().unwrap().status_code == 400);
+}
+
+
+#[test]
+fn test_try_route() {
+    let mut app = Pencil::new("/test");
+    assert!(app.try_route("/", &[Get], "index", index).is_ok());
+    assert!(app.try_route("/home", &[Get], "index", index) == Err(RuleError::EndpointCollision(String::from("index"))));
+    assert!(app.try_route("home", &[Get], "home", index) == Err(RuleError::MissingLeadingSlash(String::from("home"))));
+    assert!(app.try_route("/users/<id:int", &[Get], "user", index) == Err(RuleError::Malformed(String::from("/users/<id:int"))));
+    assert!(app.try_route("/users/<int>", &[Get], "user", index) == Err(RuleError::Malformed(String::from("/users/<int>"))));
+    assert!(app.try_route("/<a:int>/<a:int>", &[Get], "pair", index) == Err(RuleError::DuplicateVariable(String::from("a"))));
+    let error = app.try_route("/archive/<year:year>", &[Get], "archive", index).unwrap_err();
+    assert!(error == RuleError::UnknownConverter(String::from("year")));
+    assert!(error.to_string() == "the converter year does not exist");
+    assert!(app.url_map.iter_rules().count() == 1);
+    assert!(app.try_route("/users/<id:int>", &[Get], "user", index).is_ok());
+    assert!(app.test_client().get_path("/users/1").send().unwrap().status_code == 200);
+}
+
+
+fn about(request: &mut Request) -> PencilResult {
+    let url = request.url_for("about", &ViewArgs::new()).unwrap();
+    Ok(Response::from(format!("{} {}", request.locale().unwrap(), url)))
+}
+
+
+#[test]
+fn test_locale_negotiation() {
+    let mut app = Pencil::new("/test");
+    app.enable_locale_prefix(&["en", "de", "pt-BR"], "en");
+    app.get("/about", "about", about);
+    let client = app.test_client();
+    let get = |path: &str, accept_language: Option<&str>| {
+        let mut request = client.get_path(path);
+        if let Some(accept_language) = accept_language {
+            request = request.header("Accept-Language", accept_language);
+        }
+        request.send().unwrap().body_text()
+    };
+    assert!(get("/about", None) == "en /about");
+    assert!(get("/about", Some("de-AT, fr;q=0.9")) == "de /about");
+    assert!(get("/about", Some("fr, pt;q=0.8, en;q=0.5")) == "pt-BR /about");
+    assert!(get("/about", Some("fr, *;q=0.5")) == "en /about");
+    assert!(get("/de/about", Some("pt-BR")) == "de /de/about");
+}
@@ -0,0 +1,54 @@
+// Test signals.
+
+extern crate pencil;
+extern crate rustc_serialize as serialize;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use pencil::{Pencil, Request, PencilResult, UserError};
+
+
+fn hello(request: &mut Request) -> PencilResult {
+    let mut context = BTreeMap::new();
+    context.insert("name".to_string(), "World".to_string());
+    request.app.render_template_string("Hello {{name}}!", &context)
+}
+
+
+fn broken(_: &mut Request) -> PencilResult {
+    Err(UserError::new("broken").into())
+}
+
+
+#[test]
+fn test_request_signals() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut app = Pencil::new("/test");
+    app.get("/hello", "hello", hello);
+    app.get("/broken", "broken", broken);
+    let log = events.clone();
+    app.signals.connect_request_started(move |request| {
+        log.lock().unwrap().push(format!("started {}", request.path()));
+    });
+    let log = events.clone();
+    app.signals.connect_template_rendered(move |name, context| {
+        log.lock().unwrap().push(format!("rendered {:?} {}", name, context["name"]));
+    });
+    let log = events.clone();
+    app.signals.connect_got_request_exception(move |_, e| {
+        log.lock().unwrap().push(format!("exception {}", e));
+    });
+    let log = events.clone();
+    app.signals.connect_request_finished(move |_, response| {
+        log.lock().unwrap().push(format!("finished {}", response.status_code));
+    });
+    let client = app.test_client();
+    assert!(client.get_path("/hello").send().unwrap().status_code == 200);
+    assert!(client.get_path("/broken").send().unwrap().status_code == 500);
+    let events = events.lock().unwrap();
+    assert!(*events == vec![
+        "started /hello", "rendered None \"World\"", "finished 200",
+        "started /broken", "exception broken", "finished 500",
+    ]);
+}
@@ -0,0 +1,70 @@
+// Test the slow request warnings.
+
+extern crate pencil;
+extern crate log;
+#[macro_use]
+extern crate lazy_static;
+extern crate rustc_serialize as serialize;
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use log::{Log, LogLevel, LogLevelFilter, LogMetadata, LogRecord};
+use serialize::json::Json;
+
+use pencil::{Pencil, Request, PencilResult, Response};
+
+
+lazy_static! {
+    /// The warnings that were logged.
+    static ref WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+}
+
+
+/// Keeps the warnings so the test can look at them.
+struct TestLogger;
+
+impl Log for TestLogger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        metadata.level() <= LogLevel::Warn
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if self.enabled(record.metadata()) {
+            WARNINGS.lock().unwrap().push(format!("{}", record.args()));
+        }
+    }
+}
+
+
+fn slow(_: &mut Request) -> PencilResult {
+    thread::sleep(Duration::from_millis(20));
+    Ok(Response::from("slow"))
+}
+
+
+fn fast(_: &mut Request) -> PencilResult {
+    Ok(Response::from("fast"))
+}
+
+
+#[test]
+fn test_slow_request_warning() {
+    log::set_logger(|max_log_level| {
+        max_log_level.set(LogLevelFilter::Warn);
+        Box::new(TestLogger)
+    }).unwrap();
+    let mut app = Pencil::new("/test");
+    app.config.set("SLOW_REQUEST_MS", Json::U64(10));
+    app.get("/slow/<id:int>", "slow", slow);
+    app.get("/fast", "fast", fast);
+    let client = app.test_client();
+    assert!(client.get_path("/fast").send().unwrap().status_code == 200);
+    assert!(WARNINGS.lock().unwrap().is_empty());
+    assert!(client.get_path("/slow/42").send().unwrap().status_code == 200);
+    let warnings = WARNINGS.lock().unwrap();
+    assert!(warnings.len() == 1);
+    assert!(warnings[0].starts_with("Slow request to slow \"GET /slow/42\" took "));
+    assert!(warnings[0].contains("\"id\": \"42\""));
+}
@@ -0,0 +1,49 @@
+// Test the webhook signature verification.
+
+extern crate pencil;
+extern crate rustc_serialize as serialize;
+
+use serialize::json::ToJson;
+
+use pencil::{Pencil, Request, PencilResult, Response};
+use pencil::webhooks::require_signature;
+
+
+fn github_hook(request: &mut Request) -> PencilResult {
+    let data = request.get_data().to_vec();
+    Ok(Response::from(data))
+}
+
+
+#[test]
+fn test_require_signature() {
+    let payload = "{\"zen\": \"Keep it simple.\"}";
+    let mut app = Pencil::new("/test");
+    app.post_with("/hooks/github", "github_hook", github_hook).wrap_with(require_signature());
+    let response = app.test_client().post("/hooks/github").body(payload, "application/json").send().unwrap();
+    assert!(response.status_code == 500);
+
+    app.config.set("WEBHOOK_SECRET", "secret".to_json());
+    let client = app.test_client();
+    let response = client.post("/hooks/github").body(payload, "application/json").send().unwrap();
+    assert!(response.status_code == 403);
+    let response = client.post("/hooks/github").body(payload, "application/json")
+        .header("X-Hub-Signature-256", "sha256=a9759d37dbef6aed1ab1ad316940ef5167c35073e094ca98b729f58fb2614e55")
+        .send().unwrap();
+    assert!(response.status_code == 200);
+    let response = client.post("/hooks/github").body("{}", "application/json")
+        .header("X-Hub-Signature-256", "sha256=a9759d37dbef6aed1ab1ad316940ef5167c35073e094ca98b729f58fb2614e55")
+        .send().unwrap();
+    assert!(response.status_code == 403);
+    let response = client.post("/hooks/github").body(payload, "application/json")
+        .header("X-Hub-Signature", "sha1=99ac0391daa7467f0e315f51d9e01de6e4da6615")
+        .send().unwrap();
+    assert!(response.status_code == 403);
+
+    app.config.set("WEBHOOK_ALLOW_SHA1", true.to_json());
+    let client = app.test_client();
+    let response = client.post("/hooks/github").body(payload, "application/json")
+        .header("X-Hub-Signature", "sha1=99ac0391daa7467f0e315f51d9e01de6e4da6615")
+        .send().unwrap();
+    assert!(response.status_code == 200);
+}